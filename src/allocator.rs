@@ -0,0 +1,47 @@
+//! Kernel heap allocation.
+//!
+//! Enables the [alloc](https://doc.rust-lang.org/alloc/) crate (`Box`, `Vec`, `String`, `Arc`,
+//! etc.) inside the kernel.
+
+use linked_list_allocator::LockedHeap;
+
+/// Size of the kernel heap in bytes.
+pub const HEAP_SIZE: usize = 2 * 1024 * 1024; // 2 MiB
+
+#[global_allocator]
+static ALLOCATOR: LockedHeap = LockedHeap::empty();
+
+/// Initialise the kernel heap. Must be called exactly once, before anything is allocated.
+pub fn init() {
+    // Kept in the kernel image, which the bootloader has already mapped, so the heap needs no
+    // frames of its own
+    static mut HEAP: [u8; HEAP_SIZE] = [0; HEAP_SIZE];
+
+    // UNSAFE: `HEAP` is only ever handed to the allocator, and only once.
+    unsafe {
+        ALLOCATOR
+            .lock()
+            .init((&raw mut HEAP).cast::<u8>(), HEAP_SIZE);
+    }
+}
+
+/// Statistics about kernel heap usage.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct HeapStats {
+    /// Size of the heap in bytes.
+    pub size: usize,
+    /// Bytes currently allocated.
+    pub used: usize,
+    /// Bytes available for allocation.
+    pub free: usize,
+}
+
+/// Statistics about kernel heap usage.
+pub fn stats() -> HeapStats {
+    let heap = ALLOCATOR.lock();
+    HeapStats {
+        size: heap.size(),
+        used: heap.used(),
+        free: heap.free(),
+    }
+}
@@ -0,0 +1,608 @@
+//! [ATA](https://wiki.osdev.org/ATA_PIO_Mode) PIO storage driver.
+//!
+//! Drives up to four disks on the two legacy IDE channels. Transfers go through the data port one
+//! word at a time, using 28-bit LBA addressing where it suffices and 48-bit LBA beyond that.
+//!
+//! Completion is signalled by IRQ 14/15 when interrupts are enabled, which lets the CPU halt while
+//! the drive is busy instead of spinning. The status register is still polled afterwards, so a
+//! lost interrupt only costs time.
+
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::Mutex;
+use x86_64::instructions::{
+    interrupts,
+    port::{Port, PortReadOnly, PortWriteOnly},
+};
+
+use crate::{
+    block::{check_request, BlockDevice, BlockError, SECTOR_SIZE},
+    fs::devfs,
+    interrupts::{unmask, InterruptIndex},
+};
+
+const PRIMARY_IO_BASE: u16 = 0x01F0;
+const PRIMARY_CONTROL_BASE: u16 = 0x03F6;
+const SECONDARY_IO_BASE: u16 = 0x0170;
+const SECONDARY_CONTROL_BASE: u16 = 0x0376;
+
+const STATUS_ERR: u8 = 0b0000_0001;
+const STATUS_DRQ: u8 = 0b0000_1000;
+const STATUS_DF: u8 = 0b0010_0000;
+const STATUS_BSY: u8 = 0b1000_0000;
+// Nothing drives the bus, so it reads as all ones
+const STATUS_FLOATING: u8 = 0xFF;
+
+const CONTROL_NIEN: u8 = 0b0000_0010;
+
+const DRIVE_SELECT_BASE: u8 = 0b1010_0000;
+const DRIVE_SELECT_LBA: u8 = 0b0100_0000;
+const DRIVE_SELECT_SLAVE: u8 = 0b0001_0000;
+
+const COMMAND_READ_SECTORS: u8 = 0x20;
+const COMMAND_READ_SECTORS_EXT: u8 = 0x24;
+const COMMAND_WRITE_SECTORS: u8 = 0x30;
+const COMMAND_WRITE_SECTORS_EXT: u8 = 0x34;
+const COMMAND_CACHE_FLUSH: u8 = 0xE7;
+const COMMAND_CACHE_FLUSH_EXT: u8 = 0xEA;
+const COMMAND_IDENTIFY: u8 = 0xEC;
+
+/// Highest sector count addressable with 28-bit LBA.
+pub const LBA28_LIMIT: u64 = 1 << 28;
+
+// 28-bit commands take an 8-bit sector count, where 0 means 256
+const MAX_SECTORS_PER_COMMAND: usize = 256;
+const WORDS_PER_SECTOR: usize = SECTOR_SIZE / 2;
+
+// How many times to poll the status register before giving up
+const POLL_LIMIT: usize = 1_000_000;
+// How many times to halt waiting for an interrupt before falling back to polling
+const INTERRUPT_WAIT_LIMIT: usize = 64;
+
+const IDENTIFY_SERIAL: (usize, usize) = (10, 20);
+const IDENTIFY_FIRMWARE: (usize, usize) = (23, 27);
+const IDENTIFY_MODEL: (usize, usize) = (27, 47);
+const IDENTIFY_LBA28_SECTORS: usize = 60;
+const IDENTIFY_COMMAND_SETS: usize = 83;
+const IDENTIFY_LBA48_SUPPORTED: u16 = 1 << 10;
+const IDENTIFY_LBA48_SECTORS: usize = 100;
+
+static CHANNELS: [Mutex<Registers>; 2] = [
+    Mutex::new(Registers::new(PRIMARY_IO_BASE, PRIMARY_CONTROL_BASE)),
+    Mutex::new(Registers::new(SECONDARY_IO_BASE, SECONDARY_CONTROL_BASE)),
+];
+
+static INTERRUPT_FIRED: [AtomicBool; 2] = [AtomicBool::new(false), AtomicBool::new(false)];
+static INTERRUPTS_ENABLED: [AtomicBool; 2] = [AtomicBool::new(false), AtomicBool::new(false)];
+
+static DRIVES: Mutex<Vec<Arc<AtaDrive>>> = Mutex::new(Vec::new());
+
+/// The two legacy IDE channels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[allow(missing_docs)]
+pub enum Channel {
+    Primary,
+    Secondary,
+}
+impl Channel {
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn interrupt_index(self) -> InterruptIndex {
+        match self {
+            Self::Primary => InterruptIndex::PrimaryAta,
+            Self::Secondary => InterruptIndex::SecondaryAta,
+        }
+    }
+
+    fn io_base(self) -> u16 {
+        match self {
+            Self::Primary => PRIMARY_IO_BASE,
+            Self::Secondary => SECONDARY_IO_BASE,
+        }
+    }
+}
+
+/// The position of a drive on its [Channel].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[allow(missing_docs)]
+pub enum Position {
+    Master,
+    Slave,
+}
+impl Position {
+    fn select_bits(self) -> u8 {
+        match self {
+            Self::Master => 0,
+            Self::Slave => DRIVE_SELECT_SLAVE,
+        }
+    }
+}
+
+/// Addressing mode of a single command.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Addressing {
+    Lba28,
+    Lba48,
+}
+
+/// Information reported by a drive in response to IDENTIFY DEVICE.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AtaIdentity {
+    /// Model name.
+    pub model: String,
+    /// Serial number.
+    pub serial: String,
+    /// Firmware revision.
+    pub firmware: String,
+    /// Number of addressable sectors.
+    pub sectors: u64,
+    /// Whether the drive supports 48-bit LBA.
+    pub lba48: bool,
+}
+impl AtaIdentity {
+    fn parse(words: &[u16; WORDS_PER_SECTOR]) -> Self {
+        let lba48 = (words[IDENTIFY_COMMAND_SETS] & IDENTIFY_LBA48_SUPPORTED) != 0;
+        let sectors = if lba48 {
+            words[IDENTIFY_LBA48_SECTORS..(IDENTIFY_LBA48_SECTORS + 4)]
+                .iter()
+                .rev()
+                .fold(0, |acc, &w| (acc << 16) | u64::from(w))
+        } else {
+            (u64::from(words[IDENTIFY_LBA28_SECTORS + 1]) << 16)
+                | u64::from(words[IDENTIFY_LBA28_SECTORS])
+        };
+
+        Self {
+            model: identify_string(&words[IDENTIFY_MODEL.0..IDENTIFY_MODEL.1]),
+            serial: identify_string(&words[IDENTIFY_SERIAL.0..IDENTIFY_SERIAL.1]),
+            firmware: identify_string(&words[IDENTIFY_FIRMWARE.0..IDENTIFY_FIRMWARE.1]),
+            sectors,
+            lba48,
+        }
+    }
+}
+
+/// IDENTIFY strings store two characters per word, high byte first, padded with spaces.
+fn identify_string(words: &[u16]) -> String {
+    let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+    String::from_utf8_lossy(&bytes).trim().into()
+}
+
+/// The I/O ports of one channel.
+struct Registers {
+    data: Port<u16>,
+    error: PortReadOnly<u8>,
+    sector_count: PortWriteOnly<u8>,
+    lba_low: PortWriteOnly<u8>,
+    lba_mid: Port<u8>,
+    lba_high: Port<u8>,
+    drive_select: PortWriteOnly<u8>,
+    status: PortReadOnly<u8>,
+    command: PortWriteOnly<u8>,
+    alt_status: PortReadOnly<u8>,
+    control: PortWriteOnly<u8>,
+}
+impl Registers {
+    const fn new(io_base: u16, control_base: u16) -> Self {
+        Self {
+            data: Port::new(io_base),
+            error: PortReadOnly::new(io_base + 1),
+            sector_count: PortWriteOnly::new(io_base + 2),
+            lba_low: PortWriteOnly::new(io_base + 3),
+            lba_mid: Port::new(io_base + 4),
+            lba_high: Port::new(io_base + 5),
+            drive_select: PortWriteOnly::new(io_base + 6),
+            status: PortReadOnly::new(io_base + 7),
+            command: PortWriteOnly::new(io_base + 7),
+            alt_status: PortReadOnly::new(control_base),
+            control: PortWriteOnly::new(control_base),
+        }
+    }
+
+    // UNSAFE (all methods below): Only ever called on the fixed legacy IDE ports, which have no
+    // memory side effects.
+
+    /// Give the drive time to update its status after a command or drive select. Each read of
+    /// the alternate status register takes about 100ns.
+    fn delay_400ns(&mut self) {
+        for _ in 0..4 {
+            unsafe { self.alt_status.read() };
+        }
+    }
+
+    fn status(&mut self) -> u8 {
+        unsafe { self.status.read() }
+    }
+
+    /// Wait until the drive isn't busy, then return its status.
+    fn wait_not_busy(&mut self) -> Result<u8, BlockError> {
+        for _ in 0..POLL_LIMIT {
+            let status = unsafe { self.alt_status.read() };
+            if (status & STATUS_BSY) == 0 {
+                return Ok(status);
+            }
+            core::hint::spin_loop();
+        }
+        Err(BlockError::Timeout)
+    }
+
+    /// Wait until the command has completed, checking for errors.
+    fn wait_done(&mut self) -> Result<(), BlockError> {
+        self.wait_not_busy()?;
+        // Reading the regular status register acknowledges the interrupt
+        let status = self.status();
+        self.check_error(status)
+    }
+
+    /// Wait until the drive is ready to transfer data, checking for errors.
+    fn wait_data_request(&mut self) -> Result<(), BlockError> {
+        for _ in 0..POLL_LIMIT {
+            let status = self.wait_not_busy()?;
+            self.check_error(status)?;
+            if (status & STATUS_DRQ) != 0 {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(BlockError::Timeout)
+    }
+
+    fn check_error(&mut self, status: u8) -> Result<(), BlockError> {
+        if (status & (STATUS_ERR | STATUS_DF)) != 0 {
+            Err(BlockError::Device(unsafe { self.error.read() }))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn select(&mut self, value: u8) {
+        unsafe { self.drive_select.write(value) };
+        self.delay_400ns();
+    }
+
+    fn set_interrupts_enabled(&mut self, enabled: bool) {
+        let control = if enabled { 0 } else { CONTROL_NIEN };
+        unsafe { self.control.write(control) };
+    }
+
+    /// Load the task file registers for a transfer of `count` sectors starting at `lba`.
+    fn setup_transfer(&mut self, position: Position, addressing: Addressing, lba: u64, count: u16) {
+        let lba = lba.to_le_bytes();
+        let count = count.to_le_bytes();
+        unsafe {
+            match addressing {
+                Addressing::Lba28 => {
+                    self.select(
+                        DRIVE_SELECT_BASE
+                            | DRIVE_SELECT_LBA
+                            | position.select_bits()
+                            | (lba[3] & 0x0F),
+                    );
+                    self.sector_count.write(count[0]);
+                    self.lba_low.write(lba[0]);
+                    self.lba_mid.write(lba[1]);
+                    self.lba_high.write(lba[2]);
+                }
+                Addressing::Lba48 => {
+                    self.select(DRIVE_SELECT_LBA | position.select_bits());
+                    // High bytes first, then low bytes
+                    self.sector_count.write(count[1]);
+                    self.lba_low.write(lba[3]);
+                    self.lba_mid.write(lba[4]);
+                    self.lba_high.write(lba[5]);
+                    self.sector_count.write(count[0]);
+                    self.lba_low.write(lba[0]);
+                    self.lba_mid.write(lba[1]);
+                    self.lba_high.write(lba[2]);
+                }
+            }
+        }
+    }
+
+    fn read_sector(&mut self, sector: &mut [u8]) {
+        for word in sector.chunks_exact_mut(2) {
+            word.copy_from_slice(&unsafe { self.data.read() }.to_le_bytes());
+        }
+    }
+
+    fn write_sector(&mut self, sector: &[u8]) {
+        for word in sector.chunks_exact(2) {
+            unsafe { self.data.write(u16::from_le_bytes([word[0], word[1]])) };
+        }
+    }
+}
+
+/// Called by the interrupt handler of `channel`.
+pub fn handle_interrupt(channel: Channel) {
+    // Read the status register to acknowledge the interrupt. Bypass the channel lock, which is
+    // held by whoever is waiting for this interrupt.
+    let mut status = PortReadOnly::<u8>::new(channel.io_base() + 7);
+    // UNSAFE: Reading the status register of a legacy IDE channel has no memory side effects.
+    unsafe { status.read() };
+    INTERRUPT_FIRED[channel.index()].store(true, Ordering::Release);
+}
+
+/// Halt until `channel` raises an interrupt. Returns immediately if interrupts can't be used, in
+/// which case the caller's status polling does all the waiting.
+fn wait_for_interrupt(channel: Channel) {
+    let index = channel.index();
+    if !INTERRUPTS_ENABLED[index].load(Ordering::Relaxed) || !interrupts::are_enabled() {
+        return;
+    }
+
+    for _ in 0..INTERRUPT_WAIT_LIMIT {
+        // Check and halt with interrupts disabled so the interrupt can't slip in between
+        interrupts::disable();
+        if INTERRUPT_FIRED[index].swap(false, Ordering::Acquire) {
+            interrupts::enable();
+            return;
+        }
+        interrupts::enable_and_hlt();
+    }
+}
+
+/// A drive attached to one of the IDE channels.
+#[derive(Debug)]
+pub struct AtaDrive {
+    name: String,
+    channel: Channel,
+    position: Position,
+    identity: AtaIdentity,
+}
+impl AtaDrive {
+    /// The channel the drive is attached to.
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+
+    /// The position of the drive on its channel.
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    /// What the drive reported about itself.
+    pub fn identity(&self) -> &AtaIdentity {
+        &self.identity
+    }
+
+    /// Choose the addressing mode for a transfer ending before sector `end`.
+    fn addressing_for(&self, end: u64) -> Addressing {
+        if end <= LBA28_LIMIT {
+            Addressing::Lba28
+        } else {
+            Addressing::Lba48
+        }
+    }
+
+    fn read_with(
+        &self,
+        addressing: Addressing,
+        lba: u64,
+        buf: &mut [u8],
+    ) -> Result<(), BlockError> {
+        let command = match addressing {
+            Addressing::Lba28 => COMMAND_READ_SECTORS,
+            Addressing::Lba48 => COMMAND_READ_SECTORS_EXT,
+        };
+
+        let mut lba = lba;
+        for chunk in buf.chunks_mut(MAX_SECTORS_PER_COMMAND * SECTOR_SIZE) {
+            let count = chunk.len() / SECTOR_SIZE;
+            let mut regs = CHANNELS[self.channel.index()].lock();
+            regs.wait_not_busy()?;
+            regs.setup_transfer(self.position, addressing, lba, count as u16);
+            INTERRUPT_FIRED[self.channel.index()].store(false, Ordering::Relaxed);
+            unsafe { regs.command.write(command) };
+
+            for sector in chunk.chunks_exact_mut(SECTOR_SIZE) {
+                regs.delay_400ns();
+                wait_for_interrupt(self.channel);
+                regs.wait_data_request()?;
+                regs.read_sector(sector);
+            }
+            regs.wait_done()?;
+            lba += count as u64;
+        }
+        Ok(())
+    }
+
+    fn write_with(&self, addressing: Addressing, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        let command = match addressing {
+            Addressing::Lba28 => COMMAND_WRITE_SECTORS,
+            Addressing::Lba48 => COMMAND_WRITE_SECTORS_EXT,
+        };
+
+        let mut lba = lba;
+        for chunk in buf.chunks(MAX_SECTORS_PER_COMMAND * SECTOR_SIZE) {
+            let count = chunk.len() / SECTOR_SIZE;
+            let mut regs = CHANNELS[self.channel.index()].lock();
+            regs.wait_not_busy()?;
+            regs.setup_transfer(self.position, addressing, lba, count as u16);
+            INTERRUPT_FIRED[self.channel.index()].store(false, Ordering::Relaxed);
+            unsafe { regs.command.write(command) };
+
+            // The first sector is requested straight away; every following one (and the end of
+            // the command) is signalled by an interrupt
+            for sector in chunk.chunks_exact(SECTOR_SIZE) {
+                regs.delay_400ns();
+                regs.wait_data_request()?;
+                regs.write_sector(sector);
+                wait_for_interrupt(self.channel);
+            }
+            regs.wait_done()?;
+            lba += count as u64;
+        }
+        Ok(())
+    }
+}
+impl BlockDevice for AtaDrive {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_count(&self) -> u64 {
+        self.identity.sectors
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        let count = check_request(self, lba, buf.len())?;
+        self.read_with(self.addressing_for(lba + count), lba, buf)
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        let count = check_request(self, lba, buf.len())?;
+        self.write_with(self.addressing_for(lba + count), lba, buf)
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        let command = if self.identity.lba48 {
+            COMMAND_CACHE_FLUSH_EXT
+        } else {
+            COMMAND_CACHE_FLUSH
+        };
+
+        let mut regs = CHANNELS[self.channel.index()].lock();
+        regs.wait_not_busy()?;
+        regs.select(DRIVE_SELECT_BASE | self.position.select_bits());
+        INTERRUPT_FIRED[self.channel.index()].store(false, Ordering::Relaxed);
+        unsafe { regs.command.write(command) };
+        regs.delay_400ns();
+        wait_for_interrupt(self.channel);
+        regs.wait_done()
+    }
+}
+
+/// Send IDENTIFY DEVICE to the given drive. Returns [None] if there's no ATA drive there.
+fn identify(regs: &mut Registers, position: Position) -> Option<AtaIdentity> {
+    regs.select(DRIVE_SELECT_BASE | position.select_bits());
+    unsafe {
+        regs.sector_count.write(0);
+        regs.lba_low.write(0);
+        regs.lba_mid.write(0);
+        regs.lba_high.write(0);
+        regs.command.write(COMMAND_IDENTIFY);
+    }
+    regs.delay_400ns();
+
+    if regs.status() == 0 {
+        // No drive
+        return None;
+    }
+    regs.wait_not_busy().ok()?;
+
+    // ATAPI devices (e.g. the QEMU CD-ROM) leave a signature in the LBA mid/high registers
+    let signature = unsafe { (regs.lba_mid.read(), regs.lba_high.read()) };
+    if signature != (0, 0) {
+        return None;
+    }
+    regs.wait_data_request().ok()?;
+
+    let mut words = [0; WORDS_PER_SECTOR];
+    for word in words.iter_mut() {
+        *word = unsafe { regs.data.read() };
+    }
+    Some(AtaIdentity::parse(&words))
+}
+
+/// Probe both IDE channels for ATA drives and enable their interrupts.
+pub fn init() {
+    let mut drives = Vec::new();
+
+    for channel in [Channel::Primary, Channel::Secondary] {
+        let mut regs = CHANNELS[channel.index()].lock();
+        regs.set_interrupts_enabled(false);
+        if regs.status() == STATUS_FLOATING {
+            continue;
+        }
+
+        let mut found = false;
+        for position in [Position::Master, Position::Slave] {
+            if let Some(identity) = identify(&mut regs, position) {
+                let index = channel.index() * 2 + position as usize;
+                drives.push(Arc::new(AtaDrive {
+                    name: format!("ata{}", index),
+                    channel,
+                    position,
+                    identity,
+                }));
+                found = true;
+            }
+        }
+
+        if found {
+            regs.set_interrupts_enabled(true);
+            INTERRUPTS_ENABLED[channel.index()].store(true, Ordering::Relaxed);
+            unmask(channel.interrupt_index());
+        }
+    }
+
+    for drive in &drives {
+        log::info!(
+            "{}: {} ({} sectors, {})",
+            drive.name,
+            drive.identity.model,
+            drive.identity.sectors,
+            if drive.identity.lba48 {
+                "LBA48"
+            } else {
+                "LBA28"
+            }
+        );
+        let _ = devfs::register_disk(Arc::clone(drive) as _);
+    }
+    *DRIVES.lock() = drives;
+}
+
+/// All ATA drives found by [init].
+pub fn drives() -> Vec<Arc<AtaDrive>> {
+    DRIVES.lock().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_drive() -> Arc<AtaDrive> {
+        drives()
+            .into_iter()
+            .find(|d| d.channel() == Channel::Primary && d.position() == Position::Slave)
+            .expect("test disk not attached as primary slave")
+    }
+
+    #[test_case]
+    fn identify_string_swaps_bytes() {
+        let words = [
+            u16::from_be_bytes(*b"QE"),
+            u16::from_be_bytes(*b"MU"),
+            u16::from_be_bytes(*b"  "),
+        ];
+        assert_eq!(identify_string(&words), "QEMU");
+    }
+
+    #[test_case]
+    fn lba28_and_lba48_agree() {
+        let drive = test_drive();
+        let mut lba28 = [0; SECTOR_SIZE * 2];
+        let mut lba48 = [0xAA; SECTOR_SIZE * 2];
+        drive.read_with(Addressing::Lba28, 1, &mut lba28).unwrap();
+        drive.read_with(Addressing::Lba48, 1, &mut lba48).unwrap();
+        assert!(lba28 == lba48);
+    }
+
+    #[test_case]
+    fn out_of_range() {
+        let drive = test_drive();
+        let mut buf = [0; SECTOR_SIZE];
+        assert_eq!(
+            drive.read_blocks(drive.block_count(), &mut buf),
+            Err(BlockError::OutOfRange)
+        );
+        assert_eq!(
+            drive.read_blocks(0, &mut buf[..100]),
+            Err(BlockError::BadBufferSize)
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! Block storage devices.
+//!
+//! Storage drivers implement [BlockDevice]; filesystems and partition tables only ever talk to
+//! the trait, so they work the same on top of any driver. [cache] keeps recently used blocks in
+//! memory, and [partition] splits partitioned disks into a device per partition.
+
+use alloc::vec;
+use core::fmt;
+
+use crate::vfs::VfsError;
+
+pub mod cache;
+pub mod partition;
+
+/// Size of a logical block (i.e. an ATA sector) in bytes.
+pub const SECTOR_SIZE: usize = 512;
+
+/// Errors returned by [BlockDevice] operations.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BlockError {
+    /// The request extends past the end of the device.
+    OutOfRange,
+    /// The buffer length isn't a multiple of the block size.
+    BadBufferSize,
+    /// The device doesn't accept writes.
+    ReadOnly,
+    /// The device didn't respond in time.
+    Timeout,
+    /// The device reported an error. Holds the device-specific error code.
+    Device(u8),
+}
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfRange => write!(f, "block out of range"),
+            Self::BadBufferSize => write!(f, "buffer size not a multiple of the block size"),
+            Self::ReadOnly => write!(f, "device is read-only"),
+            Self::Timeout => write!(f, "device timed out"),
+            Self::Device(code) => write!(f, "device error {:#04X}", code),
+        }
+    }
+}
+impl From<BlockError> for VfsError {
+    fn from(value: BlockError) -> Self {
+        match value {
+            BlockError::OutOfRange => Self::NoSpace,
+            BlockError::ReadOnly => Self::ReadOnly,
+            BlockError::BadBufferSize => Self::InvalidArgument,
+            BlockError::Timeout | BlockError::Device(_) => Self::Io,
+        }
+    }
+}
+
+/// A device made up of fixed-size, individually addressable blocks.
+pub trait BlockDevice: Send + Sync {
+    /// Human-readable name of the device, e.g. `"ata0"`.
+    fn name(&self) -> &str;
+
+    /// Size of a single block in bytes.
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    /// Total number of blocks on the device.
+    fn block_count(&self) -> u64;
+
+    /// Read consecutive blocks starting at `lba` into `buf`, whose length must be a multiple of
+    /// [BlockDevice::block_size].
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError>;
+
+    /// Write consecutive blocks starting at `lba` from `buf`, whose length must be a multiple of
+    /// [BlockDevice::block_size].
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError>;
+
+    /// Make sure every completed write has reached persistent storage.
+    fn flush(&self) -> Result<(), BlockError> {
+        Ok(())
+    }
+
+    /// Check if the device accepts writes.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+}
+
+/// Check that a request for `len` bytes starting at block `lba` fits on `device`. Returns the
+/// number of blocks covered by the request.
+pub fn check_request(device: &dyn BlockDevice, lba: u64, len: usize) -> Result<u64, BlockError> {
+    let block_size = device.block_size();
+    if !len.is_multiple_of(block_size) {
+        return Err(BlockError::BadBufferSize);
+    }
+    let count = (len / block_size) as u64;
+    match lba.checked_add(count) {
+        Some(end) if end <= device.block_count() => Ok(count),
+        _ => Err(BlockError::OutOfRange),
+    }
+}
+
+/// Read `buf.len()` bytes starting at byte `offset` of `device`, which needn't be block-aligned.
+pub fn read_bytes(device: &dyn BlockDevice, offset: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+    let block_size = device.block_size() as u64;
+    let mut block = vec![0; block_size as usize];
+    let mut done = 0;
+    while done < buf.len() {
+        let position = offset + done as u64;
+        let lba = position / block_size;
+        let start = (position % block_size) as usize;
+        let remaining = buf.len() - done;
+
+        // Whole blocks go straight into the caller's buffer
+        if start == 0 && remaining >= block.len() {
+            let len = remaining - remaining % block.len();
+            device.read_blocks(lba, &mut buf[done..(done + len)])?;
+            done += len;
+            continue;
+        }
+
+        let len = remaining.min(block.len() - start);
+        device.read_blocks(lba, &mut block)?;
+        buf[done..(done + len)].copy_from_slice(&block[start..(start + len)]);
+        done += len;
+    }
+    Ok(())
+}
+
+/// Write `buf` starting at byte `offset` of `device`, which needn't be block-aligned. Partially
+/// covered blocks are read first so the bytes around `buf` are preserved.
+pub fn write_bytes(device: &dyn BlockDevice, offset: u64, buf: &[u8]) -> Result<(), BlockError> {
+    let block_size = device.block_size() as u64;
+    let mut block = vec![0; block_size as usize];
+    let mut done = 0;
+    while done < buf.len() {
+        let position = offset + done as u64;
+        let lba = position / block_size;
+        let start = (position % block_size) as usize;
+        let remaining = buf.len() - done;
+
+        if start == 0 && remaining >= block.len() {
+            let len = remaining - remaining % block.len();
+            device.write_blocks(lba, &buf[done..(done + len)])?;
+            done += len;
+            continue;
+        }
+
+        let len = remaining.min(block.len() - start);
+        device.read_blocks(lba, &mut block)?;
+        block[start..(start + len)].copy_from_slice(&buf[done..(done + len)]);
+        device.write_blocks(lba, &block)?;
+        done += len;
+    }
+    Ok(())
+}
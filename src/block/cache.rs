@@ -0,0 +1,488 @@
+//! Buffer cache between filesystems and [BlockDevice]s.
+//!
+//! Wrap a device with [cached] and use the wrapper in its place. Blocks read or written through
+//! any wrapper of a device are kept in one [BlockCache], keyed by device and block number, and
+//! evicted least recently used first. Writes stay in memory until their block is evicted, the
+//! wrapper is flushed, [BlockCache::sync] is called, or the block has been dirty for
+//! [DIRTY_EXPIRE_TICKS] and [writeback_task] gets to it. A read that carries on where the last
+//! read of the same device ended also fetches the blocks after it.
+
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+
+use super::{check_request, BlockDevice, BlockError};
+use crate::interrupts;
+
+/// Number of blocks the global [CACHE] holds.
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// Most blocks fetched ahead of a sequential read.
+pub const MAX_READAHEAD: u64 = 32;
+
+/// Timer ticks a block may stay dirty before [writeback_task] writes it back, about five
+/// seconds.
+pub const DIRTY_EXPIRE_TICKS: u64 = 5 * interrupts::TIMER_HZ;
+
+/// Timer ticks between passes of [writeback_task], about a second.
+pub const WRITEBACK_INTERVAL_TICKS: u64 = interrupts::TIMER_HZ;
+
+/// The cache used by [cached].
+pub static CACHE: BlockCache = BlockCache::new(DEFAULT_CAPACITY);
+
+/// Wrap `device` so its blocks go through the global [CACHE].
+pub fn cached(device: Arc<dyn BlockDevice>) -> Arc<CachedDevice> {
+    CACHE.wrap(device)
+}
+
+/// Tick of the next pass of [writeback_due].
+static NEXT_WRITEBACK: AtomicU64 = AtomicU64::new(0);
+
+/// Write back every expired dirty block in the global [CACHE] once a second. Never returns;
+/// until there's a scheduler to run it as a task of its own, it's the kernel's idle loop.
+pub fn writeback_task() -> ! {
+    loop {
+        x86_64::instructions::hlt();
+        writeback_due();
+    }
+}
+
+/// Write back expired dirty blocks in the global [CACHE] if the last pass was at least
+/// [WRITEBACK_INTERVAL_TICKS] ago. Idle loops call this in place of running [writeback_task].
+pub fn writeback_due() {
+    let now = interrupts::ticks();
+    if now >= NEXT_WRITEBACK.load(Ordering::Relaxed) {
+        // Blocks that fail to write stay dirty, so they're retried on the next pass
+        let _ = CACHE.writeback_expired(now);
+        NEXT_WRITEBACK.store(now + WRITEBACK_INTERVAL_TICKS, Ordering::Relaxed);
+    }
+}
+
+/// Counters describing how well a [BlockCache] is doing.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Block reads served from the cache.
+    pub hits: u64,
+    /// Block reads that had to go to the device.
+    pub misses: u64,
+    /// Blocks fetched by readahead.
+    pub readahead: u64,
+    /// Dirty blocks written back to their device.
+    pub writebacks: u64,
+    /// Blocks dropped to make room for others.
+    pub evictions: u64,
+    /// Blocks currently cached.
+    pub cached: usize,
+    /// Cached blocks not yet written back.
+    pub dirty: usize,
+}
+
+/// Device index in [Inner::devices] and block number.
+type Key = (usize, u64);
+
+struct Buffer {
+    data: Box<[u8]>,
+    /// Tick at which the buffer was first written since it was last clean.
+    dirty_since: Option<u64>,
+    /// Position in [Inner::lru].
+    stamp: u64,
+}
+
+struct Inner {
+    capacity: usize,
+    devices: Vec<Arc<dyn BlockDevice>>,
+    /// Where the last read of each device ended, to spot sequential reads.
+    read_ends: Vec<u64>,
+    buffers: BTreeMap<Key, Buffer>,
+    /// Buffers by when they were last used, oldest first.
+    lru: BTreeMap<u64, Key>,
+    next_stamp: u64,
+    stats: CacheStats,
+}
+impl Inner {
+    fn touch(&mut self, key: Key) {
+        let stamp = self.next_stamp;
+        self.next_stamp += 1;
+        if let Some(buffer) = self.buffers.get_mut(&key) {
+            self.lru.remove(&buffer.stamp);
+            buffer.stamp = stamp;
+            self.lru.insert(stamp, key);
+        }
+    }
+
+    /// Add a buffer that isn't cached yet, evicting others to make room.
+    fn insert(
+        &mut self,
+        key: Key,
+        data: &[u8],
+        dirty_since: Option<u64>,
+    ) -> Result<(), BlockError> {
+        while self.buffers.len() >= self.capacity {
+            self.evict()?;
+        }
+        let stamp = self.next_stamp;
+        self.next_stamp += 1;
+        self.lru.insert(stamp, key);
+        let buffer = Buffer {
+            data: data.into(),
+            dirty_since,
+            stamp,
+        };
+        self.buffers.insert(key, buffer);
+        Ok(())
+    }
+
+    /// Drop the least recently used buffer, writing it back first if it's dirty.
+    fn evict(&mut self) -> Result<(), BlockError> {
+        let Some((&stamp, &key)) = self.lru.first_key_value() else {
+            return Ok(());
+        };
+        if self.buffers[&key].dirty_since.is_some() {
+            self.write_back(&[key])?;
+        }
+        self.lru.remove(&stamp);
+        self.buffers.remove(&key);
+        self.stats.evictions += 1;
+        Ok(())
+    }
+
+    /// Write the given dirty buffers to their devices, merging runs of consecutive blocks into
+    /// single requests. `keys` must be sorted.
+    fn write_back(&mut self, keys: &[Key]) -> Result<(), BlockError> {
+        let mut start = 0;
+        while start < keys.len() {
+            let (device, lba) = keys[start];
+            let mut end = start + 1;
+            while end < keys.len() && keys[end] == (device, lba + (end - start) as u64) {
+                end += 1;
+            }
+
+            let data: Vec<u8> = keys[start..end]
+                .iter()
+                .flat_map(|key| self.buffers[key].data.iter().copied())
+                .collect();
+            self.devices[device].write_blocks(lba, &data)?;
+            for key in &keys[start..end] {
+                self.buffers.get_mut(key).expect("cached").dirty_since = None;
+            }
+            self.stats.writebacks += (end - start) as u64;
+            start = end;
+        }
+        Ok(())
+    }
+
+    /// Dirty buffers matching `filter`, in order.
+    fn dirty_keys(&self, filter: impl Fn(&Key, &Buffer) -> bool) -> Vec<Key> {
+        self.buffers
+            .iter()
+            .filter(|(key, buffer)| buffer.dirty_since.is_some() && filter(key, buffer))
+            .map(|(&key, _)| key)
+            .collect()
+    }
+
+    fn read(&mut self, device: usize, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        let disk = Arc::clone(&self.devices[device]);
+        let count = check_request(disk.as_ref(), lba, buf.len())?;
+        let block_size = disk.block_size();
+        let sequential = self.read_ends[device] == lba;
+        let out = |i: u64| (i as usize * block_size)..((i as usize + 1) * block_size);
+
+        let mut i = 0;
+        while i < count {
+            if let Some(buffer) = self.buffers.get(&(device, lba + i)) {
+                buf[out(i)].copy_from_slice(&buffer.data);
+                self.touch((device, lba + i));
+                self.stats.hits += 1;
+                i += 1;
+                continue;
+            }
+
+            // Read the whole run of missing blocks at once, plus whatever follows it if the
+            // reads so far have been sequential
+            let mut end = i + 1;
+            while end < count && !self.buffers.contains_key(&(device, lba + end)) {
+                end += 1;
+            }
+            let mut extra = 0;
+            if sequential && end == count {
+                let limit = MAX_READAHEAD
+                    .min(self.capacity as u64 / 4)
+                    .min(disk.block_count() - (lba + count));
+                while extra < limit && !self.buffers.contains_key(&(device, lba + count + extra)) {
+                    extra += 1;
+                }
+            }
+
+            let mut data = vec![0; (end - i + extra) as usize * block_size];
+            disk.read_blocks(lba + i, &mut data)?;
+            let len = (end - i) as usize * block_size;
+            buf[out(i).start..out(end - 1).end].copy_from_slice(&data[..len]);
+            for (j, block) in data.chunks_exact(block_size).enumerate() {
+                self.insert((device, lba + i + j as u64), block, None)?;
+            }
+            self.stats.misses += end - i;
+            self.stats.readahead += extra;
+            i = end;
+        }
+
+        self.read_ends[device] = lba + count;
+        Ok(())
+    }
+
+    fn write(&mut self, device: usize, lba: u64, buf: &[u8], now: u64) -> Result<(), BlockError> {
+        let disk = Arc::clone(&self.devices[device]);
+        check_request(disk.as_ref(), lba, buf.len())?;
+        if disk.is_read_only() {
+            return Err(BlockError::ReadOnly);
+        }
+
+        for (i, block) in buf.chunks_exact(disk.block_size()).enumerate() {
+            let key = (device, lba + i as u64);
+            match self.buffers.get_mut(&key) {
+                Some(buffer) => {
+                    buffer.data.copy_from_slice(block);
+                    buffer.dirty_since.get_or_insert(now);
+                    self.touch(key);
+                }
+                None => self.insert(key, block, Some(now))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An LRU cache of blocks from any number of devices.
+pub struct BlockCache {
+    inner: Mutex<Inner>,
+}
+impl BlockCache {
+    /// An empty cache holding up to `capacity` blocks. Panics if `capacity` is 0, as every block
+    /// read or written goes through the cache.
+    pub const fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "a block cache must hold at least one block");
+        Self {
+            inner: Mutex::new(Inner {
+                capacity,
+                devices: Vec::new(),
+                read_ends: Vec::new(),
+                buffers: BTreeMap::new(),
+                lru: BTreeMap::new(),
+                next_stamp: 0,
+                stats: CacheStats {
+                    hits: 0,
+                    misses: 0,
+                    readahead: 0,
+                    writebacks: 0,
+                    evictions: 0,
+                    cached: 0,
+                    dirty: 0,
+                },
+            }),
+        }
+    }
+
+    /// Wrap `device` so its blocks go through this cache. Every wrapper of the same device
+    /// shares the same blocks.
+    pub fn wrap(&'static self, device: Arc<dyn BlockDevice>) -> Arc<CachedDevice> {
+        let mut inner = self.inner.lock();
+        let index = match inner.devices.iter().position(|d| Arc::ptr_eq(d, &device)) {
+            Some(index) => index,
+            None => {
+                inner.devices.push(Arc::clone(&device));
+                inner.read_ends.push(u64::MAX);
+                inner.devices.len() - 1
+            }
+        };
+        Arc::new(CachedDevice {
+            cache: self,
+            index,
+            device,
+        })
+    }
+
+    /// Write back every dirty block and flush every device.
+    pub fn sync(&self) -> Result<(), BlockError> {
+        let mut inner = self.inner.lock();
+        let keys = inner.dirty_keys(|_, _| true);
+        inner.write_back(&keys)?;
+        inner.devices.iter().try_for_each(|device| device.flush())
+    }
+
+    /// Write back the blocks that have been dirty for at least [DIRTY_EXPIRE_TICKS] as of tick
+    /// `now`.
+    pub fn writeback_expired(&self, now: u64) -> Result<(), BlockError> {
+        let mut inner = self.inner.lock();
+        let keys = inner.dirty_keys(|_, buffer| {
+            buffer
+                .dirty_since
+                .is_some_and(|since| now.saturating_sub(since) >= DIRTY_EXPIRE_TICKS)
+        });
+        inner.write_back(&keys)
+    }
+
+    /// The cache's counters so far.
+    pub fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock();
+        CacheStats {
+            cached: inner.buffers.len(),
+            dirty: inner.dirty_keys(|_, _| true).len(),
+            ..inner.stats
+        }
+    }
+}
+
+/// A [BlockDevice] whose blocks go through a [BlockCache]. Flushing it writes back its dirty
+/// blocks before flushing the device itself.
+pub struct CachedDevice {
+    cache: &'static BlockCache,
+    index: usize,
+    device: Arc<dyn BlockDevice>,
+}
+impl CachedDevice {
+    /// The device underneath the cache.
+    pub fn device(&self) -> &Arc<dyn BlockDevice> {
+        &self.device
+    }
+}
+impl BlockDevice for CachedDevice {
+    fn name(&self) -> &str {
+        self.device.name()
+    }
+
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.device.block_count()
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.cache.inner.lock().read(self.index, lba, buf)
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        let now = interrupts::ticks();
+        self.cache.inner.lock().write(self.index, lba, buf, now)
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        let mut inner = self.cache.inner.lock();
+        let keys = inner.dirty_keys(|&(device, _), _| device == self.index);
+        inner.write_back(&keys)?;
+        self.device.flush()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.device.is_read_only()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::SECTOR_SIZE;
+
+    /// A device in memory that counts requests.
+    struct MemDevice {
+        data: Mutex<Vec<u8>>,
+        reads: Mutex<usize>,
+        writes: Mutex<usize>,
+    }
+    impl MemDevice {
+        fn new(blocks: usize) -> Arc<Self> {
+            Arc::new(Self {
+                data: Mutex::new((0..(blocks * SECTOR_SIZE)).map(|i| i as u8).collect()),
+                reads: Mutex::new(0),
+                writes: Mutex::new(0),
+            })
+        }
+    }
+    impl BlockDevice for MemDevice {
+        fn name(&self) -> &str {
+            "mem"
+        }
+
+        fn block_count(&self) -> u64 {
+            (self.data.lock().len() / SECTOR_SIZE) as u64
+        }
+
+        fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+            check_request(self, lba, buf.len())?;
+            let start = lba as usize * SECTOR_SIZE;
+            buf.copy_from_slice(&self.data.lock()[start..(start + buf.len())]);
+            *self.reads.lock() += 1;
+            Ok(())
+        }
+
+        fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+            check_request(self, lba, buf.len())?;
+            let start = lba as usize * SECTOR_SIZE;
+            self.data.lock()[start..(start + buf.len())].copy_from_slice(buf);
+            *self.writes.lock() += 1;
+            Ok(())
+        }
+    }
+
+    fn new_cache(capacity: usize) -> &'static BlockCache {
+        Box::leak(Box::new(BlockCache::new(capacity)))
+    }
+
+    #[test_case]
+    fn hits_and_misses() {
+        let device = MemDevice::new(64);
+        let cached = new_cache(16).wrap(device.clone());
+        let mut buf = [0; SECTOR_SIZE * 2];
+        cached.read_blocks(10, &mut buf).unwrap();
+        cached.read_blocks(10, &mut buf).unwrap();
+        assert_eq!(buf[0], (10 * SECTOR_SIZE) as u8);
+        assert_eq!(*device.reads.lock(), 1);
+
+        let stats = cached.cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.cached), (2, 2, 2));
+    }
+
+    #[test_case]
+    fn write_back() {
+        let device = MemDevice::new(64);
+        let cache = new_cache(4);
+        let cached = cache.wrap(device.clone());
+        cached.write_blocks(0, &[0xAA; SECTOR_SIZE * 2]).unwrap();
+        assert_eq!(*device.writes.lock(), 0);
+        assert_eq!(cache.stats().dirty, 2);
+
+        // Not expired yet
+        cache.writeback_expired(DIRTY_EXPIRE_TICKS - 1).unwrap();
+        assert_eq!(*device.writes.lock(), 0);
+
+        // Consecutive blocks are written together
+        cached.flush().unwrap();
+        assert_eq!(*device.writes.lock(), 1);
+        assert_eq!(device.data.lock()[SECTOR_SIZE * 2 - 1], 0xAA);
+        assert_eq!(cache.stats().dirty, 0);
+
+        // Evicting a dirty block writes it back
+        cached.write_blocks(5, &[0xBB; SECTOR_SIZE]).unwrap();
+        let mut buf = [0; SECTOR_SIZE * 4];
+        cached.read_blocks(20, &mut buf).unwrap();
+        assert_eq!(device.data.lock()[5 * SECTOR_SIZE], 0xBB);
+        assert_eq!(cache.stats().evictions, 3);
+    }
+
+    #[test_case]
+    fn readahead() {
+        let device = MemDevice::new(64);
+        let cached = new_cache(64).wrap(device.clone());
+        let mut buf = [0; SECTOR_SIZE];
+        cached.read_blocks(0, &mut buf).unwrap();
+        cached.read_blocks(1, &mut buf).unwrap();
+        // The second read was sequential, so the next blocks are already there
+        for lba in 2..10 {
+            cached.read_blocks(lba, &mut buf).unwrap();
+        }
+        assert_eq!(*device.reads.lock(), 2);
+        assert_eq!(buf[0], (9 * SECTOR_SIZE) as u8);
+        assert_eq!(cached.cache.stats().readahead, 16);
+    }
+}
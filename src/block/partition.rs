@@ -0,0 +1,169 @@
+//! Partition tables.
+//!
+//! [partitions] reads the MBR or GPT partition table of a device and returns every partition on
+//! it as a [BlockDevice] of its own, so filesystems can be mounted on a partition just like on a
+//! whole disk.
+
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+use core::fmt;
+
+use super::{check_request, BlockDevice, BlockError};
+
+mod gpt;
+mod mbr;
+
+pub use gpt::Guid;
+
+/// Errors returned by [partitions].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PartitionError {
+    /// Reading the partition table failed.
+    Device(BlockError),
+    /// Both the primary and backup GPT failed their CRC checks.
+    BadChecksum,
+    /// The partition table is malformed, e.g. its partitions don't fit on the device.
+    Invalid,
+}
+impl fmt::Display for PartitionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Device(e) => write!(f, "{}", e),
+            Self::BadChecksum => write!(f, "partition table checksum mismatch"),
+            Self::Invalid => write!(f, "invalid partition table"),
+        }
+    }
+}
+impl From<BlockError> for PartitionError {
+    fn from(value: BlockError) -> Self {
+        Self::Device(value)
+    }
+}
+
+/// What a partition holds, according to the partition table.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PartitionType {
+    /// MBR partition type byte, e.g. `0x83` for Linux.
+    Mbr(u8),
+    /// GPT partition type GUID.
+    Gpt(Guid),
+}
+
+/// A partition table entry, before it's tied to a device.
+struct Entry {
+    number: u32,
+    start: u64,
+    block_count: u64,
+    kind: PartitionType,
+    label: String,
+}
+
+/// Read the partition table of `device`. A device without one has no partitions.
+///
+/// MBR partitions are numbered 1 to 4 by their slot in the table, and logical partitions inside
+/// an extended partition from 5 onwards. GPT partitions are numbered from 1 by their slot in the
+/// partition array. A GPT whose primary header or partition array is damaged is read from the
+/// backup at the end of the disk instead.
+pub fn partitions(device: &Arc<dyn BlockDevice>) -> Result<Vec<Arc<Partition>>, PartitionError> {
+    let mut sector = vec![0; device.block_size()];
+    device.read_blocks(0, &mut sector)?;
+    let Some(table) = mbr::parse(&sector, device.block_count()) else {
+        return Ok(Vec::new());
+    };
+
+    let entries = if table.iter().any(|e| e.kind == mbr::PROTECTIVE) {
+        gpt::read(device.as_ref())?
+    } else {
+        mbr::read(device.as_ref(), &table)?
+    };
+    let separator = if device.name().ends_with(|c: char| c.is_ascii_digit()) {
+        "p"
+    } else {
+        ""
+    };
+    entries
+        .into_iter()
+        .map(|entry| {
+            let end = entry.start.checked_add(entry.block_count);
+            if entry.start == 0 || end.is_none_or(|end| end > device.block_count()) {
+                return Err(PartitionError::Invalid);
+            }
+            Ok(Arc::new(Partition {
+                device: Arc::clone(device),
+                name: format!("{}{}{}", device.name(), separator, entry.number),
+                number: entry.number,
+                start: entry.start,
+                block_count: entry.block_count,
+                kind: entry.kind,
+                label: entry.label,
+            }))
+        })
+        .collect()
+}
+
+/// A contiguous range of blocks on another device.
+pub struct Partition {
+    device: Arc<dyn BlockDevice>,
+    name: String,
+    number: u32,
+    start: u64,
+    block_count: u64,
+    kind: PartitionType,
+    label: String,
+}
+impl Partition {
+    /// The partition's number in its table, counting from 1.
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    /// First block of the partition on the underlying device.
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// Partition type from the table.
+    pub fn kind(&self) -> PartitionType {
+        self.kind
+    }
+
+    /// GPT partition name. MBR partitions don't have one.
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// The device the partition is on.
+    pub fn device(&self) -> &Arc<dyn BlockDevice> {
+        &self.device
+    }
+}
+impl BlockDevice for Partition {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        check_request(self, lba, buf.len())?;
+        self.device.read_blocks(self.start + lba, buf)
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        check_request(self, lba, buf.len())?;
+        self.device.write_blocks(self.start + lba, buf)
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        self.device.flush()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.device.is_read_only()
+    }
+}
@@ -0,0 +1,215 @@
+//! GUID partition tables.
+
+use alloc::{string::String, vec, vec::Vec};
+use core::fmt;
+
+use super::{Entry, PartitionError, PartitionType};
+use crate::block::BlockDevice;
+
+const SIGNATURE: &[u8; 8] = b"EFI PART";
+const PRIMARY_HEADER_LBA: u64 = 1;
+
+const HEADER_SIZE: usize = 12;
+const HEADER_CRC: usize = 16;
+const MY_LBA: usize = 24;
+const ENTRIES_LBA: usize = 72;
+const ENTRY_COUNT: usize = 80;
+const ENTRY_SIZE: usize = 84;
+const ENTRIES_CRC: usize = 88;
+const MIN_HEADER_SIZE: usize = 92;
+
+const TYPE_GUID: usize = 0;
+const FIRST_LBA: usize = 32;
+const LAST_LBA: usize = 40;
+const NAME: usize = 56;
+const NAME_LEN: usize = 72;
+const MIN_ENTRY_SIZE: usize = 128;
+// Far more than any real table, which normally holds 128 entries
+const MAX_ENTRIES_SIZE: usize = 1 << 20;
+
+/// A GUID, as used for GPT partition types.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Guid(pub [u8; 16]);
+impl Guid {
+    /// Marks unused partition entries.
+    pub const UNUSED: Self = Self([0; 16]);
+    /// EFI system partition.
+    pub const EFI_SYSTEM: Self = Self::new(0xC12A7328, 0xF81F, 0x11D2, 0xBA4B_00A0C93EC93B);
+    /// Windows basic data partition, also used for FAT volumes.
+    pub const BASIC_DATA: Self = Self::new(0xEBD0A0A2, 0xB9E5, 0x4433, 0x87C0_68B6B72699C7);
+    /// Linux filesystem partition.
+    pub const LINUX_FILESYSTEM: Self = Self::new(0x0FC63DAF, 0x8483, 0x4772, 0x8E79_3D69D8477DE4);
+
+    /// The GUID written `a-b-c-d`, with `d` holding the last two groups. The first three groups
+    /// are stored little-endian and the rest big-endian.
+    pub const fn new(a: u32, b: u16, c: u16, d: u64) -> Self {
+        let a = a.to_le_bytes();
+        let b = b.to_le_bytes();
+        let c = c.to_le_bytes();
+        let d = d.to_be_bytes();
+        Self([
+            a[0], a[1], a[2], a[3], b[0], b[1], c[0], c[1], d[0], d[1], d[2], d[3], d[4], d[5],
+            d[6], d[7],
+        ])
+    }
+}
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let g = &self.0;
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-",
+            u32::from_le_bytes([g[0], g[1], g[2], g[3]]),
+            u16::from_le_bytes([g[4], g[5]]),
+            u16::from_le_bytes([g[6], g[7]]),
+        )?;
+        g[8..10].iter().try_for_each(|b| write!(f, "{:02X}", b))?;
+        write!(f, "-")?;
+        g[10..].iter().try_for_each(|b| write!(f, "{:02X}", b))
+    }
+}
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Guid({})", self)
+    }
+}
+
+/// CRC-32 as used by GPT (and Ethernet, zlib, ...).
+pub fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xEDB8_8320
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+    !data.iter().fold(!0, |crc, &b| {
+        TABLE[((crc ^ u32::from(b)) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+fn u32_at(raw: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(raw[offset..(offset + 4)].try_into().unwrap())
+}
+
+fn u64_at(raw: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(raw[offset..(offset + 8)].try_into().unwrap())
+}
+
+/// The partitions in the GPT of `device`, from the backup table if the primary one is damaged.
+pub(super) fn read(device: &dyn BlockDevice) -> Result<Vec<Entry>, PartitionError> {
+    let backup_lba = device.block_count() - 1;
+    read_table(device, PRIMARY_HEADER_LBA).or_else(|primary| match primary {
+        PartitionError::Device(_) => Err(primary),
+        _ => read_table(device, backup_lba).map_err(|backup| {
+            if primary == PartitionError::BadChecksum {
+                primary
+            } else {
+                backup
+            }
+        }),
+    })
+}
+
+/// Read the partition table whose header is at `header_lba`.
+fn read_table(device: &dyn BlockDevice, header_lba: u64) -> Result<Vec<Entry>, PartitionError> {
+    let block_size = device.block_size();
+    let mut header = vec![0; block_size];
+    device.read_blocks(header_lba, &mut header)?;
+    let header_size = u32_at(&header, HEADER_SIZE) as usize;
+    if &header[..SIGNATURE.len()] != SIGNATURE
+        || !(MIN_HEADER_SIZE..=block_size).contains(&header_size)
+        || u64_at(&header, MY_LBA) != header_lba
+    {
+        return Err(PartitionError::Invalid);
+    }
+    let stored_crc = u32_at(&header, HEADER_CRC);
+    header[HEADER_CRC..(HEADER_CRC + 4)].fill(0);
+    if crc32(&header[..header_size]) != stored_crc {
+        return Err(PartitionError::BadChecksum);
+    }
+
+    let entry_size = u32_at(&header, ENTRY_SIZE) as usize;
+    let entries_size = (u32_at(&header, ENTRY_COUNT) as usize)
+        .checked_mul(entry_size)
+        .filter(|&size| size <= MAX_ENTRIES_SIZE)
+        .ok_or(PartitionError::Invalid)?;
+    if entry_size < MIN_ENTRY_SIZE || !entry_size.is_multiple_of(8) {
+        return Err(PartitionError::Invalid);
+    }
+    let entries_lba = u64_at(&header, ENTRIES_LBA);
+    let entries_blocks = entries_size.div_ceil(block_size);
+    if entries_lba
+        .checked_add(entries_blocks as u64)
+        .is_none_or(|end| end > device.block_count())
+    {
+        return Err(PartitionError::Invalid);
+    }
+    let mut raw = vec![0; entries_blocks * block_size];
+    device.read_blocks(entries_lba, &mut raw)?;
+    let raw = &raw[..entries_size];
+    if crc32(raw) != u32_at(&header, ENTRIES_CRC) {
+        return Err(PartitionError::BadChecksum);
+    }
+
+    let mut entries = Vec::new();
+    for (i, raw) in raw.chunks_exact(entry_size).enumerate() {
+        let kind = Guid(raw[TYPE_GUID..(TYPE_GUID + 16)].try_into().unwrap());
+        if kind == Guid::UNUSED {
+            continue;
+        }
+        let first = u64_at(raw, FIRST_LBA);
+        let last = u64_at(raw, LAST_LBA);
+        if last < first {
+            return Err(PartitionError::Invalid);
+        }
+        let name = raw[NAME..(NAME + NAME_LEN)]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0);
+        entries.push(Entry {
+            number: i as u32 + 1,
+            start: first,
+            block_count: last - first + 1,
+            kind: PartitionType::Gpt(kind),
+            label: char::decode_utf16(name)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect::<String>(),
+        });
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+
+    #[test_case]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test_case]
+    fn guid_layout() {
+        assert_eq!(
+            format!("{}", Guid::LINUX_FILESYSTEM),
+            "0FC63DAF-8483-4772-8E79-3D69D8477DE4"
+        );
+        assert_eq!(Guid::LINUX_FILESYSTEM.0[..4], [0xAF, 0x3D, 0xC6, 0x0F]);
+        assert_eq!(Guid::LINUX_FILESYSTEM.0[8..10], [0x8E, 0x79]);
+    }
+}
@@ -0,0 +1,203 @@
+//! Master boot record partition tables, including logical partitions in extended partitions.
+
+use alloc::{string::String, vec, vec::Vec};
+
+use super::{Entry, PartitionError, PartitionType};
+use crate::block::BlockDevice;
+
+const TABLE: usize = 446;
+const ENTRY_SIZE: usize = 16;
+const ENTRIES: usize = 4;
+const SIGNATURE: usize = 510;
+
+const STATUS: usize = 0;
+const TYPE: usize = 4;
+const START: usize = 8;
+const SECTORS: usize = 12;
+
+const STATUS_INACTIVE: u8 = 0x00;
+const STATUS_ACTIVE: u8 = 0x80;
+
+const EMPTY: u8 = 0x00;
+/// Type of the single partition covering a GPT disk.
+pub const PROTECTIVE: u8 = 0xEE;
+const EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+
+// Logical partitions are numbered after the primary slots
+const FIRST_LOGICAL: u32 = ENTRIES as u32 + 1;
+// Stops chains of extended boot records that loop back on themselves
+const MAX_LOGICAL: usize = 128;
+
+/// An entry in a partition table sector.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RawEntry {
+    /// Partition type byte.
+    pub kind: u8,
+    /// First sector, relative to whatever the table is relative to.
+    pub start: u32,
+    /// Number of sectors.
+    pub sectors: u32,
+}
+impl RawEntry {
+    fn parse(sector: &[u8], index: usize) -> Self {
+        let raw = &sector[(TABLE + index * ENTRY_SIZE)..];
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(raw[offset..(offset + 4)].try_into().unwrap());
+        Self {
+            kind: raw[TYPE],
+            start: u32_at(START),
+            sectors: u32_at(SECTORS),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.kind == EMPTY || self.sectors == 0
+    }
+
+    fn is_extended(&self) -> bool {
+        EXTENDED.contains(&self.kind)
+    }
+}
+
+fn has_signature(sector: &[u8]) -> bool {
+    sector[SIGNATURE..(SIGNATURE + 2)] == [0x55, 0xAA]
+}
+
+/// Parse the partition table in the first sector of a device with `block_count` blocks. Returns
+/// [None] if the sector doesn't hold one.
+///
+/// Boot sectors of unpartitioned FAT volumes share the signature, so the table also has to look
+/// sane: valid status bytes, at least one partition and every partition on the device.
+pub fn parse(sector: &[u8], block_count: u64) -> Option<[RawEntry; ENTRIES]> {
+    if !has_signature(sector) {
+        return None;
+    }
+    let statuses_valid = (0..ENTRIES).all(|i| {
+        let status = sector[TABLE + i * ENTRY_SIZE + STATUS];
+        status == STATUS_INACTIVE || status == STATUS_ACTIVE
+    });
+    let table: [RawEntry; ENTRIES] = core::array::from_fn(|i| RawEntry::parse(sector, i));
+    // Protective entries may claim more than the disk has
+    let entries_fit = table.iter().filter(|e| !e.is_empty()).all(|e| {
+        e.start > 0
+            && (e.kind == PROTECTIVE || u64::from(e.start) + u64::from(e.sectors) <= block_count)
+    });
+    let any = table.iter().any(|e| !e.is_empty());
+    (statuses_valid && entries_fit && any).then_some(table)
+}
+
+/// The partitions described by an MBR partition table.
+pub(super) fn read(
+    device: &dyn BlockDevice,
+    table: &[RawEntry; ENTRIES],
+) -> Result<Vec<Entry>, PartitionError> {
+    let mut entries = Vec::new();
+    let mut next_logical = FIRST_LOGICAL;
+    for (i, raw) in table.iter().enumerate() {
+        if raw.is_empty() {
+            continue;
+        }
+        if raw.is_extended() {
+            read_logical(device, raw, &mut next_logical, &mut entries)?;
+            continue;
+        }
+        entries.push(entry(i as u32 + 1, 0, raw));
+    }
+    Ok(entries)
+}
+
+/// Follow the chain of extended boot records in an extended partition. Each holds a logical
+/// partition, relative to itself, and a link to the next record, relative to the extended
+/// partition.
+fn read_logical(
+    device: &dyn BlockDevice,
+    extended: &RawEntry,
+    next_number: &mut u32,
+    entries: &mut Vec<Entry>,
+) -> Result<(), PartitionError> {
+    let base = u64::from(extended.start);
+    let mut record = base;
+    let mut sector = vec![0; device.block_size()];
+    for _ in 0..MAX_LOGICAL {
+        if record >= device.block_count() {
+            return Err(PartitionError::Invalid);
+        }
+        device.read_blocks(record, &mut sector)?;
+        if !has_signature(&sector) {
+            return Err(PartitionError::Invalid);
+        }
+
+        let logical = RawEntry::parse(&sector, 0);
+        if !logical.is_empty() {
+            entries.push(entry(*next_number, record, &logical));
+            *next_number += 1;
+        }
+        let next = RawEntry::parse(&sector, 1);
+        if next.is_empty() || !next.is_extended() {
+            return Ok(());
+        }
+        record = base + u64::from(next.start);
+    }
+    Err(PartitionError::Invalid)
+}
+
+fn entry(number: u32, base: u64, raw: &RawEntry) -> Entry {
+    Entry {
+        number,
+        start: base + u64::from(raw.start),
+        block_count: u64::from(raw.sectors),
+        kind: PartitionType::Mbr(raw.kind),
+        label: String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_entry(sector: &mut [u8], index: usize, kind: u8, start: u32, sectors: u32) {
+        let raw = &mut sector[(TABLE + index * ENTRY_SIZE)..];
+        raw[TYPE] = kind;
+        raw[START..(START + 4)].copy_from_slice(&start.to_le_bytes());
+        raw[SECTORS..(SECTORS + 4)].copy_from_slice(&sectors.to_le_bytes());
+    }
+
+    fn table_sector() -> [u8; 512] {
+        let mut sector = [0; 512];
+        sector[SIGNATURE] = 0x55;
+        sector[SIGNATURE + 1] = 0xAA;
+        sector
+    }
+
+    #[test_case]
+    fn parse_table() {
+        let mut sector = table_sector();
+        assert_eq!(parse(&sector, 1000), None);
+
+        put_entry(&mut sector, 1, 0x83, 100, 200);
+        let table = parse(&sector, 1000).unwrap();
+        assert!(table[0].is_empty());
+        assert_eq!(
+            table[1],
+            RawEntry {
+                kind: 0x83,
+                start: 100,
+                sectors: 200
+            }
+        );
+
+        // Doesn't fit
+        assert_eq!(parse(&sector, 250), None);
+
+        // Boot code where the status bytes should be
+        sector[TABLE + STATUS] = 0x12;
+        assert_eq!(parse(&sector, 1000), None);
+    }
+
+    #[test_case]
+    fn protective_may_overflow() {
+        let mut sector = table_sector();
+        put_entry(&mut sector, 0, PROTECTIVE, 1, u32::MAX);
+        assert!(parse(&sector, 1000).is_some());
+    }
+}
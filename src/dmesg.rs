@@ -0,0 +1,310 @@
+//! The kernel message ring, as shown by `dmesg`.
+//!
+//! Every line printed with [print](crate::print) or [println](crate::println), and every log
+//! record the [logger](crate::logger) lets through to its ring sink, is kept here with a sequence
+//! number and a timestamp. The ring is a fixed-size static array, so recording never allocates
+//! and [dump_to_serial] works in a panic handler; once it's full, the oldest messages make way.
+
+use alloc::{string::String, vec::Vec};
+use core::{
+    fmt::{self, Write},
+    str,
+};
+
+use log::Level;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::{interrupts, serial_println};
+
+/// Most messages kept.
+pub const DMESG_CAPACITY: usize = 256;
+
+/// Longest message kept, in bytes. Longer messages are cut short.
+pub const MAX_MESSAGE_LEN: usize = 200;
+
+static RING: Mutex<Ring> = Mutex::new(Ring::new());
+
+/// Time since boot, shown as seconds to two decimal places.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp {
+    /// Timer ticks since boot, at [TIMER_HZ].
+    pub ticks: u64,
+}
+impl Timestamp {
+    /// The current time.
+    pub fn now() -> Self {
+        Self {
+            ticks: interrupts::ticks(),
+        }
+    }
+}
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hundredths = interrupts::ticks_to_hundredths(self.ticks);
+        write!(f, "{:>5}.{:02}", hundredths / 100, hundredths % 100)
+    }
+}
+
+/// A kernel message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    /// Position among all messages since boot, including ones that have left the ring.
+    pub seq: u64,
+    /// When the message was recorded. For printed lines, when the line was started.
+    pub timestamp: Timestamp,
+    /// Level of a log record, or `None` for a printed line.
+    pub level: Option<Level>,
+    /// The message, without a newline. Log records start with their target.
+    pub text: String,
+}
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_message(f, self.timestamp, self.level, &self.text)
+    }
+}
+
+fn write_message(
+    f: &mut fmt::Formatter<'_>,
+    timestamp: Timestamp,
+    level: Option<Level>,
+    text: &str,
+) -> fmt::Result {
+    match level {
+        Some(level) => write!(f, "[{}] {:<5} {}", timestamp, level, text),
+        None => write!(f, "[{}] {}", timestamp, text),
+    }
+}
+
+/// A message in the ring.
+#[derive(Debug, Copy, Clone)]
+struct Slot {
+    seq: u64,
+    timestamp: Timestamp,
+    level: Option<Level>,
+    len: usize,
+    text: [u8; MAX_MESSAGE_LEN],
+}
+impl Slot {
+    const EMPTY: Self = Self {
+        seq: 0,
+        timestamp: Timestamp { ticks: 0 },
+        level: None,
+        len: 0,
+        text: [0; MAX_MESSAGE_LEN],
+    };
+
+    fn text(&self) -> &str {
+        // Only whole characters are ever stored
+        str::from_utf8(&self.text[..self.len]).unwrap_or_default()
+    }
+}
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_message(f, self.timestamp, self.level, self.text())
+    }
+}
+impl Write for Slot {
+    /// Append as much of `s` as fits, keeping to character boundaries.
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = MAX_MESSAGE_LEN - self.len;
+        let mut end = s.len().min(room);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.text[self.len..(self.len + end)].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+        Ok(())
+    }
+}
+
+/// The messages, and the line being printed.
+struct Ring {
+    slots: [Slot; DMESG_CAPACITY],
+    next_seq: u64,
+    // The printed line that hasn't been finished with a newline yet
+    line: Slot,
+    line_started: bool,
+}
+impl Ring {
+    const fn new() -> Self {
+        Self {
+            slots: [Slot::EMPTY; DMESG_CAPACITY],
+            next_seq: 0,
+            line: Slot::EMPTY,
+            line_started: false,
+        }
+    }
+
+    /// Add `slot` as the newest message, giving it the next sequence number.
+    fn push(&mut self, mut slot: Slot) {
+        slot.seq = self.next_seq;
+        self.slots[(self.next_seq % DMESG_CAPACITY as u64) as usize] = slot;
+        self.next_seq += 1;
+    }
+
+    /// Add printed text, recording each line as it's finished.
+    fn print(&mut self, s: &str) {
+        for (i, part) in s.split('\n').enumerate() {
+            if i > 0 {
+                // A newline came before this part
+                if !self.line_started {
+                    self.start_line();
+                }
+                self.push(self.line);
+                self.line_started = false;
+            }
+            if !part.is_empty() {
+                if !self.line_started {
+                    self.start_line();
+                }
+                let _ = self.line.write_str(part);
+            }
+        }
+    }
+
+    fn start_line(&mut self) {
+        self.line = Slot {
+            timestamp: Timestamp::now(),
+            ..Slot::EMPTY
+        };
+        self.line_started = true;
+    }
+
+    /// The messages still in the ring, oldest first.
+    fn iter(&self) -> impl Iterator<Item = &Slot> {
+        let count = self.next_seq.min(DMESG_CAPACITY as u64);
+        ((self.next_seq - count)..self.next_seq)
+            .map(|seq| &self.slots[(seq % DMESG_CAPACITY as u64) as usize])
+    }
+}
+
+/// Record printed text. Called by [print](crate::print); text is recorded a line at a time, once
+/// the line's newline has been printed.
+pub fn print(args: fmt::Arguments) {
+    struct Printer<'a>(&'a mut Ring);
+    impl Write for Printer<'_> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.0.print(s);
+            Ok(())
+        }
+    }
+
+    without_interrupts(|| {
+        let _ = Printer(&mut RING.lock()).write_fmt(args);
+    });
+}
+
+/// Record a log record from `target`. Called by the [logger](crate::logger).
+pub fn log(level: Level, target: &str, args: fmt::Arguments) {
+    let mut slot = Slot {
+        timestamp: Timestamp::now(),
+        level: Some(level),
+        ..Slot::EMPTY
+    };
+    let _ = write!(slot, "{}: {}", target, args);
+    without_interrupts(|| RING.lock().push(slot));
+}
+
+/// The messages in the ring, oldest first.
+pub fn messages() -> Vec<Message> {
+    without_interrupts(|| {
+        RING.lock()
+            .iter()
+            .map(|slot| Message {
+                seq: slot.seq,
+                timestamp: slot.timestamp,
+                level: slot.level,
+                text: String::from(slot.text()),
+            })
+            .collect()
+    })
+}
+
+/// Write every message in the ring to the serial console, along with a line that hasn't been
+/// finished. Doesn't allocate, and gives up rather than waiting if the ring is locked, so it's
+/// safe to call when panicking.
+pub fn dump_to_serial() {
+    let Some(ring) = RING.try_lock() else {
+        serial_println!("kernel messages unavailable");
+        return;
+    };
+    serial_println!("--- kernel messages ---");
+    for slot in ring.iter() {
+        serial_println!("{}", slot);
+    }
+    if ring.line_started {
+        serial_println!("{}", ring.line);
+    }
+    serial_println!("--- end of kernel messages ---");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interrupts::TIMER_HZ;
+    use alloc::format;
+
+    fn texts(ring: &Ring) -> Vec<&str> {
+        ring.iter().map(Slot::text).collect()
+    }
+
+    #[test_case]
+    fn timestamps() {
+        assert_eq!(format!("{}", Timestamp { ticks: 0 }), "    0.00");
+        assert_eq!(
+            format!(
+                "{}",
+                Timestamp {
+                    ticks: 9 * TIMER_HZ
+                }
+            ),
+            "    9.00"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                Timestamp {
+                    ticks: 12345 * TIMER_HZ + TIMER_HZ / 2
+                }
+            ),
+            "12345.50"
+        );
+    }
+
+    #[test_case]
+    fn printed_lines() {
+        let mut ring = Ring::new();
+        ring.print("partial");
+        assert!(texts(&ring).is_empty());
+        ring.print(" line\nsecond\n\nthird");
+        assert_eq!(texts(&ring), ["partial line", "second", ""]);
+        ring.print("\n");
+        assert_eq!(texts(&ring), ["partial line", "second", "", "third"]);
+        assert!(ring.iter().map(|s| s.seq).eq(0..4));
+    }
+
+    #[test_case]
+    fn bounded() {
+        let mut ring = Ring::new();
+        for i in 0..(DMESG_CAPACITY + 3) {
+            let mut slot = Slot::EMPTY;
+            let _ = write!(slot, "{}", i);
+            ring.push(slot);
+        }
+        let texts = texts(&ring);
+        assert_eq!(texts.len(), DMESG_CAPACITY);
+        assert_eq!(texts[0], "3");
+        assert_eq!(ring.iter().next().unwrap().seq, 3);
+        assert_eq!(texts[DMESG_CAPACITY - 1], format!("{}", DMESG_CAPACITY + 2));
+    }
+
+    #[test_case]
+    fn long_messages_cut_at_characters() {
+        let mut slot = Slot::EMPTY;
+        let _ = slot.write_str(&"x".repeat(MAX_MESSAGE_LEN - 1));
+        let _ = slot.write_str("é and more");
+        assert_eq!(slot.len, MAX_MESSAGE_LEN - 1);
+        assert_eq!(slot.text(), "x".repeat(MAX_MESSAGE_LEN - 1));
+    }
+}
@@ -0,0 +1,496 @@
+//! Linear framebuffers and drawing on them.
+//!
+//! A [Framebuffer] is a block of memory holding one 32-bit value per pixel, row after row, which
+//! the display shows as it is. It can draw pixels, lines, rectangles, images and text in the
+//! built-in [font].
+//!
+//! The bootloader leaves the display in VGA text mode and doesn't hand over a framebuffer, so
+//! [set_mode] sets one up through the [Bochs display](bochs) that QEMU, Bochs and VirtualBox
+//! provide. With the `framebuffer` feature, [init](crate::init) then shows the console on it (see
+//! [console]).
+
+use core::{fmt, ptr};
+
+use spin::Mutex;
+use x86_64::{instructions::interrupts, VirtAddr};
+
+use crate::memory::MemoryError;
+
+pub mod bochs;
+pub mod console;
+pub mod font;
+
+use font::{Glyph, GLYPH_HEIGHT, GLYPH_WIDTH};
+
+static FRAMEBUFFER: Mutex<Option<Framebuffer>> = Mutex::new(None);
+
+/// Errors returned when setting up a framebuffer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FramebufferError {
+    /// No display that can show a framebuffer was found.
+    NotPresent,
+    /// The display can't show the requested width and height.
+    UnsupportedMode(usize, usize),
+    /// The framebuffer couldn't be mapped.
+    Memory(MemoryError),
+}
+impl fmt::Display for FramebufferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotPresent => write!(f, "no framebuffer display"),
+            Self::UnsupportedMode(width, height) => {
+                write!(f, "unsupported mode {}x{}", width, height)
+            }
+            Self::Memory(e) => write!(f, "couldn't map framebuffer: {:?}", e),
+        }
+    }
+}
+
+/// A colour, 8 bits per channel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Colour {
+    /// Red.
+    pub r: u8,
+    /// Green.
+    pub g: u8,
+    /// Blue.
+    pub b: u8,
+}
+impl Colour {
+    /// Black.
+    pub const BLACK: Self = Self::rgb(0, 0, 0);
+    /// White.
+    pub const WHITE: Self = Self::rgb(0xFF, 0xFF, 0xFF);
+
+    /// Create a colour from its red, green and blue parts.
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+}
+
+/// How a pixel's colour is packed into its 32 bits.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PixelFormat {
+    /// Blue in the low byte, then green, then red, then 8 unused bits.
+    Xrgb8888,
+    /// Red in the low byte, then green, then blue, then 8 unused bits.
+    Xbgr8888,
+}
+impl PixelFormat {
+    fn pack(self, colour: Colour) -> u32 {
+        let Colour { r, g, b } = colour;
+        match self {
+            Self::Xrgb8888 => u32::from_le_bytes([b, g, r, 0]),
+            Self::Xbgr8888 => u32::from_le_bytes([r, g, b, 0]),
+        }
+    }
+
+    fn unpack(self, pixel: u32) -> Colour {
+        let [low, middle, high, _] = pixel.to_le_bytes();
+        match self {
+            Self::Xrgb8888 => Colour::rgb(high, middle, low),
+            Self::Xbgr8888 => Colour::rgb(low, middle, high),
+        }
+    }
+}
+
+/// The layout of a framebuffer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FramebufferInfo {
+    /// Visible pixels in each row.
+    pub width: usize,
+    /// Rows.
+    pub height: usize,
+    /// Pixels from the start of one row to the start of the next, at least `width`.
+    pub stride: usize,
+    /// How each pixel is stored.
+    pub format: PixelFormat,
+}
+impl FramebufferInfo {
+    /// Bytes taken up by the framebuffer.
+    pub fn size(&self) -> usize {
+        self.stride * self.height * 4
+    }
+}
+
+/// A rectangle of pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Rect {
+    /// Leftmost column.
+    pub x: usize,
+    /// Top row.
+    pub y: usize,
+    /// Columns covered.
+    pub width: usize,
+    /// Rows covered.
+    pub height: usize,
+}
+impl Rect {
+    /// Create a rectangle from its top left pixel and size.
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+/// An image to [blit](Framebuffer::blit): `width` pixels to a row, top row first.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Image<'a> {
+    /// Pixels in each row.
+    pub width: usize,
+    /// The pixels.
+    pub pixels: &'a [Colour],
+}
+impl Image<'_> {
+    /// Rows in the image.
+    pub fn height(&self) -> usize {
+        self.pixels.len().checked_div(self.width).unwrap_or(0)
+    }
+}
+
+/// A linear framebuffer. Anything drawn outside it is clipped.
+#[derive(Debug)]
+pub struct Framebuffer {
+    base: VirtAddr,
+    info: FramebufferInfo,
+}
+impl Framebuffer {
+    /// Draw on the framebuffer at `base`, laid out as described by `info`.
+    ///
+    /// # Safety
+    ///
+    /// `base` must point to `info.size()` bytes of memory, aligned for `u32`, that nothing else
+    /// uses for as long as the framebuffer is drawn on.
+    pub unsafe fn new(base: VirtAddr, info: FramebufferInfo) -> Self {
+        Self { base, info }
+    }
+
+    /// The framebuffer's layout.
+    pub fn info(&self) -> FramebufferInfo {
+        self.info
+    }
+
+    /// Columns and rows of the part of `rect` in the framebuffer.
+    fn clip(&self, rect: Rect) -> (usize, usize, usize, usize) {
+        let x = rect.x.min(self.info.width);
+        let y = rect.y.min(self.info.height);
+        let right = rect.x.saturating_add(rect.width).min(self.info.width);
+        let bottom = rect.y.saturating_add(rect.height).min(self.info.height);
+        (x, y, right, bottom)
+    }
+
+    fn pixel_ptr(&self, x: usize, y: usize) -> *mut u32 {
+        // In bounds, as callers clip first
+        let offset = (y * self.info.stride + x) * 4;
+        (self.base + offset as u64).as_mut_ptr()
+    }
+
+    fn write(&mut self, x: usize, y: usize, pixel: u32) {
+        // UNSAFE: `x` and `y` are in the framebuffer, which `new`'s caller promised is ours.
+        unsafe { ptr::write_volatile(self.pixel_ptr(x, y), pixel) };
+    }
+
+    fn read(&self, x: usize, y: usize) -> u32 {
+        // UNSAFE: `x` and `y` are in the framebuffer, which `new`'s caller promised is ours.
+        unsafe { ptr::read_volatile(self.pixel_ptr(x, y)) }
+    }
+
+    /// Set the pixel at `x`, `y`.
+    pub fn set_pixel(&mut self, x: usize, y: usize, colour: Colour) {
+        if x < self.info.width && y < self.info.height {
+            self.write(x, y, self.info.format.pack(colour));
+        }
+    }
+
+    /// The colour of the pixel at `x`, `y`, or `None` if that's outside the framebuffer.
+    pub fn pixel(&self, x: usize, y: usize) -> Option<Colour> {
+        (x < self.info.width && y < self.info.height)
+            .then(|| self.info.format.unpack(self.read(x, y)))
+    }
+
+    /// Fill `rect` with `colour`.
+    pub fn fill_rect(&mut self, rect: Rect, colour: Colour) {
+        let pixel = self.info.format.pack(colour);
+        let (left, top, right, bottom) = self.clip(rect);
+        for y in top..bottom {
+            for x in left..right {
+                self.write(x, y, pixel);
+            }
+        }
+    }
+
+    /// Draw the outline of `rect`, one pixel wide, in `colour`.
+    pub fn draw_rect(&mut self, rect: Rect, colour: Colour) {
+        if rect.width == 0 || rect.height == 0 {
+            return;
+        }
+        let Rect {
+            x,
+            y,
+            width,
+            height,
+        } = rect;
+        self.fill_rect(Rect::new(x, y, width, 1), colour);
+        self.fill_rect(Rect::new(x, y + height - 1, width, 1), colour);
+        self.fill_rect(Rect::new(x, y, 1, height), colour);
+        self.fill_rect(Rect::new(x + width - 1, y, 1, height), colour);
+    }
+
+    /// Fill the whole framebuffer with `colour`.
+    pub fn clear(&mut self, colour: Colour) {
+        let (width, height) = (self.info.width, self.info.height);
+        self.fill_rect(Rect::new(0, 0, width, height), colour);
+    }
+
+    /// Draw a line from `from` to `to`, both included, in `colour`.
+    pub fn draw_line(&mut self, from: (usize, usize), to: (usize, usize), colour: Colour) {
+        // Bresenham's algorithm, stepping one pixel along both axes or just the longer one
+        let (mut x, mut y) = (from.0 as isize, from.1 as isize);
+        let (end_x, end_y) = (to.0 as isize, to.1 as isize);
+        let dx = (end_x - x).abs();
+        let dy = -(end_y - y).abs();
+        let step_x = if x < end_x { 1 } else { -1 };
+        let step_y = if y < end_y { 1 } else { -1 };
+        let mut error = dx + dy;
+        loop {
+            self.set_pixel(x as usize, y as usize, colour);
+            if x == end_x && y == end_y {
+                break;
+            }
+            let double = 2 * error;
+            if double >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if double <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    /// Copy `image` with its top left corner at `x`, `y`.
+    pub fn blit(&mut self, x: usize, y: usize, image: &Image) {
+        let (left, top, right, bottom) = self.clip(Rect::new(x, y, image.width, image.height()));
+        if left == right {
+            return;
+        }
+        for row in top..bottom {
+            let start = (row - y) * image.width + (left - x);
+            let pixels = &image.pixels[start..(start + right - left)];
+            for (col, &colour) in (left..right).zip(pixels) {
+                self.write(col, row, self.info.format.pack(colour));
+            }
+        }
+    }
+
+    /// Copy the pixels in `from` so their top left corner is at `x`, `y`. The two may overlap.
+    pub fn copy_rect(&mut self, from: Rect, x: usize, y: usize) {
+        let (left, top, right, bottom) = self.clip(from);
+        // Keep to what's in the framebuffer at both ends
+        let width = (right - left).min(self.info.width.saturating_sub(x));
+        let height = (bottom - top).min(self.info.height.saturating_sub(y));
+        let copy_row = |fb: &mut Self, row: usize| {
+            for i in 0..width {
+                let col = if x <= left { i } else { width - 1 - i };
+                let pixel = fb.read(left + col, top + row);
+                fb.write(x + col, y + row, pixel);
+            }
+        };
+        // Go in the direction that reads each pixel before it's overwritten
+        if y <= top {
+            (0..height).for_each(|row| copy_row(self, row));
+        } else {
+            (0..height).rev().for_each(|row| copy_row(self, row));
+        }
+    }
+
+    /// Draw `glyph` with its top left corner at `x`, `y`, each of its pixels `scale.0` pixels
+    /// wide and `scale.1` high, in `fg` on `bg`, or on what's already there if `bg` is `None`.
+    pub fn draw_glyph(
+        &mut self,
+        x: usize,
+        y: usize,
+        glyph: &Glyph,
+        (fg, bg): (Colour, Option<Colour>),
+        scale: (usize, usize),
+    ) {
+        for (row, &bits) in glyph.iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                let colour = match bits & (0x80 >> col) {
+                    0 => match bg {
+                        Some(bg) => bg,
+                        None => continue,
+                    },
+                    _ => fg,
+                };
+                let rect = Rect::new(x + col * scale.0, y + row * scale.1, scale.0, scale.1);
+                self.fill_rect(rect, colour);
+            }
+        }
+    }
+
+    /// Draw `s` in the built-in font, starting with its top left corner at `x`, `y`, in `fg` on
+    /// `bg`, or on what's already there if `bg` is `None`. Text doesn't wrap. Returns the column
+    /// just past the last character.
+    pub fn draw_text(
+        &mut self,
+        x: usize,
+        y: usize,
+        s: &str,
+        fg: Colour,
+        bg: Option<Colour>,
+    ) -> usize {
+        let mut x = x;
+        for c in s.chars() {
+            if x >= self.info.width || y >= self.info.height {
+                break;
+            }
+            self.draw_glyph(x, y, font::glyph(c), (fg, bg), (1, 1));
+            x += GLYPH_WIDTH;
+        }
+        x
+    }
+
+    /// Height of a line of [draw_text](Self::draw_text) text.
+    pub const fn line_height() -> usize {
+        GLYPH_HEIGHT
+    }
+}
+
+/// Register the display drivers.
+pub fn init() {
+    crate::pci::register_driver(&bochs::DRIVER);
+}
+
+/// Switch the display to a framebuffer `width` by `height` pixels, cleared to black. The VGA
+/// text screen isn't shown from then on.
+pub fn set_mode(width: usize, height: usize) -> Result<FramebufferInfo, FramebufferError> {
+    let framebuffer = bochs::set_mode(width, height)?;
+    let info = framebuffer.info();
+    interrupts::without_interrupts(|| *FRAMEBUFFER.lock() = Some(framebuffer));
+    Ok(info)
+}
+
+/// The layout of the framebuffer, if [set_mode] has set one up.
+pub fn info() -> Option<FramebufferInfo> {
+    with_framebuffer(|fb| fb.info())
+}
+
+/// Run `f` on the framebuffer, if [set_mode] has set one up.
+pub fn with_framebuffer<T>(f: impl FnOnce(&mut Framebuffer) -> T) -> Option<T> {
+    interrupts::without_interrupts(|| FRAMEBUFFER.lock().as_mut().map(f))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{vec, vec::Vec};
+
+    const RED: Colour = Colour::rgb(0xFF, 0, 0);
+
+    /// A framebuffer in memory, `width` by `height` with a few spare pixels on each row.
+    fn in_memory(width: usize, height: usize) -> (Vec<u32>, Framebuffer) {
+        let info = FramebufferInfo {
+            width,
+            height,
+            stride: width + 3,
+            format: PixelFormat::Xrgb8888,
+        };
+        let mut memory = vec![0; info.size() / 4];
+        // UNSAFE: The memory is the framebuffer's size and outlives it in the tests.
+        let fb = unsafe { Framebuffer::new(VirtAddr::from_ptr(memory.as_mut_ptr()), info) };
+        (memory, fb)
+    }
+
+    fn lit(fb: &Framebuffer) -> Vec<(usize, usize)> {
+        let info = fb.info();
+        (0..info.height)
+            .flat_map(|y| (0..info.width).map(move |x| (x, y)))
+            .filter(|&(x, y)| fb.pixel(x, y) != Some(Colour::BLACK))
+            .collect()
+    }
+
+    #[test_case]
+    fn pixels() {
+        let (memory, mut fb) = in_memory(4, 3);
+        fb.set_pixel(1, 2, Colour::rgb(0x12, 0x34, 0x56));
+        fb.set_pixel(4, 0, RED);
+        assert_eq!(fb.pixel(1, 2), Some(Colour::rgb(0x12, 0x34, 0x56)));
+        assert_eq!(memory[2 * 7 + 1], 0x0012_3456);
+        assert_eq!(fb.pixel(4, 0), None);
+        assert_eq!(lit(&fb), [(1, 2)]);
+        assert_eq!(
+            PixelFormat::Xbgr8888.pack(Colour::rgb(0x12, 0x34, 0x56)),
+            0x0056_3412
+        );
+    }
+
+    #[test_case]
+    fn rectangles() {
+        let (_memory, mut fb) = in_memory(5, 5);
+        fb.fill_rect(Rect::new(3, 3, 10, 10), RED);
+        assert_eq!(lit(&fb), [(3, 3), (4, 3), (3, 4), (4, 4)]);
+        fb.clear(Colour::BLACK);
+        fb.draw_rect(Rect::new(1, 1, 3, 3), RED);
+        assert_eq!(lit(&fb).len(), 8);
+        assert_eq!(fb.pixel(2, 2), Some(Colour::BLACK));
+    }
+
+    #[test_case]
+    fn lines() {
+        let (_memory, mut fb) = in_memory(8, 8);
+        fb.draw_line((0, 0), (3, 3), RED);
+        assert_eq!(lit(&fb), [(0, 0), (1, 1), (2, 2), (3, 3)]);
+        fb.clear(Colour::BLACK);
+        fb.draw_line((6, 1), (0, 1), RED);
+        assert_eq!(lit(&fb).len(), 7);
+        fb.clear(Colour::BLACK);
+        // Shallow, so one pixel in each column
+        fb.draw_line((0, 0), (7, 2), RED);
+        assert_eq!(lit(&fb).len(), 8);
+        assert_eq!(fb.pixel(7, 2), Some(RED));
+    }
+
+    #[test_case]
+    fn blits_and_copies() {
+        let (_memory, mut fb) = in_memory(4, 4);
+        let pixels = [RED, Colour::WHITE, Colour::WHITE, RED];
+        let image = Image {
+            width: 2,
+            pixels: &pixels,
+        };
+        fb.blit(3, 2, &image);
+        assert_eq!(lit(&fb), [(3, 2), (3, 3)]);
+        assert_eq!(fb.pixel(3, 3), Some(Colour::WHITE));
+
+        fb.clear(Colour::BLACK);
+        fb.blit(0, 0, &image);
+        // Overlapping, down and to the right
+        fb.copy_rect(Rect::new(0, 0, 2, 2), 1, 1);
+        assert_eq!(fb.pixel(1, 1), Some(RED));
+        assert_eq!(fb.pixel(2, 2), Some(RED));
+        assert_eq!(fb.pixel(2, 1), Some(Colour::WHITE));
+        // And back up
+        fb.copy_rect(Rect::new(1, 1, 2, 2), 0, 0);
+        assert_eq!(fb.pixel(0, 0), Some(RED));
+        assert_eq!(fb.pixel(1, 0), Some(Colour::WHITE));
+    }
+
+    #[test_case]
+    fn text() {
+        let (_memory, mut fb) = in_memory(20, 10);
+        assert_eq!(fb.draw_text(1, 1, "I!", RED, None), 17);
+        // The first row of 'I' is 0x7E
+        assert_eq!(fb.pixel(1, 1), Some(Colour::BLACK));
+        assert_eq!(fb.pixel(2, 1), Some(RED));
+        assert_eq!(fb.pixel(7, 1), Some(RED));
+        assert_eq!(fb.pixel(8, 1), Some(Colour::BLACK));
+        // Clipped at the right
+        assert_eq!(fb.draw_text(16, 0, "WW", RED, Some(Colour::WHITE)), 24);
+    }
+}
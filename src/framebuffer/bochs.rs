@@ -0,0 +1,137 @@
+//! The Bochs display, with its "dispi" VBE extensions.
+//!
+//! QEMU's standard VGA (and `bochs-display`), Bochs and VirtualBox put a linear framebuffer
+//! behind PCI BAR 0 and let its resolution and colour depth be set through a pair of I/O ports.
+//! Once the framebuffer is enabled the VGA text screen isn't shown any more, and there's no going
+//! back without setting up the VGA registers from scratch.
+
+use spin::Mutex;
+use x86_64::{
+    instructions::{interrupts, port::Port},
+    PhysAddr,
+};
+
+use super::{Framebuffer, FramebufferError, FramebufferInfo, PixelFormat};
+use crate::{
+    memory,
+    pci::{command, Bar, PciDevice, PciDriver, PciMatch, ProbeError},
+};
+
+/// PCI vendor ID of the Bochs display.
+pub const VENDOR_ID: u16 = 0x1234;
+/// PCI device ID of the Bochs display.
+pub const DEVICE_ID: u16 = 0x1111;
+
+const INDEX_PORT: u16 = 0x01CE;
+const DATA_PORT: u16 = 0x01CF;
+
+/// Oldest interface version with 32-bit colour.
+const MIN_ID: u16 = 0xB0C2;
+const MAX_ID: u16 = 0xB0CF;
+
+const ENABLED: u16 = 0x01;
+const LFB_ENABLED: u16 = 0x40;
+
+const BITS_PER_PIXEL: u16 = 32;
+
+/// Largest resolution the interface allows.
+const MAX_WIDTH: usize = 2560;
+const MAX_HEIGHT: usize = 1600;
+
+/// The dispi registers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u16)]
+enum Register {
+    Id = 0,
+    XRes = 1,
+    YRes = 2,
+    Bpp = 3,
+    Enable = 4,
+    VirtWidth = 6,
+    XOffset = 8,
+    YOffset = 9,
+}
+
+/// Driver for the Bochs display.
+pub static DRIVER: PciDriver = PciDriver {
+    name: "bochs-display",
+    matches: &[PciMatch::id(VENDOR_ID, DEVICE_ID)],
+    probe,
+};
+
+/// Where the framebuffer memory is, and how big, once the display's been found.
+static MEMORY: Mutex<Option<(PhysAddr, u64)>> = Mutex::new(None);
+
+fn read(register: Register) -> u16 {
+    let mut index = Port::new(INDEX_PORT);
+    let mut data = Port::new(DATA_PORT);
+    // UNSAFE: The dispi ports only select and access display registers.
+    unsafe {
+        index.write(register as u16);
+        data.read()
+    }
+}
+
+fn write(register: Register, value: u16) {
+    let mut index = Port::new(INDEX_PORT);
+    let mut data = Port::new(DATA_PORT);
+    // UNSAFE: The dispi ports only select and access display registers.
+    unsafe {
+        index.write(register as u16);
+        data.write(value);
+    }
+}
+
+fn probe(device: &PciDevice) -> Result<(), ProbeError> {
+    if !(MIN_ID..=MAX_ID).contains(&read(Register::Id)) {
+        return Err(ProbeError::Unsupported);
+    }
+    let Some(Bar::Memory { address, size, .. }) = device.bars[0] else {
+        return Err(ProbeError::Failed("no framebuffer memory"));
+    };
+    device.enable(command::MEMORY_SPACE);
+    interrupts::without_interrupts(|| *MEMORY.lock() = Some((PhysAddr::new(address), size)));
+    Ok(())
+}
+
+/// Whether a Bochs display was found.
+pub fn is_present() -> bool {
+    interrupts::without_interrupts(|| MEMORY.lock().is_some())
+}
+
+/// Show a framebuffer `width` by `height` pixels, cleared to black, and return it.
+pub(super) fn set_mode(width: usize, height: usize) -> Result<Framebuffer, FramebufferError> {
+    let (phys, size) =
+        interrupts::without_interrupts(|| *MEMORY.lock()).ok_or(FramebufferError::NotPresent)?;
+    let info = FramebufferInfo {
+        width,
+        height,
+        stride: width,
+        format: PixelFormat::Xrgb8888,
+    };
+    let supported = (1..=MAX_WIDTH).contains(&width)
+        && width % 8 == 0
+        && (1..=MAX_HEIGHT).contains(&height)
+        && info.size() as u64 <= size;
+    if !supported {
+        return Err(FramebufferError::UnsupportedMode(width, height));
+    }
+    let virt = memory::map_mmio(phys, info.size() as u64).map_err(FramebufferError::Memory)?;
+
+    // Checked against the limits above
+    write(Register::Enable, 0);
+    write(Register::XRes, width as u16);
+    write(Register::YRes, height as u16);
+    write(Register::Bpp, BITS_PER_PIXEL);
+    write(Register::VirtWidth, width as u16);
+    write(Register::XOffset, 0);
+    write(Register::YOffset, 0);
+    write(Register::Enable, ENABLED | LFB_ENABLED);
+    if usize::from(read(Register::XRes)) != width || usize::from(read(Register::YRes)) != height {
+        return Err(FramebufferError::UnsupportedMode(width, height));
+    }
+
+    // UNSAFE: The framebuffer memory was just mapped, is at least `info.size()` bytes and page
+    // aligned, and only the one framebuffer is handed out for it at a time.
+    Ok(unsafe { Framebuffer::new(virt, info) })
+}
@@ -0,0 +1,176 @@
+//! The console, drawn on the framebuffer.
+//!
+//! Once [enable]d, the screen of the [virtual terminals](crate::vga_text::vt) is kept in memory
+//! instead of the VGA text buffer, and every change to it is drawn here, a character cell
+//! [CELL_WIDTH] by [CELL_HEIGHT] pixels at a time, in the 16 VGA colours of the
+//! [palette](crate::vga_text::palette). Everything
+//! [vga_text](crate::vga_text) does, [print](crate::print) included, works the same way as in
+//! text mode: escape sequences, scrollback, the cursor and switching terminals.
+//!
+//! The `framebuffer` feature has [init](crate::init) enable it at boot. Characters missing from
+//! the built-in [font](super::font) are shown as a small square.
+
+use alloc::{vec, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use super::{bochs, font, set_mode, with_framebuffer, Colour, Framebuffer, FramebufferError, Rect};
+use crate::vga_text::{cp437, palette, screen, vt, VgaChar};
+
+/// Width of a character cell in pixels.
+pub const CELL_WIDTH: usize = font::GLYPH_WIDTH;
+/// Height of a character cell in pixels, with each row of the font drawn twice.
+pub const CELL_HEIGHT: usize = 2 * font::GLYPH_HEIGHT;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static CONSOLE: Mutex<Option<Console>> = Mutex::new(None);
+
+/// The cursor as drawn on the console.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct Cursor {
+    /// Row of the cell it's on.
+    pub(crate) row: usize,
+    /// Column of the cell it's on.
+    pub(crate) col: usize,
+    /// First and last scan lines of the cell it covers.
+    pub(crate) scan_lines: (u8, u8),
+}
+
+/// What's been drawn, so only changes are drawn again.
+#[derive(Debug)]
+struct Console {
+    rows: usize,
+    cols: usize,
+    // Each cell as last drawn, or `None` if it needs drawing
+    drawn: Vec<Option<VgaChar>>,
+    cursor: Option<Cursor>,
+    // Colours to draw in, in attribute order
+    palette: [Colour; 16],
+}
+impl Console {
+    fn new(rows: usize, cols: usize) -> Self {
+        Self {
+            rows,
+            cols,
+            drawn: vec![None; rows * cols],
+            cursor: None,
+            palette: palette::DEFAULT_PALETTE,
+        }
+    }
+
+    fn draw_cell(&mut self, fb: &mut Framebuffer, row: usize, col: usize, cell: VgaChar) {
+        let index = row * self.cols + col;
+        if self.drawn[index] == Some(cell) {
+            return;
+        }
+        let glyph = font::glyph(cp437::decode(cell.text_byte));
+        let fg = self.palette[usize::from(u8::from(cell.attr.fg()))];
+        let bg = self.palette[usize::from(u8::from(cell.attr.bg()))];
+        fb.draw_glyph(
+            col * CELL_WIDTH,
+            row * CELL_HEIGHT,
+            glyph,
+            (fg, Some(bg)),
+            (1, CELL_HEIGHT / font::GLYPH_HEIGHT),
+        );
+        self.drawn[index] = Some(cell);
+        // Drawn over
+        if self
+            .cursor
+            .is_some_and(|cursor| (cursor.row, cursor.col) == (row, col))
+        {
+            self.cursor = None;
+        }
+    }
+
+    fn draw_cursor(&mut self, fb: &mut Framebuffer, cursor: Option<Cursor>) {
+        if self.cursor == cursor {
+            return;
+        }
+        if let Some(old) = self.cursor.take() {
+            // Draw the cell again without it
+            let index = old.row * self.cols + old.col;
+            if let Some(cell) = self.drawn[index].take() {
+                self.draw_cell(fb, old.row, old.col, cell);
+            }
+        }
+        let Some(cursor) = cursor.filter(|c| c.row < self.rows && c.col < self.cols) else {
+            return;
+        };
+        let colour = match self.drawn[cursor.row * self.cols + cursor.col] {
+            Some(cell) => self.palette[usize::from(u8::from(cell.attr.fg()))],
+            None => Colour::WHITE,
+        };
+        let (start, end) = cursor.scan_lines;
+        let (start, end) = (usize::from(start), usize::from(end));
+        if start <= end {
+            let rect = Rect::new(
+                cursor.col * CELL_WIDTH,
+                cursor.row * CELL_HEIGHT + start,
+                CELL_WIDTH,
+                end - start + 1,
+            );
+            fb.fill_rect(rect, colour);
+        }
+        self.cursor = Some(cursor);
+    }
+}
+
+/// Switch the display to a framebuffer and show the console on it.
+pub fn enable() -> Result<(), FramebufferError> {
+    if is_enabled() {
+        return Ok(());
+    }
+    if !bochs::is_present() {
+        return Err(FramebufferError::NotPresent);
+    }
+    let (rows, cols) = screen::size();
+    // The text buffer is cleared along with the rest of the display's memory
+    vt::move_screen_to_memory();
+    if let Err(e) = set_mode(cols * CELL_WIDTH, rows * CELL_HEIGHT) {
+        vt::move_screen_to_vga();
+        return Err(e);
+    }
+    interrupts::without_interrupts(|| *CONSOLE.lock() = Some(Console::new(rows, cols)));
+    ENABLED.store(true, Ordering::Relaxed);
+    vt::redraw();
+    Ok(())
+}
+
+/// Whether the console is shown on the framebuffer.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Have every cell drawn again next time, as the colours it's shown in have changed.
+pub(crate) fn forget_drawn() {
+    interrupts::without_interrupts(|| {
+        if let Some(console) = CONSOLE.lock().as_mut() {
+            console.drawn.fill(None);
+            // Drawn over along with its cell
+            console.cursor = None;
+        }
+    });
+}
+
+/// Draw the cells that have changed, given by `cell_at(row, col)`, and the cursor.
+pub(crate) fn draw(cell_at: impl Fn(usize, usize) -> VgaChar, cursor: Option<Cursor>) {
+    interrupts::without_interrupts(|| {
+        let mut console = CONSOLE.lock();
+        let Some(console) = console.as_mut() else {
+            return;
+        };
+        console.palette = palette::palette();
+        with_framebuffer(|fb| {
+            for row in 0..console.rows {
+                for col in 0..console.cols {
+                    console.draw_cell(fb, row, col, cell_at(row, col));
+                }
+            }
+            console.draw_cursor(fb, cursor);
+        });
+    });
+}
@@ -0,0 +1,169 @@
+//! The built-in bitmap font.
+//!
+//! Glyphs are 8 by 8 pixels, drawn for this kernel. The font covers printable ASCII, the block
+//! elements and shades, and a small square shown for every other character.
+
+/// Width of a glyph in pixels.
+pub const GLYPH_WIDTH: usize = 8;
+/// Height of a glyph in pixels.
+pub const GLYPH_HEIGHT: usize = 8;
+
+/// The rows of a glyph, top first, with the leftmost pixel in the high bit.
+pub type Glyph = [u8; GLYPH_HEIGHT];
+
+/// Glyphs of the printable ASCII characters, from space to tilde.
+const ASCII: [Glyph; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x18, 0x18, 0x18, 0x18, 0x18, 0x00, 0x18, 0x00], // '!'
+    [0x6C, 0x6C, 0x48, 0x00, 0x00, 0x00, 0x00, 0x00], // '"'
+    [0x6C, 0x6C, 0xFE, 0x6C, 0xFE, 0x6C, 0x6C, 0x00], // '#'
+    [0x10, 0x7C, 0xD0, 0x78, 0x16, 0x7C, 0x10, 0x00], // '$'
+    [0xC6, 0xCC, 0x18, 0x30, 0x60, 0xCC, 0x8C, 0x00], // '%'
+    [0x38, 0x6C, 0x38, 0x76, 0xDC, 0xCC, 0x76, 0x00], // '&'
+    [0x18, 0x18, 0x30, 0x00, 0x00, 0x00, 0x00, 0x00], // '\''
+    [0x0C, 0x18, 0x30, 0x30, 0x30, 0x18, 0x0C, 0x00], // '('
+    [0x30, 0x18, 0x0C, 0x0C, 0x0C, 0x18, 0x30, 0x00], // ')'
+    [0x00, 0x6C, 0x38, 0xFE, 0x38, 0x6C, 0x00, 0x00], // '*'
+    [0x00, 0x18, 0x18, 0x7E, 0x18, 0x18, 0x00, 0x00], // '+'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x30], // ','
+    [0x00, 0x00, 0x00, 0x7E, 0x00, 0x00, 0x00, 0x00], // '-'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x00], // '.'
+    [0x06, 0x0C, 0x18, 0x30, 0x60, 0xC0, 0x80, 0x00], // '/'
+    [0x7C, 0xC6, 0xCE, 0xDE, 0xF6, 0xE6, 0x7C, 0x00], // '0'
+    [0x18, 0x38, 0x18, 0x18, 0x18, 0x18, 0x7E, 0x00], // '1'
+    [0x7C, 0xC6, 0x06, 0x1C, 0x70, 0xC0, 0xFE, 0x00], // '2'
+    [0x7C, 0xC6, 0x06, 0x3C, 0x06, 0xC6, 0x7C, 0x00], // '3'
+    [0x0E, 0x1E, 0x36, 0x66, 0xFE, 0x06, 0x06, 0x00], // '4'
+    [0xFE, 0xC0, 0xFC, 0x06, 0x06, 0xC6, 0x7C, 0x00], // '5'
+    [0x3C, 0x60, 0xC0, 0xFC, 0xC6, 0xC6, 0x7C, 0x00], // '6'
+    [0xFE, 0x06, 0x0C, 0x18, 0x30, 0x30, 0x30, 0x00], // '7'
+    [0x7C, 0xC6, 0xC6, 0x7C, 0xC6, 0xC6, 0x7C, 0x00], // '8'
+    [0x7C, 0xC6, 0xC6, 0x7E, 0x06, 0x0C, 0x78, 0x00], // '9'
+    [0x00, 0x18, 0x18, 0x00, 0x00, 0x18, 0x18, 0x00], // ':'
+    [0x00, 0x18, 0x18, 0x00, 0x00, 0x18, 0x18, 0x30], // ';'
+    [0x0C, 0x18, 0x30, 0x60, 0x30, 0x18, 0x0C, 0x00], // '<'
+    [0x00, 0x00, 0x7E, 0x00, 0x7E, 0x00, 0x00, 0x00], // '='
+    [0x60, 0x30, 0x18, 0x0C, 0x18, 0x30, 0x60, 0x00], // '>'
+    [0x7C, 0xC6, 0x06, 0x1C, 0x18, 0x00, 0x18, 0x00], // '?'
+    [0x7C, 0xC6, 0xDE, 0xDE, 0xDE, 0xC0, 0x7C, 0x00], // '@'
+    [0x38, 0x6C, 0xC6, 0xC6, 0xFE, 0xC6, 0xC6, 0x00], // 'A'
+    [0xFC, 0xC6, 0xC6, 0xFC, 0xC6, 0xC6, 0xFC, 0x00], // 'B'
+    [0x3C, 0x66, 0xC0, 0xC0, 0xC0, 0x66, 0x3C, 0x00], // 'C'
+    [0xF8, 0xCC, 0xC6, 0xC6, 0xC6, 0xCC, 0xF8, 0x00], // 'D'
+    [0xFE, 0xC0, 0xC0, 0xFC, 0xC0, 0xC0, 0xFE, 0x00], // 'E'
+    [0xFE, 0xC0, 0xC0, 0xFC, 0xC0, 0xC0, 0xC0, 0x00], // 'F'
+    [0x3C, 0x66, 0xC0, 0xDE, 0xC6, 0x66, 0x3E, 0x00], // 'G'
+    [0xC6, 0xC6, 0xC6, 0xFE, 0xC6, 0xC6, 0xC6, 0x00], // 'H'
+    [0x7E, 0x18, 0x18, 0x18, 0x18, 0x18, 0x7E, 0x00], // 'I'
+    [0x1E, 0x06, 0x06, 0x06, 0xC6, 0xC6, 0x7C, 0x00], // 'J'
+    [0xC6, 0xCC, 0xD8, 0xF0, 0xD8, 0xCC, 0xC6, 0x00], // 'K'
+    [0xC0, 0xC0, 0xC0, 0xC0, 0xC0, 0xC0, 0xFE, 0x00], // 'L'
+    [0xC6, 0xEE, 0xFE, 0xD6, 0xC6, 0xC6, 0xC6, 0x00], // 'M'
+    [0xC6, 0xE6, 0xF6, 0xDE, 0xCE, 0xC6, 0xC6, 0x00], // 'N'
+    [0x7C, 0xC6, 0xC6, 0xC6, 0xC6, 0xC6, 0x7C, 0x00], // 'O'
+    [0xFC, 0xC6, 0xC6, 0xFC, 0xC0, 0xC0, 0xC0, 0x00], // 'P'
+    [0x7C, 0xC6, 0xC6, 0xC6, 0xD6, 0xCC, 0x76, 0x00], // 'Q'
+    [0xFC, 0xC6, 0xC6, 0xFC, 0xD8, 0xCC, 0xC6, 0x00], // 'R'
+    [0x7C, 0xC6, 0xC0, 0x7C, 0x06, 0xC6, 0x7C, 0x00], // 'S'
+    [0x7E, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x00], // 'T'
+    [0xC6, 0xC6, 0xC6, 0xC6, 0xC6, 0xC6, 0x7C, 0x00], // 'U'
+    [0xC6, 0xC6, 0xC6, 0xC6, 0x6C, 0x38, 0x10, 0x00], // 'V'
+    [0xC6, 0xC6, 0xC6, 0xD6, 0xFE, 0xEE, 0xC6, 0x00], // 'W'
+    [0xC6, 0xC6, 0x6C, 0x38, 0x6C, 0xC6, 0xC6, 0x00], // 'X'
+    [0x66, 0x66, 0x66, 0x3C, 0x18, 0x18, 0x18, 0x00], // 'Y'
+    [0xFE, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFE, 0x00], // 'Z'
+    [0x3C, 0x30, 0x30, 0x30, 0x30, 0x30, 0x3C, 0x00], // '['
+    [0xC0, 0x60, 0x30, 0x18, 0x0C, 0x06, 0x02, 0x00], // '\\'
+    [0x3C, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x3C, 0x00], // ']'
+    [0x10, 0x38, 0x6C, 0xC6, 0x00, 0x00, 0x00, 0x00], // '^'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF], // '_'
+    [0x30, 0x18, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00], // '`'
+    [0x00, 0x00, 0x7C, 0x06, 0x7E, 0xC6, 0x7E, 0x00], // 'a'
+    [0xC0, 0xC0, 0xFC, 0xC6, 0xC6, 0xC6, 0xFC, 0x00], // 'b'
+    [0x00, 0x00, 0x7C, 0xC6, 0xC0, 0xC6, 0x7C, 0x00], // 'c'
+    [0x06, 0x06, 0x7E, 0xC6, 0xC6, 0xC6, 0x7E, 0x00], // 'd'
+    [0x00, 0x00, 0x7C, 0xC6, 0xFE, 0xC0, 0x7C, 0x00], // 'e'
+    [0x1C, 0x36, 0x30, 0x7C, 0x30, 0x30, 0x30, 0x00], // 'f'
+    [0x00, 0x7E, 0xC6, 0xC6, 0x7E, 0x06, 0x7C, 0x00], // 'g'
+    [0xC0, 0xC0, 0xFC, 0xC6, 0xC6, 0xC6, 0xC6, 0x00], // 'h'
+    [0x18, 0x00, 0x38, 0x18, 0x18, 0x18, 0x3C, 0x00], // 'i'
+    [0x06, 0x00, 0x0E, 0x06, 0x06, 0xC6, 0x7C, 0x00], // 'j'
+    [0xC0, 0xC0, 0xCC, 0xD8, 0xF0, 0xD8, 0xCC, 0x00], // 'k'
+    [0x38, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, 0x00], // 'l'
+    [0x00, 0x00, 0xD8, 0xFE, 0xD6, 0xD6, 0xC6, 0x00], // 'm'
+    [0x00, 0x00, 0xFC, 0xC6, 0xC6, 0xC6, 0xC6, 0x00], // 'n'
+    [0x00, 0x00, 0x7C, 0xC6, 0xC6, 0xC6, 0x7C, 0x00], // 'o'
+    [0x00, 0xFC, 0xC6, 0xC6, 0xFC, 0xC0, 0xC0, 0x00], // 'p'
+    [0x00, 0x7E, 0xC6, 0xC6, 0x7E, 0x06, 0x06, 0x00], // 'q'
+    [0x00, 0x00, 0xDC, 0xE6, 0xC0, 0xC0, 0xC0, 0x00], // 'r'
+    [0x00, 0x00, 0x7E, 0xC0, 0x7C, 0x06, 0xFC, 0x00], // 's'
+    [0x30, 0x30, 0x7C, 0x30, 0x30, 0x36, 0x1C, 0x00], // 't'
+    [0x00, 0x00, 0xC6, 0xC6, 0xC6, 0xC6, 0x7E, 0x00], // 'u'
+    [0x00, 0x00, 0xC6, 0xC6, 0xC6, 0x6C, 0x38, 0x00], // 'v'
+    [0x00, 0x00, 0xC6, 0xD6, 0xD6, 0xFE, 0x6C, 0x00], // 'w'
+    [0x00, 0x00, 0xC6, 0x6C, 0x38, 0x6C, 0xC6, 0x00], // 'x'
+    [0x00, 0xC6, 0xC6, 0xC6, 0x7E, 0x06, 0x7C, 0x00], // 'y'
+    [0x00, 0x00, 0xFE, 0x0C, 0x38, 0x60, 0xFE, 0x00], // 'z'
+    [0x0E, 0x18, 0x18, 0x70, 0x18, 0x18, 0x0E, 0x00], // '{'
+    [0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x00], // '|'
+    [0x70, 0x18, 0x18, 0x0E, 0x18, 0x18, 0x70, 0x00], // '}'
+    [0x76, 0xDC, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '~'
+];
+
+/// Glyphs of characters beyond ASCII.
+const EXTRA: [(char, Glyph); 9] = [
+    ('█', [0xFF; GLYPH_HEIGHT]),
+    ('▀', [0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00]),
+    ('▄', [0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF]),
+    ('▌', [0xF0; GLYPH_HEIGHT]),
+    ('▐', [0x0F; GLYPH_HEIGHT]),
+    ('░', [0x22, 0x88, 0x22, 0x88, 0x22, 0x88, 0x22, 0x88]),
+    ('▒', [0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA]),
+    ('▓', [0xDD, 0x77, 0xDD, 0x77, 0xDD, 0x77, 0xDD, 0x77]),
+    ('■', REPLACEMENT),
+];
+
+/// The glyph shown for characters the font doesn't have: a small square.
+pub const REPLACEMENT: Glyph = [0x00, 0x00, 0x3C, 0x3C, 0x3C, 0x3C, 0x00, 0x00];
+
+/// Blank, for spaces that aren't ASCII.
+const BLANK: Glyph = [0; GLYPH_HEIGHT];
+
+/// The glyph for `c`, or [REPLACEMENT] if the font doesn't have one.
+pub fn glyph(c: char) -> &'static Glyph {
+    get(c).unwrap_or(&REPLACEMENT)
+}
+
+/// The glyph for `c`, if the font has one.
+pub fn get(c: char) -> Option<&'static Glyph> {
+    match c {
+        ' '..='~' => Some(&ASCII[c as usize - 0x20]),
+        '\u{A0}' => Some(&BLANK),
+        _ => EXTRA
+            .iter()
+            .find(|&&(extra, _)| extra == c)
+            .map(|(_, glyph)| glyph),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn glyphs() {
+        assert_eq!(glyph(' '), &[0; GLYPH_HEIGHT]);
+        assert_eq!(glyph('~'), ASCII.last().unwrap());
+        assert_eq!(glyph('█'), &[0xFF; GLYPH_HEIGHT]);
+        assert_eq!(glyph('日'), &REPLACEMENT);
+        assert_eq!(glyph('\n'), &REPLACEMENT);
+        assert_eq!(get('日'), None);
+        assert_eq!(get('\u{A0}'), Some(&[0; GLYPH_HEIGHT]));
+        // Every printable character but space draws something, leaving a gap before the next
+        // unless it's meant to join up
+        for c in '!'..='~' {
+            assert!(glyph(c).iter().any(|&row| row != 0));
+            assert!(glyph(c).iter().all(|&row| row & 0x01 == 0) || c == '_');
+        }
+    }
+}
@@ -0,0 +1,7 @@
+//! Concrete filesystems that plug into the [VFS](crate::vfs).
+
+pub mod devfs;
+pub mod ext2;
+pub mod fat;
+pub mod procfs;
+pub mod ramfs;
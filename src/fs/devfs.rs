@@ -0,0 +1,478 @@
+//! A filesystem of device files, normally mounted at `/dev`.
+//!
+//! Drivers call [register] (or [register_disk] for disks) when they find a device, and the device
+//! shows up in every [DevFs] under the name it was registered with. Character devices implement
+//! [CharDevice]; block devices are any [BlockDevice], read and written at byte offsets.
+//!
+//! [init] registers the devices that are always there: `console`, `vga`, `null`, `zero` and
+//! `random`, `tty1` to `tty6` for the virtual terminals, and `ttyS0` to `ttyS3` for the serial
+//! ports that were found.
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    any::Any,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use spin::Mutex;
+
+use crate::{
+    block::{cache, partition, read_bytes, write_bytes, BlockDevice},
+    serial,
+    vfs::{path, DirEntry, FileSystem, Inode, InodeKind, Metadata, Permissions, Result, VfsError},
+    vga_text::{self, vt::Vt},
+};
+
+const ROOT_INO: u64 = 1;
+
+/// `rw-rw-rw-`
+const CHAR_PERMISSIONS: Permissions = Permissions(0o666);
+/// `rw-rw----`
+const BLOCK_PERMISSIONS: Permissions = Permissions(0o660);
+
+/// A device read and written as a stream of bytes.
+pub trait CharDevice: Send + Sync {
+    /// Read up to `buf.len()` bytes into `buf`. Returns the number of bytes read, which is 0 if
+    /// there's nothing more to read.
+    fn read(&self, _buf: &mut [u8]) -> Result<usize> {
+        Err(VfsError::Unsupported)
+    }
+
+    /// Write the bytes of `buf`. Returns the number of bytes written.
+    fn write(&self, buf: &[u8]) -> Result<usize>;
+}
+
+/// A device that can be registered with devfs.
+#[derive(Clone)]
+pub enum Device {
+    /// A character device.
+    Char(Arc<dyn CharDevice>),
+    /// A block device.
+    Block(Arc<dyn BlockDevice>),
+}
+impl Device {
+    fn kind(&self) -> InodeKind {
+        match self {
+            Self::Char(_) => InodeKind::CharDevice,
+            Self::Block(_) => InodeKind::BlockDevice,
+        }
+    }
+}
+
+struct Registered {
+    ino: u64,
+    device: Device,
+}
+
+static DEVICES: Mutex<BTreeMap<String, Registered>> = Mutex::new(BTreeMap::new());
+static NEXT_INO: AtomicU64 = AtomicU64::new(ROOT_INO + 1);
+
+/// Make `device` available as `/dev/<name>`.
+pub fn register(name: &str, device: Device) -> Result<()> {
+    path::validate_name(name)?;
+    let mut devices = DEVICES.lock();
+    if devices.contains_key(name) {
+        return Err(VfsError::AlreadyExists);
+    }
+    let ino = NEXT_INO.fetch_add(1, Ordering::Relaxed);
+    devices.insert(name.to_string(), Registered { ino, device });
+    Ok(())
+}
+
+/// Register a disk under its own name, along with every partition on it, all going through the
+/// block [cache](cache::CACHE). A disk whose partition table can't be read is registered without
+/// partitions.
+pub fn register_disk(disk: Arc<dyn BlockDevice>) -> Result<()> {
+    // The partitions are on the cached disk too, so every user of the disk sees the same blocks
+    let disk: Arc<dyn BlockDevice> = cache::cached(disk);
+    register(disk.name(), Device::Block(Arc::clone(&disk)))?;
+    let partitions = partition::partitions(&disk).unwrap_or_else(|e| {
+        log::warn!("{}: can't read partition table: {}", disk.name(), e);
+        Vec::new()
+    });
+    for partition in partitions {
+        let name = partition.name().to_string();
+        log::debug!("{}: {} blocks", name, partition.block_count());
+        register(&name, Device::Block(partition))?;
+    }
+    Ok(())
+}
+
+/// Remove the device registered as `name`. Files already open on it keep working.
+pub fn unregister(name: &str) -> Result<()> {
+    DEVICES
+        .lock()
+        .remove(name)
+        .map(|_| ())
+        .ok_or(VfsError::NotFound)
+}
+
+/// The device registered as `name`.
+pub fn device(name: &str) -> Option<Device> {
+    DEVICES.lock().get(name).map(|r| r.device.clone())
+}
+
+/// Register the built-in devices and the serial ports.
+pub fn init() {
+    let builtins: [(&str, Arc<dyn CharDevice>); 5] = [
+        ("console", Arc::new(Console)),
+        ("vga", Arc::new(Vga)),
+        ("null", Arc::new(Null)),
+        ("zero", Arc::new(Zero)),
+        ("random", Arc::new(Random::new())),
+    ];
+    let terminals = Vt::ALL.into_iter().map(|vt| {
+        (
+            vt.device_name(),
+            Arc::new(Terminal(vt)) as Arc<dyn CharDevice>,
+        )
+    });
+    let ports = serial::ports().into_iter().map(|com| {
+        (
+            com.device_name(),
+            Arc::new(Serial(com)) as Arc<dyn CharDevice>,
+        )
+    });
+    for (name, device) in builtins.into_iter().chain(terminals).chain(ports) {
+        // Already there if `init` runs twice
+        let _ = register(name, Device::Char(device));
+    }
+}
+
+/// The device filesystem. Every instance shows the same devices.
+pub struct DevFs {
+    root: Arc<DevInode>,
+}
+impl DevFs {
+    /// Create a new instance of the filesystem.
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            root: Arc::new(DevInode::Root),
+        })
+    }
+}
+impl FileSystem for DevFs {
+    fn name(&self) -> &'static str {
+        "devfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::clone(&self.root) as Arc<dyn Inode>
+    }
+}
+
+enum DevInode {
+    Root,
+    Device { ino: u64, device: Device },
+}
+impl Inode for DevInode {
+    fn metadata(&self) -> Result<Metadata> {
+        let (ino, kind, size, permissions) = match self {
+            Self::Root => {
+                let count = DEVICES.lock().len();
+                (
+                    ROOT_INO,
+                    InodeKind::Directory,
+                    count as u64,
+                    Permissions::DEFAULT_DIR,
+                )
+            }
+            Self::Device {
+                ino,
+                device: Device::Char(_),
+            } => (*ino, InodeKind::CharDevice, 0, CHAR_PERMISSIONS),
+            Self::Device {
+                ino,
+                device: Device::Block(device),
+            } => (
+                *ino,
+                InodeKind::BlockDevice,
+                disk_size(device.as_ref()),
+                BLOCK_PERMISSIONS,
+            ),
+        };
+        Ok(Metadata {
+            ino,
+            kind,
+            size,
+            permissions,
+            nlink: if kind == InodeKind::Directory { 2 } else { 1 },
+            uid: 0,
+            gid: 0,
+        })
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            Self::Root => Err(VfsError::IsADirectory),
+            Self::Device {
+                device: Device::Char(device),
+                ..
+            } => device.read(buf),
+            Self::Device {
+                device: Device::Block(device),
+                ..
+            } => {
+                let size = disk_size(device.as_ref());
+                if offset >= size {
+                    return Ok(0);
+                }
+                let n = buf.len().min((size - offset) as usize);
+                read_bytes(device.as_ref(), offset, &mut buf[..n])?;
+                Ok(n)
+            }
+        }
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+        match self {
+            Self::Root => Err(VfsError::IsADirectory),
+            Self::Device {
+                device: Device::Char(device),
+                ..
+            } => device.write(buf),
+            Self::Device {
+                device: Device::Block(device),
+                ..
+            } => {
+                let size = disk_size(device.as_ref());
+                if offset >= size && !buf.is_empty() {
+                    return Err(VfsError::NoSpace);
+                }
+                let n = buf.len().min(size.saturating_sub(offset) as usize);
+                write_bytes(device.as_ref(), offset, &buf[..n])?;
+                Ok(n)
+            }
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let Self::Root = self else {
+            return Err(VfsError::NotADirectory);
+        };
+        DEVICES
+            .lock()
+            .get(name)
+            .map(|r| {
+                Arc::new(Self::Device {
+                    ino: r.ino,
+                    device: r.device.clone(),
+                }) as Arc<dyn Inode>
+            })
+            .ok_or(VfsError::NotFound)
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        let Self::Root = self else {
+            return Err(VfsError::NotADirectory);
+        };
+        Ok(DEVICES
+            .lock()
+            .iter()
+            .map(|(name, r)| DirEntry {
+                name: name.clone(),
+                ino: r.ino,
+                kind: r.device.kind(),
+            })
+            .collect())
+    }
+
+    fn create(
+        &self,
+        _name: &str,
+        _kind: InodeKind,
+        _permissions: Permissions,
+    ) -> Result<Arc<dyn Inode>> {
+        match self {
+            Self::Root => Err(VfsError::PermissionDenied),
+            Self::Device { .. } => Err(VfsError::NotADirectory),
+        }
+    }
+
+    fn unlink(&self, _name: &str) -> Result<()> {
+        match self {
+            Self::Root => Err(VfsError::PermissionDenied),
+            Self::Device { .. } => Err(VfsError::NotADirectory),
+        }
+    }
+}
+
+fn disk_size(device: &dyn BlockDevice) -> u64 {
+    device.block_count() * device.block_size() as u64
+}
+
+/// `/dev/console`: writes go to the screen and the serial port.
+struct Console;
+impl CharDevice for Console {
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        vga_text::write_bytes(buf);
+        serial::write_bytes(buf);
+        Ok(buf.len())
+    }
+}
+
+/// `/dev/ttyS0` to `/dev/ttyS3`: the serial ports. Reads take whatever has been received so far,
+/// without waiting.
+struct Serial(serial::Com);
+impl CharDevice for Serial {
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        let mut read = 0;
+        while let Some(slot) = buf.get_mut(read) {
+            match self.0.read_byte() {
+                Some(byte) => *slot = byte,
+                None => break,
+            }
+            read += 1;
+        }
+        Ok(read)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        self.0.write_bytes(buf);
+        Ok(buf.len())
+    }
+}
+
+/// `/dev/vga`: the VGA text screen.
+struct Vga;
+impl CharDevice for Vga {
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        vga_text::write_bytes(buf);
+        Ok(buf.len())
+    }
+}
+
+/// `/dev/tty1` to `/dev/tty6`: the virtual terminals.
+struct Terminal(Vt);
+impl CharDevice for Terminal {
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        self.0.write_bytes(buf);
+        Ok(buf.len())
+    }
+}
+
+/// `/dev/null`: discards writes and is always at its end.
+struct Null;
+impl CharDevice for Null {
+    fn read(&self, _buf: &mut [u8]) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        Ok(buf.len())
+    }
+}
+
+/// `/dev/zero`: discards writes and reads as endless zeroes.
+struct Zero;
+impl CharDevice for Zero {
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        buf.fill(0);
+        Ok(buf.len())
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        Ok(buf.len())
+    }
+}
+
+/// `/dev/random`: an xorshift64* generator seeded from the time stamp counter. Fine for picking
+/// test data, but not for anything that has to be unpredictable. Writes are mixed into the state.
+struct Random {
+    state: Mutex<u64>,
+}
+impl Random {
+    fn new() -> Self {
+        // UNSAFE: `rdtsc` is available on every x86_64 CPU.
+        let seed = unsafe { core::arch::x86_64::_rdtsc() };
+        // The state must never be zero
+        Self {
+            state: Mutex::new(seed | 1),
+        }
+    }
+}
+impl CharDevice for Random {
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        let mut state = self.state.lock();
+        for chunk in buf.chunks_mut(8) {
+            *state ^= *state >> 12;
+            *state ^= *state << 25;
+            *state ^= *state >> 27;
+            let value = state.wrapping_mul(0x2545_F491_4F6C_DD1D);
+            chunk.copy_from_slice(&value.to_le_bytes()[..chunk.len()]);
+        }
+        Ok(buf.len())
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        let mut state = self.state.lock();
+        for chunk in buf.chunks(8) {
+            let mut bytes = [0; 8];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            *state = (*state ^ u64::from_le_bytes(bytes)).rotate_left(17) | 1;
+        }
+        Ok(buf.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn null_and_zero() {
+        let mut buf = [0xAA; 16];
+        assert_eq!(Null.read(&mut buf).unwrap(), 0);
+        assert_eq!(Null.write(b"gone").unwrap(), 4);
+        assert_eq!(Zero.read(&mut buf).unwrap(), 16);
+        assert_eq!(buf, [0; 16]);
+    }
+
+    #[test_case]
+    fn random_bytes_vary() {
+        let random = Random::new();
+        let mut a = [0; 13];
+        let mut b = [0; 13];
+        random.read(&mut a).unwrap();
+        random.read(&mut b).unwrap();
+        assert_ne!(a, b);
+        assert_ne!(a, [0; 13]);
+    }
+
+    #[test_case]
+    fn registration() {
+        struct Sink;
+        impl CharDevice for Sink {
+            fn write(&self, buf: &[u8]) -> Result<usize> {
+                Ok(buf.len())
+            }
+        }
+
+        let root = DevFs::new().root();
+        register("devfs-test", Device::Char(Arc::new(Sink))).unwrap();
+        assert_eq!(
+            register("devfs-test", Device::Char(Arc::new(Sink))).err(),
+            Some(VfsError::AlreadyExists)
+        );
+        let inode = root.lookup("devfs-test").unwrap();
+        assert_eq!(inode.metadata().unwrap().kind, InodeKind::CharDevice);
+        assert_eq!(inode.write_at(0, b"abc").unwrap(), 3);
+        assert!(root
+            .readdir()
+            .unwrap()
+            .iter()
+            .any(|e| e.name == "devfs-test"));
+
+        unregister("devfs-test").unwrap();
+        assert_eq!(root.lookup("devfs-test").err(), Some(VfsError::NotFound));
+    }
+}
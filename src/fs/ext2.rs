@@ -0,0 +1,1080 @@
+//! The second extended filesystem, on a [BlockDevice].
+//!
+//! Files, directories, symbolic links and hard links are all supported, with the usual Unix
+//! permission bits and owners. Filesystems using features the driver doesn't understand are
+//! either refused or, if the features only matter for writing, mounted read-only.
+//!
+//! There's no clock yet, so timestamps of new inodes are left at zero. An inode is freed as soon
+//! as its last link is removed, so open handles to it fail with [VfsError::NotFound] afterwards.
+
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use core::any::Any;
+
+use spin::Mutex;
+
+use crate::{
+    block::{read_bytes, write_bytes, BlockDevice},
+    vfs::{path, DirEntry, FileSystem, Inode, InodeKind, Metadata, Permissions, Result, VfsError},
+};
+
+mod dir;
+mod inode;
+mod superblock;
+
+use dir::{file_type, Record};
+use inode::{mode, DiskInode, BASE_INODE_SIZE, FAST_SYMLINK_MAX};
+use superblock::{features, u32_at, GroupDesc, Superblock, GROUP_COUNTS, GROUP_DESC_SIZE};
+
+const ROOT_INO: u32 = 2;
+
+// Same as Linux
+const MAX_LINKS: u16 = 32000;
+// Files without the `large_file` feature must stay below 2 GiB
+const SMALL_FILE_MAX: u64 = (1 << 31) - 1;
+// `blocks` in an inode counts 512-byte sectors regardless of the block size
+const SECTOR_SIZE: u32 = 512;
+
+/// A mounted ext2 filesystem.
+pub struct Ext2Fs {
+    shared: Arc<Shared>,
+    root: Arc<Ext2Inode>,
+}
+impl Ext2Fs {
+    /// Read the ext2 filesystem on `device`. It's mounted read-only if the device is read-only
+    /// or the filesystem uses features the driver can't write.
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Arc<Self>> {
+        let superblock = Superblock::read(device.as_ref())?;
+        let groups = GroupDesc::read_all(device.as_ref(), &superblock)?;
+        let volume = Volume {
+            read_only: device.is_read_only() || superblock.needs_read_only(),
+            device,
+            superblock,
+            groups,
+        };
+        let root = volume.inode(ROOT_INO, None)?;
+        if !root.is_dir() {
+            return Err(VfsError::Corrupt);
+        }
+
+        let shared = Arc::new(Shared {
+            volume: Mutex::new(volume),
+        });
+        let root = Arc::new(Ext2Inode {
+            fs: Arc::clone(&shared),
+            ino: ROOT_INO,
+            generation: root.generation,
+        });
+        Ok(Arc::new(Self { shared, root }))
+    }
+
+    /// The volume label from the superblock.
+    pub fn label(&self) -> String {
+        self.shared.volume.lock().superblock.volume_name.clone()
+    }
+
+    /// Check if the filesystem was mounted read-only.
+    pub fn is_read_only(&self) -> bool {
+        self.shared.volume.lock().read_only
+    }
+}
+impl FileSystem for Ext2Fs {
+    fn name(&self) -> &'static str {
+        "ext2"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::clone(&self.root) as Arc<dyn Inode>
+    }
+
+    fn sync(&self) -> Result<()> {
+        // Metadata is written as soon as it changes, so the device just needs flushing
+        Ok(self.shared.volume.lock().device.flush()?)
+    }
+}
+
+/// State shared by every inode of one [Ext2Fs].
+struct Shared {
+    volume: Mutex<Volume>,
+}
+
+struct Volume {
+    device: Arc<dyn BlockDevice>,
+    superblock: Superblock,
+    groups: Vec<GroupDesc>,
+    read_only: bool,
+}
+impl Volume {
+    fn block_size(&self) -> usize {
+        self.superblock.block_size as usize
+    }
+
+    /// Number of block pointers in an indirect block.
+    fn pointers_per_block(&self) -> u64 {
+        u64::from(self.superblock.block_size / 4)
+    }
+
+    fn sectors_per_block(&self) -> u32 {
+        self.superblock.block_size / SECTOR_SIZE
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(VfsError::ReadOnly);
+        }
+        Ok(())
+    }
+
+    fn has_file_type(&self) -> bool {
+        (self.superblock.feature_incompat & features::INCOMPAT_FILETYPE) != 0
+    }
+
+    /// Byte offset of `offset` within `block`, checking that the block exists.
+    fn block_offset(&self, block: u32, offset: usize) -> Result<u64> {
+        if !(self.superblock.first_data_block..self.superblock.blocks_count).contains(&block) {
+            return Err(VfsError::Corrupt);
+        }
+        Ok(u64::from(block) * u64::from(self.superblock.block_size) + offset as u64)
+    }
+
+    fn read_block(&self, block: u32) -> Result<Vec<u8>> {
+        let mut data = vec![0; self.block_size()];
+        read_bytes(
+            self.device.as_ref(),
+            self.block_offset(block, 0)?,
+            &mut data,
+        )?;
+        Ok(data)
+    }
+
+    fn write_block(&self, block: u32, data: &[u8]) -> Result<()> {
+        Ok(write_bytes(
+            self.device.as_ref(),
+            self.block_offset(block, 0)?,
+            data,
+        )?)
+    }
+
+    fn read_pointer(&self, block: u32, index: usize) -> Result<u32> {
+        let mut bytes = [0; 4];
+        read_bytes(
+            self.device.as_ref(),
+            self.block_offset(block, index * 4)?,
+            &mut bytes,
+        )?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn write_pointer(&self, block: u32, index: usize, pointer: u32) -> Result<()> {
+        Ok(write_bytes(
+            self.device.as_ref(),
+            self.block_offset(block, index * 4)?,
+            &pointer.to_le_bytes(),
+        )?)
+    }
+
+    fn group_of_inode(&self, ino: u32) -> usize {
+        ((ino - 1) / self.superblock.inodes_per_group) as usize
+    }
+
+    /// Byte offset of the on-disk inode `ino`.
+    fn inode_offset(&self, ino: u32) -> Result<u64> {
+        if !(1..=self.superblock.inodes_count).contains(&ino) {
+            return Err(VfsError::Corrupt);
+        }
+        let group = &self.groups[self.group_of_inode(ino)];
+        let index = (ino - 1) % self.superblock.inodes_per_group;
+        Ok(self.block_offset(group.inode_table, 0)?
+            + u64::from(index) * u64::from(self.superblock.inode_size))
+    }
+
+    /// Read inode `ino`. If `generation` is given, the inode must still be in use by the file
+    /// with that generation.
+    fn inode(&self, ino: u32, generation: Option<u32>) -> Result<DiskInode> {
+        let mut raw = [0; BASE_INODE_SIZE];
+        read_bytes(self.device.as_ref(), self.inode_offset(ino)?, &mut raw)?;
+        let inode = DiskInode::parse(&raw);
+        if generation.is_some_and(|g| g != inode.generation || inode.links_count == 0) {
+            return Err(VfsError::NotFound);
+        }
+        Ok(inode)
+    }
+
+    fn write_inode(&self, ino: u32, inode: &DiskInode) -> Result<()> {
+        let offset = self.inode_offset(ino)?;
+        let mut raw = [0; BASE_INODE_SIZE];
+        read_bytes(self.device.as_ref(), offset, &mut raw)?;
+        inode.encode(&mut raw);
+        Ok(write_bytes(self.device.as_ref(), offset, &raw)?)
+    }
+
+    /// Add to the free and used counts of a block group and the superblock.
+    fn adjust_counts(&mut self, group: usize, blocks: i16, inodes: i16, dirs: i16) -> Result<()> {
+        let desc = &mut self.groups[group];
+        desc.free_blocks_count = desc.free_blocks_count.saturating_add_signed(blocks);
+        desc.free_inodes_count = desc.free_inodes_count.saturating_add_signed(inodes);
+        desc.used_dirs_count = desc.used_dirs_count.saturating_add_signed(dirs);
+        let counts = desc.encode_counts();
+        let offset =
+            self.superblock.group_table_offset() + (group * GROUP_DESC_SIZE + GROUP_COUNTS) as u64;
+        write_bytes(self.device.as_ref(), offset, &counts)?;
+
+        let superblock = &mut self.superblock;
+        superblock.free_blocks_count = superblock
+            .free_blocks_count
+            .saturating_add_signed(i32::from(blocks));
+        superblock.free_inodes_count = superblock
+            .free_inodes_count
+            .saturating_add_signed(i32::from(inodes));
+        superblock.write_counts(self.device.as_ref())
+    }
+
+    /// Find a clear bit below `bits` in a bitmap block, skipping the first `skip`, and set it.
+    fn take_bit(&self, bitmap: u32, skip: usize, bits: usize) -> Result<Option<usize>> {
+        let mut data = self.read_block(bitmap)?;
+        let Some(bit) = (skip..bits).find(|&b| (data[b / 8] & (1 << (b % 8))) == 0) else {
+            return Ok(None);
+        };
+        data[bit / 8] |= 1 << (bit % 8);
+        write_bytes(
+            self.device.as_ref(),
+            self.block_offset(bitmap, bit / 8)?,
+            &data[(bit / 8)..=(bit / 8)],
+        )?;
+        Ok(Some(bit))
+    }
+
+    /// Clear a set bit in a bitmap block.
+    fn clear_bit(&self, bitmap: u32, bit: usize) -> Result<()> {
+        let offset = self.block_offset(bitmap, bit / 8)?;
+        let mut byte = [0];
+        read_bytes(self.device.as_ref(), offset, &mut byte)?;
+        if (byte[0] & (1 << (bit % 8))) == 0 {
+            return Err(VfsError::Corrupt);
+        }
+        byte[0] &= !(1 << (bit % 8));
+        Ok(write_bytes(self.device.as_ref(), offset, &byte)?)
+    }
+
+    /// Allocate a zeroed block, preferably in block group `goal`.
+    fn allocate_block(&mut self, goal: usize) -> Result<u32> {
+        let superblock = &self.superblock;
+        let group_count = self.groups.len();
+        for group in (0..group_count).map(|i| (goal + i) % group_count) {
+            if self.groups[group].free_blocks_count == 0 {
+                continue;
+            }
+            // The last group may be cut short
+            let first = superblock.first_data_block + group as u32 * superblock.blocks_per_group;
+            let bits = (superblock.blocks_count - first).min(superblock.blocks_per_group);
+            let Some(bit) = self.take_bit(self.groups[group].block_bitmap, 0, bits as usize)?
+            else {
+                continue;
+            };
+
+            self.adjust_counts(group, -1, 0, 0)?;
+            let block = first + bit as u32;
+            self.write_block(block, &vec![0; self.block_size()])?;
+            return Ok(block);
+        }
+        Err(VfsError::NoSpace)
+    }
+
+    fn free_block(&mut self, block: u32) -> Result<()> {
+        let index = block
+            .checked_sub(self.superblock.first_data_block)
+            .ok_or(VfsError::Corrupt)?;
+        let group = (index / self.superblock.blocks_per_group) as usize;
+        let bit = (index % self.superblock.blocks_per_group) as usize;
+        let bitmap = self
+            .groups
+            .get(group)
+            .ok_or(VfsError::Corrupt)?
+            .block_bitmap;
+        self.clear_bit(bitmap, bit)?;
+        self.adjust_counts(group, 1, 0, 0)
+    }
+
+    /// Allocate an inode number. Directories go in the emptiest block group to spread them out;
+    /// everything else goes near its parent.
+    fn allocate_inode(&mut self, parent: u32, is_dir: bool) -> Result<u32> {
+        let group_count = self.groups.len();
+        let goal = if is_dir {
+            (0..group_count)
+                .max_by_key(|&g| self.groups[g].free_inodes_count)
+                .unwrap_or(0)
+        } else {
+            self.group_of_inode(parent)
+        };
+
+        let per_group = self.superblock.inodes_per_group;
+        for group in (0..group_count).map(|i| (goal + i) % group_count) {
+            if self.groups[group].free_inodes_count == 0 {
+                continue;
+            }
+            // Inodes below `first_ino` are reserved
+            let first = group as u32 * per_group + 1;
+            let skip = self.superblock.first_ino.saturating_sub(first) as usize;
+            let bitmap = self.groups[group].inode_bitmap;
+            let Some(bit) = self.take_bit(bitmap, skip, per_group as usize)? else {
+                continue;
+            };
+
+            self.adjust_counts(group, 0, -1, i16::from(is_dir))?;
+            return Ok(first + bit as u32);
+        }
+        Err(VfsError::NoSpace)
+    }
+
+    fn free_inode(&mut self, ino: u32, is_dir: bool) -> Result<()> {
+        let group = self.group_of_inode(ino);
+        let bit = ((ino - 1) % self.superblock.inodes_per_group) as usize;
+        self.clear_bit(self.groups[group].inode_bitmap, bit)?;
+        self.adjust_counts(group, 0, 1, -i16::from(is_dir))
+    }
+
+    /// Where the pointer to logical block `index` of a file lives: the index of the pointer in
+    /// the inode, then the index within each level of indirect blocks.
+    fn block_path(&self, index: u64) -> Result<(usize, Vec<usize>)> {
+        let per_block = self.pointers_per_block();
+        let mut index = index;
+        if index < inode::DIRECT_BLOCKS as u64 {
+            return Ok((index as usize, Vec::new()));
+        }
+        index -= inode::DIRECT_BLOCKS as u64;
+
+        let mut span = per_block;
+        for level in 1..=3 {
+            if index < span {
+                let path = (0..level)
+                    .rev()
+                    .map(|l| ((index / per_block.pow(l)) % per_block) as usize)
+                    .collect();
+                return Ok((inode::INDIRECT_BLOCK + level as usize - 1, path));
+            }
+            index -= span;
+            span *= per_block;
+        }
+        Err(VfsError::NoSpace)
+    }
+
+    /// The block holding logical block `index` of inode `ino`, allocating it and any indirect
+    /// blocks on the way if `allocate` is set. Returns [None] for holes when not allocating.
+    fn map_block(
+        &mut self,
+        ino: u32,
+        inode: &mut DiskInode,
+        index: u64,
+        allocate: bool,
+    ) -> Result<Option<u32>> {
+        let goal = self.group_of_inode(ino);
+        let (root, path) = self.block_path(index)?;
+
+        let mut block = inode.block[root];
+        if block == 0 {
+            if !allocate {
+                return Ok(None);
+            }
+            block = self.allocate_block(goal)?;
+            inode.block[root] = block;
+            inode.blocks += self.sectors_per_block();
+        }
+        for index in path {
+            let mut next = self.read_pointer(block, index)?;
+            if next == 0 {
+                if !allocate {
+                    return Ok(None);
+                }
+                next = self.allocate_block(goal)?;
+                self.write_pointer(block, index, next)?;
+                inode.blocks += self.sectors_per_block();
+            }
+            block = next;
+        }
+        Ok(Some(block))
+    }
+
+    fn read_pointers(&self, block: u32) -> Result<Vec<u32>> {
+        let data = self.read_block(block)?;
+        Ok((0..data.len())
+            .step_by(4)
+            .map(|i| u32_at(&data, i))
+            .collect())
+    }
+
+    /// Free `block` and, for indirect blocks (`level` > 0), everything it points to.
+    fn free_tree(&mut self, inode: &mut DiskInode, block: u32, level: u32) -> Result<()> {
+        if level > 0 {
+            for pointer in self.read_pointers(block)? {
+                if pointer != 0 {
+                    self.free_tree(inode, pointer, level - 1)?;
+                }
+            }
+        }
+        self.free_block(block)?;
+        inode.blocks = inode.blocks.saturating_sub(self.sectors_per_block());
+        Ok(())
+    }
+
+    /// Free every block below the indirect block `block` that maps logical blocks from `keep`
+    /// onwards. `base` is the first logical block mapped by `block`.
+    fn trim_tree(
+        &mut self,
+        inode: &mut DiskInode,
+        block: u32,
+        level: u32,
+        base: u64,
+        keep: u64,
+    ) -> Result<()> {
+        let span = self.pointers_per_block().pow(level - 1);
+        let mut pointers = self.read_pointers(block)?;
+        let mut changed = false;
+        for (i, pointer) in pointers.iter_mut().enumerate() {
+            let child_base = base + i as u64 * span;
+            if *pointer == 0 || child_base + span <= keep {
+                continue;
+            }
+            if keep <= child_base {
+                self.free_tree(inode, *pointer, level - 1)?;
+                *pointer = 0;
+                changed = true;
+            } else {
+                self.trim_tree(inode, *pointer, level - 1, child_base, keep)?;
+            }
+        }
+
+        if changed {
+            let data: Vec<u8> = pointers.iter().flat_map(|p| p.to_le_bytes()).collect();
+            self.write_block(block, &data)?;
+        }
+        Ok(())
+    }
+
+    /// Free every block of a file from logical block `keep` onwards.
+    fn truncate_blocks(&mut self, inode: &mut DiskInode, keep: u64) -> Result<()> {
+        for index in (keep as usize)..inode::DIRECT_BLOCKS {
+            if inode.block[index] != 0 {
+                self.free_tree(inode, inode.block[index], 0)?;
+                inode.block[index] = 0;
+            }
+        }
+
+        let per_block = self.pointers_per_block();
+        let mut base = inode::DIRECT_BLOCKS as u64;
+        let mut span = per_block;
+        for level in 1..=3 {
+            let root = inode::INDIRECT_BLOCK + level as usize - 1;
+            let block = inode.block[root];
+            if block != 0 {
+                if keep <= base {
+                    self.free_tree(inode, block, level)?;
+                    inode.block[root] = 0;
+                } else if keep < base + span {
+                    self.trim_tree(inode, block, level, base, keep)?;
+                }
+            }
+            base += span;
+            span *= per_block;
+        }
+        Ok(())
+    }
+
+    /// The largest size a file can grow to.
+    fn max_file_size(&self) -> u64 {
+        if (self.superblock.feature_ro_compat & features::RO_COMPAT_LARGE_FILE) != 0 {
+            u64::MAX
+        } else {
+            SMALL_FILE_MAX
+        }
+    }
+
+    /// Read from a file's blocks, treating holes as zeroes.
+    fn read_data(
+        &mut self,
+        ino: u32,
+        inode: &mut DiskInode,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<usize> {
+        if offset >= inode.size {
+            return Ok(0);
+        }
+        let len = buf.len().min((inode.size - offset) as usize);
+        let block_size = self.block_size() as u64;
+        let mut done = 0;
+        while done < len {
+            let position = offset + done as u64;
+            let within = (position % block_size) as usize;
+            let piece = (len - done).min(block_size as usize - within);
+            let out = &mut buf[done..(done + piece)];
+            match self.map_block(ino, inode, position / block_size, false)? {
+                Some(block) => {
+                    read_bytes(self.device.as_ref(), self.block_offset(block, within)?, out)?
+                }
+                None => out.fill(0),
+            }
+            done += piece;
+        }
+        Ok(len)
+    }
+
+    /// Write to a file's blocks, allocating them as needed and growing the file.
+    fn write_data(
+        &mut self,
+        ino: u32,
+        inode: &mut DiskInode,
+        offset: u64,
+        buf: &[u8],
+    ) -> Result<usize> {
+        if offset
+            .checked_add(buf.len() as u64)
+            .is_none_or(|end| end > self.max_file_size())
+        {
+            return Err(VfsError::NoSpace);
+        }
+        let block_size = self.block_size() as u64;
+        let mut done = 0;
+        let result = loop {
+            if done == buf.len() {
+                break Ok(());
+            }
+            let position = offset + done as u64;
+            let within = (position % block_size) as usize;
+            let piece = (buf.len() - done).min(block_size as usize - within);
+            let block = match self.map_block(ino, inode, position / block_size, true) {
+                Ok(block) => block.expect("allocated"),
+                Err(e) => break Err(e),
+            };
+            let location = self.block_offset(block, within)?;
+            write_bytes(self.device.as_ref(), location, &buf[done..(done + piece)])?;
+            done += piece;
+        };
+
+        // Whatever made it to disk counts, even if we ran out of space part way
+        inode.size = inode.size.max(offset + done as u64);
+        self.write_inode(ino, inode)?;
+        match result {
+            Err(e) if done == 0 => Err(e),
+            _ => Ok(done),
+        }
+    }
+
+    /// Set the size of a file, freeing blocks past the end and zeroing the rest of the new
+    /// last block.
+    fn set_size(&mut self, ino: u32, inode: &mut DiskInode, len: u64) -> Result<()> {
+        if len > self.max_file_size() {
+            return Err(VfsError::NoSpace);
+        }
+        if len < inode.size {
+            let block_size = self.block_size() as u64;
+            self.truncate_blocks(inode, len.div_ceil(block_size))?;
+            let within = (len % block_size) as usize;
+            if within != 0 {
+                if let Some(block) = self.map_block(ino, inode, len / block_size, false)? {
+                    let zeroes = vec![0; block_size as usize - within];
+                    write_bytes(
+                        self.device.as_ref(),
+                        self.block_offset(block, within)?,
+                        &zeroes,
+                    )?;
+                }
+            }
+        }
+        inode.size = len;
+        self.write_inode(ino, inode)
+    }
+
+    /// Every record of a directory, along with the logical block it's in.
+    fn records(&mut self, ino: u32, inode: &mut DiskInode) -> Result<Vec<(u64, Record)>> {
+        let has_file_type = self.has_file_type();
+        let mut records = Vec::new();
+        for index in 0..(inode.size / self.block_size() as u64) {
+            let block = self
+                .map_block(ino, inode, index, false)?
+                .ok_or(VfsError::Corrupt)?;
+            let data = self.read_block(block)?;
+            records.extend(
+                dir::records(&data, has_file_type)?
+                    .into_iter()
+                    .map(|r| (index, r)),
+            );
+        }
+        Ok(records)
+    }
+
+    /// Find the record called `name` in a directory.
+    fn find(
+        &mut self,
+        ino: u32,
+        inode: &mut DiskInode,
+        name: &str,
+    ) -> Result<Option<(u64, Record)>> {
+        Ok(self
+            .records(ino, inode)?
+            .into_iter()
+            .find(|(_, r)| r.ino != 0 && r.name == name.as_bytes()))
+    }
+
+    /// Check that a directory has nothing but `.` and `..` in it.
+    fn is_empty_dir(&mut self, ino: u32, inode: &mut DiskInode) -> Result<bool> {
+        Ok(self
+            .records(ino, inode)?
+            .iter()
+            .all(|(_, r)| r.ino == 0 || r.name == b"." || r.name == b".."))
+    }
+
+    fn file_type_byte(&self, kind: InodeKind) -> u8 {
+        if self.has_file_type() {
+            dir::type_byte(kind)
+        } else {
+            file_type::UNKNOWN
+        }
+    }
+
+    /// Add a record called `name` for `child` to directory `ino`, growing it if needed.
+    fn add_record(&mut self, ino: u32, name: &str, child: u32, kind: InodeKind) -> Result<()> {
+        let has_file_type = self.has_file_type();
+        let type_byte = self.file_type_byte(kind);
+        let mut inode = self.inode(ino, None)?;
+        // Any hashed index would be out of date after this
+        inode.flags &= !inode::INDEX_FLAG;
+
+        let block_size = self.block_size() as u64;
+        let count = inode.size / block_size;
+        for index in 0..count {
+            let block = self
+                .map_block(ino, &mut inode, index, false)?
+                .ok_or(VfsError::Corrupt)?;
+            let mut data = self.read_block(block)?;
+            if dir::insert(&mut data, has_file_type, name.as_bytes(), child, type_byte)? {
+                self.write_block(block, &data)?;
+                return self.write_inode(ino, &inode);
+            }
+        }
+
+        let block = self
+            .map_block(ino, &mut inode, count, true)?
+            .expect("allocated");
+        let mut data = dir::empty_block(block_size as usize);
+        dir::insert(&mut data, has_file_type, name.as_bytes(), child, type_byte)?;
+        self.write_block(block, &data)?;
+        inode.size += block_size;
+        self.write_inode(ino, &inode)
+    }
+
+    /// Remove the record at `offset` in logical block `index` of directory `ino`.
+    fn remove_record(&mut self, ino: u32, index: u64, offset: usize) -> Result<()> {
+        let mut inode = self.inode(ino, None)?;
+        inode.flags &= !inode::INDEX_FLAG;
+        let block = self
+            .map_block(ino, &mut inode, index, false)?
+            .ok_or(VfsError::Corrupt)?;
+        let mut data = self.read_block(block)?;
+        dir::remove(&mut data, self.has_file_type(), offset)?;
+        self.write_block(block, &data)?;
+        self.write_inode(ino, &inode)
+    }
+
+    /// Point the `..` record of directory `ino` at `parent`.
+    fn set_parent(&mut self, ino: u32, parent: u32) -> Result<()> {
+        let mut inode = self.inode(ino, None)?;
+        let (index, record) = self.find(ino, &mut inode, "..")?.ok_or(VfsError::Corrupt)?;
+        let block = self
+            .map_block(ino, &mut inode, index, false)?
+            .ok_or(VfsError::Corrupt)?;
+        let mut data = self.read_block(block)?;
+        dir::write_record(
+            &mut data,
+            &Record {
+                ino: parent,
+                ..record
+            },
+        );
+        self.write_block(block, &data)
+    }
+
+    fn adjust_links(&mut self, ino: u32, delta: i16) -> Result<()> {
+        let mut inode = self.inode(ino, None)?;
+        inode.links_count = inode.links_count.saturating_add_signed(delta);
+        self.write_inode(ino, &inode)
+    }
+
+    /// Create a new inode of the given kind and link it into directory `parent` as `name`.
+    fn create(
+        &mut self,
+        parent: u32,
+        name: &str,
+        kind: InodeKind,
+        permissions: Permissions,
+        target: Option<&str>,
+    ) -> Result<(u32, DiskInode)> {
+        let mut parent_inode = self.inode(parent, None)?;
+        if self.find(parent, &mut parent_inode, name)?.is_some() {
+            return Err(VfsError::AlreadyExists);
+        }
+
+        let is_dir = kind == InodeKind::Directory;
+        let ino = self.allocate_inode(parent, is_dir)?;
+        let result = self.init_inode(ino, parent, kind, permissions, target);
+        let result = result.and_then(|inode| {
+            self.add_record(parent, name, ino, kind)?;
+            Ok(inode)
+        });
+        match result {
+            Ok(inode) => {
+                if is_dir {
+                    self.adjust_links(parent, 1)?;
+                }
+                Ok((ino, inode))
+            }
+            Err(e) => {
+                // Put everything back as it was
+                let mut inode = self.inode(ino, None)?;
+                if !inode.is_fast_symlink(self.superblock.block_size) {
+                    self.truncate_blocks(&mut inode, 0)?;
+                }
+                self.release_inode(ino, inode)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Fill in a freshly allocated inode.
+    fn init_inode(
+        &mut self,
+        ino: u32,
+        parent: u32,
+        kind: InodeKind,
+        permissions: Permissions,
+        target: Option<&str>,
+    ) -> Result<DiskInode> {
+        let offset = self.inode_offset(ino)?;
+        let mut raw = vec![0; usize::from(self.superblock.inode_size)];
+        read_bytes(self.device.as_ref(), offset, &mut raw)?;
+        let old = DiskInode::parse(&raw);
+
+        // Clear everything left over from the inode's previous life
+        raw.fill(0);
+        let mode_bits = inode::type_bits(kind) | (permissions.0 & mode::PERMISSIONS_MASK);
+        let mut inode = DiskInode::new(mode_bits, old.generation.wrapping_add(1));
+        inode.links_count = 1;
+        inode.encode(&mut raw);
+        write_bytes(self.device.as_ref(), offset, &raw)?;
+
+        match kind {
+            InodeKind::Directory => {
+                let block = self
+                    .map_block(ino, &mut inode, 0, true)?
+                    .expect("allocated");
+                let has_file_type = self.has_file_type();
+                let type_byte = self.file_type_byte(InodeKind::Directory);
+                let mut data = dir::empty_block(self.block_size());
+                dir::insert(&mut data, has_file_type, b".", ino, type_byte)?;
+                dir::insert(&mut data, has_file_type, b"..", parent, type_byte)?;
+                self.write_block(block, &data)?;
+                inode.size = self.block_size() as u64;
+                inode.links_count = 2;
+            }
+            InodeKind::Symlink => {
+                let target = target.unwrap_or_default().as_bytes();
+                if target.len() < FAST_SYMLINK_MAX {
+                    let mut bytes = [0; FAST_SYMLINK_MAX];
+                    bytes[..target.len()].copy_from_slice(target);
+                    inode.set_block_bytes(&bytes);
+                    inode.size = target.len() as u64;
+                } else {
+                    self.write_data(ino, &mut inode, 0, target)?;
+                }
+            }
+            _ => {}
+        }
+        self.write_inode(ino, &inode)?;
+        Ok(inode)
+    }
+
+    /// Mark an inode with no links left as deleted and free its number.
+    fn release_inode(&mut self, ino: u32, mut inode: DiskInode) -> Result<()> {
+        let is_dir = inode.is_dir();
+        inode.links_count = 0;
+        // There's no clock yet, so the last write time has to do. Deletion times below the
+        // inode count would be mistaken for links in the orphan list.
+        inode.dtime = self.superblock.write_time.max(self.superblock.inodes_count);
+        inode.size = 0;
+        self.write_inode(ino, &inode)?;
+        self.free_inode(ino, is_dir)
+    }
+
+    /// Remove the record called `name` from directory `parent`, deleting the inode it refers to
+    /// if that was its last link.
+    fn remove(&mut self, parent: u32, name: &str) -> Result<()> {
+        let mut parent_inode = self.inode(parent, None)?;
+        let (index, record) = self
+            .find(parent, &mut parent_inode, name)?
+            .ok_or(VfsError::NotFound)?;
+        let mut inode = self.inode(record.ino, None)?;
+        if inode.is_dir() && !self.is_empty_dir(record.ino, &mut inode)? {
+            return Err(VfsError::DirectoryNotEmpty);
+        }
+
+        self.remove_record(parent, index, record.offset)?;
+        if inode.is_dir() {
+            self.adjust_links(parent, -1)?;
+            inode.links_count = 0;
+        } else {
+            inode.links_count = inode.links_count.saturating_sub(1);
+        }
+
+        if inode.links_count > 0 {
+            return self.write_inode(record.ino, &inode);
+        }
+        if !inode.is_fast_symlink(self.superblock.block_size) {
+            self.truncate_blocks(&mut inode, 0)?;
+        }
+        self.release_inode(record.ino, inode)
+    }
+}
+
+/// A file, directory or symbolic link on an [Ext2Fs].
+struct Ext2Inode {
+    fs: Arc<Shared>,
+    ino: u32,
+    // Tells a stale handle apart from a new file that reused the inode number
+    generation: u32,
+}
+impl Ext2Inode {
+    /// Downcast `inode` to an [Ext2Inode] belonging to the same filesystem as `self`.
+    fn same_fs<'a>(&self, inode: &'a Arc<dyn Inode>) -> Result<&'a Ext2Inode> {
+        inode
+            .as_any()
+            .downcast_ref::<Ext2Inode>()
+            .filter(|other| Arc::ptr_eq(&self.fs, &other.fs))
+            .ok_or(VfsError::CrossDevice)
+    }
+
+    fn child(&self, ino: u32, inode: &DiskInode) -> Arc<dyn Inode> {
+        Arc::new(Self {
+            fs: Arc::clone(&self.fs),
+            ino,
+            generation: inode.generation,
+        })
+    }
+
+    /// Read this inode, which must be a regular file.
+    fn file(&self, volume: &Volume) -> Result<DiskInode> {
+        let inode = volume.inode(self.ino, Some(self.generation))?;
+        match inode.kind() {
+            Some(InodeKind::File) => Ok(inode),
+            Some(InodeKind::Directory) => Err(VfsError::IsADirectory),
+            _ => Err(VfsError::InvalidArgument),
+        }
+    }
+
+    /// Read this inode, which must be a directory.
+    fn dir(&self, volume: &Volume) -> Result<DiskInode> {
+        let inode = volume.inode(self.ino, Some(self.generation))?;
+        if !inode.is_dir() {
+            return Err(VfsError::NotADirectory);
+        }
+        Ok(inode)
+    }
+}
+impl Inode for Ext2Inode {
+    fn metadata(&self) -> Result<Metadata> {
+        let volume = self.fs.volume.lock();
+        let inode = volume.inode(self.ino, Some(self.generation))?;
+        Ok(Metadata {
+            ino: u64::from(self.ino),
+            kind: inode.kind().ok_or(VfsError::Unsupported)?,
+            size: inode.size,
+            permissions: inode.permissions(),
+            nlink: u32::from(inode.links_count),
+            uid: inode.uid,
+            gid: inode.gid,
+        })
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let mut volume = self.fs.volume.lock();
+        let mut inode = self.file(&volume)?;
+        volume.read_data(self.ino, &mut inode, offset, buf)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+        let mut volume = self.fs.volume.lock();
+        volume.check_writable()?;
+        let mut inode = self.file(&volume)?;
+        volume.write_data(self.ino, &mut inode, offset, buf)
+    }
+
+    fn truncate(&self, len: u64) -> Result<()> {
+        let mut volume = self.fs.volume.lock();
+        volume.check_writable()?;
+        let mut inode = self.file(&volume)?;
+        volume.set_size(self.ino, &mut inode, len)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let mut volume = self.fs.volume.lock();
+        let mut inode = self.dir(&volume)?;
+        let (_, record) = volume
+            .find(self.ino, &mut inode, name)?
+            .ok_or(VfsError::NotFound)?;
+        let child = volume.inode(record.ino, None)?;
+        if child.kind().is_none() {
+            return Err(VfsError::Unsupported);
+        }
+        Ok(self.child(record.ino, &child))
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        let mut volume = self.fs.volume.lock();
+        let mut inode = self.dir(&volume)?;
+        let mut entries = Vec::new();
+        for (_, record) in volume.records(self.ino, &mut inode)? {
+            if record.ino == 0 || record.name == b"." || record.name == b".." {
+                continue;
+            }
+            let kind = match record.file_type {
+                file_type::FILE => Some(InodeKind::File),
+                file_type::DIRECTORY => Some(InodeKind::Directory),
+                file_type::SYMLINK => Some(InodeKind::Symlink),
+                file_type::CHAR_DEVICE => Some(InodeKind::CharDevice),
+                file_type::BLOCK_DEVICE => Some(InodeKind::BlockDevice),
+                file_type::UNKNOWN => volume.inode(record.ino, None)?.kind(),
+                _ => None,
+            };
+            // FIFOs and sockets have no equivalent yet
+            if let Some(kind) = kind {
+                entries.push(DirEntry {
+                    name: String::from_utf8_lossy(&record.name).into_owned(),
+                    ino: u64::from(record.ino),
+                    kind,
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    fn create(
+        &self,
+        name: &str,
+        kind: InodeKind,
+        permissions: Permissions,
+    ) -> Result<Arc<dyn Inode>> {
+        path::validate_name(name)?;
+        if !matches!(kind, InodeKind::File | InodeKind::Directory) {
+            return Err(VfsError::Unsupported);
+        }
+        let mut volume = self.fs.volume.lock();
+        volume.check_writable()?;
+        self.dir(&volume)?;
+        let (ino, inode) = volume.create(self.ino, name, kind, permissions, None)?;
+        Ok(self.child(ino, &inode))
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>> {
+        path::validate_name(name)?;
+        let mut volume = self.fs.volume.lock();
+        volume.check_writable()?;
+        self.dir(&volume)?;
+        if target.len() > volume.block_size() {
+            return Err(VfsError::NameTooLong);
+        }
+        let permissions = Permissions(0o777);
+        let (ino, inode) = volume.create(
+            self.ino,
+            name,
+            InodeKind::Symlink,
+            permissions,
+            Some(target),
+        )?;
+        Ok(self.child(ino, &inode))
+    }
+
+    fn link(&self, name: &str, target: &Arc<dyn Inode>) -> Result<()> {
+        path::validate_name(name)?;
+        let target = self.same_fs(target)?;
+        let mut volume = self.fs.volume.lock();
+        volume.check_writable()?;
+        let mut inode = self.dir(&volume)?;
+        let target_inode = volume.inode(target.ino, Some(target.generation))?;
+        let kind = target_inode.kind().ok_or(VfsError::Unsupported)?;
+        if kind == InodeKind::Directory {
+            return Err(VfsError::PermissionDenied);
+        }
+        if target_inode.links_count >= MAX_LINKS {
+            return Err(VfsError::NoSpace);
+        }
+        if volume.find(self.ino, &mut inode, name)?.is_some() {
+            return Err(VfsError::AlreadyExists);
+        }
+
+        volume.add_record(self.ino, name, target.ino, kind)?;
+        volume.adjust_links(target.ino, 1)
+    }
+
+    fn unlink(&self, name: &str) -> Result<()> {
+        let mut volume = self.fs.volume.lock();
+        volume.check_writable()?;
+        self.dir(&volume)?;
+        volume.remove(self.ino, name)
+    }
+
+    fn rename(&self, old_name: &str, new_parent: &Arc<dyn Inode>, new_name: &str) -> Result<()> {
+        path::validate_name(new_name)?;
+        let new_parent = self.same_fs(new_parent)?;
+        let mut volume = self.fs.volume.lock();
+        volume.check_writable()?;
+        let mut old_dir = self.dir(&volume)?;
+        let mut new_dir = new_parent.dir(&volume)?;
+
+        let (index, record) = volume
+            .find(self.ino, &mut old_dir, old_name)?
+            .ok_or(VfsError::NotFound)?;
+        let inode = volume.inode(record.ino, None)?;
+        let kind = inode.kind().ok_or(VfsError::Unsupported)?;
+        if let Some((_, existing)) = volume.find(new_parent.ino, &mut new_dir, new_name)? {
+            // Both names already refer to the same file
+            if existing.ino == record.ino {
+                return Ok(());
+            }
+            let existing_is_dir = volume.inode(existing.ino, None)?.is_dir();
+            match (existing_is_dir, inode.is_dir()) {
+                (true, false) => return Err(VfsError::IsADirectory),
+                (false, true) => return Err(VfsError::NotADirectory),
+                _ => volume.remove(new_parent.ino, new_name)?,
+            }
+        }
+
+        volume.add_record(new_parent.ino, new_name, record.ino, kind)?;
+        volume.remove_record(self.ino, index, record.offset)?;
+        if inode.is_dir() && new_parent.ino != self.ino {
+            volume.set_parent(record.ino, new_parent.ino)?;
+            volume.adjust_links(self.ino, -1)?;
+            volume.adjust_links(new_parent.ino, 1)?;
+        }
+        Ok(())
+    }
+
+    fn read_link(&self) -> Result<String> {
+        let mut volume = self.fs.volume.lock();
+        let mut inode = volume.inode(self.ino, Some(self.generation))?;
+        if inode.kind() != Some(InodeKind::Symlink) {
+            return Err(VfsError::InvalidArgument);
+        }
+
+        let target = if inode.is_fast_symlink(volume.superblock.block_size) {
+            let len = (inode.size as usize).min(FAST_SYMLINK_MAX);
+            inode.block_bytes()[..len].to_vec()
+        } else {
+            let mut target = vec![0; inode.size.min(volume.block_size() as u64) as usize];
+            let len = volume.read_data(self.ino, &mut inode, 0, &mut target)?;
+            target.truncate(len);
+            target
+        };
+        String::from_utf8(target).map_err(|_| VfsError::Corrupt)
+    }
+}
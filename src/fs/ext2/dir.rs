@@ -0,0 +1,248 @@
+//! Directory records within a directory block.
+//!
+//! Every block of a directory is a chain of variable-length records that covers the whole
+//! block. Free space is either a record with inode 0 or slack at the end of a record.
+
+use alloc::vec::Vec;
+
+use crate::vfs::{InodeKind, Result, VfsError};
+
+use super::superblock::{u16_at, u32_at};
+
+const INODE: usize = 0;
+const REC_LEN: usize = 4;
+const NAME_LEN: usize = 6;
+const FILE_TYPE: usize = 7;
+const NAME: usize = 8;
+const ALIGN: usize = 4;
+
+/// Values of the file type byte, used when the filesystem has the `filetype` feature.
+#[allow(missing_docs)]
+pub mod file_type {
+    pub const UNKNOWN: u8 = 0;
+    pub const FILE: u8 = 1;
+    pub const DIRECTORY: u8 = 2;
+    pub const CHAR_DEVICE: u8 = 3;
+    pub const BLOCK_DEVICE: u8 = 4;
+    pub const SYMLINK: u8 = 7;
+}
+
+/// A record in a directory block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Offset of the record within its block.
+    pub offset: usize,
+    /// Inode the record refers to, or 0 if the record is unused.
+    pub ino: u32,
+    /// Length of the record, including any slack after the name.
+    pub rec_len: usize,
+    /// Name of the entry.
+    pub name: Vec<u8>,
+    /// File type byte, or [file_type::UNKNOWN] without the `filetype` feature.
+    pub file_type: u8,
+}
+impl Record {
+    /// Bytes left over after the name.
+    fn slack(&self) -> usize {
+        self.rec_len - record_len(self.name.len())
+    }
+}
+
+/// The smallest record that can hold a name of `name_len` bytes.
+pub fn record_len(name_len: usize) -> usize {
+    (NAME + name_len).next_multiple_of(ALIGN)
+}
+
+/// The file type byte for an inode of the given kind.
+pub fn type_byte(kind: InodeKind) -> u8 {
+    match kind {
+        InodeKind::File => file_type::FILE,
+        InodeKind::Directory => file_type::DIRECTORY,
+        InodeKind::Symlink => file_type::SYMLINK,
+        InodeKind::CharDevice => file_type::CHAR_DEVICE,
+        InodeKind::BlockDevice => file_type::BLOCK_DEVICE,
+    }
+}
+
+/// Parse every record in a directory block.
+pub fn records(block: &[u8], has_file_type: bool) -> Result<Vec<Record>> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset < block.len() {
+        if block.len() - offset < NAME {
+            return Err(VfsError::Corrupt);
+        }
+        let rec_len = usize::from(u16_at(block, offset + REC_LEN));
+        let (name_len, file_type) = if has_file_type {
+            (
+                usize::from(block[offset + NAME_LEN]),
+                block[offset + FILE_TYPE],
+            )
+        } else {
+            (usize::from(u16_at(block, offset + NAME_LEN)), 0)
+        };
+        if !rec_len.is_multiple_of(ALIGN)
+            || rec_len < record_len(name_len)
+            || offset + rec_len > block.len()
+        {
+            return Err(VfsError::Corrupt);
+        }
+
+        let start = offset + NAME;
+        records.push(Record {
+            offset,
+            ino: u32_at(block, offset + INODE),
+            rec_len,
+            name: block[start..(start + name_len)].to_vec(),
+            file_type,
+        });
+        offset += rec_len;
+    }
+    Ok(records)
+}
+
+/// Write a record at `offset`.
+pub fn write_record(block: &mut [u8], record: &Record) {
+    let offset = record.offset;
+    block[(offset + INODE)..(offset + INODE + 4)].copy_from_slice(&record.ino.to_le_bytes());
+    block[(offset + REC_LEN)..(offset + REC_LEN + 2)]
+        .copy_from_slice(&(record.rec_len as u16).to_le_bytes());
+    block[offset + NAME_LEN] = record.name.len() as u8;
+    block[offset + FILE_TYPE] = record.file_type;
+    block[(offset + NAME)..(offset + NAME + record.name.len())].copy_from_slice(&record.name);
+}
+
+/// Add a record for `name` to a directory block if there's room. `file_type` must be
+/// [file_type::UNKNOWN] without the `filetype` feature.
+pub fn insert(
+    block: &mut [u8],
+    has_file_type: bool,
+    name: &[u8],
+    ino: u32,
+    file_type: u8,
+) -> Result<bool> {
+    let needed = record_len(name.len());
+    for record in records(block, has_file_type)? {
+        let mut new = Record {
+            offset: record.offset,
+            ino,
+            rec_len: record.rec_len,
+            name: name.to_vec(),
+            file_type,
+        };
+        if record.ino == 0 && record.rec_len >= needed {
+            // Reuse an unused record whole
+        } else if record.ino != 0 && record.slack() >= needed {
+            // Split the slack off the end of a used record
+            let kept = record_len(record.name.len());
+            new.offset += kept;
+            new.rec_len -= kept;
+            write_record(
+                block,
+                &Record {
+                    rec_len: kept,
+                    ..record
+                },
+            );
+        } else {
+            continue;
+        }
+        write_record(block, &new);
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+/// Remove the record at `offset` from a directory block, merging its space into the record
+/// before it.
+pub fn remove(block: &mut [u8], has_file_type: bool, offset: usize) -> Result<()> {
+    let records = records(block, has_file_type)?;
+    let index = records
+        .iter()
+        .position(|r| r.offset == offset)
+        .ok_or(VfsError::Corrupt)?;
+    match index.checked_sub(1).map(|i| &records[i]) {
+        Some(previous) => write_record(
+            block,
+            &Record {
+                rec_len: previous.rec_len + records[index].rec_len,
+                ..previous.clone()
+            },
+        ),
+        // The first record of a block can't be merged away, so it's just marked unused
+        None => block[(offset + INODE)..(offset + INODE + 4)].fill(0),
+    }
+    Ok(())
+}
+
+/// A directory block containing a single unused record.
+pub fn empty_block(block_size: usize) -> Vec<u8> {
+    let mut block = alloc::vec![0; block_size];
+    write_record(
+        &mut block,
+        &Record {
+            offset: 0,
+            ino: 0,
+            rec_len: block_size,
+            name: Vec::new(),
+            file_type: file_type::UNKNOWN,
+        },
+    );
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(block: &[u8]) -> Vec<Vec<u8>> {
+        records(block, true)
+            .unwrap()
+            .into_iter()
+            .filter(|r| r.ino != 0)
+            .map(|r| r.name)
+            .collect()
+    }
+
+    #[test_case]
+    fn insert_and_remove() {
+        let mut block = empty_block(64);
+        assert!(insert(&mut block, true, b"first", 12, file_type::FILE).unwrap());
+        assert!(insert(&mut block, true, b"second", 13, file_type::DIRECTORY).unwrap());
+        assert_eq!(names(&block), [b"first".to_vec(), b"second".to_vec()]);
+
+        let all = records(&block, true).unwrap();
+        assert_eq!(all[0].rec_len, 16);
+        assert_eq!(all[1].rec_len, 48);
+        assert_eq!(all[1].file_type, file_type::DIRECTORY);
+
+        // Four 16-byte records fill the block
+        assert!(insert(&mut block, true, b"third", 14, file_type::FILE).unwrap());
+        assert!(insert(&mut block, true, b"fourth", 15, file_type::FILE).unwrap());
+        assert!(!insert(&mut block, true, b"x", 16, file_type::FILE).unwrap());
+
+        remove(&mut block, true, all[1].offset).unwrap();
+        assert_eq!(
+            names(&block),
+            [b"first".to_vec(), b"third".to_vec(), b"fourth".to_vec()]
+        );
+        assert_eq!(records(&block, true).unwrap()[0].rec_len, 32);
+
+        // Removing the first record leaves an unused record that can be reused
+        remove(&mut block, true, 0).unwrap();
+        assert_eq!(names(&block), [b"third".to_vec(), b"fourth".to_vec()]);
+        assert!(insert(&mut block, true, b"fifth", 16, file_type::FILE).unwrap());
+        assert_eq!(records(&block, true).unwrap()[0].ino, 16);
+    }
+
+    #[test_case]
+    fn reject_bad_records() {
+        let mut block = empty_block(64);
+        block[REC_LEN] = 62;
+        assert_eq!(records(&block, true), Err(VfsError::Corrupt));
+
+        let mut block = empty_block(64);
+        block[REC_LEN] = 128;
+        assert_eq!(records(&block, true), Err(VfsError::Corrupt));
+    }
+}
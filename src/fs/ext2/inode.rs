@@ -0,0 +1,248 @@
+//! On-disk inodes.
+
+use crate::vfs::{InodeKind, Permissions};
+
+use super::superblock::{u16_at, u32_at};
+
+/// Size of the fields shared by every inode size.
+pub const BASE_INODE_SIZE: usize = 128;
+
+/// Number of block pointers stored directly in the inode.
+pub const DIRECT_BLOCKS: usize = 12;
+/// Index of the singly indirect block pointer.
+pub const INDIRECT_BLOCK: usize = DIRECT_BLOCKS;
+/// Total number of block pointers, including the three indirect ones.
+pub const BLOCK_POINTERS: usize = DIRECT_BLOCKS + 3;
+/// Symbolic link targets shorter than this are stored in the block pointers themselves.
+pub const FAST_SYMLINK_MAX: usize = BLOCK_POINTERS * 4;
+
+const MODE: usize = 0;
+const UID: usize = 2;
+const SIZE: usize = 4;
+const DTIME: usize = 20;
+const GID: usize = 24;
+const LINKS_COUNT: usize = 26;
+const BLOCKS: usize = 28;
+const FLAGS: usize = 32;
+const BLOCK: usize = 40;
+const GENERATION: usize = 100;
+const FILE_ACL: usize = 104;
+const SIZE_HIGH: usize = 108;
+const UID_HIGH: usize = 120;
+const GID_HIGH: usize = 122;
+
+/// File type bits of [DiskInode::mode].
+#[allow(missing_docs)]
+pub mod mode {
+    pub const TYPE_MASK: u16 = 0xF000;
+    pub const CHAR_DEVICE: u16 = 0x2000;
+    pub const DIRECTORY: u16 = 0x4000;
+    pub const BLOCK_DEVICE: u16 = 0x6000;
+    pub const FILE: u16 = 0x8000;
+    pub const SYMLINK: u16 = 0xA000;
+    pub const PERMISSIONS_MASK: u16 = 0o7777;
+}
+
+/// Set on directories with a hashed index, which the driver doesn't maintain.
+pub const INDEX_FLAG: u32 = 0x1000;
+
+/// The fields of an on-disk inode the driver uses. Everything else is preserved as is.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DiskInode {
+    /// File type and permission bits.
+    pub mode: u16,
+    /// Owner user ID.
+    pub uid: u32,
+    /// Size in bytes.
+    pub size: u64,
+    /// Deletion time.
+    pub dtime: u32,
+    /// Owner group ID.
+    pub gid: u32,
+    /// Number of hard links.
+    pub links_count: u16,
+    /// Number of 512-byte sectors allocated, including indirect blocks.
+    pub blocks: u32,
+    /// Inode flags.
+    pub flags: u32,
+    /// Direct and indirect block pointers.
+    pub block: [u32; BLOCK_POINTERS],
+    /// Changed every time the inode number is reused.
+    pub generation: u32,
+    /// Block holding extended attributes.
+    pub file_acl: u32,
+}
+impl DiskInode {
+    /// A new inode with the given type and permission bits.
+    pub fn new(mode: u16, generation: u32) -> Self {
+        Self {
+            mode,
+            uid: 0,
+            size: 0,
+            dtime: 0,
+            gid: 0,
+            links_count: 0,
+            blocks: 0,
+            flags: 0,
+            block: [0; BLOCK_POINTERS],
+            generation,
+            file_acl: 0,
+        }
+    }
+
+    /// Decode an inode from its first [BASE_INODE_SIZE] bytes.
+    pub fn parse(raw: &[u8]) -> Self {
+        let mode = u16_at(raw, MODE);
+        // The high size bits hold the directory ACL on anything but regular files
+        let size_high = if (mode & mode::TYPE_MASK) == mode::FILE {
+            u32_at(raw, SIZE_HIGH)
+        } else {
+            0
+        };
+        let mut block = [0; BLOCK_POINTERS];
+        for (i, pointer) in block.iter_mut().enumerate() {
+            *pointer = u32_at(raw, BLOCK + i * 4);
+        }
+        Self {
+            mode,
+            uid: u32::from(u16_at(raw, UID)) | (u32::from(u16_at(raw, UID_HIGH)) << 16),
+            size: u64::from(u32_at(raw, SIZE)) | (u64::from(size_high) << 32),
+            dtime: u32_at(raw, DTIME),
+            gid: u32::from(u16_at(raw, GID)) | (u32::from(u16_at(raw, GID_HIGH)) << 16),
+            links_count: u16_at(raw, LINKS_COUNT),
+            blocks: u32_at(raw, BLOCKS),
+            flags: u32_at(raw, FLAGS),
+            block,
+            generation: u32_at(raw, GENERATION),
+            file_acl: u32_at(raw, FILE_ACL),
+        }
+    }
+
+    /// Encode the inode over the first [BASE_INODE_SIZE] bytes of `raw`, leaving fields the
+    /// driver doesn't know about untouched.
+    pub fn encode(&self, raw: &mut [u8]) {
+        let mut put = |offset: usize, bytes: &[u8]| {
+            raw[offset..(offset + bytes.len())].copy_from_slice(bytes);
+        };
+        put(MODE, &self.mode.to_le_bytes());
+        put(UID, &(self.uid as u16).to_le_bytes());
+        put(UID_HIGH, &((self.uid >> 16) as u16).to_le_bytes());
+        put(SIZE, &(self.size as u32).to_le_bytes());
+        if self.kind() == Some(InodeKind::File) {
+            put(SIZE_HIGH, &((self.size >> 32) as u32).to_le_bytes());
+        }
+        put(DTIME, &self.dtime.to_le_bytes());
+        put(GID, &(self.gid as u16).to_le_bytes());
+        put(GID_HIGH, &((self.gid >> 16) as u16).to_le_bytes());
+        put(LINKS_COUNT, &self.links_count.to_le_bytes());
+        put(BLOCKS, &self.blocks.to_le_bytes());
+        put(FLAGS, &self.flags.to_le_bytes());
+        for (i, pointer) in self.block.iter().enumerate() {
+            put(BLOCK + i * 4, &pointer.to_le_bytes());
+        }
+        put(GENERATION, &self.generation.to_le_bytes());
+        put(FILE_ACL, &self.file_acl.to_le_bytes());
+    }
+
+    /// What sort of file this is, or [None] for FIFOs, sockets and unknown types.
+    pub fn kind(&self) -> Option<InodeKind> {
+        match self.mode & mode::TYPE_MASK {
+            mode::FILE => Some(InodeKind::File),
+            mode::DIRECTORY => Some(InodeKind::Directory),
+            mode::SYMLINK => Some(InodeKind::Symlink),
+            mode::CHAR_DEVICE => Some(InodeKind::CharDevice),
+            mode::BLOCK_DEVICE => Some(InodeKind::BlockDevice),
+            _ => None,
+        }
+    }
+
+    /// Check if this is a directory.
+    pub fn is_dir(&self) -> bool {
+        (self.mode & mode::TYPE_MASK) == mode::DIRECTORY
+    }
+
+    /// The permission bits.
+    pub fn permissions(&self) -> Permissions {
+        Permissions(self.mode & mode::PERMISSIONS_MASK)
+    }
+
+    /// Check if this is a symbolic link with its target stored in the block pointers.
+    pub fn is_fast_symlink(&self, block_size: u32) -> bool {
+        let acl_sectors = if self.file_acl == 0 {
+            0
+        } else {
+            block_size / 512
+        };
+        self.kind() == Some(InodeKind::Symlink) && self.blocks == acl_sectors
+    }
+
+    /// The block pointers as raw bytes, which is where fast symbolic links keep their target.
+    pub fn block_bytes(&self) -> [u8; FAST_SYMLINK_MAX] {
+        let mut bytes = [0; FAST_SYMLINK_MAX];
+        for (chunk, pointer) in bytes.chunks_exact_mut(4).zip(self.block.iter()) {
+            chunk.copy_from_slice(&pointer.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Set the block pointers from raw bytes.
+    pub fn set_block_bytes(&mut self, bytes: &[u8; FAST_SYMLINK_MAX]) {
+        for (pointer, chunk) in self.block.iter_mut().zip(bytes.chunks_exact(4)) {
+            *pointer = u32_at(chunk, 0);
+        }
+    }
+}
+
+/// The [mode] type bits for an inode of the given kind.
+pub fn type_bits(kind: InodeKind) -> u16 {
+    match kind {
+        InodeKind::File => mode::FILE,
+        InodeKind::Directory => mode::DIRECTORY,
+        InodeKind::Symlink => mode::SYMLINK,
+        InodeKind::CharDevice => mode::CHAR_DEVICE,
+        InodeKind::BlockDevice => mode::BLOCK_DEVICE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn round_trip() {
+        let mut inode = DiskInode::new(mode::FILE | 0o640, 7);
+        inode.uid = 0x1_0001;
+        inode.gid = 100;
+        inode.size = 0x1_2345_6789;
+        inode.links_count = 2;
+        inode.block[INDIRECT_BLOCK] = 1234;
+
+        let mut raw = [0xAA; BASE_INODE_SIZE];
+        inode.encode(&mut raw);
+        assert_eq!(DiskInode::parse(&raw), inode);
+        assert_eq!(inode.kind(), Some(InodeKind::File));
+        assert_eq!(inode.permissions(), Permissions(0o640));
+        // Untouched fields are preserved
+        assert_eq!(raw[8], 0xAA);
+    }
+
+    #[test_case]
+    fn directory_size_ignores_high_bits() {
+        let mut raw = [0; BASE_INODE_SIZE];
+        DiskInode::new(mode::DIRECTORY | 0o755, 0).encode(&mut raw);
+        raw[SIZE_HIGH] = 1;
+        assert_eq!(DiskInode::parse(&raw).size, 0);
+    }
+
+    #[test_case]
+    fn fast_symlink_bytes() {
+        let mut inode = DiskInode::new(mode::SYMLINK | 0o777, 0);
+        let mut target = [0; FAST_SYMLINK_MAX];
+        target[..9].copy_from_slice(b"hello.txt");
+        inode.set_block_bytes(&target);
+        assert_eq!(inode.block_bytes(), target);
+        assert!(inode.is_fast_symlink(1024));
+        inode.blocks = 2;
+        assert!(!inode.is_fast_symlink(1024));
+    }
+}
@@ -0,0 +1,325 @@
+//! The superblock and block group descriptors.
+
+use alloc::{string::String, vec::Vec};
+
+use crate::{
+    block::{read_bytes, write_bytes, BlockDevice},
+    vfs::{Result, VfsError},
+};
+
+/// Byte offset of the superblock from the start of the device.
+pub const SUPERBLOCK_OFFSET: u64 = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+const MAGIC: u16 = 0xEF53;
+
+const INODES_COUNT: usize = 0;
+const BLOCKS_COUNT: usize = 4;
+const FREE_BLOCKS_COUNT: usize = 12;
+const FREE_INODES_COUNT: usize = 16;
+const FIRST_DATA_BLOCK: usize = 20;
+const LOG_BLOCK_SIZE: usize = 24;
+const BLOCKS_PER_GROUP: usize = 32;
+const INODES_PER_GROUP: usize = 40;
+const WRITE_TIME: usize = 48;
+const MAGIC_OFFSET: usize = 56;
+const REV_LEVEL: usize = 76;
+const FIRST_INO: usize = 84;
+const INODE_SIZE: usize = 88;
+const FEATURE_COMPAT: usize = 92;
+const FEATURE_INCOMPAT: usize = 96;
+const FEATURE_RO_COMPAT: usize = 100;
+const VOLUME_NAME: usize = 120;
+const VOLUME_NAME_LEN: usize = 16;
+
+// Revision 0 filesystems have fixed values for these
+const GOOD_OLD_FIRST_INO: u32 = 11;
+const GOOD_OLD_INODE_SIZE: u16 = 128;
+
+const MAX_LOG_BLOCK_SIZE: u32 = 6;
+
+/// Size of a block group descriptor in bytes.
+pub const GROUP_DESC_SIZE: usize = 32;
+const BLOCK_BITMAP: usize = 0;
+const INODE_BITMAP: usize = 4;
+const INODE_TABLE: usize = 8;
+/// Offset of the free and used counts within a block group descriptor.
+pub const GROUP_COUNTS: usize = 12;
+
+/// Feature flags. Unknown incompatible features prevent mounting and unknown read-only
+/// compatible features prevent writing.
+#[allow(missing_docs)]
+pub mod features {
+    pub const INCOMPAT_FILETYPE: u32 = 0x0002;
+    pub const INCOMPAT_SUPPORTED: u32 = INCOMPAT_FILETYPE;
+
+    pub const RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+    pub const RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+    pub const RO_COMPAT_SUPPORTED: u32 = RO_COMPAT_SPARSE_SUPER | RO_COMPAT_LARGE_FILE;
+}
+
+/// The fields of the superblock the driver uses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Superblock {
+    /// Total number of inodes.
+    pub inodes_count: u32,
+    /// Total number of blocks.
+    pub blocks_count: u32,
+    /// Number of free blocks.
+    pub free_blocks_count: u32,
+    /// Number of free inodes.
+    pub free_inodes_count: u32,
+    /// Block containing the superblock, which is where the block groups start.
+    pub first_data_block: u32,
+    /// Bytes per block.
+    pub block_size: u32,
+    /// Blocks in each block group.
+    pub blocks_per_group: u32,
+    /// Inodes in each block group.
+    pub inodes_per_group: u32,
+    /// First inode number not reserved by the filesystem.
+    pub first_ino: u32,
+    /// Size of an on-disk inode in bytes.
+    pub inode_size: u16,
+    /// Compatible feature flags.
+    pub feature_compat: u32,
+    /// Incompatible feature flags.
+    pub feature_incompat: u32,
+    /// Read-only compatible feature flags.
+    pub feature_ro_compat: u32,
+    /// Volume label, without trailing NULs.
+    pub volume_name: String,
+    /// When the filesystem was last written, in seconds since the Unix epoch.
+    pub write_time: u32,
+}
+impl Superblock {
+    /// Read the superblock of `device`.
+    pub fn read(device: &dyn BlockDevice) -> Result<Self> {
+        let mut raw = [0; SUPERBLOCK_SIZE];
+        read_bytes(device, SUPERBLOCK_OFFSET, &mut raw)?;
+        Self::parse(&raw)
+    }
+
+    /// Parse a raw superblock.
+    pub fn parse(raw: &[u8; SUPERBLOCK_SIZE]) -> Result<Self> {
+        if u16_at(raw, MAGIC_OFFSET) != MAGIC {
+            return Err(VfsError::Corrupt);
+        }
+
+        let log_block_size = u32_at(raw, LOG_BLOCK_SIZE);
+        if log_block_size > MAX_LOG_BLOCK_SIZE {
+            return Err(VfsError::Corrupt);
+        }
+        let block_size = 1024 << log_block_size;
+
+        let (first_ino, inode_size, feature_compat, feature_incompat, feature_ro_compat) =
+            match u32_at(raw, REV_LEVEL) {
+                0 => (GOOD_OLD_FIRST_INO, GOOD_OLD_INODE_SIZE, 0, 0, 0),
+                _ => (
+                    u32_at(raw, FIRST_INO),
+                    u16_at(raw, INODE_SIZE),
+                    u32_at(raw, FEATURE_COMPAT),
+                    u32_at(raw, FEATURE_INCOMPAT),
+                    u32_at(raw, FEATURE_RO_COMPAT),
+                ),
+            };
+
+        let superblock = Self {
+            inodes_count: u32_at(raw, INODES_COUNT),
+            blocks_count: u32_at(raw, BLOCKS_COUNT),
+            free_blocks_count: u32_at(raw, FREE_BLOCKS_COUNT),
+            free_inodes_count: u32_at(raw, FREE_INODES_COUNT),
+            first_data_block: u32_at(raw, FIRST_DATA_BLOCK),
+            block_size,
+            blocks_per_group: u32_at(raw, BLOCKS_PER_GROUP),
+            inodes_per_group: u32_at(raw, INODES_PER_GROUP),
+            first_ino,
+            inode_size,
+            feature_compat,
+            feature_incompat,
+            feature_ro_compat,
+            volume_name: raw[VOLUME_NAME..(VOLUME_NAME + VOLUME_NAME_LEN)]
+                .iter()
+                .take_while(|&&b| b != 0)
+                .map(|&b| char::from(b))
+                .collect(),
+            write_time: u32_at(raw, WRITE_TIME),
+        };
+
+        // Bitmaps are a single block each
+        let max_per_group = block_size * 8;
+        let valid = (1..=max_per_group).contains(&superblock.blocks_per_group)
+            && (1..=max_per_group).contains(&superblock.inodes_per_group)
+            && superblock.inode_size.is_power_of_two()
+            && (GOOD_OLD_INODE_SIZE..=block_size as u16).contains(&superblock.inode_size)
+            && superblock.first_data_block < superblock.blocks_count
+            && superblock.inodes_count
+                == superblock.inodes_per_group * superblock.group_count() as u32;
+        if !valid {
+            return Err(VfsError::Corrupt);
+        }
+        if (superblock.feature_incompat & !features::INCOMPAT_SUPPORTED) != 0 {
+            return Err(VfsError::Unsupported);
+        }
+        Ok(superblock)
+    }
+
+    /// Check if the filesystem uses features that the driver can read but not write.
+    pub fn needs_read_only(&self) -> bool {
+        (self.feature_ro_compat & !features::RO_COMPAT_SUPPORTED) != 0
+    }
+
+    /// Number of block groups.
+    pub fn group_count(&self) -> usize {
+        (self.blocks_count - self.first_data_block).div_ceil(self.blocks_per_group) as usize
+    }
+
+    /// Byte offset of the block group descriptor table.
+    pub fn group_table_offset(&self) -> u64 {
+        u64::from(self.first_data_block + 1) * u64::from(self.block_size)
+    }
+
+    /// Write the free block and inode counts back to the device.
+    pub fn write_counts(&self, device: &dyn BlockDevice) -> Result<()> {
+        let mut counts = [0; 8];
+        counts[..4].copy_from_slice(&self.free_blocks_count.to_le_bytes());
+        counts[4..].copy_from_slice(&self.free_inodes_count.to_le_bytes());
+        Ok(write_bytes(
+            device,
+            SUPERBLOCK_OFFSET + FREE_BLOCKS_COUNT as u64,
+            &counts,
+        )?)
+    }
+}
+
+/// A block group descriptor.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GroupDesc {
+    /// Block holding the block bitmap.
+    pub block_bitmap: u32,
+    /// Block holding the inode bitmap.
+    pub inode_bitmap: u32,
+    /// First block of the inode table.
+    pub inode_table: u32,
+    /// Number of free blocks in the group.
+    pub free_blocks_count: u16,
+    /// Number of free inodes in the group.
+    pub free_inodes_count: u16,
+    /// Number of directories in the group.
+    pub used_dirs_count: u16,
+}
+impl GroupDesc {
+    /// Read every block group descriptor of the filesystem described by `superblock`.
+    pub fn read_all(device: &dyn BlockDevice, superblock: &Superblock) -> Result<Vec<Self>> {
+        let mut raw = alloc::vec![0; superblock.group_count() * GROUP_DESC_SIZE];
+        read_bytes(device, superblock.group_table_offset(), &mut raw)?;
+        let groups: Vec<Self> = raw.chunks_exact(GROUP_DESC_SIZE).map(Self::parse).collect();
+
+        let in_range =
+            |block: u32| (superblock.first_data_block..superblock.blocks_count).contains(&block);
+        if groups.iter().all(|g| {
+            in_range(g.block_bitmap) && in_range(g.inode_bitmap) && in_range(g.inode_table)
+        }) {
+            Ok(groups)
+        } else {
+            Err(VfsError::Corrupt)
+        }
+    }
+
+    /// Parse a raw block group descriptor.
+    pub fn parse(raw: &[u8]) -> Self {
+        Self {
+            block_bitmap: u32_at(raw, BLOCK_BITMAP),
+            inode_bitmap: u32_at(raw, INODE_BITMAP),
+            inode_table: u32_at(raw, INODE_TABLE),
+            free_blocks_count: u16_at(raw, GROUP_COUNTS),
+            free_inodes_count: u16_at(raw, GROUP_COUNTS + 2),
+            used_dirs_count: u16_at(raw, GROUP_COUNTS + 4),
+        }
+    }
+
+    /// Encode the free and used counts, which live at [GROUP_COUNTS].
+    pub fn encode_counts(&self) -> [u8; 6] {
+        let mut counts = [0; 6];
+        counts[..2].copy_from_slice(&self.free_blocks_count.to_le_bytes());
+        counts[2..4].copy_from_slice(&self.free_inodes_count.to_le_bytes());
+        counts[4..].copy_from_slice(&self.used_dirs_count.to_le_bytes());
+        counts
+    }
+}
+
+/// Read the little-endian `u16` at `offset`.
+pub fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+/// Read the little-endian `u32` at `offset`.
+pub fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The superblock of an 8 MiB, 1 KiB block filesystem with a single block group.
+    fn small() -> [u8; SUPERBLOCK_SIZE] {
+        let mut raw = [0; SUPERBLOCK_SIZE];
+        let mut set = |offset: usize, value: u32| {
+            raw[offset..(offset + 4)].copy_from_slice(&value.to_le_bytes());
+        };
+        set(INODES_COUNT, 2048);
+        set(BLOCKS_COUNT, 8192);
+        set(FREE_BLOCKS_COUNT, 7000);
+        set(FREE_INODES_COUNT, 2000);
+        set(FIRST_DATA_BLOCK, 1);
+        set(BLOCKS_PER_GROUP, 8192);
+        set(INODES_PER_GROUP, 2048);
+        set(REV_LEVEL, 1);
+        set(FIRST_INO, 11);
+        set(FEATURE_INCOMPAT, features::INCOMPAT_FILETYPE);
+        set(FEATURE_RO_COMPAT, features::RO_COMPAT_SPARSE_SUPER);
+        raw[INODE_SIZE..(INODE_SIZE + 2)].copy_from_slice(&256u16.to_le_bytes());
+        raw[MAGIC_OFFSET..(MAGIC_OFFSET + 2)].copy_from_slice(&MAGIC.to_le_bytes());
+        raw[VOLUME_NAME..(VOLUME_NAME + 4)].copy_from_slice(b"TEST");
+        raw
+    }
+
+    #[test_case]
+    fn parse_small() {
+        let superblock = Superblock::parse(&small()).unwrap();
+        assert_eq!(superblock.block_size, 1024);
+        assert_eq!(superblock.group_count(), 1);
+        assert_eq!(superblock.group_table_offset(), 2048);
+        assert_eq!(superblock.inode_size, 256);
+        assert_eq!(superblock.volume_name, "TEST");
+        assert!(!superblock.needs_read_only());
+    }
+
+    #[test_case]
+    fn features_checked() {
+        let mut raw = small();
+        raw[FEATURE_RO_COMPAT] |= 0x40;
+        assert!(Superblock::parse(&raw).unwrap().needs_read_only());
+
+        // Extents
+        let mut raw = small();
+        raw[FEATURE_INCOMPAT] |= 0x40;
+        assert_eq!(Superblock::parse(&raw), Err(VfsError::Unsupported));
+    }
+
+    #[test_case]
+    fn reject_bad_superblock() {
+        let mut raw = small();
+        raw[MAGIC_OFFSET] = 0;
+        assert_eq!(Superblock::parse(&raw), Err(VfsError::Corrupt));
+
+        let mut raw = small();
+        raw[INODES_COUNT] = 1;
+        assert_eq!(Superblock::parse(&raw), Err(VfsError::Corrupt));
+    }
+}
@@ -0,0 +1,1020 @@
+//! FAT12, FAT16 and FAT32 filesystems on a [BlockDevice].
+//!
+//! FAT has no inodes, so a file is identified by the position of its short directory entry,
+//! which doubles as its inode number. Live [FatInode]s are tracked by that position so every
+//! lookup of the same file shares one inode. Long file names are read and written; names are
+//! matched case-insensitively like on every other FAT implementation.
+//!
+//! Hard links and symbolic links can't be represented and are reported as unsupported.
+
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::any::Any;
+
+use spin::Mutex;
+
+use crate::{
+    block::{read_bytes, write_bytes, BlockDevice},
+    vfs::{path, DirEntry, FileSystem, Inode, InodeKind, Metadata, Permissions, Result, VfsError},
+};
+
+mod bpb;
+mod dir;
+
+pub use bpb::FatType;
+use bpb::{u32_at, Layout, BOOT_SECTOR_SIZE};
+use dir::{attr, LongNameBuilder, RawEntry, ShortEntry, ENTRY_SIZE};
+
+const ROOT_INO: u64 = 1;
+
+const FREE_CLUSTER: u32 = 0;
+const FAT32_ENTRY_MASK: u32 = 0x0FFF_FFFF;
+
+const DOT: &[u8; dir::SHORT_NAME_LEN] = b".          ";
+const DOT_DOT: &[u8; dir::SHORT_NAME_LEN] = b"..         ";
+
+// FSInfo sector layout
+const FS_INFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FS_INFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const FS_INFO_STRUCT_OFFSET: usize = 484;
+const FS_INFO_FREE_COUNT: usize = 488;
+const FS_INFO_NEXT_FREE: usize = 492;
+const FS_INFO_UNKNOWN: u32 = 0xFFFF_FFFF;
+
+// Give up on generating a unique short name after this many numeric tails
+const MAX_SHORT_NAME_TAIL: u32 = 999_999;
+
+/// A mounted FAT volume.
+pub struct FatFs {
+    shared: Arc<Shared>,
+    root: Arc<FatInode>,
+}
+impl FatFs {
+    /// Read the FAT volume on `device`.
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Arc<Self>> {
+        let mut boot = [0; BOOT_SECTOR_SIZE];
+        read_bytes(device.as_ref(), 0, &mut boot)?;
+        let layout = Layout::parse(&boot)?;
+
+        let mut volume = Volume {
+            device,
+            next_free: 2,
+            free_count: None,
+            fs_info_dirty: false,
+            fat_cache: None,
+            inodes: BTreeMap::new(),
+            layout,
+        };
+        volume.read_fs_info()?;
+        let root_cluster = volume.layout.root_cluster;
+        if volume.layout.fat_type == FatType::Fat32 && !volume.layout.is_data_cluster(root_cluster)
+        {
+            return Err(VfsError::Corrupt);
+        }
+
+        let shared = Arc::new(Shared {
+            volume: Mutex::new(volume),
+        });
+        let root = Arc::new(FatInode {
+            fs: Arc::clone(&shared),
+            node: Mutex::new(Node {
+                ino: ROOT_INO,
+                attr: attr::DIRECTORY,
+                cluster: root_cluster,
+                size: 0,
+                entry: None,
+            }),
+        });
+        Ok(Arc::new(Self { shared, root }))
+    }
+
+    /// Which FAT variant the volume uses.
+    pub fn fat_type(&self) -> FatType {
+        self.shared.volume.lock().layout.fat_type
+    }
+
+    /// The volume label from the boot sector.
+    pub fn label(&self) -> String {
+        self.shared.volume.lock().layout.label.clone()
+    }
+}
+impl FileSystem for FatFs {
+    fn name(&self) -> &'static str {
+        "fat"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::clone(&self.root) as Arc<dyn Inode>
+    }
+
+    fn sync(&self) -> Result<()> {
+        let mut volume = self.shared.volume.lock();
+        volume.write_fs_info()?;
+        Ok(volume.device.flush()?)
+    }
+}
+
+/// State shared by every inode of one [FatFs].
+struct Shared {
+    volume: Mutex<Volume>,
+}
+
+/// Where the entries of a directory are stored.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum DirLocation {
+    /// The fixed-size root directory of FAT12/16.
+    FixedRoot,
+    /// A cluster chain starting at the given cluster.
+    Chain(u32),
+}
+
+/// A directory entry along with its long name.
+#[derive(Debug, Clone)]
+struct Entry {
+    name: String,
+    short: ShortEntry,
+    location: EntryLocation,
+}
+
+/// Where a directory entry is stored.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct EntryLocation {
+    dir: DirLocation,
+    // Index of the first (long name) slot in the directory
+    first_slot: usize,
+    // Number of slots, including the short entry
+    slots: usize,
+    // Device offset of the short entry, which is also the inode number
+    offset: u64,
+}
+
+/// Everything about a file that's stored in its directory entry.
+struct Node {
+    ino: u64,
+    attr: u8,
+    cluster: u32,
+    size: u32,
+    // [None] for the root directory and for unlinked files
+    entry: Option<EntryLocation>,
+}
+impl Node {
+    fn is_dir(&self) -> bool {
+        (self.attr & attr::DIRECTORY) != 0
+    }
+
+    fn kind(&self) -> InodeKind {
+        if self.is_dir() {
+            InodeKind::Directory
+        } else {
+            InodeKind::File
+        }
+    }
+
+    /// Fail if the file has been unlinked.
+    fn check_linked(&self) -> Result<()> {
+        if self.ino != ROOT_INO && self.entry.is_none() {
+            return Err(VfsError::NotFound);
+        }
+        Ok(())
+    }
+}
+
+struct Volume {
+    device: Arc<dyn BlockDevice>,
+    layout: Layout,
+    // Where to start looking for a free cluster
+    next_free: u32,
+    // Number of free clusters, if known
+    free_count: Option<u32>,
+    fs_info_dirty: bool,
+    // The most recently used sector of the first FAT, by offset within the FAT
+    fat_cache: Option<(u64, Vec<u8>)>,
+    // Live inodes by inode number, so every lookup of a file yields the same inode
+    inodes: BTreeMap<u64, Weak<FatInode>>,
+}
+impl Volume {
+    fn read_fs_info(&mut self) -> Result<()> {
+        let Some(offset) = self.layout.fs_info else {
+            return Ok(());
+        };
+        let mut sector = [0; BOOT_SECTOR_SIZE];
+        read_bytes(self.device.as_ref(), offset, &mut sector)?;
+        if u32_at(&sector, 0) != FS_INFO_LEAD_SIGNATURE
+            || u32_at(&sector, FS_INFO_STRUCT_OFFSET) != FS_INFO_STRUCT_SIGNATURE
+        {
+            return Ok(());
+        }
+
+        let free_count = u32_at(&sector, FS_INFO_FREE_COUNT);
+        if free_count <= self.layout.cluster_count {
+            self.free_count = Some(free_count);
+        }
+        let next_free = u32_at(&sector, FS_INFO_NEXT_FREE);
+        if self.layout.is_data_cluster(next_free) {
+            self.next_free = next_free;
+        }
+        Ok(())
+    }
+
+    fn write_fs_info(&mut self) -> Result<()> {
+        let Some(offset) = self.layout.fs_info.filter(|_| self.fs_info_dirty) else {
+            return Ok(());
+        };
+        let mut fields = [0; 8];
+        fields[..4].copy_from_slice(&self.free_count.unwrap_or(FS_INFO_UNKNOWN).to_le_bytes());
+        fields[4..].copy_from_slice(&self.next_free.to_le_bytes());
+        write_bytes(
+            self.device.as_ref(),
+            offset + FS_INFO_FREE_COUNT as u64,
+            &fields,
+        )?;
+        self.fs_info_dirty = false;
+        Ok(())
+    }
+
+    /// Read bytes from the first FAT, starting at `offset` within it.
+    fn read_fat(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let sector_size = self.layout.sector_size;
+        for (i, byte) in buf.iter_mut().enumerate() {
+            let position = offset + i as u64;
+            let sector = position - position % sector_size;
+            let cached = match &self.fat_cache {
+                Some((cached, _)) => *cached == sector,
+                None => false,
+            };
+            if !cached {
+                let mut data = vec![0; sector_size as usize];
+                read_bytes(
+                    self.device.as_ref(),
+                    self.layout.fat_start + sector,
+                    &mut data,
+                )?;
+                self.fat_cache = Some((sector, data));
+            }
+            let (_, data) = self.fat_cache.as_ref().expect("just filled");
+            *byte = data[(position - sector) as usize];
+        }
+        Ok(())
+    }
+
+    /// Write bytes to every FAT, starting at `offset` within each.
+    fn write_fat(&mut self, offset: u64, bytes: &[u8]) -> Result<()> {
+        for copy in 0..u64::from(self.layout.num_fats) {
+            let start = self.layout.fat_start + copy * self.layout.fat_size + offset;
+            write_bytes(self.device.as_ref(), start, bytes)?;
+        }
+
+        if let Some((sector, data)) = &mut self.fat_cache {
+            for (i, &byte) in bytes.iter().enumerate() {
+                let position = offset + i as u64;
+                if (*sector..(*sector + data.len() as u64)).contains(&position) {
+                    data[(position - *sector) as usize] = byte;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the FAT entry of `cluster`.
+    fn fat_entry(&mut self, cluster: u32) -> Result<u32> {
+        let cluster64 = u64::from(cluster);
+        Ok(match self.layout.fat_type {
+            FatType::Fat12 => {
+                let mut bytes = [0; 2];
+                self.read_fat(cluster64 + cluster64 / 2, &mut bytes)?;
+                let value = u16::from_le_bytes(bytes);
+                u32::from(if cluster.is_multiple_of(2) {
+                    value & 0x0FFF
+                } else {
+                    value >> 4
+                })
+            }
+            FatType::Fat16 => {
+                let mut bytes = [0; 2];
+                self.read_fat(cluster64 * 2, &mut bytes)?;
+                u32::from(u16::from_le_bytes(bytes))
+            }
+            FatType::Fat32 => {
+                let mut bytes = [0; 4];
+                self.read_fat(cluster64 * 4, &mut bytes)?;
+                u32::from_le_bytes(bytes) & FAT32_ENTRY_MASK
+            }
+        })
+    }
+
+    /// Set the FAT entry of `cluster` in every FAT.
+    fn set_fat_entry(&mut self, cluster: u32, value: u32) -> Result<()> {
+        let cluster64 = u64::from(cluster);
+        match self.layout.fat_type {
+            FatType::Fat12 => {
+                let offset = cluster64 + cluster64 / 2;
+                let mut bytes = [0; 2];
+                self.read_fat(offset, &mut bytes)?;
+                let old = u16::from_le_bytes(bytes);
+                let new = fat12_pack(old, cluster, value as u16);
+                self.write_fat(offset, &new.to_le_bytes())
+            }
+            FatType::Fat16 => self.write_fat(cluster64 * 2, &(value as u16).to_le_bytes()),
+            FatType::Fat32 => {
+                // The top four bits are reserved and must be preserved
+                let mut bytes = [0; 4];
+                self.read_fat(cluster64 * 4, &mut bytes)?;
+                let old = u32::from_le_bytes(bytes);
+                let new = (old & !FAT32_ENTRY_MASK) | (value & FAT32_ENTRY_MASK);
+                self.write_fat(cluster64 * 4, &new.to_le_bytes())
+            }
+        }
+    }
+
+    /// The FAT entry value marking the end of a chain.
+    fn end_of_chain(&self) -> u32 {
+        match self.layout.fat_type {
+            FatType::Fat12 => 0x0FFF,
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => FAT32_ENTRY_MASK,
+        }
+    }
+
+    /// Check if a FAT entry value marks the end of a chain.
+    fn is_end_of_chain(&self, value: u32) -> bool {
+        value >= self.end_of_chain() - 7
+    }
+
+    /// Every cluster in the chain starting at `first`, which may be 0 for an empty chain.
+    fn chain(&mut self, first: u32) -> Result<Vec<u32>> {
+        let mut clusters = Vec::new();
+        let mut cluster = first;
+        if cluster == FREE_CLUSTER {
+            return Ok(clusters);
+        }
+        loop {
+            // A chain can't be longer than the volume, unless it loops
+            if !self.layout.is_data_cluster(cluster)
+                || clusters.len() >= self.layout.cluster_count as usize
+            {
+                return Err(VfsError::Corrupt);
+            }
+            clusters.push(cluster);
+            let next = self.fat_entry(cluster)?;
+            if self.is_end_of_chain(next) {
+                return Ok(clusters);
+            }
+            cluster = next;
+        }
+    }
+
+    /// Allocate a zeroed cluster and append it to the chain ending at `last`, if any.
+    fn allocate_cluster(&mut self, last: Option<u32>) -> Result<u32> {
+        let count = self.layout.cluster_count;
+        let start = self.next_free;
+        let mut found = None;
+        for i in 0..count {
+            let cluster = 2 + (start - 2 + i) % count;
+            if self.fat_entry(cluster)? == FREE_CLUSTER {
+                found = Some(cluster);
+                break;
+            }
+        }
+        let cluster = found.ok_or(VfsError::NoSpace)?;
+
+        self.zero_cluster(cluster)?;
+        self.set_fat_entry(cluster, self.end_of_chain())?;
+        if let Some(last) = last {
+            self.set_fat_entry(last, cluster)?;
+        }
+
+        self.next_free = if cluster + 1 < count + 2 {
+            cluster + 1
+        } else {
+            2
+        };
+        self.free_count = self.free_count.map(|n| n.saturating_sub(1));
+        self.fs_info_dirty = true;
+        Ok(cluster)
+    }
+
+    /// Free the given clusters.
+    fn free_clusters(&mut self, clusters: &[u32]) -> Result<()> {
+        for &cluster in clusters {
+            self.set_fat_entry(cluster, FREE_CLUSTER)?;
+        }
+        self.free_count = self.free_count.map(|n| n + clusters.len() as u32);
+        self.fs_info_dirty = true;
+        Ok(())
+    }
+
+    fn zero_cluster(&mut self, cluster: u32) -> Result<()> {
+        let zeroes = vec![0; self.layout.cluster_size as usize];
+        Ok(write_bytes(
+            self.device.as_ref(),
+            self.layout.cluster_offset(cluster),
+            &zeroes,
+        )?)
+    }
+
+    /// Make the chain starting at `*first` exactly `count` clusters long, updating `*first` if
+    /// the chain is created or removed.
+    fn resize_chain(&mut self, first: &mut u32, count: usize) -> Result<()> {
+        let mut clusters = self.chain(*first)?;
+        if count < clusters.len() {
+            let freed = clusters.split_off(count);
+            match clusters.last() {
+                Some(&last) => self.set_fat_entry(last, self.end_of_chain())?,
+                None => *first = FREE_CLUSTER,
+            }
+            self.free_clusters(&freed)?;
+        }
+        while clusters.len() < count {
+            let cluster = self.allocate_cluster(clusters.last().copied())?;
+            if clusters.is_empty() {
+                *first = cluster;
+            }
+            clusters.push(cluster);
+        }
+        Ok(())
+    }
+
+    /// Read or write the byte range `offset..(offset + len)` of the chain starting at `first`,
+    /// one contiguous piece at a time.
+    fn for_each_piece(
+        &mut self,
+        first: u32,
+        offset: u64,
+        len: usize,
+        mut f: impl FnMut(&dyn BlockDevice, u64, usize, usize) -> Result<()>,
+    ) -> Result<()> {
+        let cluster_size = self.layout.cluster_size;
+        let clusters = self.chain(first)?;
+        let mut done = 0;
+        while done < len {
+            let position = offset + done as u64;
+            let index = (position / cluster_size) as usize;
+            let within = position % cluster_size;
+            let cluster = *clusters.get(index).ok_or(VfsError::Corrupt)?;
+            let piece = (len - done).min((cluster_size - within) as usize);
+            f(
+                self.device.as_ref(),
+                self.layout.cluster_offset(cluster) + within,
+                done,
+                piece,
+            )?;
+            done += piece;
+        }
+        Ok(())
+    }
+
+    /// Every slot of a directory, with its device offset.
+    fn dir_slots(&mut self, dir: DirLocation) -> Result<Vec<(u64, RawEntry)>> {
+        let regions = match dir {
+            DirLocation::FixedRoot => Vec::from([(
+                self.layout.root_dir_start,
+                self.layout.root_entries * ENTRY_SIZE as u64,
+            )]),
+            DirLocation::Chain(first) => self
+                .chain(first)?
+                .into_iter()
+                .map(|c| (self.layout.cluster_offset(c), self.layout.cluster_size))
+                .collect(),
+        };
+
+        let mut slots = Vec::new();
+        for (start, len) in regions {
+            let mut data = vec![0; len as usize];
+            read_bytes(self.device.as_ref(), start, &mut data)?;
+            for (i, raw) in data.chunks_exact(ENTRY_SIZE).enumerate() {
+                let raw: RawEntry = raw.try_into().expect("chunks are entry sized");
+                slots.push((start + (i * ENTRY_SIZE) as u64, raw));
+            }
+        }
+        Ok(slots)
+    }
+
+    /// Every entry of a directory except `.`, `..` and the volume label.
+    fn entries(&mut self, dir: DirLocation) -> Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        let mut long_name = LongNameBuilder::default();
+        let mut long_start = 0;
+        for (slot, (offset, raw)) in self.dir_slots(dir)?.into_iter().enumerate() {
+            match raw[0] {
+                dir::END => break,
+                dir::DELETED => {
+                    long_name.reset();
+                    continue;
+                }
+                _ => {}
+            }
+            if dir::is_long_name(&raw) {
+                if dir::starts_long_name(&raw) {
+                    long_start = slot;
+                }
+                long_name.push(&raw);
+                continue;
+            }
+
+            let short = ShortEntry::parse(&raw);
+            let name = long_name.finish(&short);
+            if (short.attr & attr::VOLUME_ID) != 0 || short.name == *DOT || short.name == *DOT_DOT {
+                continue;
+            }
+            let first_slot = if name.is_some() { long_start } else { slot };
+            entries.push(Entry {
+                name: name.unwrap_or_else(|| short.display_name()),
+                short,
+                location: EntryLocation {
+                    dir,
+                    first_slot,
+                    slots: slot - first_slot + 1,
+                    offset,
+                },
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Find the entry called `name`, which is matched case-insensitively against both the long
+    /// and the short name.
+    fn find(&mut self, dir: DirLocation, name: &str) -> Result<Option<Entry>> {
+        Ok(self.entries(dir)?.into_iter().find(|e| {
+            e.name.eq_ignore_ascii_case(name) || e.short.display_name().eq_ignore_ascii_case(name)
+        }))
+    }
+
+    /// Add an entry called `name` to a directory, with the other fields taken from `short`.
+    fn add_entry(&mut self, dir: DirLocation, name: &str, short: ShortEntry) -> Result<Entry> {
+        let existing = self.entries(dir)?;
+        let taken = |short_name: &[u8; dir::SHORT_NAME_LEN]| {
+            existing.iter().any(|e| e.short.name == *short_name)
+        };
+
+        let mut short = short;
+        let mut raws = match dir::exact_short_name(name).filter(|s| !taken(s)) {
+            Some(short_name) => {
+                short.name = short_name;
+                Vec::new()
+            }
+            None => {
+                short.name = (1..=MAX_SHORT_NAME_TAIL)
+                    .map(|n| dir::generated_short_name(name, n))
+                    .find(|s| !taken(s))
+                    .ok_or(VfsError::NoSpace)?;
+                dir::long_name_entries(name, &short.name)
+            }
+        };
+        raws.push(short.encode());
+
+        let (first_slot, slots) = self.free_slots(dir, raws.len())?;
+        for (&(offset, _), raw) in slots.iter().zip(raws.iter()) {
+            write_bytes(self.device.as_ref(), offset, raw)?;
+        }
+        Ok(Entry {
+            name: name.into(),
+            short,
+            location: EntryLocation {
+                dir,
+                first_slot,
+                slots: raws.len(),
+                offset: slots[raws.len() - 1].0,
+            },
+        })
+    }
+
+    /// Find `count` consecutive free slots in a directory, growing it if needed. Returns the
+    /// index of the first one along with the slots.
+    fn free_slots(
+        &mut self,
+        dir: DirLocation,
+        count: usize,
+    ) -> Result<(usize, Vec<(u64, RawEntry)>)> {
+        loop {
+            let slots = self.dir_slots(dir)?;
+            let mut run = 0;
+            let mut after_end = false;
+            for (i, (_, raw)) in slots.iter().enumerate() {
+                after_end |= raw[0] == dir::END;
+                if after_end || raw[0] == dir::DELETED {
+                    run += 1;
+                    if run == count {
+                        let first = i + 1 - count;
+                        return Ok((first, slots[first..=i].to_vec()));
+                    }
+                } else {
+                    run = 0;
+                }
+            }
+
+            match dir {
+                DirLocation::FixedRoot => return Err(VfsError::NoSpace),
+                DirLocation::Chain(first) => {
+                    let last = *self.chain(first)?.last().ok_or(VfsError::Corrupt)?;
+                    self.allocate_cluster(Some(last))?;
+                }
+            }
+        }
+    }
+
+    /// Mark the slots of an entry as deleted.
+    fn remove_entry(&mut self, location: &EntryLocation) -> Result<()> {
+        let slots = self.dir_slots(location.dir)?;
+        let range = location.first_slot..(location.first_slot + location.slots);
+        for &(offset, _) in slots.get(range).ok_or(VfsError::Corrupt)? {
+            write_bytes(self.device.as_ref(), offset, &[dir::DELETED])?;
+        }
+        Ok(())
+    }
+
+    /// Write the cluster, size and attributes of `node` back to its directory entry.
+    fn write_node(&mut self, node: &Node) -> Result<()> {
+        let Some(location) = node.entry else {
+            return Ok(());
+        };
+        let mut raw = [0; ENTRY_SIZE];
+        read_bytes(self.device.as_ref(), location.offset, &mut raw)?;
+        let mut short = ShortEntry::parse(&raw);
+        short.attr = node.attr;
+        short.cluster = node.cluster;
+        short.size = if node.is_dir() { 0 } else { node.size };
+        Ok(write_bytes(
+            self.device.as_ref(),
+            location.offset,
+            &short.encode(),
+        )?)
+    }
+
+    /// The directory location of a directory node.
+    fn dir_location(&self, node: &Node) -> Result<DirLocation> {
+        if !node.is_dir() {
+            return Err(VfsError::NotADirectory);
+        }
+        node.check_linked()?;
+        if node.ino == ROOT_INO && self.layout.fat_type != FatType::Fat32 {
+            Ok(DirLocation::FixedRoot)
+        } else {
+            Ok(DirLocation::Chain(node.cluster))
+        }
+    }
+
+    /// The cluster `..` entries of subdirectories of a directory node refer to.
+    fn parent_cluster(&self, node: &Node) -> u32 {
+        if node.ino == ROOT_INO {
+            0
+        } else {
+            node.cluster
+        }
+    }
+
+    /// The live inode for `entry`, creating it if needed.
+    fn inode(&mut self, shared: &Arc<Shared>, entry: &Entry) -> Arc<FatInode> {
+        let ino = entry.location.offset;
+        if let Some(inode) = self.inodes.get(&ino).and_then(Weak::upgrade) {
+            return inode;
+        }
+
+        let inode = Arc::new(FatInode {
+            fs: Arc::clone(shared),
+            node: Mutex::new(Node {
+                ino,
+                attr: entry.short.attr,
+                cluster: entry.short.cluster,
+                size: entry.short.size,
+                entry: Some(entry.location),
+            }),
+        });
+        self.inodes.retain(|_, inode| inode.strong_count() > 0);
+        self.inodes.insert(ino, Arc::downgrade(&inode));
+        inode
+    }
+
+    /// Remove the entry of a file or empty directory and free its clusters.
+    fn delete(&mut self, entry: &Entry) -> Result<()> {
+        let ino = entry.location.offset;
+        if entry.short.is_dir()
+            && !self
+                .entries(DirLocation::Chain(entry.short.cluster))?
+                .is_empty()
+        {
+            return Err(VfsError::DirectoryNotEmpty);
+        }
+
+        self.remove_entry(&entry.location)?;
+        let clusters = self.chain(entry.short.cluster)?;
+        self.free_clusters(&clusters)?;
+
+        if let Some(inode) = self.inodes.remove(&ino).and_then(|i| i.upgrade()) {
+            let mut node = inode.node.lock();
+            node.entry = None;
+            node.cluster = FREE_CLUSTER;
+            node.size = 0;
+        }
+        Ok(())
+    }
+}
+
+/// Pack the 12-bit FAT entry `value` of `cluster` into the 16 bits `old` read at its offset.
+fn fat12_pack(old: u16, cluster: u32, value: u16) -> u16 {
+    if cluster.is_multiple_of(2) {
+        (old & 0xF000) | (value & 0x0FFF)
+    } else {
+        (old & 0x000F) | (value << 4)
+    }
+}
+
+/// A file or directory on a [FatFs].
+struct FatInode {
+    fs: Arc<Shared>,
+    // Only locked while holding the volume lock
+    node: Mutex<Node>,
+}
+impl FatInode {
+    /// Downcast `inode` to a [FatInode] belonging to the same filesystem as `self`.
+    fn same_fs<'a>(&self, inode: &'a Arc<dyn Inode>) -> Result<&'a FatInode> {
+        inode
+            .as_any()
+            .downcast_ref::<FatInode>()
+            .filter(|other| Arc::ptr_eq(&self.fs, &other.fs))
+            .ok_or(VfsError::CrossDevice)
+    }
+
+    /// Set the size of the file to `len`, zero-filling any new bytes.
+    fn resize(volume: &mut Volume, node: &mut Node, len: u64) -> Result<()> {
+        let len = u32::try_from(len).map_err(|_| VfsError::NoSpace)?;
+        let cluster_size = volume.layout.cluster_size;
+        let old_clusters = u64::from(node.size).div_ceil(cluster_size);
+
+        volume.resize_chain(
+            &mut node.cluster,
+            u64::from(len).div_ceil(cluster_size) as usize,
+        )?;
+        // New clusters are zeroed when allocated, but the tail of the old last one isn't
+        let zero_end = u64::from(len).min(old_clusters * cluster_size);
+        if u64::from(node.size) < zero_end {
+            let start = u64::from(node.size);
+            let zeroes = vec![0; (zero_end - start) as usize];
+            volume.for_each_piece(
+                node.cluster,
+                start,
+                zeroes.len(),
+                |device, offset, done, len| {
+                    Ok(write_bytes(device, offset, &zeroes[done..(done + len)])?)
+                },
+            )?;
+        }
+
+        node.size = len;
+        volume.write_node(node)
+    }
+}
+impl Inode for FatInode {
+    fn metadata(&self) -> Result<Metadata> {
+        let mut volume = self.fs.volume.lock();
+        let node = self.node.lock();
+        let size = match volume.dir_location(&node) {
+            Ok(DirLocation::FixedRoot) => volume.layout.root_entries * ENTRY_SIZE as u64,
+            Ok(DirLocation::Chain(first)) => {
+                volume.chain(first)?.len() as u64 * volume.layout.cluster_size
+            }
+            Err(_) => u64::from(node.size),
+        };
+
+        let mut permissions = if node.is_dir() {
+            Permissions::DEFAULT_DIR
+        } else {
+            Permissions::DEFAULT_FILE
+        };
+        if (node.attr & attr::READ_ONLY) != 0 {
+            permissions.0 &= !0o222;
+        }
+
+        Ok(Metadata {
+            ino: node.ino,
+            kind: node.kind(),
+            size,
+            permissions,
+            nlink: if node.is_dir() { 2 } else { 1 },
+            uid: 0,
+            gid: 0,
+        })
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let mut volume = self.fs.volume.lock();
+        let node = self.node.lock();
+        if node.is_dir() {
+            return Err(VfsError::IsADirectory);
+        }
+
+        let size = u64::from(node.size);
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min((size - offset) as usize);
+        volume.for_each_piece(node.cluster, offset, len, |device, position, done, len| {
+            Ok(read_bytes(device, position, &mut buf[done..(done + len)])?)
+        })?;
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+        let mut volume = self.fs.volume.lock();
+        let mut node = self.node.lock();
+        if node.is_dir() {
+            return Err(VfsError::IsADirectory);
+        }
+        node.check_linked()?;
+
+        let end = offset + buf.len() as u64;
+        if end > u64::from(node.size) {
+            Self::resize(&mut volume, &mut node, end)?;
+        }
+        volume.for_each_piece(
+            node.cluster,
+            offset,
+            buf.len(),
+            |device, position, done, len| {
+                Ok(write_bytes(device, position, &buf[done..(done + len)])?)
+            },
+        )?;
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, len: u64) -> Result<()> {
+        let mut volume = self.fs.volume.lock();
+        let mut node = self.node.lock();
+        if node.is_dir() {
+            return Err(VfsError::IsADirectory);
+        }
+        node.check_linked()?;
+        Self::resize(&mut volume, &mut node, len)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let mut volume = self.fs.volume.lock();
+        let dir = volume.dir_location(&self.node.lock())?;
+        let entry = volume.find(dir, name)?.ok_or(VfsError::NotFound)?;
+        Ok(volume.inode(&self.fs, &entry))
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        let mut volume = self.fs.volume.lock();
+        let dir = volume.dir_location(&self.node.lock())?;
+        Ok(volume
+            .entries(dir)?
+            .into_iter()
+            .map(|entry| DirEntry {
+                name: entry.name,
+                ino: entry.location.offset,
+                kind: if entry.short.is_dir() {
+                    InodeKind::Directory
+                } else {
+                    InodeKind::File
+                },
+            })
+            .collect())
+    }
+
+    fn create(
+        &self,
+        name: &str,
+        kind: InodeKind,
+        permissions: Permissions,
+    ) -> Result<Arc<dyn Inode>> {
+        path::validate_name(name)?;
+        let mut volume = self.fs.volume.lock();
+        let (dir, parent_cluster) = {
+            let node = self.node.lock();
+            (volume.dir_location(&node)?, volume.parent_cluster(&node))
+        };
+        if volume.find(dir, name)?.is_some() {
+            return Err(VfsError::AlreadyExists);
+        }
+
+        let mut attributes = match kind {
+            InodeKind::File => attr::ARCHIVE,
+            InodeKind::Directory => attr::DIRECTORY,
+            _ => return Err(VfsError::Unsupported),
+        };
+        if (permissions.0 & 0o200) == 0 {
+            attributes |= attr::READ_ONLY;
+        }
+
+        let cluster = if kind == InodeKind::Directory {
+            let cluster = volume.allocate_cluster(None)?;
+            let dot = ShortEntry::new(*DOT, attr::DIRECTORY, cluster);
+            let dot_dot = ShortEntry::new(*DOT_DOT, attr::DIRECTORY, parent_cluster);
+            let start = volume.layout.cluster_offset(cluster);
+            let device = Arc::clone(&volume.device);
+            write_bytes(device.as_ref(), start, &dot.encode())?;
+            write_bytes(
+                device.as_ref(),
+                start + ENTRY_SIZE as u64,
+                &dot_dot.encode(),
+            )?;
+            cluster
+        } else {
+            FREE_CLUSTER
+        };
+
+        let entry = match volume.add_entry(dir, name, ShortEntry::new([0; 11], attributes, cluster))
+        {
+            Ok(entry) => entry,
+            Err(e) => {
+                if cluster != FREE_CLUSTER {
+                    volume.free_clusters(&[cluster])?;
+                }
+                return Err(e);
+            }
+        };
+        Ok(volume.inode(&self.fs, &entry))
+    }
+
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>> {
+        Err(VfsError::Unsupported)
+    }
+
+    fn link(&self, _name: &str, _target: &Arc<dyn Inode>) -> Result<()> {
+        Err(VfsError::Unsupported)
+    }
+
+    fn unlink(&self, name: &str) -> Result<()> {
+        let mut volume = self.fs.volume.lock();
+        let dir = volume.dir_location(&self.node.lock())?;
+        let entry = volume.find(dir, name)?.ok_or(VfsError::NotFound)?;
+        volume.delete(&entry)
+    }
+
+    fn rename(&self, old_name: &str, new_parent: &Arc<dyn Inode>, new_name: &str) -> Result<()> {
+        path::validate_name(new_name)?;
+        let new_parent = self.same_fs(new_parent)?;
+
+        let mut volume = self.fs.volume.lock();
+        let old_dir = volume.dir_location(&self.node.lock())?;
+        let (new_dir, new_parent_cluster) = {
+            let node = new_parent.node.lock();
+            (volume.dir_location(&node)?, volume.parent_cluster(&node))
+        };
+
+        let entry = volume.find(old_dir, old_name)?.ok_or(VfsError::NotFound)?;
+        if let Some(existing) = volume.find(new_dir, new_name)? {
+            if existing.location == entry.location {
+                if existing.name == new_name {
+                    return Ok(());
+                }
+                // Only the case changes, so the old entry is replaced below
+            } else {
+                match (existing.short.is_dir(), entry.short.is_dir()) {
+                    (true, false) => return Err(VfsError::IsADirectory),
+                    (false, true) => return Err(VfsError::NotADirectory),
+                    _ => volume.delete(&existing)?,
+                }
+            }
+        }
+
+        let new_entry = volume.add_entry(new_dir, new_name, entry.short)?;
+        volume.remove_entry(&entry.location)?;
+
+        // A moved directory's `..` has to follow it
+        if entry.short.is_dir() && old_dir != new_dir {
+            let slots = volume.dir_slots(DirLocation::Chain(entry.short.cluster))?;
+            let &(offset, raw) = slots.get(1).ok_or(VfsError::Corrupt)?;
+            let mut dot_dot = ShortEntry::parse(&raw);
+            if dot_dot.name != *DOT_DOT {
+                return Err(VfsError::Corrupt);
+            }
+            dot_dot.cluster = new_parent_cluster;
+            write_bytes(volume.device.as_ref(), offset, &dot_dot.encode())?;
+        }
+
+        let old_ino = entry.location.offset;
+        if let Some(inode) = volume.inodes.remove(&old_ino).and_then(|i| i.upgrade()) {
+            let new_ino = new_entry.location.offset;
+            let mut node = inode.node.lock();
+            node.ino = new_ino;
+            node.entry = Some(new_entry.location);
+            drop(node);
+            volume.inodes.insert(new_ino, Arc::downgrade(&inode));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn fat12_packing() {
+        // Clusters 2 and 3 share the middle byte of 0x123 and 0x456
+        let even = fat12_pack(0xFFFF, 2, 0x123);
+        assert_eq!(even, 0xF123);
+        let odd = fat12_pack(0xFFFF, 3, 0x456);
+        assert_eq!(odd, 0x456F);
+    }
+}
@@ -0,0 +1,240 @@
+//! The BIOS parameter block at the start of every FAT volume.
+
+use alloc::string::String;
+
+use crate::vfs::{Result, VfsError};
+
+const BOOT_SIGNATURE_OFFSET: usize = 510;
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+const BYTES_PER_SECTOR: usize = 11;
+const SECTORS_PER_CLUSTER: usize = 13;
+const RESERVED_SECTORS: usize = 14;
+const NUM_FATS: usize = 16;
+const ROOT_ENTRIES: usize = 17;
+const TOTAL_SECTORS_16: usize = 19;
+const FAT_SIZE_16: usize = 22;
+const TOTAL_SECTORS_32: usize = 32;
+const FAT_SIZE_32: usize = 36;
+const ROOT_CLUSTER: usize = 44;
+const FS_INFO_SECTOR: usize = 48;
+const LABEL_16: usize = 43;
+const LABEL_32: usize = 71;
+const LABEL_LEN: usize = 11;
+
+// Cluster count thresholds from the Microsoft FAT specification
+const MAX_FAT12_CLUSTERS: u32 = 4084;
+const MAX_FAT16_CLUSTERS: u32 = 65524;
+
+/// Size of the boot sector in bytes.
+pub const BOOT_SECTOR_SIZE: usize = 512;
+
+/// Size of a directory entry in bytes.
+pub const DIR_ENTRY_SIZE: u64 = 32;
+
+/// The three FAT variants, named after the width of a FAT entry.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[allow(missing_docs)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+/// The on-disk layout of a FAT volume, in bytes from the start of the device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    /// FAT variant, decided by the number of clusters.
+    pub fat_type: FatType,
+    /// Bytes per sector.
+    pub sector_size: u64,
+    /// Bytes per cluster.
+    pub cluster_size: u64,
+    /// Start of the first FAT.
+    pub fat_start: u64,
+    /// Size of a single FAT.
+    pub fat_size: u64,
+    /// Number of FAT copies.
+    pub num_fats: u8,
+    /// Start of the fixed root directory (FAT12/16 only).
+    pub root_dir_start: u64,
+    /// Number of entries in the fixed root directory (0 on FAT32).
+    pub root_entries: u64,
+    /// First cluster of the root directory (FAT32 only).
+    pub root_cluster: u32,
+    /// Start of cluster 2, the first data cluster.
+    pub data_start: u64,
+    /// Number of data clusters.
+    pub cluster_count: u32,
+    /// Start of the FSInfo sector (FAT32 only).
+    pub fs_info: Option<u64>,
+    /// Volume label, without trailing spaces.
+    pub label: String,
+}
+impl Layout {
+    /// Parse the boot sector of a FAT volume.
+    pub fn parse(boot: &[u8; BOOT_SECTOR_SIZE]) -> Result<Self> {
+        if boot[BOOT_SIGNATURE_OFFSET..] != BOOT_SIGNATURE {
+            return Err(VfsError::Corrupt);
+        }
+
+        let sector_size = u64::from(u16_at(boot, BYTES_PER_SECTOR));
+        let sectors_per_cluster = u64::from(boot[SECTORS_PER_CLUSTER]);
+        let reserved = u64::from(u16_at(boot, RESERVED_SECTORS));
+        let num_fats = boot[NUM_FATS];
+        let root_entries = u64::from(u16_at(boot, ROOT_ENTRIES));
+        if !matches!(sector_size, 512 | 1024 | 2048 | 4096)
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved == 0
+            || num_fats == 0
+        {
+            return Err(VfsError::Corrupt);
+        }
+
+        let fat_sectors = match u16_at(boot, FAT_SIZE_16) {
+            0 => u64::from(u32_at(boot, FAT_SIZE_32)),
+            size => u64::from(size),
+        };
+        let total_sectors = match u16_at(boot, TOTAL_SECTORS_16) {
+            0 => u64::from(u32_at(boot, TOTAL_SECTORS_32)),
+            total => u64::from(total),
+        };
+        let root_dir_sectors = (root_entries * DIR_ENTRY_SIZE).div_ceil(sector_size);
+        let data_sector = reserved + u64::from(num_fats) * fat_sectors + root_dir_sectors;
+        let cluster_count = total_sectors
+            .checked_sub(data_sector)
+            .ok_or(VfsError::Corrupt)?
+            / sectors_per_cluster;
+        let cluster_count = u32::try_from(cluster_count).map_err(|_| VfsError::Corrupt)?;
+
+        let fat_type = if cluster_count <= MAX_FAT12_CLUSTERS {
+            FatType::Fat12
+        } else if cluster_count <= MAX_FAT16_CLUSTERS {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        };
+        if (fat_type == FatType::Fat32) != (root_entries == 0) {
+            return Err(VfsError::Corrupt);
+        }
+        // The FATs must be able to describe every cluster
+        let fat_bits = match fat_type {
+            FatType::Fat12 => 12,
+            FatType::Fat16 => 16,
+            FatType::Fat32 => 32,
+        };
+        if (u64::from(cluster_count) + 2) * fat_bits > fat_sectors * sector_size * 8 {
+            return Err(VfsError::Corrupt);
+        }
+
+        let (root_cluster, fs_info, label_offset) = match fat_type {
+            FatType::Fat32 => (
+                u32_at(boot, ROOT_CLUSTER),
+                match u16_at(boot, FS_INFO_SECTOR) {
+                    0 | 0xFFFF => None,
+                    sector => Some(u64::from(sector) * sector_size),
+                },
+                LABEL_32,
+            ),
+            _ => (0, None, LABEL_16),
+        };
+        let label = boot[label_offset..(label_offset + LABEL_LEN)]
+            .iter()
+            .map(|&b| char::from(b))
+            .collect::<String>()
+            .trim_end()
+            .into();
+
+        Ok(Self {
+            fat_type,
+            sector_size,
+            cluster_size: sectors_per_cluster * sector_size,
+            fat_start: reserved * sector_size,
+            fat_size: fat_sectors * sector_size,
+            num_fats,
+            root_dir_start: (reserved + u64::from(num_fats) * fat_sectors) * sector_size,
+            root_entries,
+            root_cluster,
+            data_start: data_sector * sector_size,
+            cluster_count,
+            fs_info,
+            label,
+        })
+    }
+
+    /// Byte offset of the first byte of `cluster`.
+    pub fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.data_start + u64::from(cluster - 2) * self.cluster_size
+    }
+
+    /// Check if `cluster` is a valid data cluster number.
+    pub fn is_data_cluster(&self, cluster: u32) -> bool {
+        (2..(self.cluster_count + 2)).contains(&cluster)
+    }
+}
+
+/// Read the little-endian `u16` at `offset`.
+pub fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+/// Read the little-endian `u32` at `offset`.
+pub fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The boot sector `mkfs.fat -F 12 -C img 1440` writes.
+    fn floppy() -> [u8; BOOT_SECTOR_SIZE] {
+        let mut boot = [0; BOOT_SECTOR_SIZE];
+        boot[BYTES_PER_SECTOR..][..2].copy_from_slice(&512u16.to_le_bytes());
+        boot[SECTORS_PER_CLUSTER] = 1;
+        boot[RESERVED_SECTORS..][..2].copy_from_slice(&1u16.to_le_bytes());
+        boot[NUM_FATS] = 2;
+        boot[ROOT_ENTRIES..][..2].copy_from_slice(&224u16.to_le_bytes());
+        boot[TOTAL_SECTORS_16..][..2].copy_from_slice(&2880u16.to_le_bytes());
+        boot[FAT_SIZE_16..][..2].copy_from_slice(&9u16.to_le_bytes());
+        boot[LABEL_16..][..LABEL_LEN].copy_from_slice(b"FLOPPY     ");
+        boot[BOOT_SIGNATURE_OFFSET..].copy_from_slice(&BOOT_SIGNATURE);
+        boot
+    }
+
+    #[test_case]
+    fn parse_floppy() {
+        let layout = Layout::parse(&floppy()).unwrap();
+        assert_eq!(layout.fat_type, FatType::Fat12);
+        assert_eq!(layout.fat_start, 512);
+        assert_eq!(layout.root_dir_start, 19 * 512);
+        assert_eq!(layout.data_start, 33 * 512);
+        assert_eq!(layout.cluster_count, 2847);
+        assert_eq!(layout.label, "FLOPPY");
+        assert_eq!(layout.cluster_offset(2), layout.data_start);
+        assert!(!layout.is_data_cluster(1));
+        assert!(layout.is_data_cluster(2848));
+        assert!(!layout.is_data_cluster(2849));
+    }
+
+    #[test_case]
+    fn reject_bad_boot_sector() {
+        let mut boot = floppy();
+        boot[BOOT_SIGNATURE_OFFSET] = 0;
+        assert_eq!(Layout::parse(&boot), Err(VfsError::Corrupt));
+
+        let mut boot = floppy();
+        boot[SECTORS_PER_CLUSTER] = 3;
+        assert_eq!(Layout::parse(&boot), Err(VfsError::Corrupt));
+
+        // FATs too small for the cluster count
+        let mut boot = floppy();
+        boot[FAT_SIZE_16..][..2].copy_from_slice(&1u16.to_le_bytes());
+        assert_eq!(Layout::parse(&boot), Err(VfsError::Corrupt));
+    }
+}
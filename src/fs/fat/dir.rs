@@ -0,0 +1,398 @@
+//! Raw directory entries, long file names and short (8.3) names.
+
+use alloc::{string::String, vec::Vec};
+
+use super::bpb::{u16_at, u32_at};
+
+/// Size of a directory entry in bytes.
+pub const ENTRY_SIZE: usize = 32;
+
+/// First name byte of a deleted entry.
+pub const DELETED: u8 = 0xE5;
+/// First name byte of the entry after the last one in use.
+pub const END: u8 = 0x00;
+// Stands in for a real 0xE5 first byte
+const KANJI_E5: u8 = 0x05;
+
+/// Entry attribute bits.
+#[allow(missing_docs)]
+pub mod attr {
+    pub const READ_ONLY: u8 = 0x01;
+    pub const HIDDEN: u8 = 0x02;
+    pub const SYSTEM: u8 = 0x04;
+    pub const VOLUME_ID: u8 = 0x08;
+    pub const DIRECTORY: u8 = 0x10;
+    pub const ARCHIVE: u8 = 0x20;
+    pub const LONG_NAME: u8 = READ_ONLY | HIDDEN | SYSTEM | VOLUME_ID;
+}
+
+const NAME: usize = 0;
+const ATTR: usize = 11;
+const NT_RESERVED: usize = 12;
+const CREATE_TIME: usize = 14;
+const CREATE_DATE: usize = 16;
+const ACCESS_DATE: usize = 18;
+const CLUSTER_HIGH: usize = 20;
+const WRITE_TIME: usize = 22;
+const WRITE_DATE: usize = 24;
+const CLUSTER_LOW: usize = 26;
+const FILE_SIZE: usize = 28;
+
+// Windows NT stores all-lowercase base names and extensions as uppercase plus these flags
+const NT_LOWER_BASE: u8 = 0x08;
+const NT_LOWER_EXT: u8 = 0x10;
+
+// There's no clock yet, so everything is created on 1980-01-01
+const DEFAULT_DATE: u16 = (1 << 5) | 1;
+
+const LFN_ORDER: usize = 0;
+const LFN_LAST: u8 = 0x40;
+const LFN_ORDER_MASK: u8 = 0x1F;
+const LFN_CHECKSUM: usize = 13;
+// Where the 13 UCS-2 characters of an LFN entry live
+const LFN_CHAR_OFFSETS: [usize; 13] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+/// Characters stored in each long file name entry.
+pub const LFN_CHARS: usize = LFN_CHAR_OFFSETS.len();
+const LFN_PADDING: u16 = 0xFFFF;
+
+/// Length of a short name, without the dot.
+pub const SHORT_NAME_LEN: usize = 11;
+const BASE_LEN: usize = 8;
+
+// Characters allowed in short names besides letters and digits
+const SHORT_NAME_SPECIAL: &[u8] = b"$%'-_@~`!(){}^#&";
+
+/// A raw 32-byte directory entry.
+pub type RawEntry = [u8; ENTRY_SIZE];
+
+/// A decoded short directory entry.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ShortEntry {
+    /// Name and extension, space padded.
+    pub name: [u8; SHORT_NAME_LEN],
+    /// Attribute bits.
+    pub attr: u8,
+    /// Case flags set by Windows NT.
+    pub nt_reserved: u8,
+    /// First cluster, or 0 for an empty file.
+    pub cluster: u32,
+    /// Size in bytes (always 0 for directories).
+    pub size: u32,
+}
+impl ShortEntry {
+    /// A new entry for an empty file or directory.
+    pub fn new(name: [u8; SHORT_NAME_LEN], attr: u8, cluster: u32) -> Self {
+        Self {
+            name,
+            attr,
+            nt_reserved: 0,
+            cluster,
+            size: 0,
+        }
+    }
+
+    /// Decode a raw entry, which must not be a long name entry.
+    pub fn parse(raw: &RawEntry) -> Self {
+        let mut name = [0; SHORT_NAME_LEN];
+        name.copy_from_slice(&raw[NAME..(NAME + SHORT_NAME_LEN)]);
+        if name[0] == KANJI_E5 {
+            name[0] = DELETED;
+        }
+        Self {
+            name,
+            attr: raw[ATTR],
+            nt_reserved: raw[NT_RESERVED],
+            cluster: (u32::from(u16_at(raw, CLUSTER_HIGH)) << 16)
+                | u32::from(u16_at(raw, CLUSTER_LOW)),
+            size: u32_at(raw, FILE_SIZE),
+        }
+    }
+
+    /// Encode the entry.
+    pub fn encode(&self) -> RawEntry {
+        let mut raw = [0; ENTRY_SIZE];
+        raw[NAME..(NAME + SHORT_NAME_LEN)].copy_from_slice(&self.name);
+        if raw[0] == DELETED {
+            raw[0] = KANJI_E5;
+        }
+        raw[ATTR] = self.attr;
+        raw[NT_RESERVED] = self.nt_reserved;
+        for offset in [CREATE_DATE, ACCESS_DATE, WRITE_DATE] {
+            raw[offset..(offset + 2)].copy_from_slice(&DEFAULT_DATE.to_le_bytes());
+        }
+        for offset in [CREATE_TIME, WRITE_TIME] {
+            raw[offset..(offset + 2)].copy_from_slice(&0u16.to_le_bytes());
+        }
+        raw[CLUSTER_HIGH..(CLUSTER_HIGH + 2)]
+            .copy_from_slice(&((self.cluster >> 16) as u16).to_le_bytes());
+        raw[CLUSTER_LOW..(CLUSTER_LOW + 2)].copy_from_slice(&(self.cluster as u16).to_le_bytes());
+        raw[FILE_SIZE..(FILE_SIZE + 4)].copy_from_slice(&self.size.to_le_bytes());
+        raw
+    }
+
+    /// Check if this is a directory.
+    pub fn is_dir(&self) -> bool {
+        (self.attr & attr::DIRECTORY) != 0
+    }
+
+    /// The name as displayed when there's no long name, e.g. `README.TXT`.
+    pub fn display_name(&self) -> String {
+        let part = |bytes: &[u8], lower: bool| -> String {
+            bytes
+                .iter()
+                .map(|&b| char::from(if lower { b.to_ascii_lowercase() } else { b }))
+                .collect::<String>()
+                .trim_end()
+                .into()
+        };
+        let mut name = part(
+            &self.name[..BASE_LEN],
+            (self.nt_reserved & NT_LOWER_BASE) != 0,
+        );
+        let ext = part(
+            &self.name[BASE_LEN..],
+            (self.nt_reserved & NT_LOWER_EXT) != 0,
+        );
+        if !ext.is_empty() {
+            name.push('.');
+            name.push_str(&ext);
+        }
+        name
+    }
+}
+
+/// Check if a raw entry is part of a long file name.
+pub fn is_long_name(raw: &RawEntry) -> bool {
+    (raw[ATTR] & attr::LONG_NAME) == attr::LONG_NAME
+}
+
+/// Check if a long name entry is the first one of its long name on disk.
+pub fn starts_long_name(raw: &RawEntry) -> bool {
+    (raw[LFN_ORDER] & LFN_LAST) != 0
+}
+
+/// The checksum of a short name stored in each of its long name entries.
+pub fn checksum(name: &[u8; SHORT_NAME_LEN]) -> u8 {
+    name.iter()
+        .fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
+
+/// Accumulates long name entries, which come in reverse order before their short entry.
+#[derive(Debug, Default)]
+pub struct LongNameBuilder {
+    chars: Vec<u16>,
+    checksum: u8,
+    // Order number of the next entry expected, or 0 if there's no long name in progress
+    expected: u8,
+}
+impl LongNameBuilder {
+    /// Feed the next long name entry.
+    pub fn push(&mut self, raw: &RawEntry) {
+        let order = raw[LFN_ORDER];
+        if (order & LFN_LAST) != 0 {
+            let count = order & LFN_ORDER_MASK;
+            self.chars = alloc::vec![0; usize::from(count) * LFN_CHARS];
+            self.checksum = raw[LFN_CHECKSUM];
+            self.expected = count;
+        }
+        if self.expected == 0
+            || (order & LFN_ORDER_MASK) != self.expected
+            || raw[LFN_CHECKSUM] != self.checksum
+        {
+            // Orphaned or out of order
+            self.reset();
+            return;
+        }
+
+        let start = usize::from(self.expected - 1) * LFN_CHARS;
+        for (i, &offset) in LFN_CHAR_OFFSETS.iter().enumerate() {
+            self.chars[start + i] = u16_at(raw, offset);
+        }
+        self.expected -= 1;
+    }
+
+    /// Finish with the short entry that follows. Returns the long name if it's complete and
+    /// belongs to `short`.
+    pub fn finish(&mut self, short: &ShortEntry) -> Option<String> {
+        let complete = self.expected == 0 && !self.chars.is_empty();
+        let chars = core::mem::take(&mut self.chars);
+        self.expected = 0;
+        if !complete || self.checksum != checksum(&short.name) {
+            return None;
+        }
+
+        let len = chars
+            .iter()
+            .position(|&c| c == 0 || c == LFN_PADDING)
+            .unwrap_or(chars.len());
+        char::decode_utf16(chars[..len].iter().copied())
+            .collect::<Result<String, _>>()
+            .ok()
+    }
+
+    /// Forget any long name in progress.
+    pub fn reset(&mut self) {
+        self.chars.clear();
+        self.expected = 0;
+    }
+}
+
+/// Encode the long name entries for `name`, in the order they're stored on disk.
+pub fn long_name_entries(name: &str, short_name: &[u8; SHORT_NAME_LEN]) -> Vec<RawEntry> {
+    let mut chars: Vec<u16> = name.encode_utf16().collect();
+    let count = chars.len().div_ceil(LFN_CHARS);
+    if !chars.len().is_multiple_of(LFN_CHARS) {
+        chars.push(0);
+    }
+    chars.resize(count * LFN_CHARS, LFN_PADDING);
+
+    let sum = checksum(short_name);
+    (1..=count)
+        .rev()
+        .map(|order| {
+            let mut raw = [0; ENTRY_SIZE];
+            raw[LFN_ORDER] = order as u8 | if order == count { LFN_LAST } else { 0 };
+            raw[ATTR] = attr::LONG_NAME;
+            raw[LFN_CHECKSUM] = sum;
+            let part = &chars[((order - 1) * LFN_CHARS)..(order * LFN_CHARS)];
+            for (&c, &offset) in part.iter().zip(LFN_CHAR_OFFSETS.iter()) {
+                raw[offset..(offset + 2)].copy_from_slice(&c.to_le_bytes());
+            }
+            raw
+        })
+        .collect()
+}
+
+fn is_short_name_char(b: u8) -> bool {
+    b.is_ascii_uppercase() || b.is_ascii_digit() || SHORT_NAME_SPECIAL.contains(&b) || b >= 0x80
+}
+
+/// Pad `base` and `ext` into an 11-byte short name.
+fn pack(base: &[u8], ext: &[u8]) -> [u8; SHORT_NAME_LEN] {
+    let mut short = [b' '; SHORT_NAME_LEN];
+    short[..base.len()].copy_from_slice(base);
+    short[BASE_LEN..(BASE_LEN + ext.len())].copy_from_slice(ext);
+    short
+}
+
+/// The short name `name` can be stored as directly, if it's a valid uppercase 8.3 name.
+pub fn exact_short_name(name: &str) -> Option<[u8; SHORT_NAME_LEN]> {
+    let (base, ext) = match name.rsplit_once('.') {
+        Some((base, ext)) => (base.as_bytes(), ext.as_bytes()),
+        None => (name.as_bytes(), &[][..]),
+    };
+    let valid = |part: &[u8], max: usize| {
+        part.len() <= max && part.iter().all(|&b| b.is_ascii() && is_short_name_char(b))
+    };
+    if base.is_empty() || !valid(base, BASE_LEN) || !valid(ext, SHORT_NAME_LEN - BASE_LEN) {
+        return None;
+    }
+    Some(pack(base, ext))
+}
+
+/// Generate a short name for the long name `name` with numeric tail `~n`, e.g. `LONGFI~1TXT`.
+pub fn generated_short_name(name: &str, n: u32) -> [u8; SHORT_NAME_LEN] {
+    let convert = |part: &str| -> Vec<u8> {
+        part.bytes()
+            .filter(|&b| b != b' ' && b != b'.')
+            .map(|b| {
+                let b = b.to_ascii_uppercase();
+                if b.is_ascii() && is_short_name_char(b) {
+                    b
+                } else {
+                    b'_'
+                }
+            })
+            .collect()
+    };
+    let name = name.trim_start_matches('.');
+    let (base, ext) = match name.rsplit_once('.') {
+        Some((base, ext)) => (convert(base), convert(ext)),
+        None => (convert(name), Vec::new()),
+    };
+
+    let tail = alloc::format!("~{}", n);
+    let mut short_base = base[..base.len().min(BASE_LEN - tail.len())].to_vec();
+    if short_base.is_empty() {
+        short_base.push(b'_');
+    }
+    short_base.extend_from_slice(tail.as_bytes());
+    pack(
+        &short_base,
+        &ext[..ext.len().min(SHORT_NAME_LEN - BASE_LEN)],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn short_entry_round_trip() {
+        let mut entry = ShortEntry::new(*b"README  TXT", attr::ARCHIVE, 0x0012_3456);
+        entry.size = 1234;
+        let parsed = ShortEntry::parse(&entry.encode());
+        assert_eq!(parsed, entry);
+        assert_eq!(parsed.display_name(), "README.TXT");
+        assert!(!is_long_name(&entry.encode()));
+    }
+
+    #[test_case]
+    fn nt_lowercase_flags() {
+        let mut entry = ShortEntry::new(*b"README  TXT", 0, 0);
+        entry.nt_reserved = NT_LOWER_BASE;
+        assert_eq!(entry.display_name(), "readme.TXT");
+        entry.nt_reserved = NT_LOWER_BASE | NT_LOWER_EXT;
+        assert_eq!(entry.display_name(), "readme.txt");
+    }
+
+    #[test_case]
+    fn checksum_matches_spec() {
+        // Computed with the reference implementation from the FAT specification
+        assert_eq!(checksum(b"FOO     BAR"), 0x53);
+    }
+
+    #[test_case]
+    fn long_name_round_trip() {
+        let name = "A rather long file name.text";
+        let short = generated_short_name(name, 1);
+        let entries = long_name_entries(name, &short);
+        assert_eq!(entries.len(), 3);
+        assert!(entries.iter().all(is_long_name));
+
+        let mut builder = LongNameBuilder::default();
+        for raw in &entries {
+            builder.push(raw);
+        }
+        let short_entry = ShortEntry::new(short, 0, 0);
+        assert_eq!(builder.finish(&short_entry).as_deref(), Some(name));
+
+        // Wrong checksum
+        for raw in &entries {
+            builder.push(raw);
+        }
+        let other = ShortEntry::new(*b"OTHER      ", 0, 0);
+        assert_eq!(builder.finish(&other), None);
+    }
+
+    #[test_case]
+    fn exact_short_names() {
+        assert_eq!(exact_short_name("README.TXT"), Some(*b"README  TXT"));
+        assert_eq!(exact_short_name("KERNEL"), Some(*b"KERNEL     "));
+        assert_eq!(exact_short_name("readme.txt"), None);
+        assert_eq!(exact_short_name("TOOLONGNAME.TXT"), None);
+        assert_eq!(exact_short_name("A.B.C"), None);
+        assert_eq!(exact_short_name(".HIDDEN"), None);
+    }
+
+    #[test_case]
+    fn generated_short_names() {
+        assert_eq!(
+            generated_short_name("Long File Name.txt", 1),
+            *b"LONGFI~1TXT"
+        );
+        assert_eq!(generated_short_name("readme.txt", 2), *b"README~2TXT");
+        assert_eq!(generated_short_name(".profile", 1), *b"PROFIL~1   ");
+        assert_eq!(generated_short_name("a+b.c", 12), *b"A_B~12  C  ");
+    }
+}
@@ -0,0 +1,394 @@
+//! A filesystem of generated text files describing the running kernel, normally mounted at
+//! `/proc`.
+//!
+//! Every file is generated from scratch on each read, so its size is reported as 0 and reading it
+//! in several pieces may mix two versions of the contents. The files are:
+//!
+//! - `cpuinfo`: vendor, model and feature flags reported by `cpuid`.
+//! - `interrupts`: how often each interrupt vector has been taken.
+//! - `meminfo`: heap, physical memory and block cache usage.
+//! - `mounts`: the mount table.
+//! - `pci`: every PCI function and the driver bound to it.
+//! - `tasks`: the running tasks.
+//! - `uptime`: seconds since boot.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{any::Any, arch::x86_64::__cpuid, fmt::Write};
+
+use crate::{
+    allocator,
+    block::cache::CACHE,
+    interrupts,
+    memory::{self, PAGE_SIZE},
+    pci,
+    vfs::{self, DirEntry, FileSystem, Inode, InodeKind, Metadata, Permissions, Result, VfsError},
+};
+
+const ROOT_INO: u64 = 1;
+
+/// `r--r--r--`
+const FILE_PERMISSIONS: Permissions = Permissions(0o444);
+/// `r-xr-xr-x`
+const DIR_PERMISSIONS: Permissions = Permissions(0o555);
+
+/// Generates the contents of a file.
+type Generator = fn() -> String;
+
+/// The files in the root directory and the functions generating them, sorted by name.
+const FILES: [(&str, Generator); 7] = [
+    ("cpuinfo", cpuinfo),
+    ("interrupts", interrupt_counts),
+    ("meminfo", meminfo),
+    ("mounts", mounts),
+    ("pci", pci_devices),
+    ("tasks", tasks),
+    ("uptime", uptime),
+];
+
+/// The kernel introspection filesystem.
+pub struct ProcFs {
+    root: Arc<ProcInode>,
+}
+impl ProcFs {
+    /// Create a new instance of the filesystem.
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            root: Arc::new(ProcInode::Root),
+        })
+    }
+}
+impl FileSystem for ProcFs {
+    fn name(&self) -> &'static str {
+        "procfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::clone(&self.root) as Arc<dyn Inode>
+    }
+}
+
+enum ProcInode {
+    Root,
+    File { index: usize },
+}
+impl Inode for ProcInode {
+    fn metadata(&self) -> Result<Metadata> {
+        let (ino, kind, size, permissions, nlink) = match self {
+            Self::Root => (
+                ROOT_INO,
+                InodeKind::Directory,
+                FILES.len() as u64,
+                DIR_PERMISSIONS,
+                2,
+            ),
+            Self::File { index } => (file_ino(*index), InodeKind::File, 0, FILE_PERMISSIONS, 1),
+        };
+        Ok(Metadata {
+            ino,
+            kind,
+            size,
+            permissions,
+            nlink,
+            uid: 0,
+            gid: 0,
+        })
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let Self::File { index } = self else {
+            return Err(VfsError::IsADirectory);
+        };
+        let contents = (FILES[*index].1)();
+        let Some(rest) = usize::try_from(offset)
+            .ok()
+            .and_then(|start| contents.as_bytes().get(start..))
+        else {
+            return Ok(0);
+        };
+        let n = buf.len().min(rest.len());
+        buf[..n].copy_from_slice(&rest[..n]);
+        Ok(n)
+    }
+
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> Result<usize> {
+        match self {
+            Self::Root => Err(VfsError::IsADirectory),
+            Self::File { .. } => Err(VfsError::PermissionDenied),
+        }
+    }
+
+    fn truncate(&self, _len: u64) -> Result<()> {
+        match self {
+            Self::Root => Err(VfsError::IsADirectory),
+            Self::File { .. } => Err(VfsError::PermissionDenied),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let Self::Root = self else {
+            return Err(VfsError::NotADirectory);
+        };
+        FILES
+            .iter()
+            .position(|&(file, _)| file == name)
+            .map(|index| Arc::new(Self::File { index }) as Arc<dyn Inode>)
+            .ok_or(VfsError::NotFound)
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        let Self::Root = self else {
+            return Err(VfsError::NotADirectory);
+        };
+        Ok(FILES
+            .iter()
+            .enumerate()
+            .map(|(index, &(name, _))| DirEntry {
+                name: name.to_string(),
+                ino: file_ino(index),
+                kind: InodeKind::File,
+            })
+            .collect())
+    }
+
+    fn create(
+        &self,
+        _name: &str,
+        _kind: InodeKind,
+        _permissions: Permissions,
+    ) -> Result<Arc<dyn Inode>> {
+        match self {
+            Self::Root => Err(VfsError::PermissionDenied),
+            Self::File { .. } => Err(VfsError::NotADirectory),
+        }
+    }
+
+    fn unlink(&self, _name: &str) -> Result<()> {
+        match self {
+            Self::Root => Err(VfsError::PermissionDenied),
+            Self::File { .. } => Err(VfsError::NotADirectory),
+        }
+    }
+}
+
+fn file_ino(index: usize) -> u64 {
+    ROOT_INO + 1 + index as u64
+}
+
+/// `uptime`: seconds since boot, to two decimal places.
+fn uptime() -> String {
+    format_uptime(interrupts::ticks())
+}
+
+fn format_uptime(ticks: u64) -> String {
+    let hundredths = interrupts::ticks_to_hundredths(ticks);
+    format!("{}.{:02}\n", hundredths / 100, hundredths % 100)
+}
+
+/// `meminfo`: one `Name: value` line per statistic.
+fn meminfo() -> String {
+    let mut out = String::new();
+    let mut kib = |name: &str, bytes: u64| {
+        let _ = writeln!(out, "{:<16}{:>10} kB", format!("{}:", name), bytes / 1024);
+    };
+
+    let heap = allocator::stats();
+    kib("HeapTotal", heap.size as u64);
+    kib("HeapUsed", heap.used as u64);
+    kib("HeapFree", heap.free as u64);
+    if let Ok(frames) = memory::frame_stats() {
+        kib("PhysTotal", frames.total * PAGE_SIZE);
+        kib("PhysAllocated", frames.allocated * PAGE_SIZE);
+    }
+
+    let cache = CACHE.stats();
+    for (name, value) in [
+        ("CacheBlocks", cache.cached as u64),
+        ("CacheDirty", cache.dirty as u64),
+        ("CacheHits", cache.hits),
+        ("CacheMisses", cache.misses),
+    ] {
+        let _ = writeln!(out, "{:<16}{:>10}", format!("{}:", name), value);
+    }
+    out
+}
+
+/// `interrupts`: vector, count and use of every vector taken at least once.
+fn interrupt_counts() -> String {
+    let mut out = String::from("vector      count  name\n");
+    for (vector, count) in interrupts::interrupt_counts() {
+        let name = interrupts::vector_name(vector).unwrap_or("?");
+        let _ = writeln!(out, "{:>6} {:>10}  {}", vector, count, name);
+    }
+    out
+}
+
+/// `mounts`: mount point and filesystem type of every mount.
+fn mounts() -> String {
+    vfs::mounts()
+        .iter()
+        .map(|m| format!("{} {}\n", m.path, m.fs.name()))
+        .collect()
+}
+
+/// `pci`: one line per PCI function.
+fn pci_devices() -> String {
+    pci::devices()
+        .iter()
+        .map(|d| {
+            let irq = match d.interrupt_line {
+                0xFF => String::from("-"),
+                line => line.to_string(),
+            };
+            format!("{} irq {} {}\n", d, irq, d.driver.unwrap_or("-"))
+        })
+        .collect()
+}
+
+/// `tasks`: there's no scheduler yet, so the kernel's own thread is the only task.
+fn tasks() -> String {
+    String::from("  id  state    name\n   0  running  kernel\n")
+}
+
+const FEATURES_EDX: [(u32, &str); 23] = [
+    (0, "fpu"),
+    (1, "vme"),
+    (2, "de"),
+    (3, "pse"),
+    (4, "tsc"),
+    (5, "msr"),
+    (6, "pae"),
+    (7, "mce"),
+    (8, "cx8"),
+    (9, "apic"),
+    (11, "sep"),
+    (12, "mtrr"),
+    (13, "pge"),
+    (14, "mca"),
+    (15, "cmov"),
+    (16, "pat"),
+    (17, "pse36"),
+    (19, "clflush"),
+    (23, "mmx"),
+    (24, "fxsr"),
+    (25, "sse"),
+    (26, "sse2"),
+    (28, "ht"),
+];
+const FEATURES_ECX: [(u32, &str); 16] = [
+    (0, "sse3"),
+    (1, "pclmulqdq"),
+    (9, "ssse3"),
+    (12, "fma"),
+    (13, "cx16"),
+    (19, "sse4_1"),
+    (20, "sse4_2"),
+    (21, "x2apic"),
+    (22, "movbe"),
+    (23, "popcnt"),
+    (25, "aes"),
+    (26, "xsave"),
+    (28, "avx"),
+    (29, "f16c"),
+    (30, "rdrand"),
+    (31, "hypervisor"),
+];
+
+const CPUID_BRAND: [u32; 3] = [0x8000_0002, 0x8000_0003, 0x8000_0004];
+
+/// `cpuinfo`: what `cpuid` says about the processor.
+fn cpuinfo() -> String {
+    let vendor_leaf = __cpuid(0);
+    let vendor: Vec<u8> = [vendor_leaf.ebx, vendor_leaf.edx, vendor_leaf.ecx]
+        .iter()
+        .flat_map(|r| r.to_le_bytes())
+        .collect();
+
+    let brand = if __cpuid(0x8000_0000).eax >= CPUID_BRAND[2] {
+        let bytes: Vec<u8> = CPUID_BRAND
+            .iter()
+            .flat_map(|&leaf| {
+                let r = __cpuid(leaf);
+                [r.eax, r.ebx, r.ecx, r.edx]
+            })
+            .flat_map(u32::to_le_bytes)
+            .take_while(|&b| b != 0)
+            .collect();
+        String::from_utf8_lossy(&bytes).trim().to_string()
+    } else {
+        String::from("unknown")
+    };
+
+    let info = __cpuid(1);
+    let (family, model, stepping) = decode_signature(info.eax);
+    let flags: Vec<&str> = FEATURES_EDX
+        .iter()
+        .filter(|&&(bit, _)| (info.edx >> bit) & 1 != 0)
+        .chain(
+            FEATURES_ECX
+                .iter()
+                .filter(|&&(bit, _)| (info.ecx >> bit) & 1 != 0),
+        )
+        .map(|&(_, name)| name)
+        .collect();
+
+    format!(
+        "vendor_id\t: {}\nmodel name\t: {}\ncpu family\t: {}\nmodel\t\t: {}\nstepping\t: {}\nflags\t\t: {}\n",
+        String::from_utf8_lossy(&vendor),
+        brand,
+        family,
+        model,
+        stepping,
+        flags.join(" ")
+    )
+}
+
+/// Family, model and stepping from the processor signature in `cpuid` leaf 1.
+fn decode_signature(eax: u32) -> (u32, u32, u32) {
+    let stepping = eax & 0xF;
+    let base_model = (eax >> 4) & 0xF;
+    let base_family = (eax >> 8) & 0xF;
+    let family = if base_family == 0xF {
+        base_family + ((eax >> 20) & 0xFF)
+    } else {
+        base_family
+    };
+    let model = if base_family == 0x6 || base_family == 0xF {
+        base_model | (((eax >> 16) & 0xF) << 4)
+    } else {
+        base_model
+    };
+    (family, model, stepping)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interrupts::TIMER_HZ;
+
+    #[test_case]
+    fn uptime_format() {
+        assert_eq!(format_uptime(0), "0.00\n");
+        assert_eq!(format_uptime(TIMER_HZ * 3 + TIMER_HZ / 2), "3.50\n");
+    }
+
+    #[test_case]
+    fn signature() {
+        // Intel Core i7-8700 and AMD Ryzen 7 3700X
+        assert_eq!(decode_signature(0x000906EA), (6, 158, 10));
+        assert_eq!(decode_signature(0x00870F10), (23, 113, 0));
+    }
+
+    #[test_case]
+    fn files_sorted() {
+        assert!(FILES.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+}
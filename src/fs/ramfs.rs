@@ -0,0 +1,587 @@
+//! A writable filesystem that lives entirely on the kernel heap.
+//!
+//! Used as the root filesystem during early boot and as scratch space for tests. Everything is
+//! lost when the [RamFs] is dropped.
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    any::Any,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use spin::RwLock;
+
+use crate::vfs::{
+    path, DirEntry, FileSystem, Inode, InodeKind, Metadata, Permissions, Result, VfsError,
+};
+
+const ROOT_INO: u64 = 1;
+
+/// A heap-backed filesystem.
+pub struct RamFs {
+    root: Arc<RamInode>,
+}
+impl RamFs {
+    /// Create a new filesystem containing only an empty root directory.
+    pub fn new() -> Arc<Self> {
+        let shared = Arc::new(Shared {
+            next_ino: AtomicU64::new(ROOT_INO + 1),
+        });
+        let root = RamInode::new(
+            ROOT_INO,
+            shared,
+            Content::Directory(BTreeMap::new()),
+            Permissions::DEFAULT_DIR,
+        );
+        Arc::new(Self { root })
+    }
+}
+impl FileSystem for RamFs {
+    fn name(&self) -> &'static str {
+        "ramfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::clone(&self.root) as Arc<dyn Inode>
+    }
+}
+
+/// State shared by every inode of one [RamFs].
+struct Shared {
+    next_ino: AtomicU64,
+}
+
+enum Content {
+    File(Vec<u8>),
+    Directory(BTreeMap<String, Arc<RamInode>>),
+    Symlink(String),
+}
+impl Content {
+    fn kind(&self) -> InodeKind {
+        match self {
+            Self::File(_) => InodeKind::File,
+            Self::Directory(_) => InodeKind::Directory,
+            Self::Symlink(_) => InodeKind::Symlink,
+        }
+    }
+}
+
+struct Data {
+    content: Content,
+    permissions: Permissions,
+    nlink: u32,
+}
+
+struct RamInode {
+    ino: u64,
+    // Lets `link` turn the `&RamInode` it downcasts to back into a strong reference.
+    this: Weak<RamInode>,
+    shared: Arc<Shared>,
+    data: RwLock<Data>,
+}
+impl RamInode {
+    fn new(ino: u64, shared: Arc<Shared>, content: Content, permissions: Permissions) -> Arc<Self> {
+        let nlink = if matches!(content, Content::Directory(_)) {
+            2
+        } else {
+            1
+        };
+        Arc::new_cyclic(|this| Self {
+            ino,
+            this: Weak::clone(this),
+            shared,
+            data: RwLock::new(Data {
+                content,
+                permissions,
+                nlink,
+            }),
+        })
+    }
+
+    /// Downcast `inode` to a [RamInode] belonging to the same filesystem as `self`.
+    fn same_fs<'a>(&self, inode: &'a Arc<dyn Inode>) -> Result<&'a RamInode> {
+        inode
+            .as_any()
+            .downcast_ref::<RamInode>()
+            .filter(|other| Arc::ptr_eq(&self.shared, &other.shared))
+            .ok_or(VfsError::CrossDevice)
+    }
+
+    /// Add a new child with the given content to this directory.
+    fn add_child(
+        &self,
+        name: &str,
+        content: Content,
+        permissions: Permissions,
+    ) -> Result<Arc<dyn Inode>> {
+        path::validate_name(name)?;
+
+        let mut data = self.data.write();
+        let entries = entries_mut(&mut data.content)?;
+        if entries.contains_key(name) {
+            return Err(VfsError::AlreadyExists);
+        }
+
+        let ino = self.shared.next_ino.fetch_add(1, Ordering::Relaxed);
+        let child = RamInode::new(ino, Arc::clone(&self.shared), content, permissions);
+        entries.insert(name.to_string(), Arc::clone(&child));
+        Ok(child)
+    }
+}
+impl Inode for RamInode {
+    fn metadata(&self) -> Result<Metadata> {
+        let data = self.data.read();
+        let size = match &data.content {
+            Content::File(bytes) => bytes.len(),
+            Content::Directory(entries) => entries.len(),
+            Content::Symlink(target) => target.len(),
+        };
+        Ok(Metadata {
+            ino: self.ino,
+            kind: data.content.kind(),
+            size: size as u64,
+            permissions: data.permissions,
+            nlink: data.nlink,
+            uid: 0,
+            gid: 0,
+        })
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let data = self.data.read();
+        let bytes = file(&data.content)?;
+        let Ok(start) = usize::try_from(offset) else {
+            return Ok(0);
+        };
+        if start >= bytes.len() {
+            return Ok(0);
+        }
+
+        let n = buf.len().min(bytes.len() - start);
+        buf[..n].copy_from_slice(&bytes[start..(start + n)]);
+        Ok(n)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+        let start = usize::try_from(offset).map_err(|_| VfsError::NoSpace)?;
+        let end = start.checked_add(buf.len()).ok_or(VfsError::NoSpace)?;
+
+        let mut data = self.data.write();
+        let bytes = file_mut(&mut data.content)?;
+        if bytes.len() < end {
+            bytes.resize(end, 0);
+        }
+        bytes[start..end].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, len: u64) -> Result<()> {
+        let len = usize::try_from(len).map_err(|_| VfsError::NoSpace)?;
+        let mut data = self.data.write();
+        let bytes = file_mut(&mut data.content)?;
+        bytes.resize(len, 0);
+        bytes.shrink_to_fit();
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let data = self.data.read();
+        entries(&data.content)?
+            .get(name)
+            .map(|child| Arc::clone(child) as Arc<dyn Inode>)
+            .ok_or(VfsError::NotFound)
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        let data = self.data.read();
+        Ok(entries(&data.content)?
+            .iter()
+            .map(|(name, child)| DirEntry {
+                name: name.clone(),
+                ino: child.ino,
+                kind: child.data.read().content.kind(),
+            })
+            .collect())
+    }
+
+    fn create(
+        &self,
+        name: &str,
+        kind: InodeKind,
+        permissions: Permissions,
+    ) -> Result<Arc<dyn Inode>> {
+        let content = match kind {
+            InodeKind::File => Content::File(Vec::new()),
+            InodeKind::Directory => Content::Directory(BTreeMap::new()),
+            _ => return Err(VfsError::Unsupported),
+        };
+        self.add_child(name, content, permissions)
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>> {
+        self.add_child(name, Content::Symlink(target.to_string()), Permissions::ALL)
+    }
+
+    fn link(&self, name: &str, target: &Arc<dyn Inode>) -> Result<()> {
+        path::validate_name(name)?;
+        let target_inode = self.same_fs(target)?;
+        if target_inode.data.read().content.kind() == InodeKind::Directory {
+            return Err(VfsError::PermissionDenied);
+        }
+        let target = target_inode.this.upgrade().ok_or(VfsError::NotFound)?;
+
+        let mut data = self.data.write();
+        let entries = entries_mut(&mut data.content)?;
+        if entries.contains_key(name) {
+            return Err(VfsError::AlreadyExists);
+        }
+        target.data.write().nlink += 1;
+        entries.insert(name.to_string(), target);
+        Ok(())
+    }
+
+    fn unlink(&self, name: &str) -> Result<()> {
+        let mut data = self.data.write();
+        let entries = entries_mut(&mut data.content)?;
+        let child = entries.get(name).ok_or(VfsError::NotFound)?;
+
+        let mut child_data = child.data.write();
+        if let Content::Directory(child_entries) = &child_data.content {
+            if !child_entries.is_empty() {
+                return Err(VfsError::DirectoryNotEmpty);
+            }
+            child_data.nlink = 0;
+        } else {
+            child_data.nlink -= 1;
+        }
+        drop(child_data);
+
+        entries.remove(name);
+        Ok(())
+    }
+
+    fn rename(&self, old_name: &str, new_parent: &Arc<dyn Inode>, new_name: &str) -> Result<()> {
+        path::validate_name(new_name)?;
+        let new_parent = self.same_fs(new_parent)?;
+
+        if core::ptr::eq(self, new_parent) {
+            if old_name == new_name {
+                return Ok(());
+            }
+            let mut data = self.data.write();
+            let entries = entries_mut(&mut data.content)?;
+            let child = entries.get(old_name).ok_or(VfsError::NotFound)?;
+            check_replace(child, entries.get(new_name))?;
+            let child = entries.remove(old_name).expect("entry was just found");
+            if let Some(replaced) = entries.insert(new_name.to_string(), child) {
+                replaced.data.write().nlink = 0;
+            }
+            return Ok(());
+        }
+
+        // Replacing either parent would lock it twice, and neither is empty anyway
+        if let Some(existing) = entries(&new_parent.data.read().content)?.get(new_name) {
+            let existing = existing.as_ref();
+            if core::ptr::eq(existing, self) || core::ptr::eq(existing, new_parent) {
+                return Err(VfsError::DirectoryNotEmpty);
+            }
+        }
+
+        // Always lock in inode number order so two opposite renames can't deadlock
+        let (mut old_data, mut new_data) = if self.ino < new_parent.ino {
+            let old = self.data.write();
+            (old, new_parent.data.write())
+        } else {
+            let new = new_parent.data.write();
+            (self.data.write(), new)
+        };
+        let old_entries = entries_mut(&mut old_data.content)?;
+        let new_entries = entries_mut(&mut new_data.content)?;
+
+        let child = old_entries.get(old_name).ok_or(VfsError::NotFound)?;
+        check_replace(child, new_entries.get(new_name))?;
+        let child = old_entries.remove(old_name).expect("entry was just found");
+        if let Some(replaced) = new_entries.insert(new_name.to_string(), child) {
+            replaced.data.write().nlink = 0;
+        }
+        Ok(())
+    }
+
+    fn read_link(&self) -> Result<String> {
+        match &self.data.read().content {
+            Content::Symlink(target) => Ok(target.clone()),
+            _ => Err(VfsError::InvalidArgument),
+        }
+    }
+}
+/// Check that `child` may replace `existing` in a rename.
+fn check_replace(child: &RamInode, existing: Option<&Arc<RamInode>>) -> Result<()> {
+    let Some(existing) = existing else {
+        return Ok(());
+    };
+    if core::ptr::eq(child, existing.as_ref()) {
+        return Ok(());
+    }
+
+    let child_kind = child.data.read().content.kind();
+    let existing_data = existing.data.read();
+    match (&existing_data.content, child_kind) {
+        (Content::Directory(entries), InodeKind::Directory) if !entries.is_empty() => {
+            Err(VfsError::DirectoryNotEmpty)
+        }
+        (Content::Directory(_), InodeKind::Directory) => Ok(()),
+        (Content::Directory(_), _) => Err(VfsError::IsADirectory),
+        (_, InodeKind::Directory) => Err(VfsError::NotADirectory),
+        _ => Ok(()),
+    }
+}
+
+fn file(content: &Content) -> Result<&Vec<u8>> {
+    match content {
+        Content::File(bytes) => Ok(bytes),
+        Content::Directory(_) => Err(VfsError::IsADirectory),
+        Content::Symlink(_) => Err(VfsError::InvalidArgument),
+    }
+}
+
+fn file_mut(content: &mut Content) -> Result<&mut Vec<u8>> {
+    match content {
+        Content::File(bytes) => Ok(bytes),
+        Content::Directory(_) => Err(VfsError::IsADirectory),
+        Content::Symlink(_) => Err(VfsError::InvalidArgument),
+    }
+}
+
+fn entries(content: &Content) -> Result<&BTreeMap<String, Arc<RamInode>>> {
+    match content {
+        Content::Directory(entries) => Ok(entries),
+        _ => Err(VfsError::NotADirectory),
+    }
+}
+
+fn entries_mut(content: &mut Content) -> Result<&mut BTreeMap<String, Arc<RamInode>>> {
+    match content {
+        Content::Directory(entries) => Ok(entries),
+        _ => Err(VfsError::NotADirectory),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_root() -> Arc<dyn Inode> {
+        RamFs::new().root()
+    }
+
+    fn new_file(dir: &Arc<dyn Inode>, name: &str) -> Arc<dyn Inode> {
+        dir.create(name, InodeKind::File, Permissions::DEFAULT_FILE)
+            .unwrap()
+    }
+
+    fn new_dir(dir: &Arc<dyn Inode>, name: &str) -> Arc<dyn Inode> {
+        dir.create(name, InodeKind::Directory, Permissions::DEFAULT_DIR)
+            .unwrap()
+    }
+
+    fn names(dir: &Arc<dyn Inode>) -> Vec<String> {
+        dir.readdir().unwrap().into_iter().map(|e| e.name).collect()
+    }
+
+    #[test_case]
+    fn write_read() {
+        let root = new_root();
+        let file = new_file(&root, "hello.txt");
+        assert_eq!(file.write_at(0, b"Hello, world!").unwrap(), 13);
+
+        let mut buf = [0; 5];
+        assert_eq!(file.read_at(7, &mut buf).unwrap(), 5);
+        assert_eq!(&buf, b"world");
+        assert_eq!(file.read_at(13, &mut buf).unwrap(), 0);
+        assert_eq!(file.metadata().unwrap().size, 13);
+    }
+
+    #[test_case]
+    fn write_past_end_zero_fills() {
+        let root = new_root();
+        let file = new_file(&root, "sparse");
+        file.write_at(4, b"ab").unwrap();
+
+        let mut buf = [0xFF; 6];
+        assert_eq!(file.read_at(0, &mut buf).unwrap(), 6);
+        assert_eq!(&buf, b"\0\0\0\0ab");
+    }
+
+    #[test_case]
+    fn truncate() {
+        let root = new_root();
+        let file = new_file(&root, "f");
+        file.write_at(0, b"0123456789").unwrap();
+
+        file.truncate(4).unwrap();
+        assert_eq!(file.metadata().unwrap().size, 4);
+
+        file.truncate(6).unwrap();
+        let mut buf = [0xFF; 8];
+        assert_eq!(file.read_at(0, &mut buf).unwrap(), 6);
+        assert_eq!(&buf[..6], b"0123\0\0");
+    }
+
+    #[test_case]
+    fn directories() {
+        let root = new_root();
+        let dir = new_dir(&root, "dir");
+        new_file(&dir, "b");
+        new_file(&dir, "a");
+
+        assert_eq!(names(&root), ["dir"]);
+        assert_eq!(names(&dir), ["a", "b"]);
+        assert_eq!(
+            root.lookup("dir").unwrap().metadata().unwrap().kind,
+            InodeKind::Directory
+        );
+        assert_eq!(root.lookup("nope").unwrap_err(), VfsError::NotFound);
+        assert_eq!(
+            dir.create("a", InodeKind::File, Permissions::DEFAULT_FILE)
+                .unwrap_err(),
+            VfsError::AlreadyExists
+        );
+        assert_eq!(
+            dir.read_at(0, &mut [0]).unwrap_err(),
+            VfsError::IsADirectory
+        );
+    }
+
+    #[test_case]
+    fn unlink() {
+        let root = new_root();
+        let dir = new_dir(&root, "dir");
+        let file = new_file(&dir, "f");
+
+        assert_eq!(root.unlink("dir").unwrap_err(), VfsError::DirectoryNotEmpty);
+        dir.unlink("f").unwrap();
+        assert_eq!(file.metadata().unwrap().nlink, 0);
+        assert_eq!(dir.lookup("f").unwrap_err(), VfsError::NotFound);
+        root.unlink("dir").unwrap();
+        assert!(names(&root).is_empty());
+        assert_eq!(root.unlink("dir").unwrap_err(), VfsError::NotFound);
+    }
+
+    #[test_case]
+    fn hard_links() {
+        let root = new_root();
+        let dir = new_dir(&root, "dir");
+        let file = new_file(&root, "original");
+        file.write_at(0, b"shared").unwrap();
+
+        dir.link("copy", &file).unwrap();
+        assert_eq!(file.metadata().unwrap().nlink, 2);
+        assert_eq!(
+            root.link("original", &file).unwrap_err(),
+            VfsError::AlreadyExists
+        );
+        assert_eq!(
+            root.link("d", &dir).unwrap_err(),
+            VfsError::PermissionDenied
+        );
+
+        root.unlink("original").unwrap();
+        let copy = dir.lookup("copy").unwrap();
+        assert_eq!(copy.metadata().unwrap().nlink, 1);
+        let mut buf = [0; 6];
+        copy.read_at(0, &mut buf).unwrap();
+        assert_eq!(&buf, b"shared");
+    }
+
+    #[test_case]
+    fn link_across_filesystems() {
+        let root = new_root();
+        let other = new_root();
+        let file = new_file(&other, "f");
+        assert_eq!(root.link("f", &file).unwrap_err(), VfsError::CrossDevice);
+    }
+
+    #[test_case]
+    fn rename_same_dir() {
+        let root = new_root();
+        let file = new_file(&root, "old");
+        root.rename("old", &root, "new").unwrap();
+        assert_eq!(names(&root), ["new"]);
+        assert_eq!(
+            root.lookup("new").unwrap().metadata().unwrap().ino,
+            file.metadata().unwrap().ino
+        );
+    }
+
+    #[test_case]
+    fn rename_across_dirs_replaces() {
+        let root = new_root();
+        let dir = new_dir(&root, "dir");
+        let moved = new_file(&root, "moved");
+        let replaced = new_file(&dir, "target");
+
+        root.rename("moved", &dir, "target").unwrap();
+        assert!(names(&root).iter().all(|n| n != "moved"));
+        assert_eq!(
+            dir.lookup("target").unwrap().metadata().unwrap().ino,
+            moved.metadata().unwrap().ino
+        );
+        assert_eq!(replaced.metadata().unwrap().nlink, 0);
+    }
+
+    #[test_case]
+    fn rename_over_parent() {
+        let root = new_root();
+        let x = new_dir(&root, "x");
+        new_file(&x, "y");
+
+        assert_eq!(
+            x.rename("y", &root, "x").unwrap_err(),
+            VfsError::DirectoryNotEmpty
+        );
+        assert_eq!(names(&root), ["x"]);
+        assert_eq!(names(&x), ["y"]);
+    }
+
+    #[test_case]
+    fn rename_kind_mismatch() {
+        let root = new_root();
+        let dir = new_dir(&root, "dir");
+        new_file(&root, "file");
+        new_file(&dir, "inner");
+        new_dir(&root, "empty");
+
+        assert_eq!(
+            root.rename("file", &root, "dir").unwrap_err(),
+            VfsError::IsADirectory
+        );
+        assert_eq!(
+            root.rename("dir", &root, "file").unwrap_err(),
+            VfsError::NotADirectory
+        );
+        assert_eq!(
+            root.rename("empty", &root, "dir").unwrap_err(),
+            VfsError::DirectoryNotEmpty
+        );
+        root.rename("dir", &root, "empty").unwrap();
+        assert_eq!(names(&root), ["empty", "file"]);
+    }
+
+    #[test_case]
+    fn symlinks() {
+        let root = new_root();
+        let link = root.symlink("link", "/some/where").unwrap();
+        assert_eq!(link.metadata().unwrap().kind, InodeKind::Symlink);
+        assert_eq!(link.read_link().unwrap(), "/some/where");
+        assert_eq!(root.read_link().unwrap_err(), VfsError::InvalidArgument);
+    }
+}
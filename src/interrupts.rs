@@ -1,7 +1,9 @@
 //! Functionality related to interrupts.
 
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
 use lazy_static::lazy_static;
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
 use pic8259::ChainedPics;
 use spin;
 use x86_64::{
@@ -10,14 +12,33 @@ use x86_64::{
 };
 
 use crate::{
+    ata,
     gdt::DOUBLE_FAULT_IST_INDEX,
-    print, println,
+    keyboard, print, println, serial,
     vga_text::{set_vga_fg, vga_fg, VgaFgColour},
 };
 
-const PS2_CONTROLLER_PORT: u16 = 0x60;
+const BREAKPOINT_VECTOR: u8 = 3;
+const DOUBLE_FAULT_VECTOR: u8 = 8;
+const MACHINE_CHECK_VECTOR: u8 = 18;
 
 const PIC_INTERRUPT_LINES: u8 = 8;
+/// PIC 1 line that PIC 2 is chained to.
+const PIC_CASCADE_LINE: u8 = 2;
+
+/// PIC lines the firmware routes PCI interrupts to (the PIIX3 PIRQ links).
+pub const PCI_IRQ_LINES: [u8; 4] = [5, 9, 10, 11];
+
+/// Rate of the timer interrupt, which [init_timer] sets the PIT to.
+pub const TIMER_HZ: u64 = 100;
+
+// The PIT's input clock, and what it's divided by for TIMER_HZ
+const PIT_FREQUENCY: u64 = 1193182;
+const PIT_DIVISOR: u16 = ((PIT_FREQUENCY + TIMER_HZ / 2) / TIMER_HZ) as u16;
+const PIT_CHANNEL_0: u16 = 0x40;
+const PIT_COMMAND: u16 = 0x43;
+// Channel 0, divisor's low byte then high byte, square wave
+const PIT_SQUARE_WAVE: u8 = 0b0011_0110;
 
 /// Start after the 32 exception slots
 pub const PIC_1_OFFSET: u8 = 32;
@@ -32,6 +53,20 @@ pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     /// Keyboard interrupt
     Keyboard,
+    /// COM2 and COM4 serial port interrupt (IRQ 3)
+    Serial2 = PIC_1_OFFSET + 3,
+    /// COM1 and COM3 serial port interrupt (IRQ 4)
+    Serial1,
+    /// Primary ATA channel interrupt (IRQ 14)
+    PrimaryAta = PIC_2_OFFSET + 6,
+    /// Secondary ATA channel interrupt (IRQ 15)
+    SecondaryAta = PIC_2_OFFSET + 7,
+}
+impl InterruptIndex {
+    /// The PIC interrupt request line of this interrupt.
+    pub fn irq(self) -> u8 {
+        u8::from(self) - PIC_1_OFFSET
+    }
 }
 impl From<InterruptIndex> for u8 {
     fn from(value: InterruptIndex) -> Self {
@@ -61,11 +96,29 @@ lazy_static! {
         // PIC hardware interrupts
         idt[Timer.into()].set_handler_fn(timer_interrupt_handler);
         idt[Keyboard.into()].set_handler_fn(keyboard_interrupt_handler);
+        idt[Serial1.into()].set_handler_fn(serial1_interrupt_handler);
+        idt[Serial2.into()].set_handler_fn(serial2_interrupt_handler);
+        idt[PrimaryAta.into()].set_handler_fn(primary_ata_interrupt_handler);
+        idt[SecondaryAta.into()].set_handler_fn(secondary_ata_interrupt_handler);
+        idt[pci_vector(5)].set_handler_fn(pci_irq5_handler);
+        idt[pci_vector(9)].set_handler_fn(pci_irq9_handler);
+        idt[pci_vector(10)].set_handler_fn(pci_irq10_handler);
+        idt[pci_vector(11)].set_handler_fn(pci_irq11_handler);
 
         idt
     };
 }
 
+/// A PCI interrupt handler, along with the PIC line it's attached to.
+type PciHandler = (u8, fn());
+
+static PCI_HANDLERS: spin::Mutex<Vec<PciHandler>> = spin::Mutex::new(Vec::new());
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Number of interrupts taken on each IDT vector.
+static COUNTS: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+
 /// The Intel 8259 primary/secondary PIC layout used for hardware interrupts.
 pub static PICS: spin::Mutex<ChainedPics> =
     // UNSAFE: Can cause UB if the PIC is misconfigured.
@@ -79,6 +132,7 @@ pub fn init_idt() {
 
 /// Handler for breakpoints.
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    count(BREAKPOINT_VECTOR);
     exception_title();
     println!("BREAKPOINT\n{:#?}", stack_frame);
 }
@@ -90,6 +144,7 @@ extern "x86-interrupt" fn double_fault_handler(
     // Error code is always 0 for double faults
     _error_code: u64,
 ) -> ! {
+    count(DOUBLE_FAULT_VECTOR);
     exception_title();
     // Must diverge- x86_64 prevents returning from a double fault.
     panic!("DOUBLE FAULT\n{:#?}", stack_frame);
@@ -98,12 +153,72 @@ extern "x86-interrupt" fn double_fault_handler(
 /// Handler for machine check. Unrecoverable- invoked when the processor detects internal errors
 /// (bad memory, bus errors, cache errors, etc.).
 extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    count(MACHINE_CHECK_VECTOR);
     exception_title();
     panic!("MACHINE_CHECK\n{:#?}", stack_frame)
 }
 
+/// Record an interrupt on IDT vector `vector`.
+fn count(vector: impl Into<usize>) {
+    COUNTS[vector.into()].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Number of interrupts taken on each IDT vector since boot, for the vectors taken at least once.
+pub fn interrupt_counts() -> Vec<(u8, u64)> {
+    COUNTS
+        .iter()
+        .enumerate()
+        .map(|(vector, count)| (vector as u8, count.load(Ordering::Relaxed)))
+        .filter(|&(_, count)| count > 0)
+        .collect()
+}
+
+/// Short description of what IDT vector `vector` is used for, if it has a handler.
+pub fn vector_name(vector: u8) -> Option<&'static str> {
+    use InterruptIndex::*;
+
+    let irq = vector.wrapping_sub(PIC_1_OFFSET);
+    match vector {
+        BREAKPOINT_VECTOR => Some("breakpoint"),
+        DOUBLE_FAULT_VECTOR => Some("double fault"),
+        MACHINE_CHECK_VECTOR => Some("machine check"),
+        _ if vector == Timer.into() => Some("timer"),
+        _ if vector == Keyboard.into() => Some("keyboard"),
+        _ if vector == Serial1.into() => Some("serial com1/com3"),
+        _ if vector == Serial2.into() => Some("serial com2/com4"),
+        _ if vector == PrimaryAta.into() => Some("ata primary"),
+        _ if vector == SecondaryAta.into() => Some("ata secondary"),
+        _ if PCI_IRQ_LINES.contains(&irq) => Some("pci"),
+        _ => None,
+    }
+}
+
+/// Have the PIT interrupt [TIMER_HZ] times a second.
+pub fn init_timer() {
+    let [low, high] = PIT_DIVISOR.to_le_bytes();
+    // UNSAFE: The ports belong to the PIT, and channel 0 only drives the timer interrupt.
+    unsafe {
+        Port::<u8>::new(PIT_COMMAND).write(PIT_SQUARE_WAVE);
+        let mut channel = Port::<u8>::new(PIT_CHANNEL_0);
+        channel.write(low);
+        channel.write(high);
+    }
+}
+
+/// Number of timer interrupts since boot, at [TIMER_HZ].
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// The time `ticks` timer interrupts take, in hundredths of a second.
+pub fn ticks_to_hundredths(ticks: u64) -> u64 {
+    ticks * 100 / TIMER_HZ
+}
+
 /// Handler for the hardware timer interrupt.
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    count(InterruptIndex::Timer);
+    TICKS.fetch_add(1, Ordering::Relaxed);
     unsafe {
         send_eoi(InterruptIndex::Timer);
     }
@@ -111,34 +226,136 @@ extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFr
 
 /// Handler for the hardware keyboard interrupt.
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    lazy_static! {
-        static ref KEYBOARD: spin::Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
-            spin::Mutex::new(Keyboard::new(
-                ScancodeSet1::new(),
-                layouts::Us104Key,
-                HandleControl::Ignore
-            ));
+    count(InterruptIndex::Keyboard);
+    keyboard::handle_interrupt();
+
+    unsafe {
+        send_eoi(InterruptIndex::Keyboard);
     }
+}
 
-    // Read scancode
-    let mut keyboard = KEYBOARD.lock();
-    let mut port = Port::new(PS2_CONTROLLER_PORT);
-    let scancode: u8 = unsafe { port.read() };
-
-    // Print key
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            match key {
-                // DecodedKey::Unicode(character) => print!("{:#?}", character),
-                DecodedKey::Unicode(character) => print!("{}", character),
-                // DecodedKey::RawKey(key) => print!("{:?}", key),
-                DecodedKey::RawKey(_) => (),
-            }
+/// Handler for the COM1 and COM3 serial port hardware interrupt.
+extern "x86-interrupt" fn serial1_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    count(InterruptIndex::Serial1);
+    serial::handle_interrupt(InterruptIndex::Serial1);
+
+    unsafe {
+        send_eoi(InterruptIndex::Serial1);
+    }
+}
+
+/// Handler for the COM2 and COM4 serial port hardware interrupt.
+extern "x86-interrupt" fn serial2_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    count(InterruptIndex::Serial2);
+    serial::handle_interrupt(InterruptIndex::Serial2);
+
+    unsafe {
+        send_eoi(InterruptIndex::Serial2);
+    }
+}
+
+/// Handler for the primary ATA channel hardware interrupt.
+extern "x86-interrupt" fn primary_ata_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    count(InterruptIndex::PrimaryAta);
+    ata::handle_interrupt(ata::Channel::Primary);
+
+    unsafe {
+        send_eoi(InterruptIndex::PrimaryAta);
+    }
+}
+
+/// Handler for the secondary ATA channel hardware interrupt.
+extern "x86-interrupt" fn secondary_ata_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    count(InterruptIndex::SecondaryAta);
+    ata::handle_interrupt(ata::Channel::Secondary);
+
+    unsafe {
+        send_eoi(InterruptIndex::SecondaryAta);
+    }
+}
+
+macro_rules! pci_interrupt_handler {
+    ($name:ident, $irq:literal) => {
+        /// Handler for a PIC line shared by PCI devices.
+        extern "x86-interrupt" fn $name(_stack_frame: InterruptStackFrame) {
+            dispatch_pci_interrupt($irq);
+        }
+    };
+}
+pci_interrupt_handler!(pci_irq5_handler, 5);
+pci_interrupt_handler!(pci_irq9_handler, 9);
+pci_interrupt_handler!(pci_irq10_handler, 10);
+pci_interrupt_handler!(pci_irq11_handler, 11);
+
+/// IDT index of the given PIC line.
+fn pci_vector(irq: u8) -> usize {
+    usize::from(PIC_1_OFFSET + irq)
+}
+
+/// Run every handler registered for PIC line `irq`. PCI interrupts are level-triggered and may be
+/// shared, so each handler has to check whether its device actually raised the interrupt.
+fn dispatch_pci_interrupt(irq: u8) {
+    count(pci_vector(irq));
+    for &(line, handler) in PCI_HANDLERS.lock().iter() {
+        if line == irq {
+            handler();
         }
     }
 
+    // UNSAFE: `irq` is the line this interrupt arrived on.
     unsafe {
-        send_eoi(InterruptIndex::Keyboard);
+        PICS.lock().notify_end_of_interrupt(PIC_1_OFFSET + irq);
+    }
+}
+
+/// Call `handler` whenever PCI interrupt line `line` is raised, and unmask that line. Returns
+/// `false` if `line` isn't one of [PCI_IRQ_LINES].
+pub fn register_pci_handler(line: u8, handler: fn()) -> bool {
+    if !PCI_IRQ_LINES.contains(&line) {
+        return false;
+    }
+
+    // The handler list is also locked from interrupt context
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        PCI_HANDLERS.lock().push((line, handler));
+    });
+    unmask_irq(line);
+    true
+}
+
+/// Let the PICs deliver the given hardware interrupt, which may be masked by default.
+pub fn unmask(interrupt_index: InterruptIndex) {
+    unmask_irq(interrupt_index.irq());
+}
+
+/// Unmask PIC line `irq`, which must have a handler in the IDT.
+fn unmask_irq(irq: u8) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut pics = PICS.lock();
+        // UNSAFE: Unmasking an interrupt is fine as long as it has a handler in the IDT, which
+        // every `InterruptIndex` and PCI line does.
+        unsafe {
+            let [mut mask1, mut mask2] = pics.read_masks();
+            if irq < PIC_INTERRUPT_LINES {
+                mask1 &= !(1 << irq);
+            } else {
+                mask1 &= !(1 << PIC_CASCADE_LINE);
+                mask2 &= !(1 << (irq - PIC_INTERRUPT_LINES));
+            }
+            pics.write_masks(mask1, mask2);
+        }
+    });
+}
+
+/// Halt until the next interrupt, unless `ready` returns `true`. Interrupts are disabled while
+/// `ready` runs, so whatever it checks for can't arrive between the check and the `hlt` and leave
+/// the CPU asleep with work waiting.
+pub fn hlt_unless(ready: impl FnOnce() -> bool) {
+    x86_64::instructions::interrupts::disable();
+    if ready() {
+        x86_64::instructions::interrupts::enable();
+    } else {
+        x86_64::instructions::interrupts::enable_and_hlt();
     }
 }
 
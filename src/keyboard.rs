@@ -0,0 +1,193 @@
+//! PS/2 keyboard input.
+//!
+//! The keyboard interrupt handler decodes scancodes with [pc_keyboard] and queues the resulting
+//! keys for the [virtual terminal](crate::vga_text::vt) on the screen. Each terminal has its own
+//! queue, and the console terminal's keys are picked up with [read_key]. The queues have a fixed
+//! size so nothing is allocated in interrupt context; keys arriving while one is full are dropped.
+//! Shift+PageUp and Shift+PageDown aren't queued, but move through the screen's
+//! [scrollback](crate::vga_text::scrollback) straight away, and Alt+F1 to Alt+F6 switch to the
+//! terminal with that number.
+
+use lazy_static::lazy_static;
+use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, Keyboard, ScancodeSet1};
+use spin::Mutex;
+use x86_64::instructions::{interrupts, port::Port};
+
+use crate::vga_text::{
+    screen, scrollback,
+    vt::{self, Role, Vt, VT_COUNT},
+};
+
+const PS2_DATA_PORT: u16 = 0x60;
+
+/// Most keys held in each terminal's queue.
+pub const QUEUE_SIZE: usize = 64;
+
+/// Lines moved through the scrollback by Shift+PageUp and Shift+PageDown: half a screen.
+fn scroll_page() -> usize {
+    screen::size().0 / 2
+}
+
+lazy_static! {
+    static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
+        Mutex::new(Keyboard::new(
+            ScancodeSet1::new(),
+            layouts::Us104Key,
+            HandleControl::Ignore
+        ));
+}
+
+/// Each terminal's queue, by `Vt::index`.
+static KEYS: [Mutex<KeyQueue>; VT_COUNT] = [const { Mutex::new(KeyQueue::new()) }; VT_COUNT];
+
+/// A ring buffer of decoded keys.
+struct KeyQueue {
+    keys: [Option<DecodedKey>; QUEUE_SIZE],
+    head: usize,
+    len: usize,
+}
+impl KeyQueue {
+    const fn new() -> Self {
+        Self {
+            keys: [None; QUEUE_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Add `key` to the back of the queue. Returns `false` if the queue is full.
+    fn push(&mut self, key: DecodedKey) -> bool {
+        if self.len == QUEUE_SIZE {
+            return false;
+        }
+        self.keys[(self.head + self.len) % QUEUE_SIZE] = Some(key);
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<DecodedKey> {
+        if self.len == 0 {
+            return None;
+        }
+        let key = self.keys[self.head].take();
+        self.head = (self.head + 1) % QUEUE_SIZE;
+        self.len -= 1;
+        key
+    }
+}
+
+/// Read the pending scancode from the keyboard controller and queue the key it completes, if
+/// any. Called by the keyboard interrupt handler.
+pub fn handle_interrupt() {
+    let mut port = Port::new(PS2_DATA_PORT);
+    // UNSAFE: Reading the data port only takes the scancode the interrupt is about.
+    let scancode: u8 = unsafe { port.read() };
+
+    let mut keyboard = KEYBOARD.lock();
+    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
+        if let Some(key) = keyboard.process_keyevent(key_event) {
+            let modifiers = keyboard.get_modifiers();
+            let shifted = modifiers.is_shifted();
+            let alt = modifiers.lalt || modifiers.ralt;
+            match key {
+                DecodedKey::RawKey(KeyCode::PageUp) if shifted => {
+                    scrollback::scroll_back(scroll_page())
+                }
+                DecodedKey::RawKey(KeyCode::PageDown) if shifted => {
+                    scrollback::scroll_forward(scroll_page())
+                }
+                DecodedKey::RawKey(code) if alt => match switch_key(code) {
+                    Some(vt) => vt::switch_to(vt),
+                    None => {
+                        queue_key(key);
+                    }
+                },
+                key => {
+                    queue_key(key);
+                }
+            }
+        }
+    }
+}
+
+/// The terminal switched to by Alt and `code`.
+fn switch_key(code: KeyCode) -> Option<Vt> {
+    match code {
+        KeyCode::F1 => Some(Vt::Tty1),
+        KeyCode::F2 => Some(Vt::Tty2),
+        KeyCode::F3 => Some(Vt::Tty3),
+        KeyCode::F4 => Some(Vt::Tty4),
+        KeyCode::F5 => Some(Vt::Tty5),
+        KeyCode::F6 => Some(Vt::Tty6),
+        _ => None,
+    }
+}
+
+/// Queue `key` for the terminal on the screen. Returns `false` if its queue is full.
+fn queue_key(key: DecodedKey) -> bool {
+    KEYS[vt::active().index()].lock().push(key)
+}
+
+/// Take the oldest key from the console terminal's queue, if there is one.
+pub fn read_key() -> Option<DecodedKey> {
+    read_key_from(vt::role(Role::Console))
+}
+
+/// Take the oldest key from `vt`'s queue, if there is one.
+pub fn read_key_from(vt: Vt) -> Option<DecodedKey> {
+    interrupts::without_interrupts(|| KEYS[vt.index()].lock().pop())
+}
+
+/// Whether there's a key waiting in the console terminal's queue.
+pub fn key_waiting() -> bool {
+    key_waiting_for(vt::role(Role::Console))
+}
+
+/// Whether there's a key waiting in `vt`'s queue.
+pub fn key_waiting_for(vt: Vt) -> bool {
+    interrupts::without_interrupts(|| KEYS[vt.index()].lock().len > 0)
+}
+
+/// Halt until the next interrupt, unless a key is already waiting.
+pub fn wait_for_key() {
+    crate::interrupts::hlt_unless(key_waiting);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn queue_order_and_overflow() {
+        let mut queue = KeyQueue::new();
+        assert_eq!(queue.pop(), None);
+        for i in 0..QUEUE_SIZE {
+            assert!(queue.push(DecodedKey::Unicode(char::from(i as u8))));
+        }
+        assert!(!queue.push(DecodedKey::Unicode('x')));
+        for i in 0..QUEUE_SIZE {
+            assert_eq!(queue.pop(), Some(DecodedKey::Unicode(char::from(i as u8))));
+        }
+        assert_eq!(queue.pop(), None);
+
+        // Wraps around
+        queue.push(DecodedKey::Unicode('a'));
+        assert_eq!(queue.pop(), Some(DecodedKey::Unicode('a')));
+    }
+
+    #[test_case]
+    fn keys_go_to_the_terminal_on_the_screen() {
+        interrupts::without_interrupts(|| {
+            assert!(queue_key(DecodedKey::Unicode('1')));
+            vt::switch_to(Vt::Tty5);
+            assert!(queue_key(DecodedKey::Unicode('5')));
+            vt::switch_to(Vt::Tty1);
+        });
+        assert!(key_waiting_for(Vt::Tty5));
+        assert_eq!(read_key_from(Vt::Tty5), Some(DecodedKey::Unicode('5')));
+        assert!(!key_waiting_for(Vt::Tty5));
+        assert_eq!(read_key(), Some(DecodedKey::Unicode('1')));
+        assert_eq!(switch_key(KeyCode::F3), Some(Vt::Tty3));
+        assert_eq!(switch_key(KeyCode::F7), None);
+    }
+}
@@ -7,22 +7,55 @@
 #![test_runner(test_framework::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
+extern crate alloc;
+
+use bootloader::BootInfo;
+
+pub mod allocator;
+pub mod ata;
+pub mod block;
+pub mod dmesg;
+pub mod framebuffer;
+pub mod fs;
 pub mod gdt;
 pub mod interrupts;
+pub mod keyboard;
+pub mod logger;
+pub mod memory;
+pub mod pci;
+pub mod power;
 pub mod qemu;
 pub mod serial;
+pub mod shell;
 pub mod test_framework;
+pub mod vfs;
 pub mod vga_text;
+pub mod virtio;
 
 pub use test_framework::{test_panic_handler, test_runner};
 
 /// General initialisation routines.
-pub fn init() {
+pub fn init(boot_info: &'static BootInfo) {
+    memory::init(boot_info);
+    allocator::init();
+    vga_text::scrollback::set_depth(vga_text::scrollback::DEFAULT_DEPTH);
+    logger::init();
     gdt::init();
     interrupts::init_idt();
     unsafe { interrupts::PICS.lock().initialize() };
+    interrupts::init_timer();
     // Execute `sti` instruction to enable external interrupts.
     x86_64::instructions::interrupts::enable();
+    serial::init();
+    fs::devfs::init();
+    ata::init();
+    pci::init();
+    virtio::init();
+    framebuffer::init();
+    #[cfg(feature = "framebuffer")]
+    if let Err(e) = framebuffer::console::enable() {
+        log::warn!("staying in text mode: {}", e);
+    }
 }
 
 /// Halt the CPU until the next interrupt arrives. Thin wrapper around the assembly instruction.
@@ -0,0 +1,173 @@
+//! Kernel logging through the [log] crate.
+//!
+//! Anything in the kernel can log with `log::error!`, `log::info!` and the like. Each record is
+//! stamped with the time since boot and its target, which is the module it came from unless
+//! given, and goes to every [Sink] whose level filter lets it through. The filters start out as
+//! [DEFAULT_LEVELS] and can be changed with [set_level].
+
+use alloc::format;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::{
+    dmesg::{self, Timestamp},
+    serial::{self, Role},
+    vga_text::{vt, VgaFgColour},
+};
+
+/// Level filter of each [Sink] at boot: warnings on the screen, where they'd get in the way of the
+/// shell otherwise, information on the serial port, and everything but tracing in the ring.
+pub const DEFAULT_LEVELS: [LevelFilter; 3] =
+    [LevelFilter::Warn, LevelFilter::Info, LevelFilter::Debug];
+
+static LOGGER: KernelLogger = KernelLogger;
+
+/// Level filter of each [Sink], as `LevelFilter as u8`.
+static LEVELS: [AtomicU8; 3] = [
+    AtomicU8::new(DEFAULT_LEVELS[0] as u8),
+    AtomicU8::new(DEFAULT_LEVELS[1] as u8),
+    AtomicU8::new(DEFAULT_LEVELS[2] as u8),
+];
+
+/// Where log records go.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Sink {
+    /// The VGA text screen, on the log [terminal](vt).
+    Vga,
+    /// The serial port with the [Role::Log] role.
+    Serial,
+    /// The [dmesg] ring of kernel messages.
+    Ring,
+}
+impl Sink {
+    /// Every sink.
+    pub const ALL: [Self; 3] = [Self::Vga, Self::Serial, Self::Ring];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Start handling the `log` macros. Records logged before this are dropped.
+pub fn init() {
+    // Only fails if this has already happened
+    let _ = log::set_logger(&LOGGER);
+    update_max_level();
+}
+
+/// Set the most verbose level `sink` shows.
+pub fn set_level(sink: Sink, filter: LevelFilter) {
+    LEVELS[sink.index()].store(filter as u8, Ordering::Relaxed);
+    update_max_level();
+}
+
+/// The most verbose level `sink` shows.
+pub fn level(sink: Sink) -> LevelFilter {
+    let value = LEVELS[sink.index()].load(Ordering::Relaxed);
+    LevelFilter::iter()
+        .nth(usize::from(value))
+        .unwrap_or(LevelFilter::Trace)
+}
+
+/// Let `log` skip formatting records no sink would show.
+fn update_max_level() {
+    let max = Sink::ALL.into_iter().map(level).max();
+    log::set_max_level(max.unwrap_or(LevelFilter::Off));
+}
+
+/// The target to show for `record`. Kernel modules are shown without the crate name, so
+/// `tlenek_core::ata` is just `ata`.
+fn short_target<'a>(record: &Record<'a>) -> &'a str {
+    let target = record.target();
+    target
+        .strip_prefix(concat!(env!("CARGO_CRATE_NAME"), "::"))
+        .unwrap_or(target)
+}
+
+fn level_colour(level: Level) -> VgaFgColour {
+    match level {
+        Level::Error => VgaFgColour::LightRed,
+        Level::Warn => VgaFgColour::Yellow,
+        Level::Info => VgaFgColour::LightGreen,
+        Level::Debug => VgaFgColour::LightCyan,
+        Level::Trace => VgaFgColour::DarkGray,
+    }
+}
+
+/// Sends records to the sinks.
+struct KernelLogger;
+impl KernelLogger {
+    fn write_vga(timestamp: Timestamp, record: &Record, target: &str) {
+        // Not printed, as the ring sink records it if it's wanted there
+        let vt = vt::role(vt::Role::Log);
+        vt.write_fmt(format_args!("[{}] ", timestamp));
+        let old_attr = vt.attr();
+        let mut attr = old_attr;
+        attr.set_fg(level_colour(record.level()));
+        vt.set_attr(attr);
+        vt.write_fmt(format_args!("{:<5}", record.level()));
+        vt.set_attr(old_attr);
+        vt.write_fmt(format_args!(" {}: {}\n", target, record.args()));
+    }
+
+    fn write_serial(timestamp: Timestamp, record: &Record, target: &str) {
+        if let Some(com) = serial::role(Role::Log) {
+            let line = format!(
+                "[{}] {:<5} {}: {}\n",
+                timestamp,
+                record.level(),
+                target,
+                record.args()
+            );
+            com.write_bytes(line.as_bytes());
+        }
+    }
+}
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        Sink::ALL
+            .into_iter()
+            .any(|sink| metadata.level() <= level(sink))
+    }
+
+    fn log(&self, record: &Record) {
+        let timestamp = Timestamp::now();
+        let target = short_target(record);
+        for sink in Sink::ALL {
+            if record.level() > level(sink) {
+                continue;
+            }
+            match sink {
+                Sink::Vga => Self::write_vga(timestamp, record, target),
+                Sink::Serial => Self::write_serial(timestamp, record, target),
+                Sink::Ring => dmesg::log(record.level(), target, *record.args()),
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn level_round_trip() {
+        let old = level(Sink::Ring);
+        for filter in LevelFilter::iter() {
+            set_level(Sink::Ring, filter);
+            assert_eq!(level(Sink::Ring), filter);
+        }
+        set_level(Sink::Ring, old);
+    }
+
+    #[test_case]
+    fn targets_shortened() {
+        let record = Record::builder().target("tlenek_core::ata").build();
+        assert_eq!(short_target(&record), "ata");
+        let record = Record::builder().target("elsewhere").build();
+        assert_eq!(short_target(&record), "elsewhere");
+    }
+}
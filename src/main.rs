@@ -4,11 +4,13 @@
 #![test_runner(test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
+use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
 #[cfg(not(test))]
-use tlenek_core::vga_text::VgaBgColour;
+use tlenek_core::{dmesg, hlt_loop, vga_text::VgaBgColour};
 use tlenek_core::{
-    hlt_loop, init, print, println,
+    fs::{devfs::DevFs, procfs::ProcFs, ramfs::RamFs},
+    init, print, println, shell, vfs,
     vga_text::{
         set_default_vga_attr, set_vga_attr, set_vga_fg, vga_bg, vga_blink, vga_fg, VgaFgColour,
     },
@@ -18,19 +20,19 @@ use tlenek_core::{test_panic_handler, test_runner};
 
 const VERSION_MSG: &str = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
 
-/// Entry point.
-///
-/// Use Linux conventions- make sure it's called `_start`
-#[no_mangle]
-pub extern "C" fn _start() -> ! {
-    init();
+entry_point!(kernel_main);
+
+/// Entry point, called by the bootloader through `_start`.
+fn kernel_main(boot_info: &'static BootInfo) -> ! {
+    init(boot_info);
+    mount_filesystems();
 
     welcome();
 
     #[cfg(test)]
     test_main();
 
-    hlt_loop();
+    shell::run();
 }
 
 /// Called on panic.
@@ -40,6 +42,8 @@ fn panic(info: &PanicInfo) -> ! {
     // format & print panic message
     set_vga_attr(VgaBgColour::default(), VgaFgColour::LightRed, false);
     println!("{}", info);
+    // ...keep what led up to it...
+    dmesg::dump_to_serial();
     // ...loop forever...
     hlt_loop();
 }
@@ -51,6 +55,15 @@ fn panic(info: &PanicInfo) -> ! {
     test_panic_handler(info)
 }
 
+/// Mount the root filesystem, `/dev` and `/proc`.
+fn mount_filesystems() {
+    vfs::mount("/", RamFs::new()).expect("failed to mount root");
+    vfs::mkdir("/dev").expect("failed to create /dev");
+    vfs::mount("/dev", DevFs::new()).expect("failed to mount /dev");
+    vfs::mkdir("/proc").expect("failed to create /proc");
+    vfs::mount("/proc", ProcFs::new()).expect("failed to mount /proc");
+}
+
 /// Friendly welcome message.
 fn welcome() {
     let old_bg = vga_bg();
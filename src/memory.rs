@@ -0,0 +1,219 @@
+//! Physical memory and paging.
+//!
+//! The bootloader maps all of physical memory at a fixed offset (see
+//! [BootInfo::physical_memory_offset]), which lets the kernel reach page tables and DMA buffers.
+//! Physical frames are handed out from the usable regions of the boot memory map and are never
+//! freed.
+
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use bootloader::BootInfo;
+use spin::Mutex;
+use x86_64::{
+    registers::control::Cr3,
+    structures::paging::{
+        FrameAllocator, Mapper, OffsetPageTable, Page, PageSize, PageTable, PageTableFlags,
+        PhysFrame, Size4KiB, Translate,
+    },
+    PhysAddr, VirtAddr,
+};
+
+/// Size of a page (and a physical frame) in bytes.
+pub const PAGE_SIZE: u64 = Size4KiB::SIZE;
+
+static MEMORY: Mutex<Option<Memory>> = Mutex::new(None);
+
+struct Memory {
+    mapper: OffsetPageTable<'static>,
+    frames: BootInfoFrameAllocator,
+}
+
+/// Errors returned by memory operations.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MemoryError {
+    /// [init] hasn't been called yet.
+    Uninitialised,
+    /// No physical frames are left.
+    OutOfFrames,
+    /// The page table couldn't be updated.
+    MapFailed,
+}
+
+/// Statistics about physical frame usage.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FrameStats {
+    /// Frames in usable regions of the boot memory map.
+    pub total: u64,
+    /// Frames handed out so far.
+    pub allocated: u64,
+}
+
+/// Hands out frames from the usable regions of the boot memory map, in order.
+struct BootInfoFrameAllocator {
+    memory_map: &'static MemoryMap,
+    // Physical address of the next frame to hand out
+    next: u64,
+    allocated: u64,
+}
+impl BootInfoFrameAllocator {
+    fn usable_regions(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.memory_map
+            .iter()
+            .filter(|r| r.region_type == MemoryRegionType::Usable)
+            .map(|r| (r.range.start_addr(), r.range.end_addr()))
+    }
+
+    /// Allocate `count` physically contiguous frames.
+    fn allocate_contiguous(&mut self, count: u64) -> Option<PhysFrame> {
+        let len = count * PAGE_SIZE;
+        let (start, _) = self
+            .usable_regions()
+            .map(|(start, end)| (start.max(self.next), end))
+            .find(|&(start, end)| start + len <= end)?;
+
+        self.next = start + len;
+        self.allocated += count;
+        Some(PhysFrame::containing_address(PhysAddr::new(start)))
+    }
+
+    fn stats(&self) -> FrameStats {
+        FrameStats {
+            total: self
+                .usable_regions()
+                .map(|(start, end)| (end - start) / PAGE_SIZE)
+                .sum(),
+            allocated: self.allocated,
+        }
+    }
+}
+// UNSAFE: Only frames marked usable by the bootloader are returned, and each is returned once.
+unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        self.allocate_contiguous(1)
+    }
+}
+
+/// Set up access to the active page table and the physical frame allocator.
+pub fn init(boot_info: &'static BootInfo) {
+    let offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let (level_4_frame, _) = Cr3::read();
+    let level_4_table: *mut PageTable =
+        (offset + level_4_frame.start_address().as_u64()).as_mut_ptr();
+
+    // UNSAFE: The bootloader guarantees that all physical memory is mapped at `offset`, and this
+    // is the only place a reference to the active level 4 table is created.
+    let mapper = unsafe { OffsetPageTable::new(&mut *level_4_table, offset) };
+
+    *MEMORY.lock() = Some(Memory {
+        mapper,
+        frames: BootInfoFrameAllocator {
+            memory_map: &boot_info.memory_map,
+            next: 0,
+            allocated: 0,
+        },
+    });
+}
+
+fn with_memory<T>(f: impl FnOnce(&mut Memory) -> Result<T, MemoryError>) -> Result<T, MemoryError> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        MEMORY
+            .lock()
+            .as_mut()
+            .ok_or(MemoryError::Uninitialised)
+            .and_then(f)
+    })
+}
+
+/// Get the virtual address at which the given physical address is reachable.
+pub fn phys_to_virt(addr: PhysAddr) -> Result<VirtAddr, MemoryError> {
+    with_memory(|m| Ok(m.mapper.phys_offset() + addr.as_u64()))
+}
+
+/// Translate a virtual address to the physical address it's mapped to.
+pub fn virt_to_phys(addr: VirtAddr) -> Result<Option<PhysAddr>, MemoryError> {
+    with_memory(|m| Ok(m.mapper.translate_addr(addr)))
+}
+
+/// Allocate `pages` zeroed, physically contiguous pages for DMA. Returns both the virtual and
+/// the physical address of the first byte.
+pub fn alloc_dma(pages: u64) -> Result<(VirtAddr, PhysAddr), MemoryError> {
+    with_memory(|m| {
+        let frame = m
+            .frames
+            .allocate_contiguous(pages)
+            .ok_or(MemoryError::OutOfFrames)?;
+        let phys = frame.start_address();
+        let virt = m.mapper.phys_offset() + phys.as_u64();
+        // UNSAFE: The frames were just allocated, so nothing else refers to them.
+        unsafe {
+            core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, (pages * PAGE_SIZE) as usize);
+        }
+        Ok((virt, phys))
+    })
+}
+
+/// Make the physical range `phys..(phys + size)` (e.g. a PCI memory BAR) accessible, uncached,
+/// and return its virtual address.
+pub fn map_mmio(phys: PhysAddr, size: u64) -> Result<VirtAddr, MemoryError> {
+    with_memory(|m| {
+        let virt = m.mapper.phys_offset() + phys.as_u64();
+        let first = Page::<Size4KiB>::containing_address(virt);
+        let last = Page::<Size4KiB>::containing_address(virt + size.saturating_sub(1));
+        let flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::NO_CACHE
+            | PageTableFlags::WRITE_THROUGH;
+
+        for page in Page::range_inclusive(first, last) {
+            // Device memory above the end of RAM isn't covered by the bootloader's mapping
+            if m.mapper.translate_addr(page.start_address()).is_some() {
+                continue;
+            }
+            let frame = PhysFrame::containing_address(PhysAddr::new(
+                page.start_address() - m.mapper.phys_offset(),
+            ));
+            // UNSAFE: The page lies in the physical memory window and maps exactly the frame
+            // the rest of the kernel expects to find there.
+            unsafe {
+                m.mapper
+                    .map_to(page, frame, flags, &mut m.frames)
+                    .map_err(|_| MemoryError::MapFailed)?
+                    .flush();
+            }
+        }
+        Ok(virt)
+    })
+}
+
+/// Statistics about physical frame usage.
+pub fn frame_stats() -> Result<FrameStats, MemoryError> {
+    with_memory(|m| Ok(m.frames.stats()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn dma_is_contiguous_and_zeroed() {
+        let (virt, phys) = alloc_dma(2).unwrap();
+        assert!(phys.is_aligned(PAGE_SIZE));
+        for page in 0..2 {
+            let offset = page * PAGE_SIZE;
+            assert_eq!(virt_to_phys(virt + offset).unwrap(), Some(phys + offset));
+        }
+        let bytes =
+            unsafe { core::slice::from_raw_parts(virt.as_ptr::<u8>(), (2 * PAGE_SIZE) as usize) };
+        assert!(bytes.iter().all(|&b| b == 0));
+    }
+
+    #[test_case]
+    fn translate_heap() {
+        let boxed = alloc::boxed::Box::new(42u64);
+        let phys = virt_to_phys(VirtAddr::from_ptr(&*boxed))
+            .unwrap()
+            .expect("heap not mapped");
+        // Same value through the physical memory window
+        let alias = phys_to_virt(phys).unwrap().as_ptr::<u64>();
+        assert_eq!(unsafe { *alias }, 42);
+    }
+}
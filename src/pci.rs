@@ -0,0 +1,705 @@
+//! [PCI](https://wiki.osdev.org/PCI) bus enumeration and driver matching.
+//!
+//! Uses configuration mechanism #1 (I/O ports `0xCF8`/`0xCFC`). Every function on every
+//! reachable bus is found by [init]; drivers then claim devices with [register_driver].
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+const CONFIG_ADDRESS_PORT: u16 = 0x0CF8;
+const CONFIG_DATA_PORT: u16 = 0x0CFC;
+const CONFIG_ENABLE: u32 = 1 << 31;
+
+const MAX_DEVICES: u8 = 32;
+const MAX_FUNCTIONS: u8 = 8;
+
+const VENDOR_NONE: u16 = 0xFFFF;
+
+// Capability pointers are dword-aligned and the low bits are reserved
+const CAPABILITY_POINTER_MASK: u8 = 0xFC;
+// Bound on the capability list walk, in case of a malformed (cyclic) list
+const MAX_CAPABILITIES: usize = 48;
+
+/// Offsets of configuration space registers.
+#[allow(missing_docs)]
+pub mod reg {
+    pub const VENDOR_ID: u8 = 0x00;
+    pub const DEVICE_ID: u8 = 0x02;
+    pub const COMMAND: u8 = 0x04;
+    pub const STATUS: u8 = 0x06;
+    pub const REVISION: u8 = 0x08;
+    pub const PROG_IF: u8 = 0x09;
+    pub const SUBCLASS: u8 = 0x0A;
+    pub const CLASS: u8 = 0x0B;
+    pub const HEADER_TYPE: u8 = 0x0E;
+    pub const BAR0: u8 = 0x10;
+    pub const SECONDARY_BUS: u8 = 0x19;
+    pub const SUBSYSTEM_VENDOR_ID: u8 = 0x2C;
+    pub const SUBSYSTEM_ID: u8 = 0x2E;
+    pub const CAPABILITIES: u8 = 0x34;
+    pub const INTERRUPT_LINE: u8 = 0x3C;
+    pub const INTERRUPT_PIN: u8 = 0x3D;
+}
+
+/// Bits of the command register.
+#[allow(missing_docs)]
+pub mod command {
+    pub const IO_SPACE: u16 = 1 << 0;
+    pub const MEMORY_SPACE: u16 = 1 << 1;
+    pub const BUS_MASTER: u16 = 1 << 2;
+    pub const INTERRUPT_DISABLE: u16 = 1 << 10;
+}
+
+/// Bits of the status register.
+#[allow(missing_docs)]
+pub mod status {
+    pub const CAPABILITIES_LIST: u16 = 1 << 4;
+}
+
+const HEADER_TYPE_MASK: u8 = 0x7F;
+const HEADER_MULTIFUNCTION: u8 = 0x80;
+const HEADER_GENERAL: u8 = 0x00;
+const HEADER_PCI_BRIDGE: u8 = 0x01;
+
+const CLASS_BRIDGE: u8 = 0x06;
+const SUBCLASS_PCI_BRIDGE: u8 = 0x04;
+
+const BAR_IO: u32 = 0b1;
+const BAR_MEM_TYPE_MASK: u32 = 0b110;
+const BAR_MEM_TYPE_64: u32 = 0b100;
+const BAR_MEM_PREFETCHABLE: u32 = 0b1000;
+const BAR_IO_ADDR_MASK: u32 = !0b11;
+const BAR_MEM_ADDR_MASK: u32 = !0b1111;
+
+static CONFIG_PORTS: Mutex<(Port<u32>, Port<u32>)> =
+    Mutex::new((Port::new(CONFIG_ADDRESS_PORT), Port::new(CONFIG_DATA_PORT)));
+
+static DEVICES: Mutex<Vec<PciDevice>> = Mutex::new(Vec::new());
+static DRIVERS: Mutex<Vec<&'static PciDriver>> = Mutex::new(Vec::new());
+
+/// The location of a PCI function.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PciAddress {
+    /// Bus number.
+    pub bus: u8,
+    /// Device number on the bus.
+    pub device: u8,
+    /// Function number of the device.
+    pub function: u8,
+}
+impl PciAddress {
+    /// Create a new [PciAddress].
+    pub const fn new(bus: u8, device: u8, function: u8) -> Self {
+        Self {
+            bus,
+            device,
+            function,
+        }
+    }
+
+    fn config_address(&self, offset: u8) -> u32 {
+        CONFIG_ENABLE
+            | (u32::from(self.bus) << 16)
+            | (u32::from(self.device) << 11)
+            | (u32::from(self.function) << 8)
+            | u32::from(offset & 0xFC)
+    }
+
+    /// Read the 32-bit configuration register containing `offset`.
+    pub fn read_u32(&self, offset: u8) -> u32 {
+        let address = self.config_address(offset);
+        let mut ports = CONFIG_PORTS.lock();
+        // UNSAFE: The configuration ports are only ever accessed through this lock, so the
+        // address and data accesses can't be interleaved with anyone else's.
+        unsafe {
+            ports.0.write(address);
+            ports.1.read()
+        }
+    }
+
+    /// Write the 32-bit configuration register containing `offset`.
+    pub fn write_u32(&self, offset: u8, value: u32) {
+        let address = self.config_address(offset);
+        let mut ports = CONFIG_PORTS.lock();
+        // UNSAFE: See `read_u32`.
+        unsafe {
+            ports.0.write(address);
+            ports.1.write(value);
+        }
+    }
+
+    /// Read a 16-bit configuration register.
+    pub fn read_u16(&self, offset: u8) -> u16 {
+        (self.read_u32(offset) >> (u32::from(offset & 0b10) * 8)) as u16
+    }
+
+    /// Write a 16-bit configuration register.
+    pub fn write_u16(&self, offset: u8, value: u16) {
+        let shift = u32::from(offset & 0b10) * 8;
+        let old = self.read_u32(offset);
+        let new = (old & !(0xFFFF << shift)) | (u32::from(value) << shift);
+        self.write_u32(offset, new);
+    }
+
+    /// Read an 8-bit configuration register.
+    pub fn read_u8(&self, offset: u8) -> u8 {
+        (self.read_u32(offset) >> (u32::from(offset & 0b11) * 8)) as u8
+    }
+
+    fn vendor_id(&self) -> u16 {
+        self.read_u16(reg::VENDOR_ID)
+    }
+
+    fn header_type(&self) -> u8 {
+        self.read_u8(reg::HEADER_TYPE)
+    }
+}
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// A decoded base address register.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Bar {
+    /// Memory-mapped region.
+    Memory {
+        /// Physical base address.
+        address: u64,
+        /// Size in bytes.
+        size: u64,
+        /// Whether reads have no side effects.
+        prefetchable: bool,
+        /// Whether the BAR is 64 bits wide (and uses the following BAR slot too).
+        is_64bit: bool,
+    },
+    /// I/O port range.
+    Io {
+        /// First port.
+        port: u16,
+        /// Number of ports.
+        size: u32,
+    },
+}
+
+/// Decode the low BAR register `raw` (and `raw_high`, for 64-bit BARs) along with the size
+/// masks read back after writing all ones. Returns [None] for an unimplemented BAR.
+fn decode_bar(raw: u32, raw_high: u32, mask: u32, mask_high: u32) -> Option<Bar> {
+    if (raw & BAR_IO) != 0 {
+        let mask = mask & BAR_IO_ADDR_MASK & 0xFFFF;
+        if mask == 0 {
+            return None;
+        }
+        return Some(Bar::Io {
+            port: (raw & BAR_IO_ADDR_MASK) as u16,
+            size: (!mask & 0xFFFF) + 1,
+        });
+    }
+
+    let is_64bit = (raw & BAR_MEM_TYPE_MASK) == BAR_MEM_TYPE_64;
+    let low_mask = mask & BAR_MEM_ADDR_MASK;
+    let (address, mask) = if is_64bit {
+        (
+            (u64::from(raw_high) << 32) | u64::from(raw & BAR_MEM_ADDR_MASK),
+            (u64::from(mask_high) << 32) | u64::from(low_mask),
+        )
+    } else if low_mask == 0 {
+        return None;
+    } else {
+        (
+            u64::from(raw & BAR_MEM_ADDR_MASK),
+            // Upper bits don't exist, so extend with ones
+            0xFFFF_FFFF_0000_0000 | u64::from(low_mask),
+        )
+    };
+    if mask == 0 {
+        return None;
+    }
+
+    Some(Bar::Memory {
+        address,
+        size: (!mask).wrapping_add(1),
+        prefetchable: (raw & BAR_MEM_PREFETCHABLE) != 0,
+        is_64bit,
+    })
+}
+
+/// A single PCI function found during enumeration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PciDevice {
+    /// Where the function lives.
+    pub address: PciAddress,
+    /// Vendor ID.
+    pub vendor_id: u16,
+    /// Device ID.
+    pub device_id: u16,
+    /// Subsystem vendor ID (general devices only).
+    pub subsystem_vendor_id: u16,
+    /// Subsystem ID (general devices only).
+    pub subsystem_id: u16,
+    /// Base class code.
+    pub class: u8,
+    /// Subclass code.
+    pub subclass: u8,
+    /// Programming interface.
+    pub prog_if: u8,
+    /// Revision ID.
+    pub revision: u8,
+    /// Header type, without the multifunction bit.
+    pub header_type: u8,
+    /// Decoded base address registers. 64-bit BARs take up two slots, the second being [None].
+    pub bars: [Option<Bar>; 6],
+    /// Legacy PIC interrupt line, or `0xFF` if none.
+    pub interrupt_line: u8,
+    /// Interrupt pin (1 = INTA# ... 4 = INTD#), or 0 if none.
+    pub interrupt_pin: u8,
+    /// Name of the driver bound to this device.
+    pub driver: Option<&'static str>,
+}
+impl PciDevice {
+    fn read(address: PciAddress) -> Self {
+        let header_type = address.header_type() & HEADER_TYPE_MASK;
+        let class = address.read_u8(reg::CLASS);
+        let general = header_type == HEADER_GENERAL;
+
+        let mut device = Self {
+            address,
+            vendor_id: address.vendor_id(),
+            device_id: address.read_u16(reg::DEVICE_ID),
+            subsystem_vendor_id: if general {
+                address.read_u16(reg::SUBSYSTEM_VENDOR_ID)
+            } else {
+                0
+            },
+            subsystem_id: if general {
+                address.read_u16(reg::SUBSYSTEM_ID)
+            } else {
+                0
+            },
+            class,
+            subclass: address.read_u8(reg::SUBCLASS),
+            prog_if: address.read_u8(reg::PROG_IF),
+            revision: address.read_u8(reg::REVISION),
+            header_type,
+            bars: [None; 6],
+            interrupt_line: address.read_u8(reg::INTERRUPT_LINE),
+            interrupt_pin: address.read_u8(reg::INTERRUPT_PIN),
+            driver: None,
+        };
+        device.size_bars();
+        device
+    }
+
+    /// Decode and size every BAR. Decoding is switched off while sizing so the device doesn't
+    /// respond at the temporary all-ones address.
+    fn size_bars(&mut self) {
+        let bar_count = match self.header_type {
+            HEADER_GENERAL => 6,
+            HEADER_PCI_BRIDGE => 2,
+            _ => 0,
+        };
+
+        let command = self.address.read_u16(reg::COMMAND);
+        self.address.write_u16(
+            reg::COMMAND,
+            command & !(command::IO_SPACE | command::MEMORY_SPACE),
+        );
+
+        let mut i = 0;
+        while i < bar_count {
+            let offset = reg::BAR0 + (i as u8) * 4;
+            let raw = self.address.read_u32(offset);
+            let mask = self.probe_bar(offset, raw);
+
+            let is_64bit = (raw & BAR_IO) == 0 && (raw & BAR_MEM_TYPE_MASK) == BAR_MEM_TYPE_64;
+            let (raw_high, mask_high) = if is_64bit && (i + 1) < bar_count {
+                let raw_high = self.address.read_u32(offset + 4);
+                (raw_high, self.probe_bar(offset + 4, raw_high))
+            } else {
+                (0, 0)
+            };
+
+            self.bars[i] = decode_bar(raw, raw_high, mask, mask_high);
+            i += if is_64bit { 2 } else { 1 };
+        }
+
+        self.address.write_u16(reg::COMMAND, command);
+    }
+
+    /// Write all ones to a BAR, read back the size mask, then restore it.
+    fn probe_bar(&self, offset: u8, raw: u32) -> u32 {
+        self.address.write_u32(offset, 0xFFFF_FFFF);
+        let mask = self.address.read_u32(offset);
+        self.address.write_u32(offset, raw);
+        mask
+    }
+
+    /// Check if this function is a PCI-to-PCI bridge.
+    pub fn is_bridge(&self) -> bool {
+        self.header_type == HEADER_PCI_BRIDGE
+            && self.class == CLASS_BRIDGE
+            && self.subclass == SUBCLASS_PCI_BRIDGE
+    }
+
+    /// Set bits in the command register, e.g. [command::BUS_MASTER].
+    pub fn enable(&self, bits: u16) {
+        let command = self.address.read_u16(reg::COMMAND);
+        self.address.write_u16(reg::COMMAND, command | bits);
+    }
+
+    /// Human-readable description of the class code.
+    pub fn class_name(&self) -> &'static str {
+        class_name(self.class, self.subclass)
+    }
+
+    /// Walk the capability list.
+    pub fn capabilities(&self) -> Vec<Capability> {
+        let mut capabilities = Vec::new();
+        if (self.address.read_u16(reg::STATUS) & status::CAPABILITIES_LIST) == 0 {
+            return capabilities;
+        }
+
+        let mut offset = self.address.read_u8(reg::CAPABILITIES) & CAPABILITY_POINTER_MASK;
+        while offset != 0 && capabilities.len() < MAX_CAPABILITIES {
+            capabilities.push(Capability {
+                id: self.address.read_u8(offset),
+                offset,
+            });
+            offset = self.address.read_u8(offset + 1) & CAPABILITY_POINTER_MASK;
+        }
+        capabilities
+    }
+}
+impl fmt::Display for PciDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:04x}:{:04x} {} ({:02x}{:02x}{:02x})",
+            self.address,
+            self.vendor_id,
+            self.device_id,
+            self.class_name(),
+            self.class,
+            self.subclass,
+            self.prog_if
+        )
+    }
+}
+
+/// An entry in a function's capability list.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Capability {
+    /// Capability ID, e.g. `0x05` for MSI or `0x09` for vendor-specific.
+    pub id: u8,
+    /// Offset of the capability in configuration space.
+    pub offset: u8,
+}
+
+/// Human-readable description of a class/subclass pair.
+pub fn class_name(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x00, _) => "Unclassified device",
+        (0x01, 0x01) => "IDE controller",
+        (0x01, 0x06) => "SATA controller",
+        (0x01, 0x08) => "NVMe controller",
+        (0x01, 0x00) => "SCSI storage controller",
+        (0x01, _) => "Mass storage controller",
+        (0x02, 0x00) => "Ethernet controller",
+        (0x02, _) => "Network controller",
+        (0x03, 0x00) => "VGA compatible controller",
+        (0x03, _) => "Display controller",
+        (0x04, _) => "Multimedia controller",
+        (0x05, _) => "Memory controller",
+        (0x06, 0x00) => "Host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI bridge",
+        (0x06, _) => "Bridge",
+        (0x07, _) => "Communication controller",
+        (0x08, _) => "System peripheral",
+        (0x09, _) => "Input device controller",
+        (0x0C, 0x03) => "USB controller",
+        (0x0C, 0x05) => "SMBus",
+        (0x0C, _) => "Serial bus controller",
+        (0x0D, _) => "Wireless controller",
+        (0xFF, _) => "Unassigned class",
+        _ => "Unknown device",
+    }
+}
+
+/// Which devices a [PciDriver] supports. [None] fields match anything.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PciMatch {
+    /// Vendor ID.
+    pub vendor_id: Option<u16>,
+    /// Device ID.
+    pub device_id: Option<u16>,
+    /// Base class code.
+    pub class: Option<u8>,
+    /// Subclass code.
+    pub subclass: Option<u8>,
+}
+impl PciMatch {
+    /// Match a specific vendor and device ID.
+    pub const fn id(vendor_id: u16, device_id: u16) -> Self {
+        Self {
+            vendor_id: Some(vendor_id),
+            device_id: Some(device_id),
+            class: None,
+            subclass: None,
+        }
+    }
+
+    /// Match any device of the given class and subclass.
+    pub const fn class(class: u8, subclass: u8) -> Self {
+        Self {
+            vendor_id: None,
+            device_id: None,
+            class: Some(class),
+            subclass: Some(subclass),
+        }
+    }
+
+    /// Check if `device` matches.
+    pub fn matches(&self, device: &PciDevice) -> bool {
+        self.vendor_id.is_none_or(|v| v == device.vendor_id)
+            && self.device_id.is_none_or(|d| d == device.device_id)
+            && self.class.is_none_or(|c| c == device.class)
+            && self.subclass.is_none_or(|s| s == device.subclass)
+    }
+}
+
+/// Errors returned by a [PciDriver] probe function.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ProbeError {
+    /// The driver doesn't support this particular device after all.
+    Unsupported,
+    /// The device failed to initialise.
+    Failed(&'static str),
+}
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported => write!(f, "unsupported device"),
+            Self::Failed(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// A driver for PCI devices.
+#[derive(Debug)]
+pub struct PciDriver {
+    /// Name of the driver.
+    pub name: &'static str,
+    /// The devices the driver supports.
+    pub matches: &'static [PciMatch],
+    /// Called for each unclaimed matching device. The device is bound to the driver if this
+    /// returns `Ok`.
+    pub probe: fn(&PciDevice) -> Result<(), ProbeError>,
+}
+
+/// Scan every function on `bus`, recursing through bridges.
+fn scan_bus(bus: u8, found: &mut Vec<PciDevice>) {
+    for device in 0..MAX_DEVICES {
+        let address = PciAddress::new(bus, device, 0);
+        if address.vendor_id() == VENDOR_NONE {
+            continue;
+        }
+
+        let functions = if (address.header_type() & HEADER_MULTIFUNCTION) != 0 {
+            MAX_FUNCTIONS
+        } else {
+            1
+        };
+        for function in 0..functions {
+            let address = PciAddress::new(bus, device, function);
+            if address.vendor_id() == VENDOR_NONE {
+                continue;
+            }
+
+            let pci_device = PciDevice::read(address);
+            let secondary_bus = address.read_u8(reg::SECONDARY_BUS);
+            let is_bridge = pci_device.is_bridge();
+            found.push(pci_device);
+            if is_bridge && secondary_bus > bus {
+                scan_bus(secondary_bus, found);
+            }
+        }
+    }
+}
+
+/// Enumerate every PCI function.
+pub fn init() {
+    let mut found = Vec::new();
+
+    // A multifunction host bridge means there are several host controllers, each with its own
+    // root bus numbered after its function
+    let root = PciAddress::new(0, 0, 0);
+    if (root.header_type() & HEADER_MULTIFUNCTION) == 0 {
+        scan_bus(0, &mut found);
+    } else {
+        for function in 0..MAX_FUNCTIONS {
+            if PciAddress::new(0, 0, function).vendor_id() != VENDOR_NONE {
+                scan_bus(function, &mut found);
+            }
+        }
+    }
+
+    for device in &found {
+        log::debug!("{}", device);
+    }
+    *DEVICES.lock() = found;
+
+    // Drivers registered before the scan still get their devices
+    let drivers = DRIVERS.lock().clone();
+    for driver in drivers {
+        bind(driver);
+    }
+}
+
+/// All PCI functions found by [init].
+pub fn devices() -> Vec<PciDevice> {
+    DEVICES.lock().clone()
+}
+
+/// Register `driver` and probe every unclaimed device it matches.
+pub fn register_driver(driver: &'static PciDriver) {
+    DRIVERS.lock().push(driver);
+    bind(driver);
+}
+
+/// Offer every matching unclaimed device to `driver`.
+fn bind(driver: &'static PciDriver) {
+    let candidates: Vec<PciDevice> = DEVICES
+        .lock()
+        .iter()
+        .filter(|d| d.driver.is_none() && driver.matches.iter().any(|m| m.matches(d)))
+        .cloned()
+        .collect();
+
+    // Don't hold the device list while probing; probe functions may well want to look at it
+    for device in candidates {
+        match (driver.probe)(&device) {
+            Ok(()) => (),
+            Err(ProbeError::Unsupported) => continue,
+            Err(e) => {
+                log::warn!("{}: {} probe failed: {}", device.address, driver.name, e);
+                continue;
+            }
+        }
+        log::info!("{}: bound to {}", device.address, driver.name);
+        if let Some(d) = DEVICES
+            .lock()
+            .iter_mut()
+            .find(|d| d.address == device.address)
+        {
+            d.driver = Some(driver.name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // QEMU's default i440FX machine
+    const HOST_BRIDGE: PciMatch = PciMatch::id(0x8086, 0x1237);
+
+    #[test_case]
+    fn finds_host_bridge() {
+        let devices = devices();
+        let host = devices
+            .iter()
+            .find(|d| HOST_BRIDGE.matches(d))
+            .expect("no host bridge");
+        assert_eq!(host.address, PciAddress::new(0, 0, 0));
+        assert_eq!(host.class_name(), "Host bridge");
+    }
+
+    #[test_case]
+    fn decode_io_bar() {
+        let bar = decode_bar(0xC041, 0, 0xFFFF_FFE1, 0);
+        assert_eq!(
+            bar,
+            Some(Bar::Io {
+                port: 0xC040,
+                size: 0x20
+            })
+        );
+    }
+
+    #[test_case]
+    fn decode_memory_bars() {
+        let bar = decode_bar(0xFEBF_0000, 0, 0xFFFF_F000, 0);
+        assert_eq!(
+            bar,
+            Some(Bar::Memory {
+                address: 0xFEBF_0000,
+                size: 0x1000,
+                prefetchable: false,
+                is_64bit: false
+            })
+        );
+
+        let bar = decode_bar(0xFE00_000C, 0x1, 0xFFFF_C00C, 0xFFFF_FFFF);
+        assert_eq!(
+            bar,
+            Some(Bar::Memory {
+                address: 0x1_FE00_0000,
+                size: 0x4000,
+                prefetchable: true,
+                is_64bit: true
+            })
+        );
+    }
+
+    #[test_case]
+    fn decode_unimplemented_bar() {
+        assert_eq!(decode_bar(0, 0, 0, 0), None);
+    }
+
+    fn accept(_: &PciDevice) -> Result<(), ProbeError> {
+        Ok(())
+    }
+
+    fn reject(_: &PciDevice) -> Result<(), ProbeError> {
+        Err(ProbeError::Unsupported)
+    }
+
+    static REJECTING_DRIVER: PciDriver = PciDriver {
+        name: "rejecting",
+        matches: &[HOST_BRIDGE],
+        probe: reject,
+    };
+
+    static ACCEPTING_DRIVER: PciDriver = PciDriver {
+        name: "accepting",
+        matches: &[HOST_BRIDGE],
+        probe: accept,
+    };
+
+    #[test_case]
+    fn register_driver_binds() {
+        let host_driver = || {
+            devices()
+                .into_iter()
+                .find(|d| HOST_BRIDGE.matches(d))
+                .and_then(|d| d.driver)
+        };
+
+        register_driver(&REJECTING_DRIVER);
+        assert_eq!(host_driver(), None);
+        register_driver(&ACCEPTING_DRIVER);
+        assert_eq!(host_driver(), Some("accepting"));
+    }
+
+    #[test_case]
+    fn match_class() {
+        let devices = devices();
+        let bridge = PciMatch::class(CLASS_BRIDGE, 0x00);
+        assert!(devices.iter().any(|d| bridge.matches(d)));
+    }
+}
@@ -0,0 +1,70 @@
+//! Rebooting and powering off the machine.
+
+use x86_64::{
+    instructions::{self, interrupts, port::Port, tables::lidt},
+    structures::DescriptorTablePointer,
+    VirtAddr,
+};
+
+use crate::{block::cache::CACHE, vfs};
+
+const PS2_STATUS_PORT: u16 = 0x64;
+const PS2_COMMAND_PORT: u16 = 0x64;
+const PS2_STATUS_INPUT_FULL: u8 = 1 << 1;
+/// Pulses the CPU reset line.
+const PS2_COMMAND_RESET: u8 = 0xFE;
+
+/// ACPI PM1a control ports and the values that power off QEMU, Bochs (and older QEMU) and
+/// VirtualBox.
+const ACPI_SHUTDOWN: [(u16, u16); 3] = [(0x0604, 0x2000), (0xB004, 0x2000), (0x4004, 0x3400)];
+
+/// Sync filesystems and the block cache so nothing is lost when the machine goes down. There's
+/// nowhere to report failures to at this point, so they're ignored.
+fn prepare() {
+    let _ = vfs::sync();
+    let _ = CACHE.sync();
+    interrupts::disable();
+}
+
+/// Restart the machine.
+pub fn reboot() -> ! {
+    prepare();
+
+    let mut status = Port::<u8>::new(PS2_STATUS_PORT);
+    let mut command = Port::<u8>::new(PS2_COMMAND_PORT);
+    // UNSAFE: Only the keyboard controller's own ports are touched, and the machine is going down
+    // anyway.
+    unsafe {
+        while (status.read() & PS2_STATUS_INPUT_FULL) != 0 {}
+        command.write(PS2_COMMAND_RESET);
+    }
+
+    // If the controller didn't reset the machine, a triple fault will
+    let empty = DescriptorTablePointer {
+        limit: 0,
+        base: VirtAddr::zero(),
+    };
+    // UNSAFE: Taking an exception without an IDT is exactly what's wanted here.
+    unsafe {
+        lidt(&empty);
+    }
+    instructions::interrupts::int3();
+    unreachable!("survived a triple fault");
+}
+
+/// Power off the machine. Only works on emulators; real hardware needs the ACPI tables to be
+/// parsed, so there it just halts.
+pub fn shutdown() -> ! {
+    prepare();
+
+    for (port, value) in ACPI_SHUTDOWN {
+        // UNSAFE: Nothing else lives at these ports on the emulators that use them.
+        unsafe {
+            Port::<u16>::new(port).write(value);
+        }
+    }
+
+    loop {
+        instructions::hlt();
+    }
+}
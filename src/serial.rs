@@ -1,28 +1,419 @@
 //! Serial port functionality.
+//!
+//! Up to four UARTs are found at boot, at the addresses the firmware left in the BIOS data area or
+//! at the standard COM port addresses, and confirmed through their scratch registers. Each one
+//! can be [configured](Com::configure) on its own, and is given [Role]s which decide what it's
+//! used for, so one port can carry the console while another carries logs or a debugger. COM1 is
+//! the console until told otherwise, and works before [init] so early output isn't lost.
+//!
+//! Output is written straight to the UART. Input arrives on the port's interrupt and is buffered
+//! until it's picked up with [read_byte] or [read_line], so the kernel can be driven entirely over
+//! the serial port.
 
-use core::fmt::{self, Write};
+use alloc::{string::String, vec::Vec};
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+};
 
-use lazy_static::lazy_static;
 use spin::Mutex;
-use uart_16550::SerialPort;
-use x86_64::instructions::interrupts;
+use x86_64::{instructions::interrupts, PhysAddr};
 
-const SERIAL1_PORT: u16 = 0x03F8;
+use crate::{
+    interrupts::{hlt_unless, unmask, InterruptIndex},
+    memory,
+};
 
-lazy_static! {
-    /// The serial port.
-    pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(SERIAL1_PORT) };
-        serial_port.init();
-        Mutex::new(serial_port)
+pub mod uart;
+
+use uart::Uart;
+pub use uart::{Config, DataBits, FifoTrigger, Parity, StopBits};
+
+const COM1_BASE: u16 = 0x03F8;
+const COM2_BASE: u16 = 0x02F8;
+const COM3_BASE: u16 = 0x03E8;
+const COM4_BASE: u16 = 0x02E8;
+
+/// Physical address of the BIOS data area's table of COM port base addresses.
+const BDA_COM_PORTS: u64 = 0x0400;
+
+/// Most received bytes held for each port before they're picked up.
+pub const RX_BUFFER_SIZE: usize = 1024;
+
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7F;
+
+// UNSAFE: These are the standard COM port addresses, and `init` only moves them to where the
+// firmware says the ports are.
+static PORTS: [Mutex<Uart>; 4] = unsafe {
+    [
+        Mutex::new(Uart::new(COM1_BASE)),
+        Mutex::new(Uart::new(COM2_BASE)),
+        Mutex::new(Uart::new(COM3_BASE)),
+        Mutex::new(Uart::new(COM4_BASE)),
+    ]
+};
+
+/// Which ports were found. COM1 is assumed to be there until [init] looks.
+static PRESENT: [AtomicBool; 4] = [
+    AtomicBool::new(true),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+];
+
+static RX: [Mutex<ByteQueue>; 4] = [const { Mutex::new(ByteQueue::new()) }; 4];
+
+/// The port given each [Role], as `1 + Com::index`, or 0 for none.
+static ROLES: [AtomicU8; 3] = [AtomicU8::new(1), AtomicU8::new(1), AtomicU8::new(0)];
+
+/// Whether the last byte [read_line] took was a carriage return, so a line feed right after it
+/// doesn't end another line.
+static AFTER_CR: AtomicBool = AtomicBool::new(false);
+
+/// Errors returned when using serial ports.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SerialError {
+    /// The port wasn't found.
+    NotPresent(Com),
+    /// The baud rate can't be generated from the UART's clock.
+    BadBaudRate(u32),
+}
+impl fmt::Display for SerialError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotPresent(com) => write!(f, "{} not present", com),
+            Self::BadBaudRate(baud_rate) => write!(f, "unsupported baud rate {}", baud_rate),
+        }
+    }
+}
+
+/// The four PC serial ports.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[allow(missing_docs)]
+pub enum Com {
+    Com1,
+    Com2,
+    Com3,
+    Com4,
+}
+impl Com {
+    /// Every port, in order.
+    pub const ALL: [Self; 4] = [Self::Com1, Self::Com2, Self::Com3, Self::Com4];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn from_index(index: usize) -> Option<Self> {
+        Self::ALL.get(index).copied()
+    }
+
+    fn interrupt_index(self) -> InterruptIndex {
+        match self {
+            Self::Com1 | Self::Com3 => InterruptIndex::Serial1,
+            Self::Com2 | Self::Com4 => InterruptIndex::Serial2,
+        }
+    }
+
+    /// Name of the port under `/dev`.
+    pub fn device_name(self) -> &'static str {
+        match self {
+            Self::Com1 => "ttyS0",
+            Self::Com2 => "ttyS1",
+            Self::Com3 => "ttyS2",
+            Self::Com4 => "ttyS3",
+        }
+    }
+
+    /// Whether the port was found.
+    pub fn is_present(self) -> bool {
+        PRESENT[self.index()].load(Ordering::Relaxed)
+    }
+
+    fn check_present(self) -> Result<(), SerialError> {
+        match self.is_present() {
+            true => Ok(()),
+            false => Err(SerialError::NotPresent(self)),
+        }
+    }
+
+    /// The first I/O port of the UART.
+    pub fn base(self) -> u16 {
+        interrupts::without_interrupts(|| PORTS[self.index()].lock().base())
+    }
+
+    /// The port's line settings, or `None` if it isn't present.
+    pub fn config(self) -> Option<Config> {
+        if !self.is_present() {
+            return None;
+        }
+        interrupts::without_interrupts(|| PORTS[self.index()].lock().config())
+    }
+
+    /// Change the port's line settings.
+    pub fn configure(self, config: Config) -> Result<(), SerialError> {
+        self.check_present()?;
+        interrupts::without_interrupts(|| PORTS[self.index()].lock().configure(config))
+    }
+
+    /// Run `f` on the port's UART, configuring it with the defaults first if that hasn't happened
+    /// yet. Does nothing if the port isn't present.
+    fn with_uart<T>(self, f: impl FnOnce(&mut Uart) -> T) -> Option<T> {
+        if !self.is_present() {
+            return None;
+        }
+        interrupts::without_interrupts(|| {
+            let mut uart = PORTS[self.index()].lock();
+            if uart.config().is_none() {
+                // The default baud rate is always supported
+                let _ = uart.configure(Config::default());
+            }
+            Some(f(&mut uart))
+        })
+    }
+
+    /// Send raw bytes through the port.
+    pub fn write_bytes(self, bytes: &[u8]) {
+        self.with_uart(|uart| {
+            for &byte in bytes {
+                uart.send(byte);
+            }
+        });
+    }
+
+    /// Take the oldest byte received on the port, if there is one.
+    pub fn read_byte(self) -> Option<u8> {
+        interrupts::without_interrupts(|| RX[self.index()].lock().pop())
+    }
+
+    /// Number of bytes received on the port and waiting to be read.
+    pub fn bytes_waiting(self) -> usize {
+        interrupts::without_interrupts(|| RX[self.index()].lock().len)
+    }
+
+    /// Loop transmitted bytes straight back into the port's receiver instead of sending them, or
+    /// go back to normal. Lets the receive path be tested without anything on the other end.
+    pub fn set_loopback(self, enabled: bool) {
+        self.with_uart(|uart| uart.set_loopback(enabled));
+    }
+}
+impl fmt::Display for Com {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "COM{}", self.index() + 1)
+    }
+}
+
+/// What a serial port is used for. A port can have several roles.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Role {
+    /// Kernel console and shell, and test output. COM1 by default.
+    Console,
+    /// Kernel log. COM1 by default.
+    Log,
+    /// Debugger. None by default.
+    Debug,
+}
+impl Role {
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Use `com` for `role`, or nothing if `com` is `None`.
+pub fn set_role(role: Role, com: Option<Com>) -> Result<(), SerialError> {
+    if let Some(com) = com {
+        com.check_present()?;
+    }
+    let value = com.map_or(0, |com| com.index() as u8 + 1);
+    ROLES[role.index()].store(value, Ordering::Relaxed);
+    Ok(())
+}
+
+/// The port used for `role`, if any.
+pub fn role(role: Role) -> Option<Com> {
+    match ROLES[role.index()].load(Ordering::Relaxed) {
+        0 => None,
+        value => Com::from_index(usize::from(value) - 1),
+    }
+}
+
+/// The ports that were found.
+pub fn ports() -> Vec<Com> {
+    Com::ALL
+        .into_iter()
+        .filter(|com| com.is_present())
+        .collect()
+}
+
+/// A ring buffer of received bytes.
+struct ByteQueue {
+    bytes: [u8; RX_BUFFER_SIZE],
+    head: usize,
+    len: usize,
+}
+impl ByteQueue {
+    const fn new() -> Self {
+        Self {
+            bytes: [0; RX_BUFFER_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Add `byte` to the back of the queue. Returns `false` if the queue is full.
+    fn push(&mut self, byte: u8) -> bool {
+        if self.len == RX_BUFFER_SIZE {
+            return false;
+        }
+        self.bytes[(self.head + self.len) % RX_BUFFER_SIZE] = byte;
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.bytes[self.head];
+        self.head = (self.head + 1) % RX_BUFFER_SIZE;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+/// Base addresses of COM1 to COM4 recorded by the firmware in the BIOS data area. Zero where the
+/// firmware found nothing, or everywhere if the BIOS data area can't be reached.
+fn bios_bases() -> [u16; 4] {
+    let Ok(addr) = memory::phys_to_virt(PhysAddr::new(BDA_COM_PORTS)) else {
+        return [0; 4];
     };
+    let table = addr.as_ptr::<u16>();
+    // UNSAFE: All of physical memory is mapped, and the BIOS data area's first four words are the
+    // COM port table.
+    core::array::from_fn(|i| unsafe { table.add(i).read_volatile() })
+}
+
+/// Find the serial ports, configure the ones that haven't been with the defaults, and start
+/// receiving on their interrupts. Roles given to ports that weren't found are dropped.
+pub fn init() {
+    let bios_bases = bios_bases();
+    for com in Com::ALL {
+        let base = match bios_bases[com.index()] {
+            0 => com.base(),
+            base => base,
+        };
+        let present = interrupts::without_interrupts(|| {
+            let mut uart = PORTS[com.index()].lock();
+            // UNSAFE: `base` is either where the firmware found this port or its standard address.
+            unsafe {
+                if uart.base() != base {
+                    *uart = Uart::new(base);
+                }
+                uart::probe(base)
+            }
+        });
+        PRESENT[com.index()].store(present, Ordering::Relaxed);
+    }
+
+    for role in [Role::Console, Role::Log, Role::Debug] {
+        if self::role(role).is_some_and(|com| !com.is_present()) {
+            let _ = set_role(role, None);
+        }
+    }
+
+    for com in ports() {
+        // Configures the port with the defaults unless that's already happened
+        com.with_uart(|_| ());
+        unmask(com.interrupt_index());
+        if let Some(config) = com.config() {
+            log::info!("{}: {:#x}, {}", com, com.base(), config);
+        }
+    }
+}
+
+/// Move everything in the receive FIFOs of the ports on `interrupt_index` into their buffers.
+/// Called by the serial interrupt handlers. Bytes arriving while a buffer is full are dropped.
+pub fn handle_interrupt(interrupt_index: InterruptIndex) {
+    let ports = Com::ALL
+        .into_iter()
+        .filter(|com| u8::from(com.interrupt_index()) == u8::from(interrupt_index))
+        .filter(|com| com.is_present());
+    for com in ports {
+        let mut uart = PORTS[com.index()].lock();
+        let mut rx = RX[com.index()].lock();
+        while let Some(byte) = uart.try_receive() {
+            rx.push(byte);
+        }
+    }
+}
+
+/// Take the oldest byte received on the console port, if there is one.
+pub fn read_byte() -> Option<u8> {
+    role(Role::Console).and_then(Com::read_byte)
+}
+
+/// Number of bytes received on the console port and waiting to be read.
+pub fn bytes_waiting() -> usize {
+    role(Role::Console).map_or(0, Com::bytes_waiting)
+}
+
+/// Take the oldest byte received on the console port, halting until one arrives.
+pub fn wait_for_byte() -> u8 {
+    loop {
+        if let Some(byte) = read_byte() {
+            return byte;
+        }
+        hlt_unless(|| bytes_waiting() > 0);
+    }
+}
+
+/// Read a line from the console port, echoing it back and handling backspace. Returns the line
+/// without its line ending, which may be `\r`, `\n` or `\r\n`. Only printable ASCII is kept.
+pub fn read_line() -> String {
+    let mut line = Vec::new();
+    loop {
+        let byte = wait_for_byte();
+        let after_cr = AFTER_CR.swap(byte == b'\r', Ordering::Relaxed);
+        match byte {
+            b'\n' if after_cr => (),
+            b'\r' | b'\n' => {
+                write_bytes(b"\n");
+                return line.into_iter().map(char::from).collect();
+            }
+            BACKSPACE | DELETE => {
+                if line.pop().is_some() {
+                    write_bytes(b"\x08 \x08");
+                }
+            }
+            b' '..=b'~' => {
+                line.push(byte);
+                write_bytes(&[byte]);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Put the console port in or out of loopback mode. See [Com::set_loopback].
+pub fn set_loopback(enabled: bool) {
+    if let Some(com) = role(Role::Console) {
+        com.set_loopback(enabled);
+    }
+}
+
+/// Send raw bytes through the console port.
+pub fn write_bytes(bytes: &[u8]) {
+    if let Some(com) = role(Role::Console) {
+        com.write_bytes(bytes);
+    }
 }
 
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
-    interrupts::without_interrupts(|| {
-        SERIAL1.lock().write_fmt(args).expect("serial print failed");
-    });
+    if let Some(com) = role(Role::Console) {
+        com.with_uart(|uart| uart.write_fmt(args).expect("serial print failed"));
+    }
 }
 
 /// Prints to host through serial interface.
@@ -40,3 +431,35 @@ macro_rules! serial_println {
     ($fmt:expr) => ($crate::serial_print!(concat!($fmt, "\n")));
     ($fmt:expr, $($arg:tt)*) => ($crate::serial_print!(concat!($fmt, "\n"), $($arg)*));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn queue_order_and_overflow() {
+        let mut queue = ByteQueue::new();
+        assert_eq!(queue.pop(), None);
+        for i in 0..RX_BUFFER_SIZE {
+            assert!(queue.push(i as u8));
+        }
+        assert!(!queue.push(0));
+        for i in 0..RX_BUFFER_SIZE {
+            assert_eq!(queue.pop(), Some(i as u8));
+        }
+        assert_eq!(queue.pop(), None);
+
+        // Wraps around
+        queue.push(b'a');
+        assert_eq!(queue.pop(), Some(b'a'));
+    }
+
+    #[test_case]
+    fn com_ports() {
+        assert_eq!(Com::from_index(2), Some(Com::Com3));
+        assert_eq!(Com::from_index(4), None);
+        assert_eq!(alloc::format!("{}", Com::Com4), "COM4");
+        assert_eq!(Com::Com2.device_name(), "ttyS1");
+        assert_eq!(role(Role::Console), Some(Com::Com1));
+    }
+}
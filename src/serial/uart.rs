@@ -0,0 +1,335 @@
+//! Register-level driver for 16550-compatible UARTs.
+
+use core::fmt;
+
+use x86_64::instructions::port::Port;
+
+use super::SerialError;
+
+/// Input clock of the baud rate generator, divided by 16.
+pub const UART_CLOCK: u32 = 115_200;
+
+// Register offsets from the base port
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+const SCRATCH: u16 = 7;
+// With the divisor latch enabled, the first two registers hold the divisor instead
+const DIVISOR_LOW: u16 = 0;
+const DIVISOR_HIGH: u16 = 1;
+
+const IER_RX_AVAILABLE: u8 = 0b0000_0001;
+
+const FCR_ENABLE: u8 = 0b0000_0001;
+const FCR_CLEAR_RX: u8 = 0b0000_0010;
+const FCR_CLEAR_TX: u8 = 0b0000_0100;
+
+const LCR_TWO_STOP_BITS: u8 = 0b0000_0100;
+const LCR_DIVISOR_LATCH: u8 = 0b1000_0000;
+
+/// Data terminal ready, request to send and OUT2, which gates the IRQ line.
+const MCR_NORMAL: u8 = 0b0000_1011;
+const MCR_LOOPBACK: u8 = 0b0001_0000;
+
+const LSR_DATA_READY: u8 = 0b0000_0001;
+const LSR_TX_EMPTY: u8 = 0b0010_0000;
+
+// How many times to poll for room to send before dropping the byte
+const POLL_LIMIT: usize = 100_000;
+
+/// Bits in each character.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[allow(missing_docs)]
+pub enum DataBits {
+    Five = 5,
+    Six = 6,
+    Seven = 7,
+    Eight = 8,
+}
+
+/// Parity bit added to each character.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Parity {
+    /// No parity bit.
+    None,
+    /// Set to make the number of ones odd.
+    Odd,
+    /// Set to make the number of ones even.
+    Even,
+    /// Always set.
+    Mark,
+    /// Always clear.
+    Space,
+}
+impl Parity {
+    fn line_control_bits(self) -> u8 {
+        match self {
+            Self::None => 0b0000_0000,
+            Self::Odd => 0b0000_1000,
+            Self::Even => 0b0001_1000,
+            Self::Mark => 0b0010_1000,
+            Self::Space => 0b0011_1000,
+        }
+    }
+
+    fn letter(self) -> char {
+        match self {
+            Self::None => 'N',
+            Self::Odd => 'O',
+            Self::Even => 'E',
+            Self::Mark => 'M',
+            Self::Space => 'S',
+        }
+    }
+}
+
+/// Stop bits after each character. With five data bits, [StopBits::Two] gives one and a half.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[allow(missing_docs)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+/// How full the receive FIFO gets before the UART raises an interrupt.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FifoTrigger {
+    /// No FIFO: interrupt on every byte.
+    Disabled,
+    /// Interrupt at 1 byte.
+    Bytes1,
+    /// Interrupt at 4 bytes.
+    Bytes4,
+    /// Interrupt at 8 bytes.
+    Bytes8,
+    /// Interrupt at 14 bytes.
+    Bytes14,
+}
+impl FifoTrigger {
+    fn fifo_control(self) -> u8 {
+        let trigger = match self {
+            Self::Disabled => return 0,
+            Self::Bytes1 => 0b0000_0000,
+            Self::Bytes4 => 0b0100_0000,
+            Self::Bytes8 => 0b1000_0000,
+            Self::Bytes14 => 0b1100_0000,
+        };
+        trigger | FCR_ENABLE | FCR_CLEAR_RX | FCR_CLEAR_TX
+    }
+}
+
+/// Line settings of a serial port. Defaults to 115200 baud, 8N1, with a 14-byte FIFO threshold.
+///
+/// Bytes still in the FIFO when the threshold isn't reached are picked up by the UART's
+/// character timeout interrupt, so a high threshold only saves interrupts.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Config {
+    /// Bits per second. Must divide [UART_CLOCK].
+    pub baud_rate: u32,
+    /// Bits in each character.
+    pub data_bits: DataBits,
+    /// Parity bit added to each character.
+    pub parity: Parity,
+    /// Stop bits after each character.
+    pub stop_bits: StopBits,
+    /// Receive FIFO interrupt threshold.
+    pub fifo_trigger: FifoTrigger,
+}
+impl Config {
+    /// Value of the baud rate divisor, if the baud rate can be generated.
+    pub fn divisor(&self) -> Option<u16> {
+        if self.baud_rate == 0 || UART_CLOCK % self.baud_rate != 0 {
+            return None;
+        }
+        u16::try_from(UART_CLOCK / self.baud_rate).ok()
+    }
+
+    fn line_control(&self) -> u8 {
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 0,
+            StopBits::Two => LCR_TWO_STOP_BITS,
+        };
+        (self.data_bits as u8 - 5) | stop_bits | self.parity.line_control_bits()
+    }
+}
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            baud_rate: UART_CLOCK,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            fifo_trigger: FifoTrigger::Bytes14,
+        }
+    }
+}
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        };
+        write!(
+            f,
+            "{} {}{}{}",
+            self.baud_rate,
+            self.data_bits as u8,
+            self.parity.letter(),
+            stop_bits
+        )
+    }
+}
+
+/// Check whether a UART answers at I/O port `base`, by writing to its scratch register and
+/// reading the values back. Nothing there reads back as all ones.
+///
+/// # Safety
+///
+/// Writes to `base + 7`, which must not belong to some other device.
+pub unsafe fn probe(base: u16) -> bool {
+    let mut scratch = Port::<u8>::new(base + SCRATCH);
+    [0x5A, 0xA5].into_iter().all(|value| {
+        scratch.write(value);
+        scratch.read() == value
+    })
+}
+
+/// A 16550-compatible UART.
+#[derive(Debug)]
+pub struct Uart {
+    base: u16,
+    config: Option<Config>,
+}
+impl Uart {
+    /// The UART at I/O port `base`. Nothing is touched until it's configured.
+    ///
+    /// # Safety
+    ///
+    /// `base` must be where a UART is, or nothing is.
+    pub const unsafe fn new(base: u16) -> Self {
+        Self { base, config: None }
+    }
+
+    /// The first I/O port of the UART.
+    pub fn base(&self) -> u16 {
+        self.base
+    }
+
+    /// The current line settings, or `None` if the UART hasn't been configured.
+    pub fn config(&self) -> Option<Config> {
+        self.config
+    }
+
+    /// Apply `config`, clear the FIFOs and enable the receive interrupt.
+    pub fn configure(&mut self, config: Config) -> Result<(), SerialError> {
+        let divisor = config
+            .divisor()
+            .ok_or(SerialError::BadBaudRate(config.baud_rate))?;
+        let [low, high] = divisor.to_le_bytes();
+        // UNSAFE: These are the registers of the UART at `base`, per `new`.
+        unsafe {
+            self.write(INTERRUPT_ENABLE, 0);
+            self.write(LINE_CONTROL, LCR_DIVISOR_LATCH);
+            self.write(DIVISOR_LOW, low);
+            self.write(DIVISOR_HIGH, high);
+            self.write(LINE_CONTROL, config.line_control());
+            self.write(FIFO_CONTROL, config.fifo_trigger.fifo_control());
+            self.write(MODEM_CONTROL, MCR_NORMAL);
+            self.write(INTERRUPT_ENABLE, IER_RX_AVAILABLE);
+        }
+        self.config = Some(config);
+        Ok(())
+    }
+
+    /// Send a byte, waiting for room in the transmitter. The byte is dropped if there's still no
+    /// room after a while, so a stuck port can't hang the kernel.
+    pub fn send(&mut self, byte: u8) {
+        // UNSAFE: These are the registers of the UART at `base`, per `new`.
+        unsafe {
+            for _ in 0..POLL_LIMIT {
+                if self.read(LINE_STATUS) & LSR_TX_EMPTY != 0 {
+                    self.write(DATA, byte);
+                    return;
+                }
+                core::hint::spin_loop();
+            }
+        }
+    }
+
+    /// Take a received byte, if there is one.
+    pub fn try_receive(&mut self) -> Option<u8> {
+        // UNSAFE: These are the registers of the UART at `base`, per `new`. Reading the data
+        // register only takes the received byte.
+        unsafe { (self.read(LINE_STATUS) & LSR_DATA_READY != 0).then(|| self.read(DATA)) }
+    }
+
+    /// Loop transmitted bytes straight back into the receiver instead of sending them, or go back
+    /// to normal.
+    pub fn set_loopback(&mut self, enabled: bool) {
+        let value = if enabled {
+            MCR_NORMAL | MCR_LOOPBACK
+        } else {
+            MCR_NORMAL
+        };
+        // UNSAFE: This is a register of the UART at `base`, per `new`.
+        unsafe { self.write(MODEM_CONTROL, value) };
+    }
+
+    unsafe fn read(&mut self, register: u16) -> u8 {
+        Port::new(self.base + register).read()
+    }
+
+    unsafe fn write(&mut self, register: u16, value: u8) {
+        Port::new(self.base + register).write(value);
+    }
+}
+impl fmt::Write for Uart {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn divisors() {
+        let at = |baud_rate| Config {
+            baud_rate,
+            ..Config::default()
+        };
+        assert_eq!(at(115_200).divisor(), Some(1));
+        assert_eq!(at(9600).divisor(), Some(12));
+        assert_eq!(at(50).divisor(), Some(2304));
+        assert_eq!(at(0).divisor(), None);
+        assert_eq!(at(100_000).divisor(), None);
+        assert_eq!(at(230_400).divisor(), None);
+    }
+
+    #[test_case]
+    fn line_control() {
+        assert_eq!(Config::default().line_control(), 0b0000_0011);
+        let config = Config {
+            data_bits: DataBits::Seven,
+            parity: Parity::Even,
+            stop_bits: StopBits::Two,
+            ..Config::default()
+        };
+        assert_eq!(config.line_control(), 0b0001_1110);
+        assert_eq!(alloc::format!("{}", config), "115200 7E2");
+    }
+
+    #[test_case]
+    fn fifo_control() {
+        assert_eq!(FifoTrigger::Disabled.fifo_control(), 0);
+        assert_eq!(FifoTrigger::Bytes1.fifo_control(), 0b0000_0111);
+        assert_eq!(FifoTrigger::Bytes14.fifo_control(), 0b1100_0111);
+    }
+}
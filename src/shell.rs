@@ -0,0 +1,111 @@
+//! The interactive kernel shell on the VGA console and the serial port.
+//!
+//! [run] reads lines with a [LineEditor](line::LineEditor), splits them into arguments with
+//! [parse::split] and runs the matching command from [builtins]. Keys come from both the keyboard
+//! and the serial port, and everything the shell prints goes to both the screen and the serial
+//! port, so it works the same headless.
+
+use core::fmt;
+
+use pc_keyboard::DecodedKey;
+
+use crate::{
+    block::cache,
+    interrupts, keyboard, serial,
+    vga_text::{self, set_vga_fg, vga_fg, VgaFgColour},
+};
+
+/// Prints to the screen and the serial port.
+macro_rules! shell_print {
+    ($($arg:tt)*) => {
+        $crate::shell::_print(format_args!($($arg)*))
+    };
+}
+
+/// Prints to the screen and the serial port, with a newline.
+macro_rules! shell_println {
+    () => (shell_print!("\n"));
+    ($fmt:expr) => (shell_print!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => (shell_print!(concat!($fmt, "\n"), $($arg)*));
+}
+
+pub mod builtins;
+pub mod line;
+pub mod parse;
+pub mod term;
+
+const PROMPT: &str = "tlenek> ";
+
+/// Run the shell. Never returns; until there's a scheduler it's the kernel's idle loop, so it
+/// also keeps the block cache written back.
+pub fn run() -> ! {
+    let mut editor = line::LineEditor::new();
+    let mut term = term::TermDecoder::new();
+    loop {
+        prompt();
+        let line = editor.read_line(|| next_key(&mut term));
+        execute(&line);
+    }
+}
+
+/// Run a single command line.
+pub fn execute(line: &str) {
+    let args = match parse::split(line) {
+        Ok(args) => args,
+        Err(e) => return error(format_args!("{}", e)),
+    };
+    let Some((name, args)) = args.split_first() else {
+        return;
+    };
+    match builtins::find(name) {
+        Some(builtin) => {
+            if let Err(message) = (builtin.run)(args) {
+                error(format_args!("{}: {}", name, message));
+            }
+        }
+        None => error(format_args!("{}: command not found", name)),
+    }
+}
+
+fn prompt() {
+    let old_fg = vga_fg();
+    set_vga_fg(VgaFgColour::LightGreen);
+    shell_print!("{}", PROMPT);
+    set_vga_fg(old_fg);
+}
+
+fn error(message: fmt::Arguments) {
+    let old_fg = vga_fg();
+    set_vga_fg(VgaFgColour::LightRed);
+    shell_println!("{}", message);
+    set_vga_fg(old_fg);
+}
+
+/// Write raw bytes to the screen and the serial port.
+pub fn write_bytes(bytes: &[u8]) {
+    vga_text::write_bytes(bytes);
+    serial::write_bytes(bytes);
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    vga_text::write_fmt(args);
+    serial::_print(args);
+}
+
+/// Wait for the next key from the keyboard or the terminal on the serial port, doing the idle
+/// work in the meantime.
+fn next_key(term: &mut term::TermDecoder) -> DecodedKey {
+    loop {
+        if let Some(key) = keyboard::read_key() {
+            return key;
+        }
+        while let Some(byte) = serial::read_byte() {
+            if let Some(key) = term.feed(byte) {
+                return key;
+            }
+        }
+        cache::writeback_due();
+        interrupts::hlt_unless(|| keyboard::key_waiting() || serial::bytes_waiting() > 0);
+    }
+}
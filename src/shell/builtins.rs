@@ -0,0 +1,314 @@
+//! Commands built into the shell.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::{
+    allocator, dmesg, interrupts,
+    memory::{self, PAGE_SIZE},
+    power, serial,
+    vga_text::{
+        clear_screen, palette, set_vga_bg, set_vga_fg, vga_bg, vga_fg, VgaBgColour, VgaFgColour,
+    },
+};
+
+/// Runs a built-in command with the arguments after its name. Returns a message to show the user
+/// if the command failed.
+pub type Handler = fn(&[String]) -> Result<(), String>;
+
+/// A command built into the shell.
+pub struct Builtin {
+    /// Name the command is run by.
+    pub name: &'static str,
+    /// Arguments the command takes, as shown by `help`.
+    pub usage: &'static str,
+    /// One-line description, as shown by `help`.
+    pub summary: &'static str,
+    /// Runs the command.
+    pub run: Handler,
+}
+
+/// Every built-in command, sorted by name.
+pub static BUILTINS: [Builtin; 9] = [
+    Builtin {
+        name: "clear",
+        usage: "",
+        summary: "Clear the screen",
+        run: clear,
+    },
+    Builtin {
+        name: "color",
+        usage: "[FG [BG]]",
+        summary: "Set the text colours, or list them",
+        run: color,
+    },
+    Builtin {
+        name: "dmesg",
+        usage: "[COUNT]",
+        summary: "Show the kernel messages, or the last COUNT",
+        run: dmesg,
+    },
+    Builtin {
+        name: "echo",
+        usage: "[ARG...]",
+        summary: "Print the arguments",
+        run: echo,
+    },
+    Builtin {
+        name: "help",
+        usage: "",
+        summary: "List the built-in commands",
+        run: help,
+    },
+    Builtin {
+        name: "meminfo",
+        usage: "",
+        summary: "Show memory usage",
+        run: meminfo,
+    },
+    Builtin {
+        name: "reboot",
+        usage: "",
+        summary: "Restart the machine",
+        run: reboot,
+    },
+    Builtin {
+        name: "shutdown",
+        usage: "",
+        summary: "Power off the machine",
+        run: shutdown,
+    },
+    Builtin {
+        name: "uptime",
+        usage: "",
+        summary: "Show the time since boot",
+        run: uptime,
+    },
+];
+
+/// The built-in command called `name`.
+pub fn find(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|b| b.name == name)
+}
+
+fn no_args(args: &[String]) -> Result<(), String> {
+    match args.first() {
+        None => Ok(()),
+        Some(arg) => Err(format!("unexpected argument '{}'", arg)),
+    }
+}
+
+fn clear(args: &[String]) -> Result<(), String> {
+    no_args(args)?;
+    clear_screen();
+    // Clear the terminal and home its cursor
+    serial::write_bytes(b"\x1b[2J\x1b[H");
+    Ok(())
+}
+
+const FG_NAMES: [(&str, VgaFgColour); 16] = [
+    ("black", VgaFgColour::Black),
+    ("blue", VgaFgColour::Blue),
+    ("green", VgaFgColour::Green),
+    ("cyan", VgaFgColour::Cyan),
+    ("red", VgaFgColour::Red),
+    ("magenta", VgaFgColour::Magenta),
+    ("brown", VgaFgColour::Brown),
+    ("lightgray", VgaFgColour::LightGray),
+    ("darkgray", VgaFgColour::DarkGray),
+    ("lightblue", VgaFgColour::LightBlue),
+    ("lightgreen", VgaFgColour::LightGreen),
+    ("lightcyan", VgaFgColour::LightCyan),
+    ("lightred", VgaFgColour::LightRed),
+    ("pink", VgaFgColour::Pink),
+    ("yellow", VgaFgColour::Yellow),
+    ("white", VgaFgColour::White),
+];
+
+/// Parse a colour name, ignoring case, `-` and `_` (so `Light-Green` works too).
+fn parse_fg(name: &str) -> Option<VgaFgColour> {
+    let name: String = name
+        .chars()
+        .filter(|c| !matches!(c, '-' | '_'))
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    FG_NAMES
+        .iter()
+        .find(|&&(known, _)| known == name)
+        .map(|&(_, colour)| colour)
+}
+
+fn fg_name(colour: VgaFgColour) -> &'static str {
+    FG_NAMES
+        .iter()
+        .find(|&&(_, known)| known == colour)
+        .map_or("?", |&(name, _)| name)
+}
+
+/// The bits a background colour can have, with the bright one only in use with bright
+/// backgrounds.
+fn bg_mask() -> u8 {
+    if palette::bright_backgrounds() {
+        0xF
+    } else {
+        0x7
+    }
+}
+
+fn color(args: &[String]) -> Result<(), String> {
+    let (fg, bg) = match args {
+        [] => {
+            let bg = VgaFgColour::try_from(u8::from(vga_bg())).map_or("?", fg_name);
+            shell_println!("foreground {}, background {}", fg_name(vga_fg()), bg);
+            let names: Vec<&str> = FG_NAMES.iter().map(|&(name, _)| name).collect();
+            shell_println!("foregrounds: {}", names.join(" "));
+            let backgrounds = &names[..usize::from(bg_mask()) + 1];
+            shell_println!("backgrounds: {}", backgrounds.join(" "));
+            return Ok(());
+        }
+        [fg] => (fg, None),
+        [fg, bg] => (fg, Some(bg)),
+        _ => return Err("expected at most two colours".to_string()),
+    };
+
+    let unknown = |name: &String| format!("unknown colour '{}'", name);
+    let fg = parse_fg(fg).ok_or_else(|| unknown(fg))?;
+    let bg = bg
+        .map(|name| {
+            parse_fg(name)
+                .map(u8::from)
+                .filter(|&colour| colour & !bg_mask() == 0)
+                .and_then(|colour| VgaBgColour::try_from(colour).ok())
+                .ok_or_else(|| format!("'{}' can't be a background colour", name))
+        })
+        .transpose()?;
+
+    set_vga_fg(fg);
+    if let Some(bg) = bg {
+        set_vga_bg(bg);
+    }
+    Ok(())
+}
+
+fn dmesg(args: &[String]) -> Result<(), String> {
+    let messages = dmesg::messages();
+    let count = match args {
+        [] => messages.len(),
+        [count] => count
+            .parse()
+            .map_err(|_| format!("invalid count '{}'", count))?,
+        [_, extra, ..] => return Err(format!("unexpected argument '{}'", extra)),
+    };
+    for message in &messages[messages.len().saturating_sub(count)..] {
+        shell_println!("{}", message);
+    }
+    Ok(())
+}
+
+fn echo(args: &[String]) -> Result<(), String> {
+    shell_println!("{}", args.join(" "));
+    Ok(())
+}
+
+fn help(args: &[String]) -> Result<(), String> {
+    no_args(args)?;
+    for builtin in &BUILTINS {
+        let command = format!("{} {}", builtin.name, builtin.usage);
+        shell_println!("  {:<18}{}", command, builtin.summary);
+    }
+    Ok(())
+}
+
+/// Format `bytes` with a binary unit.
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut unit = 0;
+    let mut value = bytes;
+    while value >= 10 * 1024 && unit + 1 < UNITS.len() {
+        value /= 1024;
+        unit += 1;
+    }
+    format!("{} {}", value, UNITS[unit])
+}
+
+fn meminfo(args: &[String]) -> Result<(), String> {
+    no_args(args)?;
+    let heap = allocator::stats();
+    shell_println!(
+        "heap:     {} used, {} free, {} total",
+        human_size(heap.used as u64),
+        human_size(heap.free as u64),
+        human_size(heap.size as u64)
+    );
+    if let Ok(frames) = memory::frame_stats() {
+        shell_println!(
+            "physical: {} allocated, {} usable",
+            human_size(frames.allocated * PAGE_SIZE),
+            human_size(frames.total * PAGE_SIZE)
+        );
+    }
+    Ok(())
+}
+
+fn reboot(args: &[String]) -> Result<(), String> {
+    no_args(args)?;
+    power::reboot();
+}
+
+fn shutdown(args: &[String]) -> Result<(), String> {
+    no_args(args)?;
+    power::shutdown();
+}
+
+fn uptime(args: &[String]) -> Result<(), String> {
+    no_args(args)?;
+    let seconds = interrupts::ticks_to_hundredths(interrupts::ticks()) / 100;
+    shell_println!(
+        "up {}:{:02}:{:02}",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn sorted_and_found() {
+        assert!(BUILTINS.windows(2).all(|w| w[0].name < w[1].name));
+        assert_eq!(find("echo").map(|b| b.name), Some("echo"));
+        assert!(find("nope").is_none());
+    }
+
+    #[test_case]
+    fn colour_names() {
+        assert_eq!(parse_fg("Light-Green"), Some(VgaFgColour::LightGreen));
+        assert_eq!(parse_fg("light_gray"), Some(VgaFgColour::LightGray));
+        assert_eq!(parse_fg("mauve"), None);
+        assert_eq!(fg_name(VgaFgColour::Pink), "pink");
+    }
+
+    #[test_case]
+    fn sizes() {
+        assert_eq!(human_size(512), "512 B");
+        assert_eq!(human_size(2 * 1024 * 1024), "2048 KiB");
+        assert_eq!(human_size(300 * 1024 * 1024), "300 MiB");
+    }
+
+    #[test_case]
+    fn argument_checks() {
+        let args = [String::from("x")];
+        assert!(no_args(&[]).is_ok());
+        assert!(no_args(&args).is_err());
+        assert!(color(&[String::from("mauve")]).is_err());
+        assert!(color(&[String::from("white"), String::from("yellow")]).is_err());
+        assert!(dmesg(&[String::from("-1")]).is_err());
+        assert!(dmesg(&[String::from("1"), String::from("2")]).is_err());
+    }
+}
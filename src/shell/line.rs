@@ -0,0 +1,292 @@
+//! Line editing on the VGA console.
+
+use alloc::{collections::VecDeque, string::String, vec, vec::Vec};
+
+use pc_keyboard::{DecodedKey, KeyCode};
+
+use crate::{serial, vga_text};
+
+/// Longest line the editor accepts, in characters.
+pub const MAX_LINE: usize = 256;
+
+/// Lines kept in the history.
+pub const MAX_HISTORY: usize = 32;
+
+const BACKSPACE: u8 = 0x08;
+/// Erases from the terminal's cursor to the end of the line.
+const ERASE_TO_END: &[u8] = b"\x1b[K";
+
+/// Reads lines from the keyboard, with cursor movement and a history of earlier lines.
+///
+/// The line is redrawn after every key by erasing it with backspaces and writing it out again,
+/// then the hardware cursor is moved back to the editing position. Only printable ASCII is
+/// accepted, so every character takes up exactly one cell. The line is mirrored to the terminal on
+/// the serial port, which has a cursor of its own.
+pub struct LineEditor {
+    line: Vec<u8>,
+    cursor: usize,
+    history: VecDeque<String>,
+    // Index into `history` of the line being shown while moving through the history
+    recalled: Option<usize>,
+    // The line being typed before moving into the history
+    draft: Vec<u8>,
+    // Cells drawn since the start of the line
+    shown: usize,
+    // Column of the terminal's cursor, from the start of the line
+    term_cursor: usize,
+}
+impl LineEditor {
+    /// Create an editor with an empty history.
+    pub const fn new() -> Self {
+        Self {
+            line: Vec::new(),
+            cursor: 0,
+            history: VecDeque::new(),
+            recalled: None,
+            draft: Vec::new(),
+            shown: 0,
+            term_cursor: 0,
+        }
+    }
+
+    /// Read a line, getting keys from `next_key`. Editing starts wherever the screen's output
+    /// position is, so print the prompt first. Returns the line without its newline.
+    pub fn read_line(&mut self, mut next_key: impl FnMut() -> DecodedKey) -> String {
+        self.clear();
+        self.draw(true);
+        loop {
+            if let Some(line) = self.handle_key(next_key()) {
+                self.draw(false);
+                super::write_bytes(b"\n");
+                return line;
+            }
+            self.draw(true);
+        }
+    }
+
+    /// Start a new, empty line.
+    fn clear(&mut self) {
+        self.line.clear();
+        self.cursor = 0;
+        self.shown = 0;
+        self.term_cursor = 0;
+    }
+
+    /// Apply a single key. Returns the finished line when the key is Enter.
+    fn handle_key(&mut self, key: DecodedKey) -> Option<String> {
+        match key {
+            DecodedKey::Unicode('\n' | '\r') | DecodedKey::RawKey(KeyCode::Return) => {
+                return Some(self.submit());
+            }
+            DecodedKey::Unicode('\u{8}') | DecodedKey::RawKey(KeyCode::Backspace) => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.line.remove(self.cursor);
+                }
+            }
+            DecodedKey::Unicode('\u{7f}') | DecodedKey::RawKey(KeyCode::Delete) => {
+                if self.cursor < self.line.len() {
+                    self.line.remove(self.cursor);
+                }
+            }
+            DecodedKey::Unicode(c @ ' '..='~') => {
+                if self.line.len() < MAX_LINE {
+                    self.line.insert(self.cursor, c as u8);
+                    self.cursor += 1;
+                }
+            }
+            DecodedKey::RawKey(KeyCode::ArrowLeft) => self.cursor = self.cursor.saturating_sub(1),
+            DecodedKey::RawKey(KeyCode::ArrowRight) => {
+                self.cursor = (self.cursor + 1).min(self.line.len());
+            }
+            DecodedKey::RawKey(KeyCode::Home) => self.cursor = 0,
+            DecodedKey::RawKey(KeyCode::End) => self.cursor = self.line.len(),
+            DecodedKey::RawKey(KeyCode::ArrowUp) => self.older(),
+            DecodedKey::RawKey(KeyCode::ArrowDown) => self.newer(),
+            _ => (),
+        }
+        None
+    }
+
+    /// Finish the current line, adding it to the history. The line stays in place until the next
+    /// one is started.
+    fn submit(&mut self) -> String {
+        let line: String = self.line.iter().map(|&b| char::from(b)).collect();
+        let is_repeat = self.history.back().is_some_and(|last| *last == line);
+        if !line.trim().is_empty() && !is_repeat {
+            if self.history.len() == MAX_HISTORY {
+                self.history.pop_front();
+            }
+            self.history.push_back(line.clone());
+        }
+
+        self.cursor = self.line.len();
+        self.recalled = None;
+        self.draft.clear();
+        line
+    }
+
+    /// Show the previous line in the history.
+    fn older(&mut self) {
+        let index = match self.recalled {
+            None if self.history.is_empty() => return,
+            None => {
+                self.draft = core::mem::take(&mut self.line);
+                self.history.len() - 1
+            }
+            Some(0) => return,
+            Some(index) => index - 1,
+        };
+        self.recall(Some(index));
+    }
+
+    /// Show the next line in the history, or the line being typed before the history was
+    /// entered.
+    fn newer(&mut self) {
+        match self.recalled {
+            None => (),
+            Some(index) if index + 1 < self.history.len() => self.recall(Some(index + 1)),
+            Some(_) => self.recall(None),
+        }
+    }
+
+    fn recall(&mut self, index: Option<usize>) {
+        self.line = match index {
+            Some(index) => self.history[index].bytes().collect(),
+            None => core::mem::take(&mut self.draft),
+        };
+        self.cursor = self.line.len();
+        self.recalled = index;
+    }
+
+    /// Erase what was drawn of the line and draw it again.
+    fn draw(&mut self, with_cursor: bool) {
+        self.draw_vga(with_cursor);
+        self.draw_term();
+    }
+
+    fn draw_vga(&mut self, with_cursor: bool) {
+        let mut out = vec![BACKSPACE; self.shown];
+        out.extend_from_slice(&self.line);
+        vga_text::write_bytes(&out);
+        if with_cursor {
+            vga_text::place_cursor_back(self.line.len() - self.cursor);
+        }
+        self.shown = self.line.len();
+    }
+
+    fn draw_term(&mut self) {
+        let mut out = vec![BACKSPACE; self.term_cursor];
+        out.extend_from_slice(&self.line);
+        out.extend_from_slice(ERASE_TO_END);
+        out.resize(out.len() + self.line.len() - self.cursor, BACKSPACE);
+        serial::write_bytes(&out);
+        self.term_cursor = self.cursor;
+    }
+
+    /// The line as typed so far.
+    #[cfg(test)]
+    fn line(&self) -> &[u8] {
+        &self.line
+    }
+}
+impl Default for LineEditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_str(editor: &mut LineEditor, s: &str) {
+        for c in s.chars() {
+            assert_eq!(editor.handle_key(DecodedKey::Unicode(c)), None);
+        }
+    }
+
+    fn key(editor: &mut LineEditor, code: KeyCode) {
+        assert_eq!(editor.handle_key(DecodedKey::RawKey(code)), None);
+    }
+
+    fn enter(editor: &mut LineEditor) -> String {
+        let line = editor.handle_key(DecodedKey::Unicode('\n')).unwrap();
+        editor.clear();
+        line
+    }
+
+    #[test_case]
+    fn insert_and_delete() {
+        let mut editor = LineEditor::new();
+        type_str(&mut editor, "helo");
+        key(&mut editor, KeyCode::ArrowLeft);
+        type_str(&mut editor, "l");
+        assert_eq!(editor.line(), b"hello");
+
+        key(&mut editor, KeyCode::Home);
+        type_str(&mut editor, "\u{7f}");
+        key(&mut editor, KeyCode::End);
+        type_str(&mut editor, "\u{8}!");
+        assert_eq!(enter(&mut editor), "ell!");
+    }
+
+    #[test_case]
+    fn cursor_stays_in_line() {
+        let mut editor = LineEditor::new();
+        key(&mut editor, KeyCode::ArrowLeft);
+        type_str(&mut editor, "\u{8}ab");
+        key(&mut editor, KeyCode::ArrowRight);
+        type_str(&mut editor, "c");
+        assert_eq!(enter(&mut editor), "abc");
+    }
+
+    #[test_case]
+    fn only_printable_ascii() {
+        let mut editor = LineEditor::new();
+        type_str(&mut editor, "a\té\u{1b}b");
+        assert_eq!(enter(&mut editor), "ab");
+
+        type_str(&mut editor, &"x".repeat(MAX_LINE + 10));
+        assert_eq!(enter(&mut editor).len(), MAX_LINE);
+    }
+
+    #[test_case]
+    fn history() {
+        let mut editor = LineEditor::new();
+        for line in ["first", "second", "second", "   "] {
+            type_str(&mut editor, line);
+            enter(&mut editor);
+        }
+
+        type_str(&mut editor, "draft");
+        key(&mut editor, KeyCode::ArrowUp);
+        assert_eq!(editor.line(), b"second");
+        key(&mut editor, KeyCode::ArrowUp);
+        assert_eq!(editor.line(), b"first");
+        key(&mut editor, KeyCode::ArrowUp);
+        assert_eq!(editor.line(), b"first");
+        key(&mut editor, KeyCode::ArrowDown);
+        key(&mut editor, KeyCode::ArrowDown);
+        assert_eq!(editor.line(), b"draft");
+
+        key(&mut editor, KeyCode::ArrowUp);
+        type_str(&mut editor, "!");
+        assert_eq!(enter(&mut editor), "second!");
+        key(&mut editor, KeyCode::ArrowUp);
+        assert_eq!(editor.line(), b"second!");
+    }
+
+    #[test_case]
+    fn history_is_bounded() {
+        let mut editor = LineEditor::new();
+        for i in 0..(MAX_HISTORY + 5) {
+            type_str(&mut editor, &alloc::format!("{}", i));
+            enter(&mut editor);
+        }
+        for _ in 0..(MAX_HISTORY + 5) {
+            key(&mut editor, KeyCode::ArrowUp);
+        }
+        assert_eq!(editor.line(), b"5");
+    }
+}
@@ -0,0 +1,111 @@
+//! Splitting command lines into arguments.
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+/// Errors returned by [split].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ParseError {
+    /// A quote was opened but never closed.
+    UnterminatedQuote,
+    /// The line ends with a backslash.
+    TrailingBackslash,
+}
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnterminatedQuote => write!(f, "unterminated quote"),
+            Self::TrailingBackslash => write!(f, "trailing backslash"),
+        }
+    }
+}
+
+/// Split `line` into arguments at unquoted whitespace, the way a POSIX shell does.
+///
+/// Inside single quotes everything is literal. Inside double quotes a backslash escapes `"` and
+/// `\`, and is kept before anything else. Outside quotes a backslash escapes any character.
+/// Quoted and unquoted parts next to each other make up a single argument, and `''` or `""` is an
+/// empty argument.
+pub fn split(line: &str) -> Result<Vec<String>, ParseError> {
+    let mut args = Vec::new();
+    // The argument being built, if one has started. Quotes start an argument even if it ends up
+    // empty.
+    let mut current: Option<String> = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => args.extend(current.take()),
+            '\'' => {
+                let arg = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or(ParseError::UnterminatedQuote)? {
+                        '\'' => break,
+                        c => arg.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let arg = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or(ParseError::UnterminatedQuote)? {
+                        '"' => break,
+                        '\\' => match chars.next().ok_or(ParseError::UnterminatedQuote)? {
+                            c @ ('"' | '\\') => arg.push(c),
+                            c => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                        },
+                        c => arg.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                let escaped = chars.next().ok_or(ParseError::TrailingBackslash)?;
+                current.get_or_insert_with(String::new).push(escaped);
+            }
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(current);
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        split(line).unwrap()
+    }
+
+    #[test_case]
+    fn whitespace() {
+        assert!(args("").is_empty());
+        assert!(args("   ").is_empty());
+        assert_eq!(args("  echo   hello\tworld "), ["echo", "hello", "world"]);
+    }
+
+    #[test_case]
+    fn quotes() {
+        assert_eq!(args("echo 'a  b' \"c  d\""), ["echo", "a  b", "c  d"]);
+        assert_eq!(args("a'b c'd"), ["ab cd"]);
+        assert_eq!(args("'' \"\""), ["", ""]);
+        assert_eq!(args(r#"'it"s' "it's""#), ["it\"s", "it's"]);
+    }
+
+    #[test_case]
+    fn backslashes() {
+        assert_eq!(args(r"a\ b c\\d"), ["a b", r"c\d"]);
+        assert_eq!(args(r#""say \"hi\" \n""#), [r#"say "hi" \n"#]);
+        assert_eq!(args(r"'no \escape'"), [r"no \escape"]);
+    }
+
+    #[test_case]
+    fn errors() {
+        assert_eq!(split("echo 'oops"), Err(ParseError::UnterminatedQuote));
+        assert_eq!(split("echo \"oops\\\""), Err(ParseError::UnterminatedQuote));
+        assert_eq!(split("echo oops\\"), Err(ParseError::TrailingBackslash));
+    }
+}
@@ -0,0 +1,125 @@
+//! Decoding keys sent by a terminal on the serial port.
+
+use pc_keyboard::{DecodedKey, KeyCode};
+
+const ESCAPE: u8 = 0x1B;
+const DELETE: u8 = 0x7F;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    Ground,
+    /// After an escape byte.
+    Escape,
+    /// After `ESC [` or `ESC O`, with the numeric parameter so far.
+    Sequence(u8),
+}
+
+/// Turns the bytes a terminal sends into keys, including the escape sequences for the arrow,
+/// Home, End and Delete keys. Unrecognised escape sequences are dropped.
+#[derive(Debug)]
+pub struct TermDecoder {
+    state: State,
+    after_cr: bool,
+}
+impl TermDecoder {
+    /// Create a decoder expecting the start of a key.
+    pub const fn new() -> Self {
+        Self {
+            state: State::Ground,
+            after_cr: false,
+        }
+    }
+
+    /// Feed in the next byte. Returns the key it completes, if any.
+    pub fn feed(&mut self, byte: u8) -> Option<DecodedKey> {
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+        match (self.state, byte) {
+            (State::Ground, ESCAPE) => {
+                self.state = State::Escape;
+                None
+            }
+            // Enter may arrive as `\r\n`
+            (State::Ground, b'\n') if after_cr => None,
+            (State::Ground, b'\r') => Some(DecodedKey::Unicode('\n')),
+            // Terminals send DEL for the backspace key
+            (State::Ground, DELETE) => Some(DecodedKey::Unicode('\u{8}')),
+            (State::Ground, byte) => Some(DecodedKey::Unicode(char::from(byte))),
+            (State::Escape, b'[' | b'O') => {
+                self.state = State::Sequence(0);
+                None
+            }
+            (State::Escape, _) => {
+                self.state = State::Ground;
+                None
+            }
+            (State::Sequence(param), b'0'..=b'9') => {
+                self.state = State::Sequence(param.saturating_mul(10).saturating_add(byte - b'0'));
+                None
+            }
+            (State::Sequence(param), _) => {
+                self.state = State::Ground;
+                let code = match (byte, param) {
+                    (b'A', _) => KeyCode::ArrowUp,
+                    (b'B', _) => KeyCode::ArrowDown,
+                    (b'C', _) => KeyCode::ArrowRight,
+                    (b'D', _) => KeyCode::ArrowLeft,
+                    (b'H', _) | (b'~', 1 | 7) => KeyCode::Home,
+                    (b'F', _) | (b'~', 4 | 8) => KeyCode::End,
+                    (b'~', 3) => KeyCode::Delete,
+                    _ => return None,
+                };
+                Some(DecodedKey::RawKey(code))
+            }
+        }
+    }
+}
+impl Default for TermDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn decode(bytes: &[u8]) -> Vec<DecodedKey> {
+        let mut decoder = TermDecoder::new();
+        bytes.iter().filter_map(|&b| decoder.feed(b)).collect()
+    }
+
+    #[test_case]
+    fn plain_bytes() {
+        assert_eq!(
+            decode(b"a\x7f\r\r\nb\n"),
+            [
+                DecodedKey::Unicode('a'),
+                DecodedKey::Unicode('\u{8}'),
+                DecodedKey::Unicode('\n'),
+                DecodedKey::Unicode('\n'),
+                DecodedKey::Unicode('b'),
+                DecodedKey::Unicode('\n'),
+            ]
+        );
+    }
+
+    #[test_case]
+    fn escape_sequences() {
+        assert_eq!(
+            decode(b"\x1b[A\x1b[D\x1bOH\x1b[4~\x1b[3~"),
+            [
+                DecodedKey::RawKey(KeyCode::ArrowUp),
+                DecodedKey::RawKey(KeyCode::ArrowLeft),
+                DecodedKey::RawKey(KeyCode::Home),
+                DecodedKey::RawKey(KeyCode::End),
+                DecodedKey::RawKey(KeyCode::Delete),
+            ]
+        );
+        // Unknown sequences disappear without eating the next key
+        assert_eq!(
+            decode(b"\x1b[15~x\x1bqy"),
+            [DecodedKey::Unicode('x'), DecodedKey::Unicode('y')]
+        );
+    }
+}
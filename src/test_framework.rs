@@ -3,7 +3,7 @@
 use core::panic::PanicInfo;
 
 use crate::{
-    hlt_loop,
+    dmesg, hlt_loop,
     qemu::{exit_qemu, QemuExitCode},
     serial_print, serial_println,
 };
@@ -34,16 +34,17 @@ pub fn test_runner(tests: &[&dyn Testable]) {
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
     serial_println!("[FAIL]\n");
     serial_println!("Error: {}\n", info);
+    dmesg::dump_to_serial();
     exit_qemu(QemuExitCode::Failure);
 
     hlt_loop();
 }
 
-/// Test entry point.
+/// Test entry point. The bootloader passes a pointer to its [bootloader::BootInfo].
 #[cfg(test)]
 #[no_mangle]
-pub extern "C" fn _start() -> ! {
-    super::init();
+pub extern "C" fn _start(boot_info: &'static bootloader::BootInfo) -> ! {
+    super::init(boot_info);
     super::test_main();
     hlt_loop();
 }
@@ -0,0 +1,748 @@
+//! The virtual filesystem layer.
+//!
+//! Concrete filesystems implement [FileSystem] and [Inode] and are attached to the directory tree
+//! with [mount]. Everything else in the kernel goes through the path-based operations in this
+//! module ([open], [read], [write], [seek], [close], [readdir], [stat], etc.), which resolve
+//! paths across mount points and symbolic links.
+
+use alloc::{collections::VecDeque, string::String, sync::Arc, vec::Vec};
+use core::{any::Any, fmt, ops::BitOr};
+
+use spin::Mutex;
+
+pub mod mount;
+pub mod path;
+
+pub use mount::{mounts, Mount};
+
+/// Maximum length of a single path component in bytes.
+pub const MAX_NAME_LEN: usize = 255;
+
+/// Maximum number of symbolic links followed while resolving a single path.
+pub const MAX_SYMLINKS: usize = 40;
+
+/// Maximum number of simultaneously open files.
+pub const MAX_OPEN_FILES: usize = 256;
+
+/// Errors returned by filesystem operations.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[allow(missing_docs)]
+pub enum VfsError {
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    DirectoryNotEmpty,
+    InvalidPath,
+    NameTooLong,
+    TooManySymlinks,
+    BadDescriptor,
+    TooManyOpenFiles,
+    PermissionDenied,
+    ReadOnly,
+    NoSpace,
+    CrossDevice,
+    Busy,
+    InvalidArgument,
+    Unsupported,
+    Corrupt,
+    Io,
+}
+impl fmt::Display for VfsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            Self::NotFound => "no such file or directory",
+            Self::NotADirectory => "not a directory",
+            Self::IsADirectory => "is a directory",
+            Self::AlreadyExists => "file exists",
+            Self::DirectoryNotEmpty => "directory not empty",
+            Self::InvalidPath => "invalid path",
+            Self::NameTooLong => "file name too long",
+            Self::TooManySymlinks => "too many levels of symbolic links",
+            Self::BadDescriptor => "bad file descriptor",
+            Self::TooManyOpenFiles => "too many open files",
+            Self::PermissionDenied => "permission denied",
+            Self::ReadOnly => "read-only filesystem",
+            Self::NoSpace => "no space left on device",
+            Self::CrossDevice => "cross-device link",
+            Self::Busy => "device or resource busy",
+            Self::InvalidArgument => "invalid argument",
+            Self::Unsupported => "operation not supported",
+            Self::Corrupt => "filesystem corrupted",
+            Self::Io => "input/output error",
+        };
+        f.write_str(msg)
+    }
+}
+
+/// Result type of filesystem operations.
+pub type Result<T> = core::result::Result<T, VfsError>;
+
+/// The different kinds of [Inode].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[allow(missing_docs)]
+pub enum InodeKind {
+    File,
+    Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+}
+
+/// Unix-style permission bits.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct Permissions(pub u16);
+impl Permissions {
+    /// `rw-r--r--`
+    pub const DEFAULT_FILE: Self = Self(0o644);
+    /// `rwxr-xr-x`
+    pub const DEFAULT_DIR: Self = Self(0o755);
+    /// `rwxrwxrwx`
+    pub const ALL: Self = Self(0o777);
+}
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const CHARS: [char; 3] = ['r', 'w', 'x'];
+        for bit in (0..9).rev() {
+            let c = if (self.0 >> bit) & 1 != 0 {
+                CHARS[2 - (bit % 3)]
+            } else {
+                '-'
+            };
+            fmt::Write::write_char(f, c)?;
+        }
+        Ok(())
+    }
+}
+
+/// Information about an [Inode].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    /// Inode number, unique within its filesystem.
+    pub ino: u64,
+    /// What sort of inode this is.
+    pub kind: InodeKind,
+    /// Size in bytes.
+    pub size: u64,
+    /// Permission bits.
+    pub permissions: Permissions,
+    /// Number of hard links to the inode.
+    pub nlink: u32,
+    /// Owner user ID.
+    pub uid: u32,
+    /// Owner group ID.
+    pub gid: u32,
+}
+
+/// An entry in a directory listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    /// Name of the entry within its directory.
+    pub name: String,
+    /// Inode number of the entry.
+    pub ino: u64,
+    /// What sort of inode the entry refers to.
+    pub kind: InodeKind,
+}
+
+/// A mountable filesystem.
+pub trait FileSystem: Send + Sync {
+    /// Short name of the filesystem type, e.g. `"ramfs"`.
+    fn name(&self) -> &'static str;
+
+    /// The root directory of the filesystem.
+    fn root(&self) -> Arc<dyn Inode>;
+
+    /// Flush any pending writes to the backing device.
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A file, directory, symbolic link, or device node within a [FileSystem].
+///
+/// Every operation has a default implementation returning an appropriate error, so implementors
+/// only need to provide the operations that make sense for them.
+pub trait Inode: Send + Sync {
+    /// Information about this inode.
+    fn metadata(&self) -> Result<Metadata>;
+
+    /// Used to downcast to the concrete inode type, e.g. to check that the target of
+    /// [Inode::rename] or [Inode::link] belongs to the same filesystem.
+    fn as_any(&self) -> &dyn Any;
+
+    /// Read bytes starting at `offset` into `buf`. Returns the number of bytes read, which is 0 at
+    /// the end of the file.
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> Result<usize> {
+        Err(not_a_file(self.metadata()))
+    }
+
+    /// Write the bytes of `buf` starting at `offset`. Returns the number of bytes written.
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> Result<usize> {
+        Err(not_a_file(self.metadata()))
+    }
+
+    /// Set the size of the file to `len`, discarding or zero-filling as needed.
+    fn truncate(&self, _len: u64) -> Result<()> {
+        Err(not_a_file(self.metadata()))
+    }
+
+    /// Find the entry called `name` in this directory.
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Inode>> {
+        Err(VfsError::NotADirectory)
+    }
+
+    /// List the entries of this directory, excluding `.` and `..`.
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        Err(VfsError::NotADirectory)
+    }
+
+    /// Create a new empty file or directory called `name` in this directory.
+    fn create(
+        &self,
+        _name: &str,
+        _kind: InodeKind,
+        _permissions: Permissions,
+    ) -> Result<Arc<dyn Inode>> {
+        Err(VfsError::NotADirectory)
+    }
+
+    /// Create a symbolic link called `name` pointing at `target` in this directory.
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>> {
+        Err(VfsError::NotADirectory)
+    }
+
+    /// Create a new entry called `name` in this directory referring to the existing `target`.
+    fn link(&self, _name: &str, _target: &Arc<dyn Inode>) -> Result<()> {
+        Err(VfsError::NotADirectory)
+    }
+
+    /// Remove the entry called `name` from this directory. Directories must be empty.
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(VfsError::NotADirectory)
+    }
+
+    /// Move the entry called `old_name` in this directory to `new_name` in `new_parent`,
+    /// replacing any existing entry there.
+    fn rename(&self, _old_name: &str, _new_parent: &Arc<dyn Inode>, _new_name: &str) -> Result<()> {
+        Err(VfsError::NotADirectory)
+    }
+
+    /// The target of this symbolic link.
+    fn read_link(&self) -> Result<String> {
+        Err(VfsError::InvalidArgument)
+    }
+}
+
+/// The error for attempting a file-only operation on an inode with the given metadata.
+fn not_a_file(metadata: Result<Metadata>) -> VfsError {
+    match metadata.map(|m| m.kind) {
+        Ok(InodeKind::Directory) => VfsError::IsADirectory,
+        _ => VfsError::Unsupported,
+    }
+}
+
+/// Options for [open]. Combine with `|`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct OpenFlags(u8);
+impl OpenFlags {
+    /// Open for reading.
+    pub const READ: Self = Self(1 << 0);
+    /// Open for writing.
+    pub const WRITE: Self = Self(1 << 1);
+    /// Create the file if it doesn't exist.
+    pub const CREATE: Self = Self(1 << 2);
+    /// With [OpenFlags::CREATE], fail if the file already exists.
+    pub const EXCLUSIVE: Self = Self(1 << 3);
+    /// Truncate the file to zero length on open.
+    pub const TRUNCATE: Self = Self(1 << 4);
+    /// Always write to the end of the file.
+    pub const APPEND: Self = Self(1 << 5);
+    /// Don't follow a symbolic link in the final path component.
+    pub const NO_FOLLOW: Self = Self(1 << 6);
+    /// Open for reading and writing.
+    pub const READ_WRITE: Self = Self(Self::READ.0 | Self::WRITE.0);
+
+    /// Check if all of the flags in `other` are set.
+    pub fn contains(self, other: Self) -> bool {
+        (self.0 & other.0) == other.0
+    }
+}
+impl BitOr for OpenFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Where to [seek] from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SeekFrom {
+    /// Offset from the start of the file.
+    Start(u64),
+    /// Offset from the end of the file.
+    End(i64),
+    /// Offset from the current position.
+    Current(i64),
+}
+
+/// A handle to an open file, as returned by [open].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct Fd(pub usize);
+
+struct OpenFile {
+    inode: Arc<dyn Inode>,
+    flags: OpenFlags,
+    offset: u64,
+}
+
+static OPEN_FILES: Mutex<Vec<Option<OpenFile>>> = Mutex::new(Vec::new());
+
+static CWD: Mutex<String> = Mutex::new(String::new());
+
+/// One resolved step of a path walk.
+#[derive(Clone)]
+struct Dentry {
+    name: String,
+    inode: Arc<dyn Inode>,
+    fs: Arc<dyn FileSystem>,
+}
+
+/// The chain of [Dentry]s from the root to a resolved inode. Keeping the whole chain around
+/// means `..` always goes back the way we came, including across mount points.
+#[derive(Clone)]
+struct Walk(Vec<Dentry>);
+impl Walk {
+    fn root() -> Result<Self> {
+        let fs = mount::get(path::ROOT).ok_or(VfsError::NotFound)?;
+        Ok(Self(Vec::from([Dentry {
+            name: String::new(),
+            inode: fs.root(),
+            fs,
+        }])))
+    }
+
+    fn last(&self) -> &Dentry {
+        self.0.last().expect("walk always contains the root")
+    }
+
+    fn pop(&mut self) {
+        if self.0.len() > 1 {
+            self.0.pop();
+        }
+    }
+
+    fn path(&self) -> String {
+        path::from_components(self.0.iter().skip(1).map(|d| d.name.as_str()))
+    }
+
+    fn child_path(&self, name: &str) -> String {
+        path::join(&self.path(), name)
+    }
+}
+
+/// Resolve `path` to a [Walk]. If `follow` is false, a symbolic link in the final component is
+/// returned as-is instead of being followed.
+fn resolve(path: &str, follow: bool) -> Result<Walk> {
+    let mut walk = Walk::root()?;
+    let mut pending: VecDeque<String> = VecDeque::new();
+    if !path::is_absolute(path) {
+        pending.extend(path::components(&cwd()).map(String::from));
+    }
+    pending.extend(path::components(path).map(String::from));
+
+    let mut links_followed = 0;
+    while let Some(component) = pending.pop_front() {
+        match component.as_str() {
+            "." => continue,
+            ".." => {
+                walk.pop();
+                continue;
+            }
+            _ => {}
+        }
+
+        let parent = walk.last();
+        if parent.inode.metadata()?.kind != InodeKind::Directory {
+            return Err(VfsError::NotADirectory);
+        }
+        path::validate_name(&component)?;
+
+        let child_path = walk.child_path(&component);
+        let (inode, fs) = match mount::get(&child_path) {
+            Some(fs) => (fs.root(), fs),
+            None => (parent.inode.lookup(&component)?, Arc::clone(&parent.fs)),
+        };
+
+        let is_last = pending.is_empty();
+        if (follow || !is_last) && inode.metadata()?.kind == InodeKind::Symlink {
+            links_followed += 1;
+            if links_followed > MAX_SYMLINKS {
+                return Err(VfsError::TooManySymlinks);
+            }
+
+            let target = inode.read_link()?;
+            if path::is_absolute(&target) {
+                walk = Walk::root()?;
+            }
+            for c in path::components(&target).rev() {
+                pending.push_front(String::from(c));
+            }
+            continue;
+        }
+
+        walk.0.push(Dentry {
+            name: component,
+            inode,
+            fs,
+        });
+    }
+
+    Ok(walk)
+}
+
+/// Resolve the parent directory of `path`, returning it along with the final component name.
+fn resolve_parent(path: &str) -> Result<(Walk, &str)> {
+    let (parent, name) = path::split_last(path)?;
+    let walk = resolve(parent, true)?;
+    if walk.last().inode.metadata()?.kind != InodeKind::Directory {
+        return Err(VfsError::NotADirectory);
+    }
+    Ok((walk, name))
+}
+
+/// Check that `path` isn't currently a mount point, which can't be removed or renamed.
+fn ensure_not_mount_point(walk: &Walk, name: &str) -> Result<()> {
+    if mount::get(&walk.child_path(name)).is_some() {
+        Err(VfsError::Busy)
+    } else {
+        Ok(())
+    }
+}
+
+/// Attach `fs` to the directory tree at `path`.
+///
+/// The first filesystem must be mounted at `/`. After that, `path` must be an existing directory.
+pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> Result<()> {
+    let at = match resolve(path, true) {
+        Ok(walk) => {
+            if walk.last().inode.metadata()?.kind != InodeKind::Directory {
+                return Err(VfsError::NotADirectory);
+            }
+            walk.path()
+        }
+        // Nothing mounted yet; only the root can be mounted
+        Err(VfsError::NotFound) if mount::get(path::ROOT).is_none() => {
+            if path::normalize(path) != path::ROOT {
+                return Err(VfsError::NotFound);
+            }
+            String::from(path::ROOT)
+        }
+        Err(e) => return Err(e),
+    };
+    mount::insert(at, fs)
+}
+
+/// Detach the filesystem mounted at `path`, syncing it first.
+pub fn unmount(path: &str) -> Result<()> {
+    let at = resolve(path, true)?.path();
+    let fs = mount::get(&at).ok_or(VfsError::InvalidArgument)?;
+    fs.sync()?;
+    mount::remove(&at).map(|_| ())
+}
+
+/// Flush all pending writes on every mounted filesystem.
+pub fn sync() -> Result<()> {
+    mounts().iter().try_for_each(|m| m.fs.sync())
+}
+
+/// The current working directory.
+pub fn cwd() -> String {
+    let cwd = CWD.lock();
+    if cwd.is_empty() {
+        String::from(path::ROOT)
+    } else {
+        cwd.clone()
+    }
+}
+
+/// Change the current working directory.
+pub fn chdir(path: &str) -> Result<()> {
+    let walk = resolve(path, true)?;
+    if walk.last().inode.metadata()?.kind != InodeKind::Directory {
+        return Err(VfsError::NotADirectory);
+    }
+    *CWD.lock() = walk.path();
+    Ok(())
+}
+
+/// Resolve `path` to its canonical absolute form, following all symbolic links.
+pub fn canonicalize(path: &str) -> Result<String> {
+    resolve(path, true).map(|walk| walk.path())
+}
+
+/// Open the file at `path`.
+pub fn open(path: &str, flags: OpenFlags) -> Result<Fd> {
+    let follow = !flags.contains(OpenFlags::NO_FOLLOW);
+    let inode = match resolve(path, follow) {
+        Ok(_) if flags.contains(OpenFlags::CREATE | OpenFlags::EXCLUSIVE) => {
+            return Err(VfsError::AlreadyExists);
+        }
+        Ok(walk) => Arc::clone(&walk.last().inode),
+        Err(VfsError::NotFound) if flags.contains(OpenFlags::CREATE) => {
+            let (parent, name) = resolve_parent(path)?;
+            parent
+                .last()
+                .inode
+                .create(name, InodeKind::File, Permissions::DEFAULT_FILE)?
+        }
+        Err(e) => return Err(e),
+    };
+
+    let kind = inode.metadata()?.kind;
+    if kind == InodeKind::Directory && flags.contains(OpenFlags::WRITE) {
+        return Err(VfsError::IsADirectory);
+    }
+    if kind == InodeKind::File && flags.contains(OpenFlags::WRITE | OpenFlags::TRUNCATE) {
+        inode.truncate(0)?;
+    }
+
+    let file = OpenFile {
+        inode,
+        flags,
+        offset: 0,
+    };
+
+    let mut files = OPEN_FILES.lock();
+    if let Some(index) = files.iter().position(Option::is_none) {
+        files[index] = Some(file);
+        Ok(Fd(index))
+    } else if files.len() < MAX_OPEN_FILES {
+        files.push(Some(file));
+        Ok(Fd(files.len() - 1))
+    } else {
+        Err(VfsError::TooManyOpenFiles)
+    }
+}
+
+/// Close an open file.
+pub fn close(fd: Fd) -> Result<()> {
+    OPEN_FILES
+        .lock()
+        .get_mut(fd.0)
+        .and_then(Option::take)
+        .map(|_| ())
+        .ok_or(VfsError::BadDescriptor)
+}
+
+/// Get a copy of the inode, flags and offset of an open file. The table isn't kept locked while
+/// doing I/O, so a slow device can't hold up every other open file.
+fn file_state(fd: Fd) -> Result<(Arc<dyn Inode>, OpenFlags, u64)> {
+    OPEN_FILES
+        .lock()
+        .get(fd.0)
+        .and_then(Option::as_ref)
+        .map(|f| (Arc::clone(&f.inode), f.flags, f.offset))
+        .ok_or(VfsError::BadDescriptor)
+}
+
+fn set_offset(fd: Fd, offset: u64) -> Result<()> {
+    OPEN_FILES
+        .lock()
+        .get_mut(fd.0)
+        .and_then(Option::as_mut)
+        .map(|f| f.offset = offset)
+        .ok_or(VfsError::BadDescriptor)
+}
+
+/// Read from an open file into `buf`, advancing its offset. Returns the number of bytes read,
+/// which is 0 at the end of the file.
+pub fn read(fd: Fd, buf: &mut [u8]) -> Result<usize> {
+    let (inode, flags, offset) = file_state(fd)?;
+    if !flags.contains(OpenFlags::READ) {
+        return Err(VfsError::BadDescriptor);
+    }
+    let n = inode.read_at(offset, buf)?;
+    set_offset(fd, offset + n as u64)?;
+    Ok(n)
+}
+
+/// Write `buf` to an open file, advancing its offset. Returns the number of bytes written.
+pub fn write(fd: Fd, buf: &[u8]) -> Result<usize> {
+    let (inode, flags, mut offset) = file_state(fd)?;
+    if !flags.contains(OpenFlags::WRITE) {
+        return Err(VfsError::BadDescriptor);
+    }
+    if flags.contains(OpenFlags::APPEND) {
+        offset = inode.metadata()?.size;
+    }
+    let n = inode.write_at(offset, buf)?;
+    set_offset(fd, offset + n as u64)?;
+    Ok(n)
+}
+
+/// Move the offset of an open file. Returns the new offset from the start of the file.
+pub fn seek(fd: Fd, pos: SeekFrom) -> Result<u64> {
+    let (inode, _, offset) = file_state(fd)?;
+    let new_offset = match pos {
+        SeekFrom::Start(n) => Some(n),
+        SeekFrom::End(n) => inode.metadata()?.size.checked_add_signed(n),
+        SeekFrom::Current(n) => offset.checked_add_signed(n),
+    }
+    .ok_or(VfsError::InvalidArgument)?;
+    set_offset(fd, new_offset)?;
+    Ok(new_offset)
+}
+
+/// Get information about an open file.
+pub fn fstat(fd: Fd) -> Result<Metadata> {
+    file_state(fd)?.0.metadata()
+}
+
+/// Get information about the file at `path`, following symbolic links.
+pub fn stat(path: &str) -> Result<Metadata> {
+    resolve(path, true)?.last().inode.metadata()
+}
+
+/// Get information about the file at `path` without following a final symbolic link.
+pub fn lstat(path: &str) -> Result<Metadata> {
+    resolve(path, false)?.last().inode.metadata()
+}
+
+/// List the entries of the directory at `path`, excluding `.` and `..`.
+pub fn readdir(path: &str) -> Result<Vec<DirEntry>> {
+    resolve(path, true)?.last().inode.readdir()
+}
+
+/// Create a new directory at `path`.
+pub fn mkdir(path: &str) -> Result<()> {
+    let (parent, name) = resolve_parent(path)?;
+    parent
+        .last()
+        .inode
+        .create(name, InodeKind::Directory, Permissions::DEFAULT_DIR)
+        .map(|_| ())
+}
+
+/// Remove the file, symbolic link, or empty directory at `path`.
+pub fn unlink(path: &str) -> Result<()> {
+    let (parent, name) = resolve_parent(path)?;
+    ensure_not_mount_point(&parent, name)?;
+    parent.last().inode.unlink(name)
+}
+
+/// Create a symbolic link at `path` pointing at `target`. `target` isn't checked for existence.
+pub fn symlink(target: &str, path: &str) -> Result<()> {
+    if target.is_empty() {
+        return Err(VfsError::InvalidPath);
+    }
+    let (parent, name) = resolve_parent(path)?;
+    parent.last().inode.symlink(name, target).map(|_| ())
+}
+
+/// The target of the symbolic link at `path`.
+pub fn read_link(path: &str) -> Result<String> {
+    resolve(path, false)?.last().inode.read_link()
+}
+
+/// Create a hard link at `new_path` to the existing file at `old_path`.
+pub fn link(old_path: &str, new_path: &str) -> Result<()> {
+    let target = resolve(old_path, false)?;
+    let (parent, name) = resolve_parent(new_path)?;
+    if !Arc::ptr_eq(&target.last().fs, &parent.last().fs) {
+        return Err(VfsError::CrossDevice);
+    }
+    if target.last().inode.metadata()?.kind == InodeKind::Directory {
+        return Err(VfsError::PermissionDenied);
+    }
+    parent.last().inode.link(name, &target.last().inode)
+}
+
+/// Move the entry at `old_path` to `new_path`, replacing any existing entry there.
+pub fn rename(old_path: &str, new_path: &str) -> Result<()> {
+    let (old_parent, old_name) = resolve_parent(old_path)?;
+    let (new_parent, new_name) = resolve_parent(new_path)?;
+    if !Arc::ptr_eq(&old_parent.last().fs, &new_parent.last().fs) {
+        return Err(VfsError::CrossDevice);
+    }
+    ensure_not_mount_point(&old_parent, old_name)?;
+    ensure_not_mount_point(&new_parent, new_name)?;
+
+    // Don't allow moving a directory inside itself
+    let old_full = old_parent.child_path(old_name);
+    let new_full = new_parent.child_path(new_name);
+    if new_full
+        .strip_prefix(old_full.as_str())
+        .is_some_and(|rest| rest.starts_with(path::SEPARATOR))
+    {
+        return Err(VfsError::InvalidArgument);
+    }
+
+    old_parent
+        .last()
+        .inode
+        .rename(old_name, &new_parent.last().inode, new_name)
+}
+
+/// Read the whole file at `path` into a [String]. Handy for small text files.
+pub fn read_to_string(path: &str) -> Result<String> {
+    let fd = open(path, OpenFlags::READ)?;
+    let mut contents = Vec::new();
+    let mut buf = [0; 512];
+    let result = loop {
+        match read(fd, &mut buf) {
+            Ok(0) => break Ok(()),
+            Ok(n) => contents.extend_from_slice(&buf[..n]),
+            Err(e) => break Err(e),
+        }
+    };
+    close(fd)?;
+    result?;
+    String::from_utf8(contents).map_err(|_| VfsError::InvalidArgument)
+}
+
+/// Replace the contents of the file at `path` with `contents`, creating it if needed.
+pub fn write_all(path: &str, contents: &[u8]) -> Result<()> {
+    let fd = open(
+        path,
+        OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE,
+    )?;
+    let mut written = 0;
+    let result = loop {
+        if written == contents.len() {
+            break Ok(());
+        }
+        match write(fd, &contents[written..]) {
+            Ok(0) => break Err(VfsError::NoSpace),
+            Ok(n) => written += n,
+            Err(e) => break Err(e),
+        }
+    };
+    close(fd)?;
+    result
+}
+
+impl fmt::Debug for dyn Inode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.metadata() {
+            Ok(m) => write!(f, "Inode({}, {:?})", m.ino, m.kind),
+            Err(e) => write!(f, "Inode(<{}>)", e),
+        }
+    }
+}
+
+impl fmt::Debug for dyn FileSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl fmt::Debug for Mount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} on {}", self.fs, self.path)
+    }
+}
@@ -0,0 +1,72 @@
+//! The mount table.
+//!
+//! Mount points are keyed by their canonical absolute path, i.e. the path left over once every
+//! `.`, `..`, and symbolic link has been resolved.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+use spin::Mutex;
+
+use super::{path, FileSystem, VfsError};
+
+static MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
+
+/// A filesystem attached to the directory tree.
+#[derive(Clone)]
+pub struct Mount {
+    /// Canonical absolute path of the mount point.
+    pub path: String,
+    /// The mounted filesystem.
+    pub fs: Arc<dyn FileSystem>,
+}
+
+/// Attach `fs` at the canonical path `at`.
+pub(super) fn insert(at: String, fs: Arc<dyn FileSystem>) -> Result<(), VfsError> {
+    let mut mounts = MOUNTS.lock();
+    if mounts.iter().any(|m| m.path == at) {
+        return Err(VfsError::Busy);
+    }
+    mounts.push(Mount { path: at, fs });
+    Ok(())
+}
+
+/// Detach the filesystem mounted at the canonical path `at`.
+///
+/// Fails with [VfsError::Busy] if another filesystem is mounted beneath it.
+pub(super) fn remove(at: &str) -> Result<Arc<dyn FileSystem>, VfsError> {
+    let mut mounts = MOUNTS.lock();
+    let index = mounts
+        .iter()
+        .position(|m| m.path == at)
+        .ok_or(VfsError::InvalidArgument)?;
+
+    let nested = mounts
+        .iter()
+        .any(|m| m.path != at && is_beneath(&m.path, at));
+    if nested {
+        return Err(VfsError::Busy);
+    }
+
+    Ok(mounts.remove(index).fs)
+}
+
+/// Get the filesystem mounted at the canonical path `at`, if any.
+pub(super) fn get(at: &str) -> Option<Arc<dyn FileSystem>> {
+    MOUNTS
+        .lock()
+        .iter()
+        .find(|m| m.path == at)
+        .map(|m| Arc::clone(&m.fs))
+}
+
+/// A snapshot of the mount table, in mount order.
+pub fn mounts() -> Vec<Mount> {
+    MOUNTS.lock().clone()
+}
+
+fn is_beneath(child: &str, parent: &str) -> bool {
+    parent == path::ROOT
+        || child
+            .strip_prefix(parent)
+            .is_some_and(|rest| rest.starts_with(path::SEPARATOR))
+}
@@ -0,0 +1,160 @@
+//! Lexical path handling. Nothing in here touches a filesystem.
+
+use alloc::{string::String, vec::Vec};
+
+use super::{VfsError, MAX_NAME_LEN};
+
+/// The path separator.
+pub const SEPARATOR: char = '/';
+
+/// The root directory.
+pub const ROOT: &str = "/";
+
+/// Check if the given path starts at the root directory.
+pub fn is_absolute(path: &str) -> bool {
+    path.starts_with(SEPARATOR)
+}
+
+/// Iterate over the non-empty components of a path. `.` and `..` are returned as-is.
+pub fn components(path: &str) -> impl DoubleEndedIterator<Item = &str> {
+    path.split(SEPARATOR).filter(|c| !c.is_empty())
+}
+
+/// Split a path into its parent and its final component, ignoring trailing separators.
+///
+/// Returns [VfsError::InvalidPath] if there is no final component to split off (e.g. `/`), or if
+/// the final component is `.` or `..`.
+pub fn split_last(path: &str) -> Result<(&str, &str), VfsError> {
+    let trimmed = path.trim_end_matches(SEPARATOR);
+    if trimmed.is_empty() {
+        return Err(VfsError::InvalidPath);
+    }
+
+    let (parent, name) = match trimmed.rfind(SEPARATOR) {
+        Some(0) => (ROOT, &trimmed[1..]),
+        Some(i) => (&trimmed[..i], &trimmed[(i + 1)..]),
+        None => (".", trimmed),
+    };
+
+    validate_name(name)?;
+    Ok((parent, name))
+}
+
+/// Check that the given string can be used as a directory entry name.
+pub fn validate_name(name: &str) -> Result<(), VfsError> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(SEPARATOR) {
+        Err(VfsError::InvalidPath)
+    } else if name.len() > MAX_NAME_LEN {
+        Err(VfsError::NameTooLong)
+    } else {
+        Ok(())
+    }
+}
+
+/// Join `path` onto `base`. If `path` is absolute, `base` is ignored.
+pub fn join(base: &str, path: &str) -> String {
+    if is_absolute(path) {
+        return String::from(path);
+    }
+
+    let mut joined = String::from(base.trim_end_matches(SEPARATOR));
+    joined.push(SEPARATOR);
+    joined.push_str(path);
+    joined
+}
+
+/// Lexically normalise an absolute path: collapse repeated separators and resolve `.` and `..`.
+///
+/// Symbolic links are _not_ taken into account, so this is only correct for paths known to be
+/// free of them.
+pub fn normalize(path: &str) -> String {
+    let mut stack: Vec<&str> = Vec::new();
+    for component in components(path) {
+        match component {
+            "." => {}
+            ".." => {
+                stack.pop();
+            }
+            name => stack.push(name),
+        }
+    }
+    from_components(stack)
+}
+
+/// Build an absolute path from a sequence of components.
+pub fn from_components<'a>(components: impl IntoIterator<Item = &'a str>) -> String {
+    let mut path = String::new();
+    for component in components {
+        path.push(SEPARATOR);
+        path.push_str(component);
+    }
+    if path.is_empty() {
+        path.push(SEPARATOR);
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn absolute() {
+        assert!(is_absolute("/"));
+        assert!(is_absolute("/a/b"));
+        assert!(!is_absolute("a/b"));
+        assert!(!is_absolute(""));
+    }
+
+    #[test_case]
+    fn components_skip_empty() {
+        let mut c = components("//a///b/./../c/");
+        assert_eq!(c.next(), Some("a"));
+        assert_eq!(c.next(), Some("b"));
+        assert_eq!(c.next(), Some("."));
+        assert_eq!(c.next(), Some(".."));
+        assert_eq!(c.next(), Some("c"));
+        assert_eq!(c.next(), None);
+    }
+
+    #[test_case]
+    fn split_last_ok() {
+        assert_eq!(split_last("/a/b/c"), Ok(("/a/b", "c")));
+        assert_eq!(split_last("/a/b/c//"), Ok(("/a/b", "c")));
+        assert_eq!(split_last("/a"), Ok(("/", "a")));
+        assert_eq!(split_last("a"), Ok((".", "a")));
+        assert_eq!(split_last("a/b"), Ok(("a", "b")));
+    }
+
+    #[test_case]
+    fn split_last_err() {
+        assert_eq!(split_last("/"), Err(VfsError::InvalidPath));
+        assert_eq!(split_last(""), Err(VfsError::InvalidPath));
+        assert_eq!(split_last("/a/.."), Err(VfsError::InvalidPath));
+        assert_eq!(split_last("/a/."), Err(VfsError::InvalidPath));
+    }
+
+    #[test_case]
+    fn name_too_long() {
+        let name = "x".repeat(MAX_NAME_LEN + 1);
+        assert_eq!(validate_name(&name), Err(VfsError::NameTooLong));
+        assert_eq!(validate_name(&name[1..]), Ok(()));
+    }
+
+    #[test_case]
+    fn join_paths() {
+        assert_eq!(join("/a/b", "c"), "/a/b/c");
+        assert_eq!(join("/a/b/", "c/d"), "/a/b/c/d");
+        assert_eq!(join("/", "c"), "/c");
+        assert_eq!(join("/a/b", "/c"), "/c");
+    }
+
+    #[test_case]
+    fn normalize_paths() {
+        assert_eq!(normalize("/"), "/");
+        assert_eq!(normalize("//a//b/"), "/a/b");
+        assert_eq!(normalize("/a/./b/../c"), "/a/c");
+        assert_eq!(normalize("/../.."), "/");
+        assert_eq!(normalize("/a/b/../../.."), "/");
+    }
+}
@@ -1,28 +1,54 @@
 //! Write to the VGA buffer.
+//!
+//! Output can change colours, move around and erase parts of the screen with the ANSI escape
+//! sequences a VT100-style terminal understands, such as `\x1b[31m` for red text or `\x1b[2J` to
+//! clear the screen, so the same text shows the same way on a terminal on the serial port.
+//!
+//! The screen is shared by several [virtual terminals](vt), each with its own text, attribute and
+//! cursor. [print] and the functions here write to the console terminal, whether it's the one on
+//! the screen or not.
 
 use core::{
     default::Default,
     fmt::{self, Write},
+    ops::Range,
 };
 
-use lazy_static::lazy_static;
-use spin::Mutex;
 use volatile::Volatile;
 use x86_64::instructions::interrupts;
 
-const VGA_BUFFER_ADDR: usize = 0xB8000;
-const VGA_BUFFER_HEIGHT: usize = 25;
-const VGA_BUFFER_WIDTH: usize = 80;
+use crate::dmesg;
+
+pub use cursor::CursorShape;
+pub use mode::{Font, TextMode, TextModeError};
+
+pub mod cp437;
+pub mod mode;
+pub mod palette;
+pub mod screen;
+pub mod scrollback;
+pub mod vt;
+
+mod ansi;
+mod cursor;
+mod registers;
+
+use ansi::{Action, AnsiState};
+use cp437::{Utf8, Utf8Decoder};
+use scrollback::Scrollback;
+use vt::{Role, WriterGuard};
 
-const PRINTABLE_RANGE_START: u8 = 0x20;
-const PRINTABLE_RANGE_END: u8 = 0x7E;
+const VGA_BUFFER_ADDR: usize = 0xB8000;
+/// Most rows of any [TextMode].
+const MAX_HEIGHT: usize = 60;
+/// Most columns of any [TextMode].
+const MAX_WIDTH: usize = 90;
 
-const VGA_UNPRINTABLE: u8 = 0xFE;
 const VGA_WHITESPACE: u8 = 0x20;
-const BACKSPACE_BYTE: u8 = 0x08;
+const BACKSPACE: char = '\u{8}';
 
 const BLINK_ATTR_MASK: u8 = 0b1000_0000;
-const BG_ATTR_MASK: u8 = 0b0111_0000;
+const BG_ATTR_MASK: u8 = 0b1111_0000;
 const FG_ATTR_MASK: u8 = 0b0000_1111;
 const BLINK_ATTR_OFFSET: u8 = 7;
 const BG_ATTR_OFFSET: u8 = 4;
@@ -43,15 +69,6 @@ macro_rules! println {
     ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
 }
 
-lazy_static! {
-    /// Writes to the VGA buffer.
-    static ref WRITER: Mutex<Writer> = Mutex::new(Writer::new(
-        VgaBgColour::default(),
-        VgaFgColour::default(),
-        false
-    ));
-}
-
 /// All the possible VGA foreground colours.
 #[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(u8)]
@@ -106,7 +123,8 @@ impl TryFrom<u8> for VgaFgColour {
     }
 }
 
-/// All the possible VGA backgrond colours.
+/// All the possible VGA background colours. The bright ones, from [DarkGray](Self::DarkGray) on,
+/// share their bit with blinking, and are only shown with [palette::set_bright_backgrounds].
 #[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(u8)]
 #[allow(missing_docs)]
@@ -120,6 +138,14 @@ pub enum VgaBgColour {
     Magenta = 0x5,
     Brown = 0x6,
     LightGray = 0x7,
+    DarkGray = 0x8,
+    LightBlue = 0x9,
+    LightGreen = 0xA,
+    LightCyan = 0xB,
+    LightRed = 0xC,
+    Pink = 0xD,
+    Yellow = 0xE,
+    White = 0xF,
 }
 impl From<VgaBgColour> for u8 {
     fn from(value: VgaBgColour) -> Self {
@@ -139,40 +165,68 @@ impl TryFrom<u8> for VgaBgColour {
             0x5 => Ok(Self::Magenta),
             0x6 => Ok(Self::Brown),
             0x7 => Ok(Self::LightGray),
+            0x8 => Ok(Self::DarkGray),
+            0x9 => Ok(Self::LightBlue),
+            0xA => Ok(Self::LightGreen),
+            0xB => Ok(Self::LightCyan),
+            0xC => Ok(Self::LightRed),
+            0xD => Ok(Self::Pink),
+            0xE => Ok(Self::Yellow),
+            0xF => Ok(Self::White),
             _ => Err("Given value does not match an enum variant."),
         }
     }
 }
 
+/// The colours and blink bit of a character cell. The blink bit is also the background's bright
+/// bit, and which one it means is up to [palette::set_bright_backgrounds].
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(transparent)]
-struct VgaAttr(u8);
+pub struct VgaAttr(u8);
 impl VgaAttr {
-    fn new(bg: VgaBgColour, fg: VgaFgColour, blink: bool) -> Self {
-        Self((if blink { 0b1000_0000 } else { 0b0000_0000 }) | ((bg as u8) << 4) | (fg as u8))
+    /// Create an attribute from its parts. A bright `bg` is set as its dark colour, as the blink
+    /// bit is only set from `blink`. Bright backgrounds are set with [set_bg](Self::set_bg) once
+    /// [palette::set_bright_backgrounds] is on.
+    pub const fn new(bg: VgaBgColour, fg: VgaFgColour, blink: bool) -> Self {
+        let blink = if blink { 0b1000_0000 } else { 0b0000_0000 };
+        Self(blink | ((bg as u8 & 0b0111) << BG_ATTR_OFFSET) | (fg as u8))
     }
 
-    fn bg(&self) -> VgaBgColour {
-        VgaBgColour::try_from((self.0 & BG_ATTR_MASK) >> BG_ATTR_OFFSET).unwrap()
+    /// Get the [VgaBgColour]. It's only ever bright with [palette::set_bright_backgrounds].
+    pub fn bg(&self) -> VgaBgColour {
+        let mask = Self::bg_mask();
+        VgaBgColour::try_from((self.0 & mask) >> BG_ATTR_OFFSET).unwrap()
     }
 
-    fn fg(&self) -> VgaFgColour {
+    /// Get the [VgaFgColour].
+    pub fn fg(&self) -> VgaFgColour {
         VgaFgColour::try_from((self.0 & FG_ATTR_MASK) >> FG_ATTR_OFFSET).unwrap()
     }
 
-    fn blink(&self) -> bool {
-        (self.0 & BLINK_ATTR_MASK) != 0
+    /// Check if the blink bit is set. It never is with [palette::set_bright_backgrounds], as the
+    /// bit brightens the background instead.
+    pub fn blink(&self) -> bool {
+        !palette::bright_backgrounds() && (self.0 & BLINK_ATTR_MASK) != 0
     }
 
-    fn set_bg(&mut self, bg: VgaBgColour) {
-        self.overwrite_mask_offset(BG_ATTR_MASK, BG_ATTR_OFFSET, bg as u8);
+    /// Set the [VgaBgColour], leaving the blink bit alone. Without
+    /// [palette::set_bright_backgrounds], a bright `bg` is set as its dark colour.
+    pub fn set_bg(&mut self, bg: VgaBgColour) {
+        let mask = Self::bg_mask();
+        self.overwrite_mask_offset(mask, BG_ATTR_OFFSET, bg as u8 & (mask >> BG_ATTR_OFFSET));
     }
 
-    fn set_fg(&mut self, fg: VgaFgColour) {
+    /// Set the [VgaFgColour].
+    pub fn set_fg(&mut self, fg: VgaFgColour) {
         self.overwrite_mask_offset(FG_ATTR_MASK, FG_ATTR_OFFSET, fg as u8);
     }
 
-    fn set_blink(&mut self, blink: bool) {
+    /// Set the blink bit. Does nothing with [palette::set_bright_backgrounds], as the bit
+    /// brightens the background instead.
+    pub fn set_blink(&mut self, blink: bool) {
+        if palette::bright_backgrounds() {
+            return;
+        }
         self.overwrite_mask_offset(
             BLINK_ATTR_MASK,
             BLINK_ATTR_OFFSET,
@@ -180,6 +234,15 @@ impl VgaAttr {
         );
     }
 
+    // The background's bits, which take in the blink bit with bright backgrounds
+    fn bg_mask() -> u8 {
+        if palette::bright_backgrounds() {
+            BG_ATTR_MASK
+        } else {
+            BG_ATTR_MASK & !BLINK_ATTR_MASK
+        }
+    }
+
     // Clear the bits of the given mask, then write those bits with the given value
     fn overwrite_mask_offset(&mut self, mask: u8, offset: u8, value: u8) {
         *self = Self((self.0 & !mask) | (value << offset))
@@ -191,61 +254,110 @@ impl Default for VgaAttr {
     }
 }
 
+/// A character cell of the screen.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
-struct VgaChar {
-    text_byte: u8,
-    attr: VgaAttr,
+pub struct VgaChar {
+    /// The character, in code page 437.
+    pub text_byte: u8,
+    /// Its colours.
+    pub attr: VgaAttr,
 }
 
+/// The cells of the screen, row after row, with as many to a row as the text mode has columns.
 #[derive(Debug, Clone)]
 #[repr(transparent)]
 struct VgaBuffer {
-    chars: [[Volatile<VgaChar>; VGA_BUFFER_WIDTH]; VGA_BUFFER_HEIGHT],
+    chars: [Volatile<VgaChar>; MAX_HEIGHT * MAX_WIDTH],
 }
 
-/// A VGA text writer.
+/// A VGA text writer, one for each virtual terminal.
 #[derive(Debug)]
 struct Writer {
-    // Current position within the last row.
+    // Rows and columns of the screen, which follow the text mode.
+    height: usize,
+    width: usize,
+    // Row written to, normally the last row of the scrolling region.
+    row: usize,
+    // Current position within the row.
     column_position: usize,
+    // Rows that scroll when output goes past the last of them.
+    scroll_region: Range<usize>,
     attr: VgaAttr,
-    // We know that the VGA text buffer is valid for the whole runtime
+    cursor_shape: CursorShape,
+    // Whether the hardware cursor is shown while the terminal is on the screen.
+    cursor_visible: bool,
+    // Cells before the output position the cursor is placed, until the next write.
+    cursor_back: usize,
+    scrollback: Scrollback,
+    ansi: AnsiState,
+    utf8: Utf8Decoder,
+    // The VGA text buffer while the terminal is on the screen, its off-screen buffer otherwise
     buffer: &'static mut VgaBuffer,
+    // The off-screen buffer, put aside while the terminal is on the screen
+    off_screen: Option<&'static mut VgaBuffer>,
 }
 impl Writer {
-    fn new(bg: VgaBgColour, fg: VgaFgColour, blink: bool) -> Self {
+    fn new(buffer: &'static mut VgaBuffer, off_screen: Option<&'static mut VgaBuffer>) -> Self {
+        let (height, width) = (TextMode::default().rows(), TextMode::default().cols());
         Self {
+            height,
+            width,
+            row: height - 1,
             column_position: 0,
-            attr: VgaAttr::new(bg, fg, blink),
-            // SAFETY: The reference points to the constant VGA_BUFFER_ADDR, so we know it's valid.
-            // Rust's bounds checking ensures we can't accidentally write outside the buffer, so
-            // all subsequent operations are safe.
-            buffer: unsafe { &mut *(VGA_BUFFER_ADDR as *mut VgaBuffer) },
+            scroll_region: 0..height,
+            attr: VgaAttr::default(),
+            cursor_shape: CursorShape::default(),
+            cursor_visible: cursor::is_shown(),
+            cursor_back: 0,
+            scrollback: Scrollback::new(),
+            ansi: AnsiState::new(height),
+            utf8: Utf8Decoder::new(),
+            buffer,
+            off_screen,
         }
     }
 
+    /// The cell at `row` and `col`, which must be on the screen.
+    fn cell(&self, row: usize, col: usize) -> VgaChar {
+        debug_assert!(row < self.height && col < self.width);
+        self.buffer.chars[row * self.width + col].read()
+    }
+
+    /// Write the cell at `row` and `col`, which must be on the screen.
+    fn set_cell(&mut self, row: usize, col: usize, cell: VgaChar) {
+        debug_assert!(row < self.height && col < self.width);
+        // Declare volatile to ensure the compiler never optimises away the writes
+        self.buffer.chars[row * self.width + col].write(cell);
+    }
+
     fn write_char(&mut self, byte: u8) {
         match byte {
             b'\n' => self.new_line(),
             b'\t' => self.write_tab(),
-            byte => {
-                if self.column_position >= VGA_BUFFER_WIDTH {
-                    self.new_line();
-                }
+            byte => self.write_glyph(byte),
+        }
+    }
 
-                let row = VGA_BUFFER_HEIGHT - 1;
-                let col = self.column_position;
+    /// Write the code page 437 glyph `glyph`, even if it's in the place of a control character.
+    fn write_glyph(&mut self, glyph: u8) {
+        if self.column_position >= self.width {
+            self.new_line();
+        }
 
-                // Declare volatile to ensure the compiler never optimises away the writes
-                self.buffer.chars[row][col].write(VgaChar {
-                    text_byte: byte,
-                    attr: self.attr,
-                });
+        let row = self.row;
+        let col = self.column_position;
 
-                self.column_position += 1;
-            }
-        }
+        self.set_cell(
+            row,
+            col,
+            VgaChar {
+                text_byte: glyph,
+                attr: self.attr,
+            },
+        );
+
+        self.column_position += 1;
     }
 
     fn erase_char(&mut self) {
@@ -253,14 +365,17 @@ impl Writer {
             self.back_line();
         }
 
-        let row = VGA_BUFFER_HEIGHT - 1;
+        let row = self.row;
         let col = self.column_position - 1;
 
-        // Declare volatile to ensure the compiler never optimises away the writes
-        self.buffer.chars[row][col].write(VgaChar {
-            text_byte: VGA_WHITESPACE,
-            attr: self.attr,
-        });
+        self.set_cell(
+            row,
+            col,
+            VgaChar {
+                text_byte: VGA_WHITESPACE,
+                attr: self.attr,
+            },
+        );
 
         self.column_position -= 1;
     }
@@ -277,43 +392,157 @@ impl Writer {
     }
 
     fn new_line(&mut self) {
-        // Shift all the lines upward
-        for row in 1..VGA_BUFFER_HEIGHT {
-            for col in 0..VGA_BUFFER_WIDTH {
-                let character = self.buffer.chars[row][col].read();
-                self.buffer.chars[row - 1][col].write(character);
-            }
+        if self.row + 1 == self.scroll_region.end {
+            self.scroll_up();
+        } else if self.row + 1 < self.height {
+            self.row += 1;
         }
-
-        self.clear_row(VGA_BUFFER_HEIGHT - 1);
         self.column_position = 0;
     }
 
     fn back_line(&mut self) {
-        // Shift all the lines downward
-        for row in (0..(VGA_BUFFER_HEIGHT - 1)).rev() {
-            for col in 0..VGA_BUFFER_WIDTH {
-                let character = self.buffer.chars[row][col].read();
-                self.buffer.chars[row + 1][col].write(character);
-            }
+        if self.row == self.scroll_region.start {
+            self.scroll_down();
+        } else if self.row > 0 {
+            self.row -= 1;
         }
+        self.column_position = self.width;
+    }
+
+    /// Shift the lines of the scrolling region upward, blanking the last.
+    fn scroll_up(&mut self) {
+        self.keep_scrolled_row();
+        let Range { start, end } = self.scroll_region;
+        for row in (start + 1)..end {
+            self.copy_row(row, row - 1);
+        }
+        self.clear_row(end - 1);
+    }
 
-        self.column_position = VGA_BUFFER_WIDTH;
+    /// Shift the lines of the scrolling region downward, blanking the first.
+    fn scroll_down(&mut self) {
+        let Range { start, end } = self.scroll_region;
+        for row in ((start + 1)..end).rev() {
+            self.copy_row(row - 1, row);
+        }
+        self.clear_row(start);
+    }
+
+    fn copy_row(&mut self, from: usize, to: usize) {
+        for col in 0..self.width {
+            let character = self.cell(from, col);
+            self.set_cell(to, col, character);
+        }
     }
 
     fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                // printable ASCII byte, tab, or newline
-                PRINTABLE_RANGE_START..=PRINTABLE_RANGE_END | b'\n' | b'\t' => {
-                    self.write_char(byte)
-                }
-                // backspace
-                BACKSPACE_BYTE => self.erase_char(),
-                // not part of printable ASCII range
-                _ => self.write_char(VGA_UNPRINTABLE),
+        self.write_bytes(s.as_bytes());
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.snap_to_live();
+        for &byte in bytes {
+            match self.ansi.parser.feed(byte) {
+                Action::Print(byte) => self.decode_byte(byte),
+                Action::Csi(csi) => self.apply_csi(&csi),
+                Action::None => {}
             }
         }
+        self.sync_cursor();
+    }
+
+    /// Add a byte of UTF-8 text, writing the character it completes.
+    fn decode_byte(&mut self, byte: u8) {
+        let mut decoded = self.utf8.feed(byte);
+        if decoded == Utf8::Interrupted {
+            self.write_glyph(cp437::REPLACEMENT);
+            decoded = self.utf8.feed(byte);
+        }
+        match decoded {
+            // tab or newline
+            Utf8::Char('\n' | '\t') => self.write_char(byte),
+            // carriage return
+            Utf8::Char('\r') => self.column_position = 0,
+            // backspace
+            Utf8::Char(BACKSPACE) => self.erase_char(),
+            Utf8::Char(c) => self.write_glyph(glyph(c)),
+            Utf8::Invalid | Utf8::Interrupted => self.write_glyph(cp437::REPLACEMENT),
+            Utf8::Pending => {}
+        }
+    }
+
+    fn clear(&mut self) {
+        self.snap_to_live();
+        for row in 0..self.height {
+            self.clear_row(row);
+        }
+        self.row = self.scroll_region.end - 1;
+        self.column_position = 0;
+        self.sync_cursor();
+    }
+
+    /// Where the next character goes. A full row stays on its last column until the next
+    /// character wraps it.
+    fn output_position(&self) -> (usize, usize) {
+        (self.row, self.column_position.min(self.width - 1))
+    }
+
+    /// Whether the terminal is the one on the screen.
+    fn is_shown(&self) -> bool {
+        self.off_screen.is_some()
+    }
+
+    /// Move the hardware cursor to where the next character goes.
+    fn sync_cursor(&mut self) {
+        self.cursor_back = 0;
+        self.place_cursor();
+    }
+
+    /// Move the hardware cursor to where it belongs, if the terminal is on the screen.
+    fn place_cursor(&self) {
+        if !self.is_shown() {
+            return;
+        }
+        let (row, col) = self.cursor_cell();
+        let (row, col) = if row >= self.height {
+            (self.height - 1, self.width - 1)
+        } else {
+            (row, col.min(self.width - 1))
+        };
+        // The screen has far fewer than 2^16 cells
+        cursor::set_offset((row * self.width + col) as u16);
+    }
+
+    /// Row and column the cursor belongs at.
+    fn cursor_cell(&self) -> (usize, usize) {
+        match self.cursor_back {
+            0 => self.output_position(),
+            back => {
+                let end = self.row * self.width + self.column_position;
+                let offset = end.saturating_sub(back);
+                (offset / self.width, offset % self.width)
+            }
+        }
+    }
+
+    /// Show or hide the hardware cursor while the terminal is on the screen.
+    fn set_cursor_visible(&mut self, visible: bool) {
+        self.cursor_visible = visible;
+        self.apply_cursor();
+    }
+
+    /// Give the hardware cursor the terminal's shape, visibility and position, if it's on the
+    /// screen.
+    fn apply_cursor(&self) {
+        if !self.is_shown() {
+            return;
+        }
+        if self.cursor_visible {
+            cursor::show(self.cursor_shape);
+            self.place_cursor();
+        } else {
+            cursor::hide();
+        }
     }
 
     fn clear_row(&mut self, row: usize) {
@@ -322,8 +551,8 @@ impl Writer {
             attr: self.attr,
         };
 
-        for col in 0..VGA_BUFFER_WIDTH {
-            self.buffer.chars[row][col].write(vga_whitepsace_char);
+        for col in 0..self.width {
+            self.set_cell(row, col, vga_whitepsace_char);
         }
     }
 }
@@ -339,9 +568,7 @@ macro_rules! vga_get {
         $(
             $(#[$doc])*
             pub fn $fn_name() -> $out {
-                interrupts::without_interrupts(|| {
-                    WRITER.lock().attr.$getter()
-                })
+                interrupts::without_interrupts(|| console().attr.$getter())
             }
         )*
     };
@@ -360,9 +587,7 @@ macro_rules! vga_set {
         $(
             $(#[$doc])*
             pub fn $fn_name(val: $in) {
-                interrupts::without_interrupts(|| {
-                    WRITER.lock().attr.$setter(val);
-                });
+                interrupts::without_interrupts(|| console().attr.$setter(val));
             }
         )*
     };
@@ -379,24 +604,113 @@ vga_set![
 /// Set the [VgaBgColour], the [VgaFgColour], and the VGA blink value.
 pub fn set_vga_attr(bg: VgaBgColour, fg: VgaFgColour, blink: bool) {
     interrupts::without_interrupts(|| {
-        WRITER.lock().attr = VgaAttr::new(bg, fg, blink);
+        let mut attr = VgaAttr::new(bg, fg, blink);
+        // Bright with bright backgrounds on
+        attr.set_bg(bg);
+        console().attr = attr;
     });
 }
 
 /// Set the VGA text attribute to the default values.
 pub fn set_default_vga_attr() {
     interrupts::without_interrupts(|| {
-        WRITER.lock().attr = VgaAttr::default();
+        console().attr = VgaAttr::default();
     });
 }
 
-#[doc(hidden)]
-pub fn _print(args: fmt::Arguments) {
+/// Blank the whole screen with the current attribute.
+pub fn clear_screen() {
+    interrupts::without_interrupts(|| {
+        console().clear();
+    });
+}
+
+/// Write raw bytes to the VGA buffer. Bytes are handled the same way as by [print].
+pub fn write_bytes(bytes: &[u8]) {
+    interrupts::without_interrupts(|| {
+        console().write_bytes(bytes);
+    });
+}
+
+/// The glyph shown for `c`: its own if the font has one, [cp437::REPLACEMENT] otherwise.
+fn glyph(c: char) -> u8 {
+    cp437::encode(c).unwrap_or(cp437::REPLACEMENT)
+}
+
+/// Show the hardware cursor, which follows the output position, while the console terminal is on
+/// the screen.
+pub fn show_cursor() {
+    interrupts::without_interrupts(|| console().set_cursor_visible(true));
+}
+
+/// Hide the hardware cursor while the console terminal is on the screen.
+pub fn hide_cursor() {
+    interrupts::without_interrupts(|| console().set_cursor_visible(false));
+}
+
+/// Check if the console terminal shows the hardware cursor.
+pub fn cursor_shown() -> bool {
+    interrupts::without_interrupts(|| console().cursor_visible)
+}
+
+/// Change what the hardware cursor looks like. It stays hidden if it's hidden.
+pub fn set_cursor_shape(shape: CursorShape) {
+    interrupts::without_interrupts(|| {
+        let mut writer = console();
+        writer.cursor_shape = shape;
+        writer.apply_cursor();
+    });
+}
+
+/// Get the current [CursorShape].
+pub fn cursor_shape() -> CursorShape {
+    interrupts::without_interrupts(|| console().cursor_shape)
+}
+
+/// The row and column the next character will be written to.
+pub fn output_position() -> (usize, usize) {
+    interrupts::without_interrupts(|| console().output_position())
+}
+
+/// The row and column the hardware cursor is on.
+pub fn cursor_position() -> (usize, usize) {
+    interrupts::without_interrupts(|| {
+        let writer = console();
+        let offset = usize::from(cursor::offset());
+        (offset / writer.width, offset % writer.width)
+    })
+}
+
+/// Put the hardware cursor `cells` cells before the output position, counting back through
+/// earlier rows, for output that's been written out ahead of where the user is. It goes back to
+/// following the output with the next write.
+pub fn place_cursor_back(cells: usize) {
     interrupts::without_interrupts(|| {
-        WRITER.lock().write_fmt(args).unwrap();
+        let mut writer = console();
+        writer.cursor_back = cells;
+        writer.place_cursor();
     });
 }
 
+/// Lock the console terminal's writer.
+fn console() -> WriterGuard {
+    vt::role(Role::Console).writer()
+}
+
+/// Write formatted text to the VGA buffer without recording it in the [dmesg](crate::dmesg)
+/// ring, for output that isn't a kernel message, like the shell's.
+pub fn write_fmt(args: fmt::Arguments) {
+    interrupts::without_interrupts(|| {
+        console().write_fmt(args).unwrap();
+    });
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    dmesg::print(args);
+    write_fmt(args);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -404,7 +718,7 @@ mod tests {
     const WRITELN_FAIL_MSG: &str = "writeln fail :(";
 
     fn clear_buffer(writer: &mut Writer) {
-        for _ in 0..(VGA_BUFFER_HEIGHT + 1) {
+        for _ in 0..(writer.height + 1) {
             writeln!(writer).expect(WRITELN_FAIL_MSG);
         }
     }
@@ -417,7 +731,7 @@ mod tests {
     #[test_case]
     fn many_println() {
         interrupts::without_interrupts(|| {
-            let mut writer = WRITER.lock();
+            let mut writer = console();
             for _ in 0..200 {
                 writeln!(writer, "ping!").expect(WRITELN_FAIL_MSG);
             }
@@ -429,11 +743,11 @@ mod tests {
         let s = "Hello, world!";
 
         interrupts::without_interrupts(|| {
-            let mut writer = WRITER.lock();
+            let mut writer = console();
             clear_buffer(&mut writer);
             writeln!(writer, "\n{}", s).expect(WRITELN_FAIL_MSG);
             for (i, c) in s.chars().enumerate() {
-                let vga_char = writer.buffer.chars[VGA_BUFFER_HEIGHT - 2][i].read();
+                let vga_char = writer.cell(writer.height - 2, i);
                 assert_eq!(char::from(vga_char.text_byte), c);
             }
         });
@@ -445,18 +759,18 @@ mod tests {
         let test_next_line_char = 'I';
 
         interrupts::without_interrupts(|| {
-            let mut writer = WRITER.lock();
+            let mut writer = console();
             clear_buffer(&mut writer);
-            for _ in 0..(VGA_BUFFER_WIDTH) {
+            for _ in 0..(writer.width) {
                 write!(writer, "{}", test_char).expect(WRITELN_FAIL_MSG);
             }
             write!(writer, "{}", test_next_line_char).expect(WRITELN_FAIL_MSG);
 
-            for i in 0..VGA_BUFFER_WIDTH {
-                let vga_char = writer.buffer.chars[VGA_BUFFER_HEIGHT - 2][i].read();
+            for i in 0..writer.width {
+                let vga_char = writer.cell(writer.height - 2, i);
                 assert_eq!(char::from(vga_char.text_byte), test_char);
             }
-            let vga_char = writer.buffer.chars[VGA_BUFFER_HEIGHT - 1][0].read();
+            let vga_char = writer.cell(writer.height - 1, 0);
             assert_eq!(char::from(vga_char.text_byte), test_next_line_char);
         });
     }
@@ -464,7 +778,7 @@ mod tests {
     #[test_case]
     fn print_tab() {
         interrupts::without_interrupts(|| {
-            let mut writer = WRITER.lock();
+            let mut writer = console();
             clear_buffer(&mut writer);
             assert_eq!(writer.column_position, 0);
             write!(writer, "\t").expect(WRITELN_FAIL_MSG);
@@ -472,6 +786,81 @@ mod tests {
         });
     }
 
+    #[test_case]
+    fn cursor_follows_output() {
+        interrupts::without_interrupts(|| {
+            let mut writer = console();
+            let (row, width) = (writer.height - 1, writer.width);
+            let at = |col: usize| (row * width + col) as u16;
+            clear_buffer(&mut writer);
+            assert_eq!(cursor::offset(), at(0));
+            write!(writer, "ab").expect(WRITELN_FAIL_MSG);
+            assert_eq!(cursor::offset(), at(2));
+            write!(writer, "\t").expect(WRITELN_FAIL_MSG);
+            assert_eq!(cursor::offset(), at(TAB_WIDTH));
+            writer.write_bytes(b"\x08");
+            assert_eq!(cursor::offset(), at(TAB_WIDTH - 1));
+            for _ in 0..width {
+                write!(writer, "x").expect(WRITELN_FAIL_MSG);
+            }
+            // Scrolled up, with the last few on the new row
+            assert_eq!(cursor::offset(), at(TAB_WIDTH - 1));
+            writeln!(writer).expect(WRITELN_FAIL_MSG);
+            assert_eq!(cursor::offset(), at(0));
+        });
+    }
+
+    #[test_case]
+    fn cursor_visibility() {
+        let shown = cursor_shown();
+        set_cursor_shape(CursorShape::Block);
+        hide_cursor();
+        assert!(!cursor_shown());
+        show_cursor();
+        assert!(cursor_shown());
+        assert_eq!(cursor_shape(), CursorShape::Block);
+        assert_eq!(cursor_position(), output_position());
+
+        place_cursor_back(3);
+        let (row, col) = output_position();
+        let width = screen::size().1;
+        let expected = (row * width + col).saturating_sub(3);
+        assert_eq!(cursor_position(), (expected / width, expected % width));
+
+        set_cursor_shape(CursorShape::default());
+        if !shown {
+            hide_cursor();
+        }
+    }
+
+    #[test_case]
+    fn print_utf8() {
+        interrupts::without_interrupts(|| {
+            let mut writer = console();
+            clear_buffer(&mut writer);
+            write!(writer, "é€░").expect(WRITELN_FAIL_MSG);
+            // A character split between writes, and one cut short
+            writer.write_bytes(&[0xC3]);
+            writer.write_bytes(&[0xA9, 0xC3]);
+            writer.write_bytes(b"a");
+
+            let row = writer.height - 1;
+            let glyphs: [u8; 6] = core::array::from_fn(|col| writer.cell(row, col).text_byte);
+            assert_eq!(
+                glyphs,
+                [
+                    0x82,
+                    cp437::REPLACEMENT,
+                    0xB0,
+                    0x82,
+                    cp437::REPLACEMENT,
+                    b'a'
+                ]
+            );
+            assert_eq!(writer.column_position, 6);
+        });
+    }
+
     #[test_case]
     fn set_get_attr() {
         set_vga_bg(VgaBgColour::Red);
@@ -483,6 +872,11 @@ mod tests {
         set_vga_blink(true);
         assert!(vga_blink());
 
+        // The background and blink bit are set separately
+        set_vga_bg(VgaBgColour::Green);
+        assert!(vga_blink());
+        assert_eq!(vga_bg(), VgaBgColour::Green);
+
         set_vga_attr(VgaBgColour::Brown, VgaFgColour::Pink, false);
         assert_eq!(vga_bg(), VgaBgColour::Brown);
         assert_eq!(vga_fg(), VgaFgColour::Pink);
@@ -540,6 +934,36 @@ mod tests {
         assert_eq!(vga_attr.0, expected);
     }
 
+    #[test_case]
+    fn vga_attr_new_bright_bg() {
+        let vga_attr = VgaAttr::new(VgaBgColour::DarkGray, VgaFgColour::White, false);
+        assert!(!vga_attr.blink());
+        assert_eq!(vga_attr.bg(), VgaBgColour::Black);
+    }
+
+    #[test_case]
+    fn vga_attr_bright_bg() {
+        // A bright background is set as its dark colour, apart from the blink bit
+        let mut clamped = VgaAttr::new(VgaBgColour::LightBlue, VgaFgColour::Black, false);
+        assert_eq!(clamped.bg(), VgaBgColour::Blue);
+        let mut blinking = VgaAttr::new(VgaBgColour::Red, VgaFgColour::Black, true);
+        blinking.set_bg(VgaBgColour::LightGreen);
+        assert_eq!(blinking.bg(), VgaBgColour::Green);
+        assert!(blinking.blink());
+
+        palette::set_bright_backgrounds(true);
+        // The clamped background stays dark, and blinking doesn't brighten it
+        assert_eq!(clamped.bg(), VgaBgColour::Blue);
+        clamped.set_blink(true);
+        assert_eq!(clamped.bg(), VgaBgColour::Blue);
+        assert!(!clamped.blink());
+        // Only setting the background does
+        clamped.set_bg(VgaBgColour::LightBlue);
+        assert_eq!(clamped.bg(), VgaBgColour::LightBlue);
+        assert!(!clamped.blink());
+        palette::set_bright_backgrounds(false);
+    }
+
     #[test_case]
     fn bad_vga_fg() {
         let _ = VgaFgColour::try_from(0x10).unwrap_err();
@@ -547,6 +971,6 @@ mod tests {
 
     #[test_case]
     fn bad_vga_bg() {
-        let _ = VgaBgColour::try_from(0x8).unwrap_err();
+        let _ = VgaBgColour::try_from(0x10).unwrap_err();
     }
 }
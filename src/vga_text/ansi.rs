@@ -0,0 +1,413 @@
+//! ANSI escape sequences in VGA output.
+//!
+//! The writer understands the control sequences a simple VT100-style terminal would, so the same
+//! bytes can go to the screen and to a terminal on the serial port:
+//!
+//! - `ESC [ n m`: select graphic rendition. 0 resets, 1 and 22 turn brightness on and off, 5 and
+//!   25 turn blinking on and off, 7 swaps the colours, 30-37 and 90-97 set the foreground, 40-47
+//!   and 100-107 the background, and 39 and 49 put back the defaults. Bright backgrounds are only
+//!   shown with [palette::set_bright_backgrounds](super::palette::set_bright_backgrounds), which
+//!   leaves no blinking.
+//! - `ESC [ n A`, `B`, `C`, `D`: move the output position up, down, right or left.
+//! - `ESC [ row ; col H` (or `f`), `ESC [ col G`: move the output position to a row and column,
+//!   counted from 1.
+//! - `ESC [ n J`: erase to the end of the screen (0), to its start (1) or all of it (2, 3).
+//! - `ESC [ n K`: erase to the end of the row (0), to its start (1) or all of it (2).
+//! - `ESC [ s`, `ESC [ u`: save and restore the output position.
+//! - `ESC [ ? 25 h`, `ESC [ ? 25 l`: show and hide the cursor.
+//!
+//! Anything else is dropped.
+
+use core::ops::Range;
+
+use super::{VgaAttr, VgaBgColour, VgaFgColour, Writer};
+
+const ESC: u8 = 0x1B;
+
+/// Most parameters kept from a control sequence.
+const MAX_PARAMS: usize = 8;
+
+const BRIGHT: u8 = 0b1000;
+
+/// VGA colours in ANSI order: black, red, green, yellow, blue, magenta, cyan and white.
+const ANSI_TO_VGA: [u8; 8] = [0x0, 0x4, 0x2, 0x6, 0x1, 0x5, 0x3, 0x7];
+
+/// A control sequence introduced by `ESC [`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) struct Csi {
+    params: [u16; MAX_PARAMS],
+    len: usize,
+    // Whether the parameters started with `?`
+    private: bool,
+    final_byte: u8,
+}
+impl Csi {
+    const fn new() -> Self {
+        Self {
+            params: [0; MAX_PARAMS],
+            len: 0,
+            private: false,
+            final_byte: 0,
+        }
+    }
+
+    /// The parameters given, which are 0 where left empty.
+    fn params(&self) -> &[u16] {
+        &self.params[..self.len]
+    }
+
+    /// Parameter `index`, or `default` if it's missing or 0.
+    fn param_or(&self, index: usize, default: u16) -> u16 {
+        match self.params().get(index) {
+            None | Some(0) => default,
+            Some(&value) => value,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    Csi,
+}
+
+/// What to do with a byte of output.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) enum Action {
+    /// Show or act on the byte as usual.
+    Print(u8),
+    /// Carry out a complete control sequence.
+    Csi(Csi),
+    /// Nothing, as the byte is part of an escape sequence.
+    None,
+}
+
+/// Picks escape sequences out of output, one byte at a time.
+#[derive(Debug)]
+pub(super) struct AnsiParser {
+    state: State,
+    csi: Csi,
+}
+impl AnsiParser {
+    pub(super) const fn new() -> Self {
+        Self {
+            state: State::Ground,
+            csi: Csi::new(),
+        }
+    }
+
+    pub(super) fn feed(&mut self, byte: u8) -> Action {
+        match (self.state, byte) {
+            (_, ESC) => {
+                self.state = State::Escape;
+                Action::None
+            }
+            (State::Ground, byte) => Action::Print(byte),
+            (State::Escape, b'[') => {
+                self.state = State::Csi;
+                self.csi = Csi::new();
+                Action::None
+            }
+            (State::Escape, _) => {
+                self.state = State::Ground;
+                Action::None
+            }
+            (State::Csi, b'?') if self.csi.len == 0 && !self.csi.private => {
+                self.csi.private = true;
+                Action::None
+            }
+            (State::Csi, b'0'..=b'9') => {
+                if self.csi.len == 0 {
+                    self.csi.len = 1;
+                }
+                if let Some(param) = self.csi.params.get_mut(self.csi.len - 1) {
+                    *param = param
+                        .saturating_mul(10)
+                        .saturating_add(u16::from(byte - b'0'));
+                }
+                Action::None
+            }
+            (State::Csi, b';') => {
+                // An empty first parameter still counts
+                self.csi.len = (self.csi.len.max(1) + 1).min(MAX_PARAMS + 1);
+                Action::None
+            }
+            // Intermediate bytes, which none of the supported sequences use
+            (State::Csi, 0x20..=0x2F) => Action::None,
+            (State::Csi, 0x40..=0x7E) => {
+                self.state = State::Ground;
+                self.csi.len = self.csi.len.min(MAX_PARAMS);
+                self.csi.final_byte = byte;
+                Action::Csi(self.csi)
+            }
+            (State::Csi, _) => {
+                self.state = State::Ground;
+                Action::None
+            }
+        }
+    }
+}
+
+impl Writer {
+    /// Carry out a control sequence.
+    pub(super) fn apply_csi(&mut self, csi: &Csi) {
+        let n = usize::from(csi.param_or(0, 1));
+        match (csi.private, csi.final_byte) {
+            (false, b'm') => self.select_graphic_rendition(csi.params()),
+            (false, b'A') => self.row = self.row.saturating_sub(n),
+            (false, b'B') => self.row = (self.row + n).min(self.height - 1),
+            (false, b'C') => self.column_position = (self.column_position + n).min(self.width - 1),
+            (false, b'D') => {
+                self.column_position = self.column_position.min(self.width - 1).saturating_sub(n)
+            }
+            (false, b'H' | b'f') => {
+                self.row = usize::from(csi.param_or(0, 1) - 1).min(self.height - 1);
+                self.column_position = usize::from(csi.param_or(1, 1) - 1).min(self.width - 1);
+            }
+            (false, b'G') => self.column_position = (n - 1).min(self.width - 1),
+            (false, b'J') => self.erase_in_display(csi.param_or(0, 0)),
+            (false, b'K') => self.erase_in_line(csi.param_or(0, 0)),
+            (false, b's') => self.ansi.saved_position = (self.row, self.column_position),
+            (false, b'u') => (self.row, self.column_position) = self.ansi.saved_position,
+            (true, b'h') if csi.params() == [25] => self.set_cursor_visible(true),
+            (true, b'l') if csi.params() == [25] => self.set_cursor_visible(false),
+            _ => {}
+        }
+    }
+
+    fn select_graphic_rendition(&mut self, params: &[u16]) {
+        // No parameters means a reset
+        let params = if params.is_empty() { &[0] } else { params };
+        for &param in params {
+            let fg = u8::from(self.attr.fg());
+            match param {
+                0 => {
+                    self.attr = VgaAttr::default();
+                    self.ansi.bright = false;
+                }
+                1 => {
+                    self.ansi.bright = true;
+                    self.set_fg_bits(fg | BRIGHT);
+                }
+                22 => {
+                    self.ansi.bright = false;
+                    self.set_fg_bits(fg & !BRIGHT);
+                }
+                5 => self.attr.set_blink(true),
+                25 => self.attr.set_blink(false),
+                7 => {
+                    let bg = u8::from(self.attr.bg());
+                    self.set_fg_bits(bg);
+                    self.set_bg_bits(fg);
+                }
+                30..=37 => {
+                    let bright = if self.ansi.bright { BRIGHT } else { 0 };
+                    self.set_fg_bits(ANSI_TO_VGA[usize::from(param - 30)] | bright);
+                }
+                90..=97 => self.set_fg_bits(ANSI_TO_VGA[usize::from(param - 90)] | BRIGHT),
+                39 => self.attr.set_fg(VgaAttr::default().fg()),
+                40..=47 => self.set_bg_bits(ANSI_TO_VGA[usize::from(param - 40)]),
+                100..=107 => self.set_bg_bits(ANSI_TO_VGA[usize::from(param - 100)] | BRIGHT),
+                49 => self.set_bg_bits(u8::from(VgaAttr::default().bg())),
+                _ => {}
+            }
+        }
+    }
+
+    fn set_fg_bits(&mut self, bits: u8) {
+        // Every 4-bit value is a colour
+        self.attr.set_fg(VgaFgColour::try_from(bits & 0xF).unwrap());
+    }
+
+    fn set_bg_bits(&mut self, bits: u8) {
+        // Every 4-bit value is a colour, though the bright ones need bright backgrounds
+        self.attr.set_bg(VgaBgColour::try_from(bits & 0xF).unwrap());
+    }
+
+    fn erase_in_display(&mut self, mode: u16) {
+        let (row, col) = (self.row, self.column_position.min(self.width - 1));
+        match mode {
+            0 => {
+                self.clear_cells(row, col..self.width);
+                for row in (row + 1)..self.height {
+                    self.clear_row(row);
+                }
+            }
+            1 => {
+                for row in 0..row {
+                    self.clear_row(row);
+                }
+                self.clear_cells(row, 0..(col + 1));
+            }
+            2 | 3 => {
+                for row in 0..self.height {
+                    self.clear_row(row);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn erase_in_line(&mut self, mode: u16) {
+        let (row, col) = (self.row, self.column_position.min(self.width - 1));
+        match mode {
+            0 => self.clear_cells(row, col..self.width),
+            1 => self.clear_cells(row, 0..(col + 1)),
+            2 => self.clear_row(row),
+            _ => {}
+        }
+    }
+
+    fn clear_cells(&mut self, row: usize, cols: Range<usize>) {
+        for col in cols {
+            let blank = super::VgaChar {
+                text_byte: super::VGA_WHITESPACE,
+                attr: self.attr,
+            };
+            self.set_cell(row, col, blank);
+        }
+    }
+}
+
+/// Escape sequence state kept by the writer.
+#[derive(Debug)]
+pub(super) struct AnsiState {
+    pub(super) parser: AnsiParser,
+    // Whether SGR 1 is in effect, so later colours are bright too
+    bright: bool,
+    saved_position: (usize, usize),
+}
+impl AnsiState {
+    /// The state for a screen `height` rows high.
+    pub(super) const fn new(height: usize) -> Self {
+        Self {
+            parser: AnsiParser::new(),
+            bright: false,
+            saved_position: (height - 1, 0),
+        }
+    }
+
+    /// Keep the saved position on a screen `height` by `width`.
+    pub(super) fn resize(&mut self, height: usize, width: usize) {
+        let (row, col) = self.saved_position;
+        self.saved_position = (row.min(height - 1), col.min(width));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(bytes: &[u8]) -> alloc::vec::Vec<Action> {
+        let mut parser = AnsiParser::new();
+        bytes
+            .iter()
+            .map(|&byte| parser.feed(byte))
+            .filter(|action| *action != Action::None)
+            .collect()
+    }
+
+    fn csi(params: &[u16], private: bool, final_byte: u8) -> Action {
+        let mut csi = Csi {
+            len: params.len(),
+            private,
+            final_byte,
+            ..Csi::new()
+        };
+        csi.params[..params.len()].copy_from_slice(params);
+        Action::Csi(csi)
+    }
+
+    #[test_case]
+    fn sequences() {
+        assert_eq!(parse(b"a\n"), [Action::Print(b'a'), Action::Print(b'\n')]);
+        assert_eq!(parse(b"\x1b[m"), [csi(&[], false, b'm')]);
+        assert_eq!(
+            parse(b"\x1b[1;31mx"),
+            [csi(&[1, 31], false, b'm'), Action::Print(b'x')]
+        );
+        assert_eq!(parse(b"\x1b[;5H"), [csi(&[0, 5], false, b'H')]);
+        assert_eq!(parse(b"\x1b[?25l"), [csi(&[25], true, b'l')]);
+        assert_eq!(parse(b"\x1b[99999A"), [csi(&[u16::MAX], false, b'A')]);
+        assert_eq!(
+            parse(b"\x1b[1;2;3;4;5;6;7;8;9;10m"),
+            [csi(&[1, 2, 3, 4, 5, 6, 7, 8], false, b'm')]
+        );
+    }
+
+    #[test_case]
+    fn dropped_sequences() {
+        assert_eq!(parse(b"\x1b7a"), [Action::Print(b'a')]);
+        assert_eq!(parse(b"\x1b[1\x1b[2K"), [csi(&[2], false, b'K')]);
+        assert_eq!(parse(b"\x1b[1\nb"), [Action::Print(b'b')]);
+    }
+
+    #[test_case]
+    fn drawn_on_screen() {
+        use crate::vga_text::{output_position, screen, write_bytes};
+
+        let saved = screen::save();
+        write_bytes(b"\x1b[0m\x1b[2J\x1b[3;5Hab\x1b[1;31mc\x1b[44;39md\x1b[0m");
+        let cell = |col| screen::cell_at(2, col).unwrap();
+        assert_eq!(cell(4).text_byte, b'a');
+        assert_eq!(cell(4).attr, VgaAttr::default());
+        assert_eq!(cell(6).text_byte, b'c');
+        assert_eq!(cell(6).attr.fg(), VgaFgColour::LightRed);
+        assert_eq!(
+            cell(7).attr,
+            VgaAttr::new(VgaBgColour::Blue, VgaFgColour::default(), false)
+        );
+        assert_eq!(output_position(), (2, 8));
+
+        write_bytes(b"\x1b[2A\x1b[3Dx\x1b[2;1H\x1b[Ky\r\x1b[3Bz\x1b[1K");
+        assert_eq!(screen::cell_at(0, 5).unwrap().text_byte, b'x');
+        assert_eq!(screen::cell_at(1, 0).unwrap().text_byte, b'y');
+        assert_eq!(screen::cell_at(2, 4).unwrap().text_byte, b'a');
+        assert_eq!(screen::cell_at(4, 0).unwrap().text_byte, b' ');
+        assert_eq!(output_position(), (4, 1));
+        screen::restore(&saved);
+    }
+
+    #[test_case]
+    fn bright_backgrounds() {
+        use crate::vga_text::{palette, screen, write_bytes};
+
+        let saved = screen::save();
+        let attr = |col| screen::cell_at(0, col).unwrap().attr;
+        write_bytes(b"\x1b[0m\x1b[2J\x1b[H\x1b[5;44ma\x1b[104mb");
+        // Blinking, and the background stays dark
+        let blinking = VgaAttr::new(VgaBgColour::Blue, VgaFgColour::default(), true);
+        assert_eq!(attr(0), blinking);
+        assert_eq!(attr(1), blinking);
+
+        palette::set_bright_backgrounds(true);
+        write_bytes(b"\x1b[0m\x1b[104mc\x1b[5;44md\x1b[7me\x1b[0m");
+        let colours = |col| {
+            let attr: VgaAttr = attr(col);
+            (attr.bg(), attr.fg(), attr.blink())
+        };
+        let cells = [colours(2), colours(3), colours(4)];
+        palette::set_bright_backgrounds(false);
+        screen::restore(&saved);
+
+        assert_eq!(
+            cells,
+            [
+                (VgaBgColour::LightBlue, VgaFgColour::default(), false),
+                (VgaBgColour::Blue, VgaFgColour::default(), false),
+                (VgaBgColour::White, VgaFgColour::Blue, false),
+            ]
+        );
+    }
+
+    #[test_case]
+    fn defaults() {
+        let Action::Csi(csi) = parse(b"\x1b[0;7H")[0] else {
+            panic!("not a control sequence");
+        };
+        assert_eq!(csi.param_or(0, 1), 1);
+        assert_eq!(csi.param_or(1, 1), 7);
+        assert_eq!(csi.param_or(2, 1), 1);
+    }
+}
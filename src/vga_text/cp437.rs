@@ -0,0 +1,210 @@
+//! Code page 437, the VGA's built-in character set.
+//!
+//! Besides ASCII, the VGA font has accented letters, box drawing characters, block elements,
+//! some Greek letters and maths symbols, and a few symbols in place of the control characters.
+//! Output is UTF-8, so each character is mapped to its glyph here; characters the font doesn't
+//! have are shown as [REPLACEMENT].
+
+/// The glyph shown for a character the font doesn't have: a small square.
+pub const REPLACEMENT: u8 = 0xFE;
+
+/// Characters shown by glyphs 0x01 to 0x1F, in place of the control characters.
+const LOW: [char; 31] = [
+    '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼', '►', '◄', '↕', '‼',
+    '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+/// Glyph 0x7F, in place of delete.
+const HOUSE: char = '⌂';
+
+/// Characters shown by glyphs 0x80 to 0xFF.
+const HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}',
+];
+
+/// Characters that look enough like a glyph to be shown by it.
+const ALIASES: [(char, u8); 5] = [
+    // Greek small beta, for the German sharp s
+    ('\u{3B2}', 0xE1),
+    // Greek small mu, for the micro sign
+    ('\u{3BC}', 0xE6),
+    // Ohm sign, for Greek capital omega
+    ('\u{2126}', 0xEA),
+    // Element of, for Greek small epsilon
+    ('\u{2208}', 0xEE),
+    // Black medium square, for the small square
+    ('\u{25FC}', 0xFE),
+];
+
+/// The glyph for `c`, if the font has one. Control characters have none, as the glyphs in their
+/// places show other characters.
+pub fn encode(c: char) -> Option<u8> {
+    match c {
+        ' '..='~' => Some(c as u8),
+        HOUSE => Some(0x7F),
+        _ => {
+            let position = |table: &[char]| table.iter().position(|&glyph| glyph == c);
+            // Both tables are short enough for the indices to fit
+            if let Some(index) = position(&LOW) {
+                Some(index as u8 + 0x01)
+            } else if let Some(index) = position(&HIGH) {
+                Some(index as u8 + 0x80)
+            } else {
+                ALIASES
+                    .iter()
+                    .find(|&&(alias, _)| alias == c)
+                    .map(|&(_, glyph)| glyph)
+            }
+        }
+    }
+}
+
+/// The character glyph `glyph` shows. Glyph 0 is blank.
+pub fn decode(glyph: u8) -> char {
+    match glyph {
+        0x00 => ' ',
+        0x01..=0x1F => LOW[usize::from(glyph - 0x01)],
+        0x7F => HOUSE,
+        0x80..=0xFF => HIGH[usize::from(glyph - 0x80)],
+        _ => char::from(glyph),
+    }
+}
+
+/// The result of feeding a byte to a [Utf8Decoder].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) enum Utf8 {
+    /// The byte is part of a character still to be completed.
+    Pending,
+    /// The byte completed a character.
+    Char(char),
+    /// The byte isn't valid where it is.
+    Invalid,
+    /// The byte cut an earlier character short, which is invalid. Feed the byte again.
+    Interrupted,
+}
+
+/// Decodes UTF-8 one byte at a time, so characters can be split between writes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) struct Utf8Decoder {
+    code_point: u32,
+    // Continuation bytes still to come
+    remaining: u8,
+    // Smallest code point the sequence may encode, to catch overlong encodings
+    min: u32,
+}
+impl Utf8Decoder {
+    pub(super) const fn new() -> Self {
+        Self {
+            code_point: 0,
+            remaining: 0,
+            min: 0,
+        }
+    }
+
+    pub(super) fn feed(&mut self, byte: u8) -> Utf8 {
+        let continuation = byte & 0b1100_0000 == 0b1000_0000;
+        if self.remaining > 0 {
+            if !continuation {
+                self.remaining = 0;
+                return Utf8::Interrupted;
+            }
+            self.code_point = (self.code_point << 6) | u32::from(byte & 0b0011_1111);
+            self.remaining -= 1;
+            if self.remaining > 0 {
+                return Utf8::Pending;
+            }
+            return match char::from_u32(self.code_point) {
+                Some(c) if self.code_point >= self.min => Utf8::Char(c),
+                _ => Utf8::Invalid,
+            };
+        }
+
+        let (bits, remaining, min) = match byte {
+            0x00..=0x7F => return Utf8::Char(char::from(byte)),
+            0b1100_0000..=0b1101_1111 => (byte & 0b0001_1111, 1, 0x80),
+            0b1110_0000..=0b1110_1111 => (byte & 0b0000_1111, 2, 0x800),
+            0b1111_0000..=0b1111_0111 => (byte & 0b0000_0111, 3, 0x1_0000),
+            _ => return Utf8::Invalid,
+        };
+        self.code_point = u32::from(bits);
+        self.remaining = remaining;
+        self.min = min;
+        Utf8::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn decode_all(bytes: &[u8]) -> Vec<Utf8> {
+        let mut decoder = Utf8Decoder::new();
+        let mut out = Vec::new();
+        for &byte in bytes {
+            let mut result = decoder.feed(byte);
+            if result == Utf8::Interrupted {
+                out.push(result);
+                result = decoder.feed(byte);
+            }
+            if result != Utf8::Pending {
+                out.push(result);
+            }
+        }
+        out
+    }
+
+    #[test_case]
+    fn glyphs() {
+        assert_eq!(encode('A'), Some(b'A'));
+        assert_eq!(encode('é'), Some(0x82));
+        assert_eq!(encode('╬'), Some(0xCE));
+        assert_eq!(encode('█'), Some(0xDB));
+        assert_eq!(encode('π'), Some(0xE3));
+        assert_eq!(encode('♥'), Some(0x03));
+        assert_eq!(encode('⌂'), Some(0x7F));
+        assert_eq!(encode('\u{3B2}'), Some(0xE1));
+        assert_eq!(encode('\n'), None);
+        assert_eq!(encode('\u{7F}'), None);
+        assert_eq!(encode('€'), None);
+        assert_eq!(encode('日'), None);
+    }
+
+    #[test_case]
+    fn round_trip() {
+        for glyph in 0x01..=0xFF {
+            assert_eq!(encode(decode(glyph)), Some(glyph));
+        }
+    }
+
+    #[test_case]
+    fn utf8() {
+        assert_eq!(
+            decode_all("aé─😀".as_bytes()),
+            [
+                Utf8::Char('a'),
+                Utf8::Char('é'),
+                Utf8::Char('─'),
+                Utf8::Char('😀')
+            ]
+        );
+        // A lone continuation byte, a cut-short character, an overlong encoding and a surrogate
+        assert_eq!(
+            decode_all(b"\x80\xC3a\xC0\xAF\xED\xA0\x80"),
+            [
+                Utf8::Invalid,
+                Utf8::Interrupted,
+                Utf8::Char('a'),
+                Utf8::Invalid,
+                Utf8::Invalid
+            ]
+        );
+    }
+}
@@ -0,0 +1,85 @@
+//! The hardware text cursor.
+
+use super::registers::{read_crtc, update_crtc, write_crtc, Crtc};
+
+const CURSOR_DISABLE: u8 = 0b0010_0000;
+const SCAN_LINE_MASK: u8 = 0b0001_1111;
+
+/// What the hardware cursor looks like.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum CursorShape {
+    /// The bottom two scan lines of the character cell.
+    #[default]
+    Underline,
+    /// The whole character cell.
+    Block,
+    /// Scan lines `start` to `end` of the character cell, counted from the top.
+    ScanLines {
+        /// First scan line.
+        start: u8,
+        /// Last scan line.
+        end: u8,
+    },
+}
+impl CursorShape {
+    /// First and last scan lines of the shape in characters `height` scan lines high.
+    pub(super) fn scan_lines(self, height: u8) -> (u8, u8) {
+        let bottom = height - 1;
+        match self {
+            Self::Underline => (bottom.saturating_sub(1), bottom),
+            Self::Block => (0, bottom),
+            Self::ScanLines { start, end } => (start.min(bottom), end.min(bottom)),
+        }
+    }
+}
+
+/// Height of each character in scan lines.
+fn char_height() -> u8 {
+    (read_crtc(Crtc::MaxScanLine) & SCAN_LINE_MASK) + 1
+}
+
+/// Show the cursor with the given shape.
+pub fn show(shape: CursorShape) {
+    let (start, end) = shape.scan_lines(char_height());
+    update_crtc(Crtc::CursorStart, CURSOR_DISABLE | SCAN_LINE_MASK, start);
+    update_crtc(Crtc::CursorEnd, SCAN_LINE_MASK, end);
+}
+
+/// Hide the cursor.
+pub fn hide() {
+    update_crtc(Crtc::CursorStart, CURSOR_DISABLE, CURSOR_DISABLE);
+}
+
+/// Whether the cursor is shown.
+pub fn is_shown() -> bool {
+    read_crtc(Crtc::CursorStart) & CURSOR_DISABLE == 0
+}
+
+/// Move the cursor to cell `offset`, counted along the rows from the top left.
+pub fn set_offset(offset: u16) {
+    let [low, high] = offset.to_le_bytes();
+    write_crtc(Crtc::CursorLocationHigh, high);
+    write_crtc(Crtc::CursorLocationLow, low);
+}
+
+/// The cell the cursor is on, counted along the rows from the top left.
+pub fn offset() -> u16 {
+    u16::from_le_bytes([
+        read_crtc(Crtc::CursorLocationLow),
+        read_crtc(Crtc::CursorLocationHigh),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn shapes() {
+        assert_eq!(CursorShape::Underline.scan_lines(16), (14, 15));
+        assert_eq!(CursorShape::Block.scan_lines(16), (0, 15));
+        assert_eq!(CursorShape::Underline.scan_lines(8), (6, 7));
+        let shape = CursorShape::ScanLines { start: 4, end: 20 };
+        assert_eq!(shape.scan_lines(16), (4, 15));
+    }
+}
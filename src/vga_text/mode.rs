@@ -0,0 +1,527 @@
+//! Text modes, and the fonts they're shown in.
+//!
+//! The screen starts out in the 80x25 mode the BIOS sets up. [set_mode] reprograms the VGA
+//! controller for a denser mode with characters 8 scan lines high, and every
+//! [terminal](super::vt) takes on the new size. The bottom rows of each screen stay at the bottom,
+//! rows that no longer fit go to the [scrollback](super::scrollback), and the scrolling region
+//! goes back to the whole screen.
+//!
+//! Characters are drawn from the font in plane 2 of the VGA's memory. Each mode loads its own:
+//! the BIOS's 8x16 font for 80x25, and for the others the
+//! [built-in 8x8 font](crate::framebuffer::font), with the BIOS's glyphs squashed to half height
+//! for the characters it doesn't have. [load_font] replaces it with any font as high as the mode's
+//! characters.
+//!
+//! Neither works while the display shows a [framebuffer](crate::framebuffer).
+
+use alloc::vec::Vec;
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use spin::Mutex;
+use x86_64::{instructions::interrupts, PhysAddr, VirtAddr};
+
+use super::{
+    cp437,
+    registers::{
+        read_graphics, read_sequencer, update_crtc, write_attribute, write_crtc, write_graphics,
+        write_misc_output, write_sequencer, Attribute, Crtc, Graphics, Sequencer,
+    },
+    vt, VgaBuffer, VgaChar, Writer, VGA_BUFFER_ADDR, VGA_WHITESPACE,
+};
+use crate::{
+    framebuffer::{self, font},
+    memory::{self, MemoryError},
+};
+
+/// Where the font plane shows up for the CPU while it's being read or written.
+const FONT_WINDOW_ADDR: u64 = 0xA0000;
+/// Bytes given to each glyph in the font plane, whatever the font's height.
+const GLYPH_STRIDE: usize = 32;
+
+// Sequencer reset values: stopped while the clock changes, then running again
+const SYNC_RESET: u8 = 0b01;
+const RUNNING: u8 = 0b11;
+// Set in the vertical retrace end to lock CRT controller registers 0 to 7
+const CRTC_PROTECT: u8 = 0b1000_0000;
+
+// Register values giving the CPU the font plane alone, as plain bytes at FONT_WINDOW_ADDR
+const PLANE_2: u8 = 0b0100;
+const SEQUENTIAL_MEMORY: u8 = 0b0110;
+const PLAIN_READS: u8 = 0;
+const WINDOW_AT_A0000: u8 = 0b0100;
+
+/// The text mode in use.
+static MODE: Mutex<TextMode> = Mutex::new(TextMode::Text80x25);
+/// The BIOS's 8x16 font, kept the first time the font plane is written.
+static BIOS_FONT: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+/// Whether all of the VGA text buffer has been mapped and given to the terminal on the screen.
+static BUFFER_MAPPED: AtomicBool = AtomicBool::new(false);
+
+/// A text mode the screen can be put in.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TextMode {
+    /// 80 columns by 25 rows of 9x16 characters, as the BIOS sets up.
+    #[default]
+    Text80x25,
+    /// 80 columns by 50 rows of 9x8 characters.
+    Text80x50,
+    /// 90 columns by 60 rows of 8x8 characters.
+    Text90x60,
+}
+impl TextMode {
+    /// Every text mode.
+    pub const ALL: [Self; 3] = [Self::Text80x25, Self::Text80x50, Self::Text90x60];
+
+    /// Rows of characters on the screen.
+    pub const fn rows(self) -> usize {
+        match self {
+            Self::Text80x25 => 25,
+            Self::Text80x50 => 50,
+            Self::Text90x60 => 60,
+        }
+    }
+
+    /// Columns of characters on the screen.
+    pub const fn cols(self) -> usize {
+        match self {
+            Self::Text80x25 | Self::Text80x50 => 80,
+            Self::Text90x60 => 90,
+        }
+    }
+
+    /// Scan lines in each character, and rows in each glyph of its font.
+    pub const fn char_height(self) -> usize {
+        match self {
+            Self::Text80x25 => 16,
+            Self::Text80x50 | Self::Text90x60 => 8,
+        }
+    }
+
+    fn timing(self) -> &'static Timing {
+        match self {
+            Self::Text80x25 => &TIMING_80X25,
+            Self::Text80x50 => &TIMING_80X50,
+            Self::Text90x60 => &TIMING_90X60,
+        }
+    }
+}
+impl fmt::Display for TextMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.cols(), self.rows())
+    }
+}
+
+/// Register values that set up a mode's resolution and character size.
+#[derive(Debug)]
+struct Timing {
+    /// Dot clock and sync polarities.
+    misc_output: u8,
+    /// Sequencer clocking mode, for the character width.
+    clocking_mode: u8,
+    /// Attribute horizontal pel panning, which is one pixel off with 9-pixel characters.
+    pel_panning: u8,
+    horizontal: [(Crtc, u8); 6],
+    vertical: [(Crtc, u8); 7],
+    /// Words from one row of text to the next.
+    offset: u8,
+    /// Scan lines in a character, less one, along with bit 9 of the line compare.
+    max_scan_line: u8,
+}
+
+/// 720x400 at 70 Hz as 80 characters 9 pixels wide, on the 28 MHz clock, with 16 scan lines to
+/// a character.
+const TIMING_80X25: Timing = Timing {
+    misc_output: 0x67,
+    clocking_mode: 0x00,
+    pel_panning: 0x08,
+    horizontal: [
+        (Crtc::HorizontalTotal, 0x5F),
+        (Crtc::EndHorizontalDisplay, 0x4F),
+        (Crtc::StartHorizontalBlanking, 0x50),
+        (Crtc::EndHorizontalBlanking, 0x82),
+        (Crtc::StartHorizontalRetrace, 0x55),
+        (Crtc::EndHorizontalRetrace, 0x81),
+    ],
+    vertical: [
+        (Crtc::VerticalTotal, 0xBF),
+        (Crtc::Overflow, 0x1F),
+        (Crtc::VerticalRetraceStart, 0x9C),
+        (Crtc::VerticalRetraceEnd, 0x0E),
+        (Crtc::VerticalDisplayEnd, 0x8F),
+        (Crtc::StartVerticalBlanking, 0x96),
+        (Crtc::EndVerticalBlanking, 0xB9),
+    ],
+    offset: 0x28,
+    max_scan_line: 0x4F,
+};
+
+/// The same as 80x25, with 8 scan lines to a character.
+const TIMING_80X50: Timing = Timing {
+    max_scan_line: 0x47,
+    ..TIMING_80X25
+};
+
+/// 720x480 at 60 Hz as 90 characters 8 pixels wide, on the 28 MHz clock with both syncs
+/// negative, with 8 scan lines to a character.
+const TIMING_90X60: Timing = Timing {
+    misc_output: 0xE7,
+    clocking_mode: 0x01,
+    pel_panning: 0x00,
+    horizontal: [
+        (Crtc::HorizontalTotal, 0x6B),
+        (Crtc::EndHorizontalDisplay, 0x59),
+        (Crtc::StartHorizontalBlanking, 0x5A),
+        (Crtc::EndHorizontalBlanking, 0x8E),
+        (Crtc::StartHorizontalRetrace, 0x60),
+        (Crtc::EndHorizontalRetrace, 0x8D),
+    ],
+    vertical: [
+        (Crtc::VerticalTotal, 0x0B),
+        (Crtc::Overflow, 0x3E),
+        (Crtc::VerticalRetraceStart, 0xEA),
+        (Crtc::VerticalRetraceEnd, 0x0C),
+        (Crtc::VerticalDisplayEnd, 0xDF),
+        (Crtc::StartVerticalBlanking, 0xE7),
+        (Crtc::EndVerticalBlanking, 0x04),
+    ],
+    offset: 0x2D,
+    max_scan_line: 0x47,
+};
+
+/// Errors returned when changing the text mode or font.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TextModeError {
+    /// The display is showing a framebuffer instead of text.
+    Framebuffer,
+    /// The font isn't 256 glyphs of 1 to 32 rows.
+    BadFont,
+    /// The font's glyphs are this many rows high, not the height of the mode's characters.
+    FontHeight(usize),
+    /// Display memory couldn't be mapped.
+    Memory(MemoryError),
+}
+impl fmt::Display for TextModeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Framebuffer => write!(f, "display is showing a framebuffer"),
+            Self::BadFont => write!(f, "font must be 256 glyphs of 1 to 32 rows"),
+            Self::FontHeight(height) => {
+                write!(
+                    f,
+                    "font is {} rows high, not the mode's character height",
+                    height
+                )
+            }
+            Self::Memory(e) => write!(f, "couldn't map display memory: {:?}", e),
+        }
+    }
+}
+
+/// A text mode font: 256 glyphs in code page 437 order, each 8 pixels wide and
+/// [height](Self::height) rows high, with a byte for each row and the leftmost pixel in the high
+/// bit.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Font<'a> {
+    height: usize,
+    glyphs: &'a [u8],
+}
+impl<'a> Font<'a> {
+    /// Glyphs in a font.
+    pub const GLYPHS: usize = 256;
+    /// Most rows a glyph can have.
+    pub const MAX_HEIGHT: usize = GLYPH_STRIDE;
+
+    /// A font of glyphs `height` rows high, one after another in `glyphs`.
+    pub fn new(height: usize, glyphs: &'a [u8]) -> Result<Self, TextModeError> {
+        if !(1..=Self::MAX_HEIGHT).contains(&height) || glyphs.len() != Self::GLYPHS * height {
+            return Err(TextModeError::BadFont);
+        }
+        Ok(Self { height, glyphs })
+    }
+
+    /// Rows in each glyph.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The rows of the glyph for the code page 437 character `byte`.
+    pub fn glyph(&self, byte: u8) -> &'a [u8] {
+        let start = usize::from(byte) * self.height;
+        &self.glyphs[start..(start + self.height)]
+    }
+}
+
+impl Writer {
+    /// Take on a screen `height` rows by `width` columns.
+    fn resize(&mut self, height: usize, width: usize) {
+        self.snap_to_live();
+        let (old_height, old_width) = (self.height, self.width);
+        let cells: Vec<VgaChar> = (0..old_height)
+            .flat_map(|row| (0..old_width).map(move |col| (row, col)))
+            .map(|(row, col)| self.cell(row, col))
+            .collect();
+        for row in 0..old_height.saturating_sub(height) {
+            self.keep_row(row);
+        }
+
+        self.height = height;
+        self.width = width;
+        let blank = VgaChar {
+            text_byte: VGA_WHITESPACE,
+            attr: self.attr,
+        };
+        for row in 0..height {
+            // The same distance from the bottom
+            let old_row = (row + old_height).checked_sub(height);
+            for col in 0..width {
+                let cell = match old_row {
+                    Some(old_row) if col < old_width => cells[old_row * old_width + col],
+                    _ => blank,
+                };
+                self.set_cell(row, col, cell);
+            }
+        }
+        self.row = (self.row + height).saturating_sub(old_height);
+        self.column_position = self.column_position.min(width);
+        self.scroll_region = 0..height;
+        self.ansi.resize(height, width);
+        self.sync_cursor();
+    }
+}
+
+/// Map the window onto the font plane.
+fn font_window() -> Result<VirtAddr, TextModeError> {
+    let size = (Font::GLYPHS * GLYPH_STRIDE) as u64;
+    memory::map_mmio(PhysAddr::new(FONT_WINDOW_ADDR), size).map_err(TextModeError::Memory)
+}
+
+/// Run `f` with the font plane in `window` instead of the text, then put the text back. Only
+/// called with every writer locked, as nothing can write text meanwhile.
+fn with_font_plane<T>(window: VirtAddr, f: impl FnOnce(*mut u8) -> T) -> T {
+    let map_mask = read_sequencer(Sequencer::MapMask);
+    let memory_mode = read_sequencer(Sequencer::MemoryMode);
+    let read_map = read_graphics(Graphics::ReadMapSelect);
+    let graphics_mode = read_graphics(Graphics::Mode);
+    let graphics_misc = read_graphics(Graphics::Misc);
+
+    write_sequencer(Sequencer::MapMask, PLANE_2);
+    write_sequencer(Sequencer::MemoryMode, SEQUENTIAL_MEMORY);
+    write_graphics(Graphics::ReadMapSelect, 2);
+    write_graphics(Graphics::Mode, PLAIN_READS);
+    write_graphics(Graphics::Misc, WINDOW_AT_A0000);
+
+    let result = f(window.as_mut_ptr());
+
+    write_sequencer(Sequencer::MapMask, map_mask);
+    write_sequencer(Sequencer::MemoryMode, memory_mode);
+    write_graphics(Graphics::ReadMapSelect, read_map);
+    write_graphics(Graphics::Mode, graphics_mode);
+    write_graphics(Graphics::Misc, graphics_misc);
+    result
+}
+
+/// Keep the BIOS's font, if it's still in the font plane, and return it.
+fn bios_font(window: VirtAddr) -> Vec<u8> {
+    let mut bios_font = BIOS_FONT.lock();
+    let height = TextMode::Text80x25.char_height();
+    bios_font
+        .get_or_insert_with(|| {
+            with_font_plane(window, |plane| {
+                (0..Font::GLYPHS * height)
+                    .map(|i| {
+                        let (glyph, row) = (i / height, i % height);
+                        // UNSAFE: The window covers every glyph's slot in the font plane.
+                        unsafe { plane.add(glyph * GLYPH_STRIDE + row).read_volatile() }
+                    })
+                    .collect()
+            })
+        })
+        .clone()
+}
+
+/// The 8x8 font: the built-in font's glyphs where it has them, the BIOS's otherwise.
+fn small_font(bios: &[u8]) -> Vec<u8> {
+    let height = TextMode::Text80x25.char_height();
+    (0..=u8::MAX)
+        .flat_map(|byte| match font::get(cp437::decode(byte)) {
+            Some(&glyph) => glyph,
+            // Each row made of two of the BIOS's, so thin lines don't disappear
+            None => core::array::from_fn(|row| {
+                let start = usize::from(byte) * height + 2 * row;
+                bios[start] | bios[start + 1]
+            }),
+        })
+        .collect()
+}
+
+fn write_font(window: VirtAddr, font: &Font) {
+    with_font_plane(window, |plane| {
+        for byte in 0..=u8::MAX {
+            let slot = usize::from(byte) * GLYPH_STRIDE;
+            let glyph = font.glyph(byte).iter().chain(core::iter::repeat(&0));
+            for (row, &bits) in glyph.take(GLYPH_STRIDE).enumerate() {
+                // UNSAFE: The window covers every glyph's slot in the font plane.
+                unsafe { plane.add(slot + row).write_volatile(bits) };
+            }
+        }
+    });
+}
+
+/// Map all of the VGA text buffer, past the page the bootloader maps, the first time it's needed.
+fn map_text_buffer() -> Result<Option<&'static mut VgaBuffer>, TextModeError> {
+    let size = core::mem::size_of::<VgaBuffer>() as u64;
+    let virt = memory::map_mmio(PhysAddr::new(VGA_BUFFER_ADDR as u64), size)
+        .map_err(TextModeError::Memory)?;
+    if BUFFER_MAPPED.swap(true, Ordering::Relaxed) {
+        return Ok(None);
+    }
+    // UNSAFE: The whole buffer was just mapped, and it's only handed out the once, to replace
+    // the terminal on the screen's smaller view of it.
+    Ok(Some(unsafe { &mut *virt.as_mut_ptr::<VgaBuffer>() }))
+}
+
+fn program(timing: &Timing) {
+    write_sequencer(Sequencer::Reset, SYNC_RESET);
+    write_misc_output(timing.misc_output);
+    write_sequencer(Sequencer::ClockingMode, timing.clocking_mode);
+    write_sequencer(Sequencer::Reset, RUNNING);
+
+    update_crtc(Crtc::VerticalRetraceEnd, CRTC_PROTECT, 0);
+    for &(register, value) in timing.horizontal.iter().chain(&timing.vertical) {
+        write_crtc(register, value);
+    }
+    write_crtc(Crtc::Offset, timing.offset);
+    write_crtc(Crtc::MaxScanLine, timing.max_scan_line);
+    update_crtc(Crtc::VerticalRetraceEnd, CRTC_PROTECT, CRTC_PROTECT);
+    write_attribute(Attribute::HorizontalPelPanning, timing.pel_panning);
+}
+
+/// Put the screen in `mode`, with its font, and resize every terminal to match.
+pub fn set_mode(mode: TextMode) -> Result<(), TextModeError> {
+    if framebuffer::info().is_some() {
+        return Err(TextModeError::Framebuffer);
+    }
+    let window = font_window()?;
+    // Last, as once it's taken it has to be used
+    let mut screen = map_text_buffer()?;
+
+    interrupts::without_interrupts(|| {
+        let mut writers = vt::lock_all();
+        let bios = bios_font(window);
+        let glyphs = match mode.char_height() {
+            16 => bios,
+            _ => small_font(&bios),
+        };
+        let font = Font::new(mode.char_height(), &glyphs)?;
+        program(mode.timing());
+        write_font(window, &font);
+
+        for writer in &mut writers {
+            if writer.is_shown() {
+                if let Some(screen) = screen.take() {
+                    writer.buffer = screen;
+                }
+            }
+            writer.resize(mode.rows(), mode.cols());
+        }
+        *MODE.lock() = mode;
+        Ok(())
+    })
+}
+
+/// The text mode the screen is in.
+pub fn mode() -> TextMode {
+    interrupts::without_interrupts(|| *MODE.lock())
+}
+
+/// Show text in `font`, which must be as high as the characters of the current mode.
+pub fn load_font(font: &Font) -> Result<(), TextModeError> {
+    if framebuffer::info().is_some() {
+        return Err(TextModeError::Framebuffer);
+    }
+    let window = font_window()?;
+    interrupts::without_interrupts(|| {
+        let _writers = vt::lock_all();
+        if font.height != MODE.lock().char_height() {
+            return Err(TextModeError::FontHeight(font.height));
+        }
+        // Kept before it's overwritten, for going back to 80x25
+        bios_font(window);
+        write_font(window, font);
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vga_text::{vt::Vt, VgaAttr, MAX_HEIGHT, MAX_WIDTH};
+
+    #[test_case]
+    fn modes() {
+        assert_eq!(TextMode::default(), TextMode::Text80x25);
+        assert_eq!(alloc::format!("{}", TextMode::Text90x60), "90x60");
+        for mode in TextMode::ALL {
+            assert!(mode.rows() <= MAX_HEIGHT && mode.cols() <= MAX_WIDTH);
+            // Rows times scan lines is the height of the mode's timing
+            let lines = mode.rows() * mode.char_height();
+            assert!(lines == 400 || lines == 480);
+        }
+    }
+
+    #[test_case]
+    fn fonts() {
+        let glyphs = [0xAA; 256 * 8];
+        let font = Font::new(8, &glyphs).unwrap();
+        assert_eq!(font.height(), 8);
+        assert_eq!(font.glyph(b'A'), &[0xAA; 8]);
+        assert_eq!(Font::new(16, &glyphs), Err(TextModeError::BadFont));
+        assert_eq!(Font::new(0, &[]), Err(TextModeError::BadFont));
+
+        let bios = [0x0F; 256 * 16];
+        let small = small_font(&bios);
+        assert_eq!(small.len(), 256 * 8);
+        // Box drawing isn't built in
+        let corner = usize::from(cp437::encode('╔').unwrap()) * 8;
+        assert_eq!(&small[corner..(corner + 8)], &[0x0F; 8]);
+        let a = usize::from(b'A') * 8;
+        assert_eq!(&small[a..(a + 8)], font::get('A').unwrap());
+    }
+
+    #[test_case]
+    fn resizing_keeps_the_bottom_rows() {
+        interrupts::without_interrupts(|| {
+            let mut writer = Vt::Tty6.writer();
+            let (height, width) = (writer.height, writer.width);
+            writer.clear();
+            writer.write_bytes(b"top\n");
+            for _ in 2..height {
+                writer.write_bytes(b"\n");
+            }
+            writer.write_bytes(b"bottom");
+            let text = |writer: &Writer, row: usize, len: usize| -> Vec<u8> {
+                (0..len)
+                    .map(|col| writer.cell(row, col).text_byte)
+                    .collect()
+            };
+
+            writer.resize(height + 5, width + 10);
+            assert_eq!(text(&writer, 5, 3), b"top");
+            assert_eq!(text(&writer, height + 4, 6), b"bottom");
+            assert_eq!(writer.output_position(), (height + 4, 6));
+            assert_eq!(writer.cell(0, 0).text_byte, VGA_WHITESPACE);
+            assert_eq!(writer.cell(height + 4, width + 9).attr, VgaAttr::default());
+
+            writer.resize(height - 1, width);
+            assert_eq!(text(&writer, height - 2, 6), b"bottom");
+            assert_eq!(writer.output_position(), (height - 2, 6));
+            assert_eq!(writer.scroll_region, 0..(height - 1));
+
+            writer.resize(height, width);
+            writer.clear();
+        });
+    }
+}
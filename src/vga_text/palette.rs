@@ -0,0 +1,160 @@
+//! The colours text is shown in.
+//!
+//! The 16 attribute colours are looked up in the VGA's DAC, and [set_colour] and [set_palette]
+//! give them any other colour, to theme the console. Bit 7 of an attribute makes the cell blink,
+//! unless [set_bright_backgrounds] has it brighten the background instead, so every colour can be
+//! a background too.
+//!
+//! The [framebuffer console](crate::framebuffer::console) draws in the same colours, and shows
+//! bright backgrounds the same way, but never blinks.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use super::{
+    registers::{read_attribute, read_palette_register, write_attribute, write_dac, Attribute},
+    vt, VgaFgColour,
+};
+use crate::framebuffer::{self, console, Colour};
+
+/// Set in the attribute mode control register for bit 7 to blink rather than brighten the
+/// background
+const BLINK_ENABLE: u8 = 0b1000;
+
+/// The VGA's default colours, in attribute order.
+pub const DEFAULT_PALETTE: [Colour; 16] = [
+    Colour::rgb(0x00, 0x00, 0x00),
+    Colour::rgb(0x00, 0x00, 0xAA),
+    Colour::rgb(0x00, 0xAA, 0x00),
+    Colour::rgb(0x00, 0xAA, 0xAA),
+    Colour::rgb(0xAA, 0x00, 0x00),
+    Colour::rgb(0xAA, 0x00, 0xAA),
+    Colour::rgb(0xAA, 0x55, 0x00),
+    Colour::rgb(0xAA, 0xAA, 0xAA),
+    Colour::rgb(0x55, 0x55, 0x55),
+    Colour::rgb(0x55, 0x55, 0xFF),
+    Colour::rgb(0x55, 0xFF, 0x55),
+    Colour::rgb(0x55, 0xFF, 0xFF),
+    Colour::rgb(0xFF, 0x55, 0x55),
+    Colour::rgb(0xFF, 0x55, 0xFF),
+    Colour::rgb(0xFF, 0xFF, 0x55),
+    Colour::rgb(0xFF, 0xFF, 0xFF),
+];
+
+/// The colours in use, as given. The DAC only keeps the top 6 bits of each part.
+static PALETTE: Mutex<[Colour; 16]> = Mutex::new(DEFAULT_PALETTE);
+static BRIGHT_BACKGROUNDS: AtomicBool = AtomicBool::new(false);
+
+/// The colour attribute colour `colour` is shown in.
+pub fn colour(colour: VgaFgColour) -> Colour {
+    palette()[usize::from(u8::from(colour))]
+}
+
+/// The colours of all 16 attribute colours, in attribute order.
+pub fn palette() -> [Colour; 16] {
+    interrupts::without_interrupts(|| *PALETTE.lock())
+}
+
+/// Show attribute colour `colour` in `rgb`, everywhere it's on the screen.
+pub fn set_colour(colour: VgaFgColour, rgb: Colour) {
+    let mut palette = palette();
+    palette[usize::from(u8::from(colour))] = rgb;
+    set_palette(&palette);
+}
+
+/// Show the 16 attribute colours in `palette`, in attribute order.
+pub fn set_palette(palette: &[Colour; 16]) {
+    change_colours(|| {
+        *PALETTE.lock() = *palette;
+        if framebuffer::info().is_some() {
+            return;
+        }
+        for (attribute, colour) in (0..).zip(palette) {
+            // The BIOS doesn't give attribute colours the DAC entries with their own numbers
+            let entry = read_palette_register(attribute);
+            write_dac(entry, [colour.r >> 2, colour.g >> 2, colour.b >> 2]);
+        }
+    });
+}
+
+/// Go back to the VGA's default colours.
+pub fn reset_palette() {
+    set_palette(&DEFAULT_PALETTE);
+}
+
+/// Whether bit 7 of an attribute brightens the background rather than blinking.
+pub fn bright_backgrounds() -> bool {
+    BRIGHT_BACKGROUNDS.load(Ordering::Relaxed)
+}
+
+/// Have bit 7 of an attribute brighten the background instead of blinking, or go back to
+/// blinking.
+pub fn set_bright_backgrounds(bright: bool) {
+    change_colours(|| {
+        BRIGHT_BACKGROUNDS.store(bright, Ordering::Relaxed);
+        if framebuffer::info().is_some() {
+            return;
+        }
+        let mode = read_attribute(Attribute::ModeControl);
+        let mode = if bright {
+            mode & !BLINK_ENABLE
+        } else {
+            mode | BLINK_ENABLE
+        };
+        write_attribute(Attribute::ModeControl, mode);
+    });
+}
+
+/// Run `f` with no terminal writing, then draw the whole screen on the framebuffer console again.
+fn change_colours(f: impl FnOnce()) {
+    interrupts::without_interrupts(|| {
+        // The terminal on the screen draws it when it's unlocked
+        let _writers = vt::lock_all();
+        f();
+        console::forget_drawn();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vga_text::{registers::read_dac, VgaAttr, VgaBgColour};
+
+    #[test_case]
+    fn colours() {
+        let teal = Colour::rgb(0x00, 0x80, 0x80);
+        set_colour(VgaFgColour::Blue, teal);
+        assert_eq!(colour(VgaFgColour::Blue), teal);
+        assert_eq!(colour(VgaFgColour::Green), DEFAULT_PALETTE[2]);
+        interrupts::without_interrupts(|| {
+            assert_eq!(read_dac(read_palette_register(1)), [0x00, 0x20, 0x20]);
+        });
+
+        reset_palette();
+        assert_eq!(palette(), DEFAULT_PALETTE);
+        interrupts::without_interrupts(|| {
+            assert_eq!(read_dac(read_palette_register(1)), [0x00, 0x00, 0x2A]);
+        });
+    }
+
+    #[test_case]
+    fn bright_background_bit() {
+        let mut attr = VgaAttr::new(VgaBgColour::Green, VgaFgColour::Black, false);
+        attr.set_bg(VgaBgColour::LightGreen);
+        assert_eq!(attr.bg(), VgaBgColour::Green);
+
+        set_bright_backgrounds(true);
+        assert!(bright_backgrounds());
+        attr.set_bg(VgaBgColour::LightGreen);
+        assert_eq!(attr.bg(), VgaBgColour::LightGreen);
+        assert!(!attr.blink());
+        let mode = interrupts::without_interrupts(|| read_attribute(Attribute::ModeControl));
+        assert_eq!(mode & BLINK_ENABLE, 0);
+
+        set_bright_backgrounds(false);
+        let mode = interrupts::without_interrupts(|| read_attribute(Attribute::ModeControl));
+        assert_eq!(mode & BLINK_ENABLE, BLINK_ENABLE);
+    }
+}
@@ -0,0 +1,230 @@
+//! Access to the VGA controller's registers.
+//!
+//! Most registers sit behind a shared index port, so they're only touched with the writer locked.
+
+use x86_64::instructions::port::Port;
+
+// The CRT controller's registers are reached by writing an index, then reading or writing the data
+// port. These are the colour addresses, which is what text mode uses.
+const CRTC_INDEX: u16 = 0x3D4;
+const CRTC_DATA: u16 = 0x3D5;
+
+const SEQUENCER_INDEX: u16 = 0x3C4;
+const SEQUENCER_DATA: u16 = 0x3C5;
+
+const GRAPHICS_INDEX: u16 = 0x3CE;
+const GRAPHICS_DATA: u16 = 0x3CF;
+
+// The attribute controller takes its index and data on the same port, one after the other.
+// Reading the input status register makes the next write an index again.
+const ATTRIBUTE: u16 = 0x3C0;
+const ATTRIBUTE_READ: u16 = 0x3C1;
+const INPUT_STATUS: u16 = 0x3DA;
+// Set in the index to keep the screen on while the attribute registers are accessed
+const PALETTE_ADDRESS_SOURCE: u8 = 0b0010_0000;
+
+const MISC_OUTPUT: u16 = 0x3C2;
+
+// The DAC's entries are reached by writing the first index to read or write, then reading or
+// writing red, green and blue in turn, each entry after the other.
+#[cfg(test)]
+const DAC_READ_INDEX: u16 = 0x3C7;
+const DAC_WRITE_INDEX: u16 = 0x3C8;
+const DAC_DATA: u16 = 0x3C9;
+
+/// CRT controller registers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Crtc {
+    /// Character clocks in a scan line, less five.
+    HorizontalTotal = 0x00,
+    /// Last character clock of a scan line that's shown.
+    EndHorizontalDisplay = 0x01,
+    /// Character clock the horizontal blanking starts on.
+    StartHorizontalBlanking = 0x02,
+    /// Character clock the horizontal blanking ends on, low bits.
+    EndHorizontalBlanking = 0x03,
+    /// Character clock the horizontal retrace starts on.
+    StartHorizontalRetrace = 0x04,
+    /// Character clock the horizontal retrace ends on, and the top bit of the blanking end.
+    EndHorizontalRetrace = 0x05,
+    /// Scan lines in a frame, less two, low byte.
+    VerticalTotal = 0x06,
+    /// High bits of the vertical timing registers.
+    Overflow = 0x07,
+    /// Bottom scan line of each character, less one, in bits 0 to 4.
+    MaxScanLine = 0x09,
+    /// First scan line of the cursor, and whether it's hidden.
+    CursorStart = 0x0A,
+    /// Last scan line of the cursor.
+    CursorEnd = 0x0B,
+    /// Cursor position, high byte.
+    CursorLocationHigh = 0x0E,
+    /// Cursor position, low byte.
+    CursorLocationLow = 0x0F,
+    /// Scan line the vertical retrace starts on, low byte.
+    VerticalRetraceStart = 0x10,
+    /// Scan line the vertical retrace ends on, and whether registers 0 to 7 are locked.
+    VerticalRetraceEnd = 0x11,
+    /// Last scan line that's shown, low byte.
+    VerticalDisplayEnd = 0x12,
+    /// Words from the start of one row of text to the next.
+    Offset = 0x13,
+    /// Scan line the vertical blanking starts on, low byte.
+    StartVerticalBlanking = 0x15,
+    /// Scan line the vertical blanking ends on.
+    EndVerticalBlanking = 0x16,
+}
+
+/// Sequencer registers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Sequencer {
+    /// Stops the sequencer while the clock changes.
+    Reset = 0x00,
+    /// Dot clock and character width.
+    ClockingMode = 0x01,
+    /// Memory planes the CPU writes to.
+    MapMask = 0x02,
+    /// How the CPU's addresses reach the memory planes.
+    MemoryMode = 0x04,
+}
+
+/// Graphics controller registers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Graphics {
+    /// Memory plane the CPU reads from.
+    ReadMapSelect = 0x04,
+    /// Read and write modes, and whether odd and even addresses go to different planes.
+    Mode = 0x05,
+    /// Where the CPU sees display memory, and whether it's text or graphics.
+    Misc = 0x06,
+}
+
+/// Attribute controller registers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Attribute {
+    /// Text or graphics, and whether bit 7 of an attribute blinks (bit 3) or brightens the
+    /// background.
+    ModeControl = 0x10,
+    /// Pixels the picture is shifted left by.
+    HorizontalPelPanning = 0x13,
+}
+
+fn read_indexed(index: u16, data: u16, register: u8) -> u8 {
+    // UNSAFE: The index and data ports belong to the VGA controller, and reading its registers
+    // has no side effects.
+    unsafe {
+        Port::<u8>::new(index).write(register);
+        Port::<u8>::new(data).read()
+    }
+}
+
+fn write_indexed(index: u16, data: u16, register: u8, value: u8) {
+    // UNSAFE: The index and data ports belong to the VGA controller, and the registers written
+    // here only affect what's shown and how display memory is reached.
+    unsafe {
+        Port::<u8>::new(index).write(register);
+        Port::<u8>::new(data).write(value);
+    }
+}
+
+/// Read a CRT controller register.
+pub fn read_crtc(register: Crtc) -> u8 {
+    read_indexed(CRTC_INDEX, CRTC_DATA, register as u8)
+}
+
+/// Write a CRT controller register.
+pub fn write_crtc(register: Crtc, value: u8) {
+    write_indexed(CRTC_INDEX, CRTC_DATA, register as u8, value);
+}
+
+/// Write only the bits of a CRT controller register set in `mask`.
+pub fn update_crtc(register: Crtc, mask: u8, value: u8) {
+    let old = read_crtc(register);
+    write_crtc(register, (old & !mask) | (value & mask));
+}
+
+/// Read a sequencer register.
+pub fn read_sequencer(register: Sequencer) -> u8 {
+    read_indexed(SEQUENCER_INDEX, SEQUENCER_DATA, register as u8)
+}
+
+/// Write a sequencer register.
+pub fn write_sequencer(register: Sequencer, value: u8) {
+    write_indexed(SEQUENCER_INDEX, SEQUENCER_DATA, register as u8, value);
+}
+
+/// Read a graphics controller register.
+pub fn read_graphics(register: Graphics) -> u8 {
+    read_indexed(GRAPHICS_INDEX, GRAPHICS_DATA, register as u8)
+}
+
+/// Write a graphics controller register.
+pub fn write_graphics(register: Graphics, value: u8) {
+    write_indexed(GRAPHICS_INDEX, GRAPHICS_DATA, register as u8, value);
+}
+
+// Select an attribute controller register, for the next read or write of its data
+fn select_attribute(index: u8) {
+    // UNSAFE: Reading the input status register only resets the attribute controller's
+    // index/data flip-flop, and selecting a register has no other side effects.
+    unsafe {
+        Port::<u8>::new(INPUT_STATUS).read();
+        Port::<u8>::new(ATTRIBUTE).write(index | PALETTE_ADDRESS_SOURCE);
+    }
+}
+
+fn read_attribute_index(index: u8) -> u8 {
+    select_attribute(index);
+    // UNSAFE: Reading an attribute controller register has no side effects.
+    unsafe { Port::<u8>::new(ATTRIBUTE_READ).read() }
+}
+
+/// Read an attribute controller register.
+pub fn read_attribute(register: Attribute) -> u8 {
+    read_attribute_index(register as u8)
+}
+
+/// The DAC entry attribute colour `colour` (0 to 15) is shown in.
+pub fn read_palette_register(colour: u8) -> u8 {
+    read_attribute_index(colour & 0xF)
+}
+
+/// Write an attribute controller register.
+pub fn write_attribute(register: Attribute, value: u8) {
+    select_attribute(register as u8);
+    // UNSAFE: The registers written here only affect what's shown.
+    unsafe { Port::<u8>::new(ATTRIBUTE).write(value) }
+}
+
+/// Write the miscellaneous output register, which picks the dot clock and sync polarities.
+pub fn write_misc_output(value: u8) {
+    // UNSAFE: The register belongs to the VGA controller and only affects the display's timing.
+    unsafe { Port::<u8>::new(MISC_OUTPUT).write(value) }
+}
+
+/// Read a DAC entry's red, green and blue, 6 bits each.
+#[cfg(test)]
+pub fn read_dac(index: u8) -> [u8; 3] {
+    // UNSAFE: The ports belong to the VGA controller, and reading the DAC only moves its index on.
+    unsafe {
+        Port::<u8>::new(DAC_READ_INDEX).write(index);
+        let mut data = Port::<u8>::new(DAC_DATA);
+        [data.read(), data.read(), data.read()]
+    }
+}
+
+/// Write a DAC entry's red, green and blue, 6 bits each.
+pub fn write_dac(index: u8, [r, g, b]: [u8; 3]) {
+    // UNSAFE: The ports belong to the VGA controller, and the DAC only affects the colours shown.
+    unsafe {
+        Port::<u8>::new(DAC_WRITE_INDEX).write(index);
+        let mut data = Port::<u8>::new(DAC_DATA);
+        data.write(r);
+        data.write(g);
+        data.write(b);
+    }
+}
@@ -0,0 +1,311 @@
+//! Drawing anywhere on the screen.
+//!
+//! [print](crate::print) only ever writes at its output position, which stays on the last row of
+//! the scrolling region unless moved. The functions here write, fill and read cells by row and
+//! column instead, counted from 0 at the top left, without moving the output position. Anything
+//! outside the screen is clipped.
+//!
+//! To keep a status line on row 0, for example, set the scrolling region to the rows below it and
+//! draw the line with [write_at]; output then scrolls underneath it.
+//!
+//! Like [print](crate::print), these act on the console [terminal](super::vt), which keeps its
+//! contents while another terminal is on the screen.
+
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use x86_64::instructions::interrupts;
+
+use super::{console, VgaAttr, VgaChar, Writer};
+
+/// A rectangle of cells.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Rect {
+    /// Top row.
+    pub row: usize,
+    /// Leftmost column.
+    pub col: usize,
+    /// Rows covered.
+    pub height: usize,
+    /// Columns covered.
+    pub width: usize,
+}
+impl Rect {
+    /// Create a rectangle from its top left cell and size.
+    pub const fn new(row: usize, col: usize, height: usize, width: usize) -> Self {
+        Self {
+            row,
+            col,
+            height,
+            width,
+        }
+    }
+
+    /// The whole screen.
+    pub fn screen() -> Self {
+        let (height, width) = size();
+        Self::new(0, 0, height, width)
+    }
+
+    /// Rows and columns of the part of the rectangle on a screen `height` by `width`.
+    fn clipped(&self, height: usize, width: usize) -> (Range<usize>, Range<usize>) {
+        let rows = self.row.min(height)..(self.row + self.height).min(height);
+        let cols = self.col.min(width)..(self.col + self.width).min(width);
+        (rows, cols)
+    }
+}
+
+/// A copy of the screen, along with the output position, attribute and scrolling region.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedScreen {
+    height: usize,
+    width: usize,
+    cells: Vec<VgaChar>,
+    row: usize,
+    column_position: usize,
+    scroll_region: Range<usize>,
+    attr: VgaAttr,
+}
+
+impl Writer {
+    fn write_at(&mut self, row: usize, col: usize, s: &str, attr: VgaAttr) -> usize {
+        if row >= self.height {
+            return 0;
+        }
+        let cols = col.min(self.width)..self.width;
+        let mut written = 0;
+        for (col, c) in cols.zip(s.chars()) {
+            let cell = VgaChar {
+                text_byte: super::glyph(c),
+                attr,
+            };
+            self.set_cell(row, col, cell);
+            written += 1;
+        }
+        written
+    }
+
+    fn fill_with(&mut self, rect: Rect, mut f: impl FnMut(VgaChar) -> VgaChar) {
+        let (rows, cols) = rect.clipped(self.height, self.width);
+        for row in rows {
+            for col in cols.clone() {
+                let cell = f(self.cell(row, col));
+                self.set_cell(row, col, cell);
+            }
+        }
+    }
+
+    fn save(&self) -> SavedScreen {
+        SavedScreen {
+            height: self.height,
+            width: self.width,
+            cells: (0..self.height)
+                .flat_map(|row| (0..self.width).map(move |col| (row, col)))
+                .map(|(row, col)| self.cell(row, col))
+                .collect(),
+            row: self.row,
+            column_position: self.column_position,
+            scroll_region: self.scroll_region.clone(),
+            attr: self.attr,
+        }
+    }
+
+    fn restore(&mut self, saved: &SavedScreen) {
+        // Saved in another text mode, only the part that fits comes back
+        for row in 0..saved.height.min(self.height) {
+            for col in 0..saved.width.min(self.width) {
+                self.set_cell(row, col, saved.cells[row * saved.width + col]);
+            }
+        }
+        self.row = saved.row.min(self.height - 1);
+        self.column_position = saved.column_position.min(self.width);
+        self.scroll_region = if saved.scroll_region.end <= self.height {
+            saved.scroll_region.clone()
+        } else {
+            0..self.height
+        };
+        self.attr = saved.attr;
+        self.sync_cursor();
+    }
+}
+
+/// Run `f` with the console terminal's writer locked, on the live screen.
+fn with_writer<T>(f: impl FnOnce(&mut Writer) -> T) -> T {
+    interrupts::without_interrupts(|| {
+        let mut writer = console();
+        writer.snap_to_live();
+        f(&mut writer)
+    })
+}
+
+/// Rows and columns of the screen, which depend on the [text mode](super::mode).
+pub fn size() -> (usize, usize) {
+    interrupts::without_interrupts(|| {
+        let writer = console();
+        (writer.height, writer.width)
+    })
+}
+
+/// Write `s` starting at `row` and `col`, with `attr`, one character to a cell. Text doesn't wrap,
+/// so whatever doesn't fit on the row is dropped. Characters are shown the same way as by
+/// [print](crate::print), except that control characters and escape sequences aren't acted on.
+/// Returns the number of cells written.
+pub fn write_at(row: usize, col: usize, s: &str, attr: VgaAttr) -> usize {
+    with_writer(|writer| writer.write_at(row, col, s, attr))
+}
+
+/// Fill `rect` with the character `text_byte` in `attr`.
+pub fn fill(rect: Rect, text_byte: u8, attr: VgaAttr) {
+    with_writer(|writer| writer.fill_with(rect, |_| VgaChar { text_byte, attr }));
+}
+
+/// Change the attribute of every cell in `rect`, keeping the characters.
+pub fn fill_attr(rect: Rect, attr: VgaAttr) {
+    with_writer(|writer| {
+        writer.fill_with(rect, |cell| VgaChar {
+            text_byte: cell.text_byte,
+            attr,
+        })
+    });
+}
+
+/// The cell at `row` and `col`, or `None` if that's off the screen.
+pub fn cell_at(row: usize, col: usize) -> Option<VgaChar> {
+    with_writer(|writer| (row < writer.height && col < writer.width).then(|| writer.cell(row, col)))
+}
+
+/// Copy the screen, to put back with [restore].
+pub fn save() -> SavedScreen {
+    with_writer(|writer| writer.save())
+}
+
+/// Put back a screen copied with [save], including where output goes.
+pub fn restore(saved: &SavedScreen) {
+    with_writer(|writer| writer.restore(saved));
+}
+
+/// Only scroll `rows` when output reaches the end of them, leaving the rest of the screen alone.
+/// Output carries on from the start of the last row of the region.
+pub fn set_scroll_region(rows: Range<usize>) -> Result<(), &'static str> {
+    with_writer(|writer| {
+        if rows.is_empty() || rows.end > writer.height {
+            return Err("Scrolling region must be a non-empty range of rows on the screen.");
+        }
+        writer.row = rows.end - 1;
+        writer.column_position = 0;
+        writer.scroll_region = rows;
+        writer.sync_cursor();
+        Ok(())
+    })
+}
+
+/// Scroll the whole screen again.
+pub fn reset_scroll_region() {
+    // The whole screen is a valid region
+    let _ = set_scroll_region(0..size().0);
+}
+
+/// The rows that scroll.
+pub fn scroll_region() -> Range<usize> {
+    with_writer(|writer| writer.scroll_region.clone())
+}
+
+/// Carry on output from `row` and `col`, clamped to the screen. Output going past the end of the
+/// scrolling region's last row scrolls the region, as usual; output going past the end of the
+/// screen's last row when that's outside the region starts that row over.
+pub fn set_output_position(row: usize, col: usize) {
+    with_writer(|writer| {
+        writer.row = row.min(writer.height - 1);
+        writer.column_position = col.min(writer.width);
+        writer.sync_cursor();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        print,
+        vga_text::{cp437, output_position, VgaBgColour, VgaFgColour},
+    };
+
+    fn row_text(row: usize, cols: Range<usize>) -> Vec<u8> {
+        cols.map(|col| cell_at(row, col).unwrap().text_byte)
+            .collect()
+    }
+
+    #[test_case]
+    fn write_and_read_cells() {
+        let (height, width) = size();
+        let saved = save();
+        let attr = VgaAttr::new(VgaBgColour::Blue, VgaFgColour::Yellow, false);
+        assert_eq!(write_at(3, 10, "status", attr), 6);
+        assert_eq!(row_text(3, 10..16), b"status");
+        assert_eq!(cell_at(3, 10).unwrap().attr, attr);
+        assert_eq!(write_at(3, width - 2, "clipped", attr), 2);
+        assert_eq!(write_at(height, 0, "gone", attr), 0);
+        assert_eq!(write_at(4, 0, "╔═é\n", attr), 4);
+        assert_eq!(row_text(4, 0..4), [0xC9, 0xCD, 0x82, cp437::REPLACEMENT]);
+        assert_eq!(cell_at(height, 0), None);
+        assert_eq!(cell_at(0, width), None);
+        restore(&saved);
+        assert_eq!(save(), saved);
+    }
+
+    #[test_case]
+    fn fill_rectangles() {
+        let width = size().1;
+        let saved = save();
+        let attr = VgaAttr::new(VgaBgColour::Red, VgaFgColour::White, false);
+        fill(Rect::new(5, 5, 2, 3), b'#', attr);
+        assert_eq!(row_text(5, 5..8), b"###");
+        assert_eq!(row_text(6, 5..8), b"###");
+        assert_eq!(cell_at(5, 4), Some(saved.cells[5 * width + 4]));
+        assert_eq!(cell_at(7, 5), Some(saved.cells[7 * width + 5]));
+
+        let other = VgaAttr::new(VgaBgColour::Green, VgaFgColour::Black, false);
+        fill_attr(Rect::new(6, 6, 10, width), other);
+        assert_eq!(cell_at(6, 5).unwrap().attr, attr);
+        assert_eq!(
+            cell_at(6, 6).unwrap(),
+            VgaChar {
+                text_byte: b'#',
+                attr: other
+            }
+        );
+        restore(&saved);
+    }
+
+    #[test_case]
+    fn scrolling_region() {
+        let height = size().0;
+        let saved = save();
+        let attr = VgaAttr::default();
+        write_at(0, 0, "top", attr);
+        set_scroll_region(1..height).unwrap();
+        assert_eq!(scroll_region(), 1..height);
+        for _ in 0..(2 * height) {
+            print!("scrolling\n");
+        }
+        assert_eq!(row_text(0, 0..3), b"top");
+        assert_eq!(row_text(height - 2, 0..9), b"scrolling");
+
+        assert!(set_scroll_region(3..3).is_err());
+        assert!(set_scroll_region(0..(height + 1)).is_err());
+        reset_scroll_region();
+        assert_eq!(scroll_region(), 0..height);
+        restore(&saved);
+    }
+
+    #[test_case]
+    fn output_position_moves() {
+        let saved = save();
+        set_output_position(2, 4);
+        print!("here\nthere");
+        assert_eq!(row_text(2, 4..8), b"here");
+        assert_eq!(row_text(3, 0..5), b"there");
+        assert_eq!(output_position(), (3, 5));
+        restore(&saved);
+        assert_eq!(output_position().0, saved.row);
+    }
+}
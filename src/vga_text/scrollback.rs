@@ -0,0 +1,266 @@
+//! Lines that have scrolled off the top of the screen, and looking back at them.
+//!
+//! Every line scrolled out of the scrolling region is kept, up to the configured depth. Scrolling
+//! back (Shift+PageUp on the keyboard) copies the live screen aside and shows older lines in its
+//! place; any new output puts the live screen back before it's written.
+//!
+//! Each [terminal](super::vt) has its own history. Scrolling acts on the terminal on the screen.
+//!
+//! The history is only allocated by [set_depth], which [init](crate::init) calls once the heap is
+//! up, so scrolling never allocates, even from an interrupt handler.
+
+use alloc::{boxed::Box, collections::VecDeque};
+
+use x86_64::instructions::interrupts;
+
+use super::{
+    cursor,
+    vt::{self, Vt},
+    VgaAttr, VgaChar, Writer, MAX_HEIGHT, MAX_WIDTH, VGA_WHITESPACE,
+};
+
+/// Lines kept by each terminal after [init](crate::init).
+pub const DEFAULT_DEPTH: usize = 250;
+
+/// A line, as wide as the widest text mode, blank past the width of the screen it was on.
+type Row = [VgaChar; MAX_WIDTH];
+
+/// The history of lines scrolled off the screen.
+#[derive(Debug)]
+pub(super) struct Scrollback {
+    lines: VecDeque<Row>,
+    depth: usize,
+    // Lines scrolled back from the live view, 0 when showing it
+    offset: usize,
+    // The live screen, kept aside while scrolled back
+    live: Option<Box<[Row; MAX_HEIGHT]>>,
+}
+impl Scrollback {
+    pub(super) const fn new() -> Self {
+        Self {
+            lines: VecDeque::new(),
+            depth: 0,
+            offset: 0,
+            live: None,
+        }
+    }
+
+    /// Keep `row`, dropping the oldest line if the history is full.
+    pub(super) fn push(&mut self, row: Row) {
+        if self.depth == 0 {
+            return;
+        }
+        if self.lines.len() == self.depth {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(row);
+    }
+
+    /// Lines scrolled back from the live view.
+    pub(super) fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl Writer {
+    fn read_row(&self, row: usize) -> Row {
+        core::array::from_fn(|col| {
+            if col < self.width {
+                self.cell(row, col)
+            } else {
+                VgaChar {
+                    text_byte: VGA_WHITESPACE,
+                    attr: VgaAttr::default(),
+                }
+            }
+        })
+    }
+
+    /// Keep the first row of the scrolling region, which is about to scroll away.
+    pub(super) fn keep_scrolled_row(&mut self) {
+        self.keep_row(self.scroll_region.start);
+    }
+
+    /// Keep `row` in the history.
+    pub(super) fn keep_row(&mut self, row: usize) {
+        let row = self.read_row(row);
+        self.scrollback.push(row);
+    }
+
+    /// Show the screen `offset` lines back from the live view.
+    fn view(&mut self, offset: usize) {
+        let offset = offset.min(self.scrollback.lines.len());
+        if offset == self.scrollback.offset {
+            return;
+        }
+        let Some(mut live) = self.scrollback.live.take() else {
+            return;
+        };
+        if self.scrollback.offset == 0 {
+            for (row, saved) in live.iter_mut().enumerate().take(self.height) {
+                *saved = self.read_row(row);
+            }
+        }
+
+        // The history followed by the live screen, of which the screen shows a window
+        let top = self.scrollback.lines.len() - offset;
+        for row in 0..self.height {
+            let line = match self.scrollback.lines.get(top + row) {
+                Some(&line) => line,
+                None => live[top + row - self.scrollback.lines.len()],
+            };
+            for (col, &cell) in line.iter().enumerate().take(self.width) {
+                self.set_cell(row, col, cell);
+            }
+        }
+        self.scrollback.live = Some(live);
+        self.scrollback.offset = offset;
+
+        // Past the end of the screen, the cursor isn't shown
+        if self.is_shown() {
+            let (row, col) = self.output_position();
+            let offset = (row + offset) * self.width + col;
+            cursor::set_offset(offset.min(u16::MAX.into()) as u16);
+        }
+    }
+
+    /// Go back to the live view, if scrolled back.
+    pub(super) fn snap_to_live(&mut self) {
+        if self.scrollback.offset > 0 {
+            self.view(0);
+            self.sync_cursor();
+        }
+    }
+
+    fn set_scrollback_depth(&mut self, depth: usize) {
+        self.snap_to_live();
+        let mut lines = VecDeque::with_capacity(depth);
+        let kept = self.scrollback.lines.len().min(depth);
+        lines.extend(self.scrollback.lines.drain(..).rev().take(kept).rev());
+        self.scrollback.lines = lines;
+        self.scrollback.depth = depth;
+        self.scrollback.live = (depth > 0).then(|| {
+            Box::new(
+                [[VgaChar {
+                    text_byte: 0,
+                    attr: self.attr,
+                }; MAX_WIDTH]; MAX_HEIGHT],
+            )
+        });
+    }
+}
+
+/// Keep up to `depth` lines on every terminal, dropping the oldest if there are more. A depth of
+/// 0 turns the scrollback off.
+pub fn set_depth(depth: usize) {
+    for vt in Vt::ALL {
+        interrupts::without_interrupts(|| vt.writer().set_scrollback_depth(depth));
+    }
+}
+
+/// Most lines kept by each terminal.
+pub fn depth() -> usize {
+    interrupts::without_interrupts(|| vt::active().writer().scrollback.depth)
+}
+
+/// Lines kept so far by the terminal on the screen.
+pub fn len() -> usize {
+    interrupts::without_interrupts(|| vt::active().writer().scrollback.lines.len())
+}
+
+/// How many lines back from the live view the screen is showing.
+pub fn offset() -> usize {
+    interrupts::without_interrupts(|| vt::active().writer().scrollback.offset)
+}
+
+/// Show `lines` older lines, stopping at the oldest kept.
+pub fn scroll_back(lines: usize) {
+    interrupts::without_interrupts(|| {
+        let mut writer = vt::active().writer();
+        let offset = writer.scrollback.offset.saturating_add(lines);
+        writer.view(offset);
+    });
+}
+
+/// Show `lines` newer lines, stopping at the live view.
+pub fn scroll_forward(lines: usize) {
+    interrupts::without_interrupts(|| {
+        let mut writer = vt::active().writer();
+        let offset = writer.scrollback.offset.saturating_sub(lines);
+        if offset == 0 {
+            writer.snap_to_live();
+        } else {
+            writer.view(offset);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{println, vga_text::screen};
+
+    /// Whether `row` as shown starts with `text`. Reading through [screen] would go back to the
+    /// live view.
+    fn row_starts_with(row: usize, text: &str) -> bool {
+        let writer = vt::active().writer();
+        text.bytes()
+            .enumerate()
+            .all(|(col, byte)| writer.cell(row, col).text_byte == byte)
+    }
+
+    #[test_case]
+    fn scroll_back_and_snap() {
+        let old_depth = depth();
+        let height = screen::size().0;
+        set_depth(2 * height);
+        for i in 0..(3 * height) {
+            println!("scrollback {}", i);
+        }
+        assert_eq!(len(), 2 * height);
+        // The last line printed is just above the empty output row
+        let last = 3 * height - 1;
+        let live = screen::save();
+
+        scroll_back(3);
+        assert_eq!(offset(), 3);
+        assert!(row_starts_with(
+            0,
+            &alloc::format!("scrollback {}", last - height + 2 - 3)
+        ));
+        scroll_back(10 * height);
+        assert_eq!(offset(), 2 * height);
+        scroll_forward(2 * height - 1);
+        assert_eq!(offset(), 1);
+        scroll_forward(5);
+        assert_eq!(offset(), 0);
+        assert_eq!(screen::save(), live);
+
+        scroll_back(4);
+        println!("new output");
+        assert_eq!(offset(), 0);
+        assert!(row_starts_with(height - 2, "new output"));
+
+        set_depth(old_depth);
+    }
+
+    #[test_case]
+    fn history_is_bounded() {
+        let mut scrollback = Scrollback::new();
+        scrollback.depth = 3;
+        for i in 0..5 {
+            let mut row = [VgaChar {
+                text_byte: 0,
+                attr: Default::default(),
+            }; MAX_WIDTH];
+            row[0].text_byte = i;
+            scrollback.push(row);
+        }
+        let firsts: alloc::vec::Vec<u8> = scrollback
+            .lines
+            .iter()
+            .map(|row| row[0].text_byte)
+            .collect();
+        assert_eq!(firsts, [2, 3, 4]);
+    }
+}
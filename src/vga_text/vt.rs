@@ -0,0 +1,361 @@
+//! Virtual terminals sharing the screen.
+//!
+//! There are [VT_COUNT] terminals, `tty1` to `tty6`, each with its own text, attribute, cursor,
+//! scrollback and [keyboard](crate::keyboard) input. One is on the screen at a time; the others
+//! keep writing to off-screen buffers, which are copied to the screen when they're switched to,
+//! with [switch_to] or Alt+F1 to Alt+F6 on the keyboard.
+//!
+//! Terminals are given [Role]s: [print](crate::print), the shell and the rest of
+//! [vga_text](super) use the console terminal, and the logger uses the log terminal. Both are
+//! `tty1` until told otherwise, so the log can be moved out of the shell's way with
+//! `set_role(Role::Log, Vt::Tty2)`.
+
+use core::{
+    fmt::{self, Write},
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use lazy_static::lazy_static;
+use spin::{Mutex, MutexGuard};
+use x86_64::instructions::interrupts;
+
+use super::{
+    VgaAttr, VgaBgColour, VgaBuffer, VgaChar, VgaFgColour, Writer, MAX_HEIGHT, MAX_WIDTH,
+    VGA_BUFFER_ADDR, VGA_WHITESPACE,
+};
+use crate::framebuffer::console::{self, Cursor, CELL_HEIGHT};
+
+/// Number of virtual terminals.
+pub const VT_COUNT: usize = 6;
+
+/// A blank cell in the default attribute.
+const BLANK: VgaChar = VgaChar {
+    text_byte: VGA_WHITESPACE,
+    attr: VgaAttr::new(VgaBgColour::Black, VgaFgColour::White, false),
+};
+
+type Cells = [VgaChar; MAX_HEIGHT * MAX_WIDTH];
+
+lazy_static! {
+    /// Each terminal's writer. The one on the screen writes to the VGA buffer.
+    static ref TERMINALS: [Mutex<Writer>; VT_COUNT] = {
+        // Static rather than on the heap, as a panic can print before the heap is set up
+        static mut OFF_SCREEN: [Cells; VT_COUNT] = [[BLANK; MAX_HEIGHT * MAX_WIDTH]; VT_COUNT];
+
+        core::array::from_fn(|index| {
+            // UNSAFE: A `VgaBuffer` is laid out like its cells, as it and `Volatile` are
+            // transparent. Each buffer is only handed out here, once, and the array is only built
+            // once.
+            let off_screen =
+                unsafe { &mut *(&raw mut OFF_SCREEN[index]).cast::<VgaBuffer>() };
+            let writer = match index {
+                // UNSAFE: The VGA text buffer is always mapped at VGA_BUFFER_ADDR, as far as the
+                // cells of the 80x25 mode the screen starts in, and the first terminal is the
+                // only one given it here. Bigger modes map the rest of it first.
+                0 => Writer::new(
+                    unsafe { &mut *(VGA_BUFFER_ADDR as *mut VgaBuffer) },
+                    Some(off_screen),
+                ),
+                _ => Writer::new(off_screen, None),
+            };
+            Mutex::new(writer)
+        })
+    };
+}
+
+/// The terminal on the screen, as `Vt::index`.
+static ACTIVE: AtomicU8 = AtomicU8::new(0);
+
+/// The terminal given each [Role], as `Vt::index`.
+static ROLES: [AtomicU8; 2] = [AtomicU8::new(0), AtomicU8::new(0)];
+
+/// The VGA text buffer, put aside while the screen is kept in memory for the framebuffer console.
+static VGA_BUFFER: Mutex<Option<&'static mut VgaBuffer>> = Mutex::new(None);
+
+/// A virtual terminal.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[allow(missing_docs)]
+pub enum Vt {
+    Tty1,
+    Tty2,
+    Tty3,
+    Tty4,
+    Tty5,
+    Tty6,
+}
+impl Vt {
+    /// Every terminal, in order.
+    pub const ALL: [Self; VT_COUNT] = [
+        Self::Tty1,
+        Self::Tty2,
+        Self::Tty3,
+        Self::Tty4,
+        Self::Tty5,
+        Self::Tty6,
+    ];
+
+    /// Position of the terminal in [Vt::ALL].
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    fn from_index(index: usize) -> Self {
+        Self::ALL[index]
+    }
+
+    /// Name of the terminal under `/dev`.
+    pub fn device_name(self) -> &'static str {
+        match self {
+            Self::Tty1 => "tty1",
+            Self::Tty2 => "tty2",
+            Self::Tty3 => "tty3",
+            Self::Tty4 => "tty4",
+            Self::Tty5 => "tty5",
+            Self::Tty6 => "tty6",
+        }
+    }
+
+    /// Lock the terminal's writer.
+    pub(super) fn writer(self) -> WriterGuard {
+        WriterGuard(TERMINALS[self.index()].lock())
+    }
+
+    fn with_writer<T>(self, f: impl FnOnce(&mut Writer) -> T) -> T {
+        interrupts::without_interrupts(|| f(&mut self.writer()))
+    }
+
+    /// Write raw bytes to the terminal, handled the same way as by [print](crate::print).
+    pub fn write_bytes(self, bytes: &[u8]) {
+        self.with_writer(|writer| writer.write_bytes(bytes));
+    }
+
+    /// Write formatted text to the terminal. Nothing is recorded in the [dmesg](crate::dmesg)
+    /// ring.
+    pub fn write_fmt(self, args: fmt::Arguments) {
+        self.with_writer(|writer| writer.write_fmt(args).unwrap());
+    }
+
+    /// The attribute the terminal writes with.
+    pub fn attr(self) -> VgaAttr {
+        self.with_writer(|writer| writer.attr)
+    }
+
+    /// Change the attribute the terminal writes with.
+    pub fn set_attr(self, attr: VgaAttr) {
+        self.with_writer(|writer| writer.attr = attr);
+    }
+
+    /// Blank the terminal with its attribute.
+    pub fn clear(self) {
+        self.with_writer(|writer| writer.clear());
+    }
+}
+impl fmt::Display for Vt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.device_name())
+    }
+}
+
+/// What a terminal is used for. A terminal can have several roles.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Role {
+    /// Kernel console and shell, and test output. `tty1` by default.
+    Console,
+    /// Kernel log. `tty1` by default.
+    Log,
+}
+impl Role {
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Use `vt` for `role`.
+pub fn set_role(role: Role, vt: Vt) {
+    ROLES[role.index()].store(vt.index() as u8, Ordering::Relaxed);
+}
+
+/// The terminal used for `role`.
+pub fn role(role: Role) -> Vt {
+    Vt::from_index(usize::from(ROLES[role.index()].load(Ordering::Relaxed)))
+}
+
+/// The terminal on the screen.
+pub fn active() -> Vt {
+    Vt::from_index(usize::from(ACTIVE.load(Ordering::Relaxed)))
+}
+
+/// A terminal's writer, locked. Changes to the screen are drawn on the
+/// [framebuffer console](console), if it's in use, once it's unlocked.
+pub(super) struct WriterGuard(MutexGuard<'static, Writer>);
+impl Deref for WriterGuard {
+    type Target = Writer;
+
+    fn deref(&self) -> &Writer {
+        &self.0
+    }
+}
+impl DerefMut for WriterGuard {
+    fn deref_mut(&mut self) -> &mut Writer {
+        &mut self.0
+    }
+}
+impl Drop for WriterGuard {
+    fn drop(&mut self) {
+        if console::is_enabled() && self.is_shown() {
+            self.draw_on_framebuffer();
+        }
+    }
+}
+
+impl Writer {
+    /// Draw the screen on the framebuffer console.
+    fn draw_on_framebuffer(&self) {
+        let cursor = self
+            .cursor_visible
+            .then(|| self.cursor_cell())
+            // Moved down with the screen while scrolled back
+            .map(|(row, col)| (row + self.scrollback.offset(), col))
+            .filter(|&(row, _)| row < self.height)
+            .map(|(row, col)| Cursor {
+                row,
+                col,
+                scan_lines: self.cursor_shape.scan_lines(CELL_HEIGHT as u8),
+            });
+        console::draw(|row, col| self.cell(row, col), cursor);
+    }
+
+    /// Copy the screen to the off-screen buffer and write there from now on. Returns the VGA
+    /// buffer, for the terminal taking the screen.
+    fn leave_screen(&mut self) -> &'static mut VgaBuffer {
+        self.snap_to_live();
+        let off_screen = self
+            .off_screen
+            .take()
+            .expect("Terminal leaving the screen should be on it.");
+        self.copy_screen(off_screen);
+        core::mem::replace(&mut self.buffer, off_screen)
+    }
+
+    /// Copy the off-screen buffer to `screen` and write there from now on.
+    fn take_screen(&mut self, screen: &'static mut VgaBuffer) {
+        self.copy_screen(screen);
+        self.off_screen = Some(core::mem::replace(&mut self.buffer, screen));
+        self.apply_cursor();
+    }
+
+    /// Copy the cells of the screen to `to`.
+    fn copy_screen(&self, to: &mut VgaBuffer) {
+        let cells = self.height * self.width;
+        for (from, to) in self.buffer.chars[..cells].iter().zip(&mut to.chars) {
+            to.write(from.read());
+        }
+    }
+}
+
+/// Keep the screen in memory instead of the VGA text buffer, for the framebuffer console to draw.
+pub(crate) fn move_screen_to_memory() {
+    // Stands in for the VGA text buffer, so it lives as long as the kernel does
+    static mut MEMORY_SCREEN: Cells = [BLANK; MAX_HEIGHT * MAX_WIDTH];
+
+    interrupts::without_interrupts(|| {
+        let mut vga_buffer = VGA_BUFFER.lock();
+        if vga_buffer.is_some() {
+            return;
+        }
+        // UNSAFE: A `VgaBuffer` is laid out like its cells. The memory screen is only handed out
+        // while the VGA text buffer is put aside, which happens once at a time.
+        let memory = unsafe { &mut *(&raw mut MEMORY_SCREEN).cast::<VgaBuffer>() };
+        let mut writer = active().writer();
+        writer.copy_screen(memory);
+        *vga_buffer = Some(core::mem::replace(&mut writer.buffer, memory));
+    });
+}
+
+/// Go back to keeping the screen in the VGA text buffer.
+pub(crate) fn move_screen_to_vga() {
+    interrupts::without_interrupts(|| {
+        let Some(vga) = VGA_BUFFER.lock().take() else {
+            return;
+        };
+        let mut writer = active().writer();
+        writer.copy_screen(vga);
+        writer.buffer = vga;
+        writer.apply_cursor();
+    });
+}
+
+/// Draw the whole screen on the framebuffer console.
+pub(crate) fn redraw() {
+    interrupts::without_interrupts(|| drop(active().writer()));
+}
+
+/// Lock every terminal's writer, to change the screen with none of them writing.
+pub(super) fn lock_all() -> [WriterGuard; VT_COUNT] {
+    Vt::ALL.map(Vt::writer)
+}
+
+/// Put `vt` on the screen, with its cursor, keeping what was there for when its terminal is
+/// switched back to.
+pub fn switch_to(vt: Vt) {
+    interrupts::without_interrupts(|| {
+        let old = active();
+        if old == vt {
+            return;
+        }
+        let screen = old.writer().leave_screen();
+        vt.writer().take_screen(screen);
+        ACTIVE.store(vt.index() as u8, Ordering::Relaxed);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{print, vga_text::screen};
+
+    /// The start of the last row of `vt`, wherever it's written.
+    fn last_row(vt: Vt, len: usize) -> alloc::vec::Vec<u8> {
+        interrupts::without_interrupts(|| {
+            let writer = vt.writer();
+            (0..len)
+                .map(|col| writer.cell(writer.height - 1, col).text_byte)
+                .collect()
+        })
+    }
+
+    #[test_case]
+    fn roles() {
+        assert_eq!(role(Role::Console), Vt::Tty1);
+        set_role(Role::Log, Vt::Tty4);
+        assert_eq!(role(Role::Log), Vt::Tty4);
+        assert_eq!(role(Role::Console), Vt::Tty1);
+        set_role(Role::Log, Vt::Tty1);
+        assert_eq!(Vt::Tty3.device_name(), "tty3");
+        assert_eq!(alloc::format!("{}", Vt::Tty6), "tty6");
+    }
+
+    #[test_case]
+    fn switching_keeps_contents() {
+        print!("\nconsole text");
+        let console = screen::save();
+        Vt::Tty2.write_bytes(b"\nother text");
+        assert_eq!(screen::save(), console);
+
+        switch_to(Vt::Tty2);
+        assert_eq!(active(), Vt::Tty2);
+        assert!(Vt::Tty2.writer().is_shown());
+        assert!(!Vt::Tty1.writer().is_shown());
+        assert_eq!(last_row(Vt::Tty2, 10), b"other text");
+        // The console carries on off the screen
+        print!(" more");
+        assert_eq!(last_row(Vt::Tty1, 17), b"console text more");
+
+        switch_to(Vt::Tty1);
+        assert_eq!(active(), Vt::Tty1);
+        assert_eq!(last_row(Vt::Tty1, 17), b"console text more");
+        print!("\n");
+        Vt::Tty2.clear();
+    }
+}
@@ -0,0 +1,35 @@
+//! [Virtio](https://docs.oasis-open.org/virtio/virtio/v1.2/virtio-v1.2.html) paravirtualised
+//! devices.
+//!
+//! Virtio devices sit on the PCI bus. [pci] hides the differences between the legacy and modern
+//! (virtio 1.0) transports, [queue] implements split virtqueues in DMA memory, and device drivers
+//! such as [blk] are built on top of the two.
+
+pub mod blk;
+pub mod pci;
+pub mod queue;
+
+/// PCI vendor ID shared by all virtio devices.
+pub const VENDOR_ID: u16 = 0x1AF4;
+
+/// Bits of the device status register.
+#[allow(missing_docs)]
+pub mod status {
+    pub const ACKNOWLEDGE: u8 = 1;
+    pub const DRIVER: u8 = 2;
+    pub const DRIVER_OK: u8 = 4;
+    pub const FEATURES_OK: u8 = 8;
+    pub const DEVICE_NEEDS_RESET: u8 = 64;
+    pub const FAILED: u8 = 128;
+}
+
+/// Feature bits that don't depend on the device type.
+#[allow(missing_docs)]
+pub mod features {
+    pub const VERSION_1: u64 = 1 << 32;
+}
+
+/// Register the virtio device drivers.
+pub fn init() {
+    crate::pci::register_driver(&blk::DRIVER);
+}
@@ -0,0 +1,419 @@
+//! virtio-blk disk driver.
+//!
+//! Transfers are split into requests of at most [MAX_REQUEST_SIZE] bytes, all of which are
+//! submitted before waiting for any of them, so the device can work on several at once. The
+//! device raises its PCI interrupt as requests complete; if no interrupt line is available the
+//! used ring is polled instead.
+
+use alloc::{collections::VecDeque, format, string::String, sync::Arc, vec, vec::Vec};
+use core::{
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use spin::Mutex;
+use x86_64::{instructions::interrupts, PhysAddr, VirtAddr};
+
+use super::{
+    pci::{Transport, ISR_QUEUE},
+    queue::{Buffer, VirtQueue},
+    VENDOR_ID,
+};
+use crate::{
+    block::{check_request, BlockDevice, BlockError, SECTOR_SIZE},
+    fs::devfs,
+    interrupts::register_pci_handler,
+    memory::{self, PAGE_SIZE},
+    pci::{PciDevice, PciDriver, PciMatch, ProbeError},
+};
+
+const DEVICE_ID_TRANSITIONAL: u16 = 0x1001;
+const DEVICE_ID_MODERN: u16 = 0x1042;
+
+const FEATURE_SEG_MAX: u64 = 1 << 2;
+const FEATURE_RO: u64 = 1 << 5;
+const FEATURE_FLUSH: u64 = 1 << 9;
+const SUPPORTED_FEATURES: u64 = FEATURE_SEG_MAX | FEATURE_RO | FEATURE_FLUSH;
+
+const CONFIG_CAPACITY: u16 = 0x00;
+const CONFIG_SEG_MAX: u16 = 0x0C;
+
+const REQUEST_IN: u32 = 0;
+const REQUEST_OUT: u32 = 1;
+const REQUEST_FLUSH: u32 = 4;
+
+const STATUS_OK: u8 = 0;
+// Written by the driver, so a status the device never touched isn't mistaken for success
+const STATUS_PENDING: u8 = 0xFF;
+
+const REQUEST_QUEUE: u16 = 0;
+
+/// Largest amount of data sent in a single request.
+pub const MAX_REQUEST_SIZE: usize = 64 * 1024;
+// Requests that can be in flight at once on one device
+const MAX_IN_FLIGHT: usize = 32;
+
+const HEADER_SIZE: u64 = 16;
+// Request headers come first in the request page, then one status byte per request
+const STATUS_AREA_OFFSET: u64 = HEADER_SIZE * MAX_IN_FLIGHT as u64;
+
+static DEVICES: Mutex<Vec<Arc<VirtioBlk>>> = Mutex::new(Vec::new());
+// Bitmap of PIC lines the interrupt handler is registered on
+static IRQ_LINES: Mutex<u16> = Mutex::new(0);
+
+/// Driver for virtio-blk devices, both transitional and modern.
+pub static DRIVER: PciDriver = PciDriver {
+    name: "virtio-blk",
+    matches: &[
+        PciMatch::id(VENDOR_ID, DEVICE_ID_TRANSITIONAL),
+        PciMatch::id(VENDOR_ID, DEVICE_ID_MODERN),
+    ],
+    probe,
+};
+
+/// Driver state that has to change together.
+#[derive(Debug)]
+struct State {
+    queue: VirtQueue,
+    // Request slot of each in-flight chain, indexed by head descriptor
+    slot_of_head: Vec<Option<usize>>,
+    free_slots: Vec<usize>,
+}
+
+/// A virtio-blk disk.
+#[derive(Debug)]
+pub struct VirtioBlk {
+    name: String,
+    transport: Transport,
+    capacity: u64,
+    read_only: bool,
+    can_flush: bool,
+    max_segments: usize,
+    uses_interrupts: bool,
+    // DMA page holding request headers and statuses
+    requests_virt: VirtAddr,
+    requests_phys: PhysAddr,
+    done: [AtomicBool; MAX_IN_FLIGHT],
+    // Also locked by the interrupt handler, so only ever locked with interrupts disabled
+    state: Mutex<State>,
+}
+impl VirtioBlk {
+    /// Whether the device uses the modern (virtio 1.0) interface.
+    pub fn is_modern(&self) -> bool {
+        self.transport.is_modern()
+    }
+
+    /// Move every finished request out of the used ring.
+    fn complete(&self, state: &mut State) {
+        while let Some((head, _)) = state.queue.pop_used() {
+            if let Some(slot) = state.slot_of_head[usize::from(head)].take() {
+                self.done[slot].store(true, Ordering::Release);
+            }
+        }
+    }
+
+    /// Submit one request, waiting for room in the queue if needed. `data` is the list of
+    /// physically contiguous pieces of the data buffer. Returns the request slot.
+    fn submit(&self, kind: u32, sector: u64, data: &[(PhysAddr, u32)]) -> usize {
+        let mut buffers = Vec::with_capacity(data.len() + 2);
+
+        self.wait_until(|state| {
+            let slot = *state.free_slots.last()?;
+            self.write_header(slot, kind, sector);
+
+            buffers.clear();
+            buffers.push(Buffer {
+                addr: self.requests_phys + HEADER_SIZE * slot as u64,
+                len: HEADER_SIZE as u32,
+                device_writable: false,
+            });
+            buffers.extend(data.iter().map(|&(addr, len)| Buffer {
+                addr,
+                len,
+                device_writable: kind == REQUEST_IN,
+            }));
+            buffers.push(Buffer {
+                addr: self.requests_phys + STATUS_AREA_OFFSET + slot as u64,
+                len: 1,
+                device_writable: true,
+            });
+
+            let head = state.queue.add(&buffers)?;
+            state.free_slots.pop();
+            state.slot_of_head[usize::from(head)] = Some(slot);
+            self.transport.notify(&state.queue);
+            Some(slot)
+        })
+    }
+
+    /// Wait for the request in `slot` to finish and free the slot.
+    fn finish(&self, slot: usize) -> Result<(), BlockError> {
+        self.wait_until(|_| self.done[slot].swap(false, Ordering::Acquire).then_some(()));
+
+        let status = self.status_ptr(slot);
+        // UNSAFE: The device is done with the request, so the status byte is ours again.
+        let status = unsafe { ptr::read_volatile(status) };
+        interrupts::without_interrupts(|| self.state.lock().free_slots.push(slot));
+        match status {
+            STATUS_OK => Ok(()),
+            code => Err(BlockError::Device(code)),
+        }
+    }
+
+    /// Run `f` with the state locked, after collecting completed requests, until it returns
+    /// something. Halts in between if completions are signalled by interrupts.
+    fn wait_until<T>(&self, mut f: impl FnMut(&mut State) -> Option<T>) -> T {
+        let halt = self.uses_interrupts && interrupts::are_enabled();
+        loop {
+            // Check and halt with interrupts disabled so a completion can't slip in between
+            if halt {
+                interrupts::disable();
+            }
+            let result = interrupts::without_interrupts(|| {
+                let mut state = self.state.lock();
+                self.complete(&mut state);
+                f(&mut state)
+            });
+            match result {
+                Some(result) => {
+                    if halt {
+                        interrupts::enable();
+                    }
+                    return result;
+                }
+                None if halt => interrupts::enable_and_hlt(),
+                None => core::hint::spin_loop(),
+            }
+        }
+    }
+
+    fn write_header(&self, slot: usize, kind: u32, sector: u64) {
+        let header = self.requests_virt + HEADER_SIZE * slot as u64;
+        // UNSAFE: Free slots aren't being read by the device.
+        unsafe {
+            ptr::write_volatile(header.as_mut_ptr::<u32>(), kind);
+            ptr::write_volatile((header + 4u64).as_mut_ptr::<u32>(), 0);
+            ptr::write_volatile((header + 8u64).as_mut_ptr::<u64>(), sector);
+            ptr::write_volatile(self.status_ptr(slot), STATUS_PENDING);
+        }
+    }
+
+    fn status_ptr(&self, slot: usize) -> *mut u8 {
+        (self.requests_virt + STATUS_AREA_OFFSET + slot as u64).as_mut_ptr()
+    }
+
+    /// Transfer `len` bytes at `buf` to or from the disk, starting at `sector`.
+    fn transfer(
+        &self,
+        kind: u32,
+        sector: u64,
+        buf: *const u8,
+        len: usize,
+    ) -> Result<(), BlockError> {
+        // An unaligned request touches one more page than its size suggests
+        let pages = self.max_segments.saturating_sub(1).max(1);
+        let request_size = MAX_REQUEST_SIZE.min(pages * PAGE_SIZE as usize);
+
+        let mut result = Ok(());
+        let mut pending = VecDeque::new();
+        let mut offset = 0;
+        while offset < len {
+            // Slots are only freed by `finish`, so don't wait for one held by this transfer
+            if pending.len() == MAX_IN_FLIGHT {
+                result = result.and(self.finish(pending.pop_front().unwrap()));
+            }
+            let chunk = request_size.min(len - offset);
+            let segments = segments(buf.wrapping_add(offset), chunk);
+            let chunk_sector = sector + (offset / SECTOR_SIZE) as u64;
+            pending.push_back(self.submit(kind, chunk_sector, &segments));
+            offset += chunk;
+        }
+
+        // Wait for every request, even after one fails, so none of them outlives `buf`
+        for slot in pending {
+            result = result.and(self.finish(slot));
+        }
+        result
+    }
+}
+impl BlockDevice for VirtioBlk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_count(&self) -> u64 {
+        self.capacity
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        check_request(self, lba, buf.len())?;
+        self.transfer(REQUEST_IN, lba, buf.as_mut_ptr(), buf.len())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        if self.read_only {
+            return Err(BlockError::ReadOnly);
+        }
+        check_request(self, lba, buf.len())?;
+        self.transfer(REQUEST_OUT, lba, buf.as_ptr(), buf.len())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        if !self.can_flush {
+            return Ok(());
+        }
+        let slot = self.submit(REQUEST_FLUSH, 0, &[]);
+        self.finish(slot)
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+}
+
+/// Split the buffer of `len` bytes at `start` into physically contiguous pieces.
+fn segments(start: *const u8, len: usize) -> Vec<(PhysAddr, u32)> {
+    let mut segments: Vec<(PhysAddr, u32)> = Vec::new();
+    let mut virt = VirtAddr::from_ptr(start);
+    let end = virt + len as u64;
+    while virt < end {
+        let page_end = (virt + 1u64).align_up(PAGE_SIZE).min(end);
+        let piece = (page_end - virt) as u32;
+        let phys = memory::virt_to_phys(virt)
+            .ok()
+            .flatten()
+            .expect("block buffer isn't mapped");
+
+        match segments.last_mut() {
+            Some((last, last_len)) if *last + u64::from(*last_len) == phys => *last_len += piece,
+            _ => segments.push((phys, piece)),
+        }
+        virt = page_end;
+    }
+    segments
+}
+
+/// Called whenever one of the PCI lines used by virtio-blk devices is raised.
+fn handle_interrupt() {
+    for device in DEVICES.lock().iter() {
+        // Reading the ISR also acknowledges the interrupt
+        if (device.transport.read_isr() & ISR_QUEUE) != 0 {
+            device.complete(&mut device.state.lock());
+        }
+    }
+}
+
+/// Make sure [handle_interrupt] runs when PIC line `line` is raised. Returns `false` if it can't.
+fn register_interrupt(line: u8) -> bool {
+    let Some(bit) = 1u16.checked_shl(u32::from(line)) else {
+        return false;
+    };
+    let mut lines = IRQ_LINES.lock();
+    if (*lines & bit) == 0 && register_pci_handler(line, handle_interrupt) {
+        *lines |= bit;
+    }
+    (*lines & bit) != 0
+}
+
+fn probe(pci_device: &PciDevice) -> Result<(), ProbeError> {
+    let transport = Transport::new(pci_device)?;
+    let features = transport.begin_init(SUPPORTED_FEATURES)?;
+
+    let setup = || {
+        let size = transport.queue_size(REQUEST_QUEUE);
+        if size == 0 {
+            return Err(ProbeError::Failed("no request queue"));
+        }
+        let mut queue = VirtQueue::new(REQUEST_QUEUE, size)
+            .map_err(|_| ProbeError::Failed("out of DMA memory"))?;
+        transport.setup_queue(&mut queue);
+        let (requests_virt, requests_phys) =
+            memory::alloc_dma(1).map_err(|_| ProbeError::Failed("out of DMA memory"))?;
+        Ok((queue, requests_virt, requests_phys))
+    };
+    let (queue, requests_virt, requests_phys) = setup().inspect_err(|_| transport.fail())?;
+
+    let max_segments = if (features & FEATURE_SEG_MAX) != 0 {
+        transport.read_config_u32(CONFIG_SEG_MAX) as usize
+    } else {
+        usize::from(queue.size()) - 2
+    };
+    let uses_interrupts = register_interrupt(pci_device.interrupt_line);
+
+    let name = interrupts::without_interrupts(|| {
+        format!("vd{}", char::from(b'a' + DEVICES.lock().len() as u8))
+    });
+    let device = Arc::new(VirtioBlk {
+        name,
+        capacity: transport.read_config_u64(CONFIG_CAPACITY),
+        read_only: (features & FEATURE_RO) != 0,
+        can_flush: (features & FEATURE_FLUSH) != 0,
+        // Leave room for the header and status descriptors
+        max_segments: max_segments.min(usize::from(queue.size()) - 2),
+        uses_interrupts,
+        requests_virt,
+        requests_phys,
+        done: [const { AtomicBool::new(false) }; MAX_IN_FLIGHT],
+        state: Mutex::new(State {
+            slot_of_head: vec![None; usize::from(queue.size())],
+            free_slots: (0..MAX_IN_FLIGHT).rev().collect(),
+            queue,
+        }),
+        transport,
+    });
+    device.transport.finish_init();
+    log::info!(
+        "{}: {} sectors{}{} at {}",
+        device.name,
+        device.capacity,
+        if device.read_only { ", read-only" } else { "" },
+        if device.uses_interrupts {
+            ""
+        } else {
+            ", polled"
+        },
+        pci_device.address
+    );
+    interrupts::without_interrupts(|| DEVICES.lock().push(Arc::clone(&device)));
+    let _ = devfs::register_disk(device);
+    Ok(())
+}
+
+/// All virtio-blk disks found so far.
+pub fn devices() -> Vec<Arc<VirtioBlk>> {
+    interrupts::without_interrupts(|| DEVICES.lock().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn segments_match_translation() {
+        let buf = vec![0u8; 3 * PAGE_SIZE as usize];
+        let pieces = segments(buf.as_ptr(), buf.len());
+        assert!(pieces.len() <= 4);
+
+        // Every byte of a piece is where the page tables say it is
+        let mut virt = VirtAddr::from_ptr(buf.as_ptr());
+        for &(phys, len) in &pieces {
+            for offset in [0, u64::from(len) - 1] {
+                assert_eq!(
+                    memory::virt_to_phys(virt + offset).unwrap(),
+                    Some(phys + offset)
+                );
+            }
+            virt += u64::from(len);
+        }
+    }
+
+    #[test_case]
+    fn segments_cover_buffer() {
+        let buf = [0u8; SECTOR_SIZE * 3];
+        let pieces = segments(buf.as_ptr(), buf.len());
+        let start = memory::virt_to_phys(VirtAddr::from_ptr(buf.as_ptr())).unwrap();
+        assert_eq!(Some(pieces[0].0), start);
+        let total: u32 = pieces.iter().map(|&(_, len)| len).sum();
+        assert_eq!(total as usize, buf.len());
+    }
+}
@@ -0,0 +1,350 @@
+//! Virtio over PCI.
+//!
+//! Legacy devices expose their registers through an I/O BAR. Modern devices describe where their
+//! register blocks live in memory BARs using vendor-specific PCI capabilities. Transitional
+//! devices offer both, in which case the modern interface is used.
+
+use core::ptr;
+
+use x86_64::{instructions::port::Port, PhysAddr, VirtAddr};
+
+use super::{features, queue::VirtQueue, status};
+use crate::{
+    memory,
+    pci::{command, Bar, PciDevice, ProbeError},
+};
+
+// Legacy I/O register offsets
+const LEGACY_DEVICE_FEATURES: u16 = 0x00;
+const LEGACY_DRIVER_FEATURES: u16 = 0x04;
+const LEGACY_QUEUE_PFN: u16 = 0x08;
+const LEGACY_QUEUE_SIZE: u16 = 0x0C;
+const LEGACY_QUEUE_SELECT: u16 = 0x0E;
+const LEGACY_QUEUE_NOTIFY: u16 = 0x10;
+const LEGACY_DEVICE_STATUS: u16 = 0x12;
+const LEGACY_ISR_STATUS: u16 = 0x13;
+// Without MSI-X, which is never enabled
+const LEGACY_DEVICE_CONFIG: u16 = 0x14;
+const LEGACY_QUEUE_PFN_SHIFT: u32 = 12;
+
+// Modern common configuration offsets
+const COMMON_DEVICE_FEATURE_SELECT: u64 = 0x00;
+const COMMON_DEVICE_FEATURE: u64 = 0x04;
+const COMMON_DRIVER_FEATURE_SELECT: u64 = 0x08;
+const COMMON_DRIVER_FEATURE: u64 = 0x0C;
+const COMMON_DEVICE_STATUS: u64 = 0x14;
+const COMMON_QUEUE_SELECT: u64 = 0x16;
+const COMMON_QUEUE_SIZE: u64 = 0x18;
+const COMMON_QUEUE_ENABLE: u64 = 0x1C;
+const COMMON_QUEUE_NOTIFY_OFF: u64 = 0x1E;
+const COMMON_QUEUE_DESC: u64 = 0x20;
+const COMMON_QUEUE_DRIVER: u64 = 0x28;
+const COMMON_QUEUE_DEVICE: u64 = 0x30;
+
+// Vendor-specific capability layout
+const CAPABILITY_VENDOR: u8 = 0x09;
+const CAP_CFG_TYPE: u8 = 3;
+const CAP_BAR: u8 = 4;
+const CAP_OFFSET: u8 = 8;
+const CAP_LENGTH: u8 = 12;
+const CAP_NOTIFY_MULTIPLIER: u8 = 16;
+
+const CFG_TYPE_COMMON: u8 = 1;
+const CFG_TYPE_NOTIFY: u8 = 2;
+const CFG_TYPE_ISR: u8 = 3;
+const CFG_TYPE_DEVICE: u8 = 4;
+
+/// ISR status bit set when a queue has new used buffers.
+pub const ISR_QUEUE: u8 = 1;
+
+/// The register interface of a device.
+#[derive(Debug)]
+enum Registers {
+    Legacy {
+        io_base: u16,
+    },
+    Modern {
+        common: VirtAddr,
+        notify: VirtAddr,
+        notify_multiplier: u32,
+        isr: VirtAddr,
+        device: VirtAddr,
+    },
+}
+
+/// Access to a virtio device over PCI.
+///
+/// Queue setup and notification select a queue first, so callers must not use a transport from
+/// several places at once.
+#[derive(Debug)]
+pub struct Transport {
+    registers: Registers,
+}
+impl Transport {
+    /// Find the registers of `device`, preferring the modern interface, and enable it as a bus
+    /// master.
+    pub fn new(device: &PciDevice) -> Result<Self, ProbeError> {
+        let registers = match modern_registers(device)? {
+            Some(registers) => registers,
+            None => match device.bars[0] {
+                Some(Bar::Io { port, .. }) => Registers::Legacy { io_base: port },
+                _ => return Err(ProbeError::Unsupported),
+            },
+        };
+        device.enable(command::IO_SPACE | command::MEMORY_SPACE | command::BUS_MASTER);
+        Ok(Self { registers })
+    }
+
+    /// Whether the modern (virtio 1.0) interface is in use.
+    pub fn is_modern(&self) -> bool {
+        matches!(self.registers, Registers::Modern { .. })
+    }
+
+    /// Reset the device and negotiate features, accepting those in `supported` that the device
+    /// offers. Returns the accepted features. On error the device is marked as failed.
+    pub fn begin_init(&self, supported: u64) -> Result<u64, ProbeError> {
+        self.set_status(0);
+        while self.status() != 0 {
+            core::hint::spin_loop();
+        }
+        self.set_status(status::ACKNOWLEDGE);
+        self.set_status(status::ACKNOWLEDGE | status::DRIVER);
+
+        let result = self.negotiate(supported);
+        if result.is_err() {
+            self.fail();
+        }
+        result
+    }
+
+    fn negotiate(&self, supported: u64) -> Result<u64, ProbeError> {
+        let offered = self.device_features();
+        if !self.is_modern() {
+            let accepted = offered & supported & u64::from(u32::MAX);
+            self.set_driver_features(accepted);
+            return Ok(accepted);
+        }
+
+        if (offered & features::VERSION_1) == 0 {
+            return Err(ProbeError::Failed("modern device without VERSION_1"));
+        }
+        let accepted = offered & (supported | features::VERSION_1);
+        self.set_driver_features(accepted);
+        self.set_status(self.status() | status::FEATURES_OK);
+        if (self.status() & status::FEATURES_OK) == 0 {
+            return Err(ProbeError::Failed("device rejected features"));
+        }
+        Ok(accepted)
+    }
+
+    /// Tell the device the driver is ready.
+    pub fn finish_init(&self) {
+        self.set_status(self.status() | status::DRIVER_OK);
+    }
+
+    /// Tell the device the driver has given up on it.
+    pub fn fail(&self) {
+        self.set_status(self.status() | status::FAILED);
+    }
+
+    /// The device status register.
+    pub fn status(&self) -> u8 {
+        match self.registers {
+            Registers::Legacy { io_base } => legacy_read(io_base + LEGACY_DEVICE_STATUS),
+            Registers::Modern { common, .. } => mmio_read(common + COMMON_DEVICE_STATUS),
+        }
+    }
+
+    fn set_status(&self, value: u8) {
+        match self.registers {
+            Registers::Legacy { io_base } => legacy_write(io_base + LEGACY_DEVICE_STATUS, value),
+            Registers::Modern { common, .. } => mmio_write(common + COMMON_DEVICE_STATUS, value),
+        }
+    }
+
+    fn device_features(&self) -> u64 {
+        match self.registers {
+            Registers::Legacy { io_base } => {
+                u64::from(legacy_read::<u32>(io_base + LEGACY_DEVICE_FEATURES))
+            }
+            Registers::Modern { common, .. } => {
+                mmio_write(common + COMMON_DEVICE_FEATURE_SELECT, 0u32);
+                let low: u32 = mmio_read(common + COMMON_DEVICE_FEATURE);
+                mmio_write(common + COMMON_DEVICE_FEATURE_SELECT, 1u32);
+                let high: u32 = mmio_read(common + COMMON_DEVICE_FEATURE);
+                (u64::from(high) << 32) | u64::from(low)
+            }
+        }
+    }
+
+    fn set_driver_features(&self, value: u64) {
+        match self.registers {
+            Registers::Legacy { io_base } => {
+                legacy_write(io_base + LEGACY_DRIVER_FEATURES, value as u32)
+            }
+            Registers::Modern { common, .. } => {
+                mmio_write(common + COMMON_DRIVER_FEATURE_SELECT, 0u32);
+                mmio_write(common + COMMON_DRIVER_FEATURE, value as u32);
+                mmio_write(common + COMMON_DRIVER_FEATURE_SELECT, 1u32);
+                mmio_write(common + COMMON_DRIVER_FEATURE, (value >> 32) as u32);
+            }
+        }
+    }
+
+    /// The number of descriptors the device supports in queue `index`, or 0 if the queue
+    /// doesn't exist. Legacy devices only support exactly this size.
+    pub fn queue_size(&self, index: u16) -> u16 {
+        match self.registers {
+            Registers::Legacy { io_base } => {
+                legacy_write(io_base + LEGACY_QUEUE_SELECT, index);
+                legacy_read(io_base + LEGACY_QUEUE_SIZE)
+            }
+            Registers::Modern { common, .. } => {
+                mmio_write(common + COMMON_QUEUE_SELECT, index);
+                mmio_read(common + COMMON_QUEUE_SIZE)
+            }
+        }
+    }
+
+    /// Hand `queue` to the device.
+    pub fn setup_queue(&self, queue: &mut VirtQueue) {
+        match self.registers {
+            Registers::Legacy { io_base } => {
+                legacy_write(io_base + LEGACY_QUEUE_SELECT, queue.index());
+                let pfn = queue.descriptor_table().as_u64() >> LEGACY_QUEUE_PFN_SHIFT;
+                legacy_write(io_base + LEGACY_QUEUE_PFN, pfn as u32);
+            }
+            Registers::Modern { common, .. } => {
+                mmio_write(common + COMMON_QUEUE_SELECT, queue.index());
+                mmio_write(common + COMMON_QUEUE_SIZE, queue.size());
+                write_addr(common + COMMON_QUEUE_DESC, queue.descriptor_table());
+                write_addr(common + COMMON_QUEUE_DRIVER, queue.avail_ring());
+                write_addr(common + COMMON_QUEUE_DEVICE, queue.used_ring());
+                queue.set_notify_offset(mmio_read(common + COMMON_QUEUE_NOTIFY_OFF));
+                mmio_write(common + COMMON_QUEUE_ENABLE, 1u16);
+            }
+        }
+    }
+
+    /// Tell the device there are new buffers in `queue`.
+    pub fn notify(&self, queue: &VirtQueue) {
+        match self.registers {
+            Registers::Legacy { io_base } => {
+                legacy_write(io_base + LEGACY_QUEUE_NOTIFY, queue.index())
+            }
+            Registers::Modern {
+                notify,
+                notify_multiplier,
+                ..
+            } => {
+                let offset = u64::from(queue.notify_offset()) * u64::from(notify_multiplier);
+                mmio_write(notify + offset, queue.index());
+            }
+        }
+    }
+
+    /// Read and clear the ISR status register. This also deasserts the interrupt.
+    pub fn read_isr(&self) -> u8 {
+        match self.registers {
+            Registers::Legacy { io_base } => legacy_read(io_base + LEGACY_ISR_STATUS),
+            Registers::Modern { isr, .. } => mmio_read(isr),
+        }
+    }
+
+    /// Read a 32-bit field of the device-specific configuration.
+    pub fn read_config_u32(&self, offset: u16) -> u32 {
+        match self.registers {
+            Registers::Legacy { io_base } => legacy_read(io_base + LEGACY_DEVICE_CONFIG + offset),
+            Registers::Modern { device, .. } => mmio_read(device + u64::from(offset)),
+        }
+    }
+
+    /// Read a 64-bit field of the device-specific configuration.
+    pub fn read_config_u64(&self, offset: u16) -> u64 {
+        let low = self.read_config_u32(offset);
+        let high = self.read_config_u32(offset + 4);
+        (u64::from(high) << 32) | u64::from(low)
+    }
+}
+
+/// Find and map the modern register blocks of `device`. Returns [None] if it doesn't have them.
+fn modern_registers(device: &PciDevice) -> Result<Option<Registers>, ProbeError> {
+    let mut common = None;
+    let mut notify = None;
+    let mut notify_multiplier = 0;
+    let mut isr = None;
+    let mut device_config = None;
+
+    let address = device.address;
+    for capability in device.capabilities() {
+        if capability.id != CAPABILITY_VENDOR {
+            continue;
+        }
+        let cap = capability.offset;
+        let Some(Some(Bar::Memory { address: base, .. })) = device
+            .bars
+            .get(usize::from(address.read_u8(cap + CAP_BAR)))
+            .copied()
+        else {
+            continue;
+        };
+
+        let phys = PhysAddr::new(base + u64::from(address.read_u32(cap + CAP_OFFSET)));
+        let length = u64::from(address.read_u32(cap + CAP_LENGTH));
+        let slot = match address.read_u8(cap + CAP_CFG_TYPE) {
+            CFG_TYPE_COMMON => &mut common,
+            CFG_TYPE_NOTIFY => {
+                notify_multiplier = address.read_u32(cap + CAP_NOTIFY_MULTIPLIER);
+                &mut notify
+            }
+            CFG_TYPE_ISR => &mut isr,
+            CFG_TYPE_DEVICE => &mut device_config,
+            _ => continue,
+        };
+        // Several capabilities of the same type may appear; the first is preferred
+        if slot.is_none() {
+            *slot = Some(
+                memory::map_mmio(phys, length)
+                    .map_err(|_| ProbeError::Failed("couldn't map virtio registers"))?,
+            );
+        }
+    }
+
+    Ok(match (common, notify, isr, device_config) {
+        (Some(common), Some(notify), Some(isr), Some(device)) => Some(Registers::Modern {
+            common,
+            notify,
+            notify_multiplier,
+            isr,
+            device,
+        }),
+        _ => None,
+    })
+}
+
+fn legacy_read<T: x86_64::instructions::port::PortRead>(port: u16) -> T {
+    // UNSAFE: Legacy registers are only reached through ports inside the device's own I/O BAR.
+    unsafe { Port::<T>::new(port).read() }
+}
+
+fn legacy_write<T: x86_64::instructions::port::PortWrite>(port: u16, value: T) {
+    // UNSAFE: See `legacy_read`.
+    unsafe { Port::<T>::new(port).write(value) }
+}
+
+fn mmio_read<T>(addr: VirtAddr) -> T {
+    // UNSAFE: Modern registers are only reached through addresses inside the device's own mapped
+    // memory BARs.
+    unsafe { ptr::read_volatile(addr.as_ptr()) }
+}
+
+fn mmio_write<T>(addr: VirtAddr, value: T) {
+    // UNSAFE: See `mmio_read`.
+    unsafe { ptr::write_volatile(addr.as_mut_ptr(), value) }
+}
+
+/// Write a 64-bit address register as two halves, since devices needn't support 64-bit accesses.
+fn write_addr(addr: VirtAddr, value: PhysAddr) {
+    mmio_write(addr, value.as_u64() as u32);
+    mmio_write(addr + 4u64, (value.as_u64() >> 32) as u32);
+}
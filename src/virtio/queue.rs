@@ -0,0 +1,290 @@
+//! Split virtqueues.
+//!
+//! A queue lives in one physically contiguous DMA allocation laid out the way the legacy
+//! transport requires: the descriptor table, then the available ring, then the used ring on the
+//! next page boundary. The modern transport accepts any layout, so both share it.
+
+use core::{
+    ptr,
+    sync::atomic::{fence, Ordering},
+};
+
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::memory::{self, MemoryError, PAGE_SIZE};
+
+const DESCRIPTOR_SIZE: u64 = 16;
+// Flags and index before the ring, used event after it
+const RING_HEADER_SIZE: u64 = 4;
+const RING_FOOTER_SIZE: u64 = 2;
+const AVAIL_ELEMENT_SIZE: u64 = 2;
+const USED_ELEMENT_SIZE: u64 = 8;
+
+const RING_IDX_OFFSET: u64 = 2;
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+/// A physically contiguous buffer handed to the device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Buffer {
+    /// Physical address of the first byte.
+    pub addr: PhysAddr,
+    /// Length in bytes.
+    pub len: u32,
+    /// Whether the device writes to the buffer (as opposed to reading from it).
+    pub device_writable: bool,
+}
+
+/// A split virtqueue.
+#[derive(Debug)]
+pub struct VirtQueue {
+    index: u16,
+    size: u16,
+    virt: VirtAddr,
+    phys: PhysAddr,
+    used_offset: u64,
+    notify_offset: u16,
+    free_head: u16,
+    num_free: u16,
+    last_used: u16,
+}
+impl VirtQueue {
+    /// Allocate queue number `index` with `size` descriptors. `size` must be a power of two.
+    pub fn new(index: u16, size: u16) -> Result<Self, MemoryError> {
+        let size64 = u64::from(size);
+        let avail_size = RING_HEADER_SIZE + AVAIL_ELEMENT_SIZE * size64 + RING_FOOTER_SIZE;
+        let used_size = RING_HEADER_SIZE + USED_ELEMENT_SIZE * size64 + RING_FOOTER_SIZE;
+        let used_offset = (DESCRIPTOR_SIZE * size64 + avail_size).next_multiple_of(PAGE_SIZE);
+        let pages = (used_offset + used_size).div_ceil(PAGE_SIZE);
+        let (virt, phys) = memory::alloc_dma(pages)?;
+
+        let queue = Self {
+            index,
+            size,
+            virt,
+            phys,
+            used_offset,
+            notify_offset: 0,
+            free_head: 0,
+            num_free: size,
+            last_used: 0,
+        };
+        // Chain every descriptor into the free list
+        for id in 0..size {
+            queue.write_descriptor(id, 0, 0, 0, id.wrapping_add(1));
+        }
+        Ok(queue)
+    }
+
+    /// Queue number within the device.
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    /// Number of descriptors.
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Number of descriptors not currently handed to the device.
+    pub fn num_free(&self) -> u16 {
+        self.num_free
+    }
+
+    /// Physical address of the descriptor table.
+    pub fn descriptor_table(&self) -> PhysAddr {
+        self.phys
+    }
+
+    /// Physical address of the available ring.
+    pub fn avail_ring(&self) -> PhysAddr {
+        self.phys + DESCRIPTOR_SIZE * u64::from(self.size)
+    }
+
+    /// Physical address of the used ring.
+    pub fn used_ring(&self) -> PhysAddr {
+        self.phys + self.used_offset
+    }
+
+    /// Offset of this queue's notification register, in units of the transport's multiplier.
+    pub fn notify_offset(&self) -> u16 {
+        self.notify_offset
+    }
+
+    /// Set by the transport while setting up the queue.
+    pub fn set_notify_offset(&mut self, offset: u16) {
+        self.notify_offset = offset;
+    }
+
+    /// Make `buffers` available to the device as one descriptor chain. Returns the ID of the head
+    /// descriptor, which [VirtQueue::pop_used] reports once the device is done, or [None] if
+    /// there aren't enough free descriptors. The device still has to be notified.
+    pub fn add(&mut self, buffers: &[Buffer]) -> Option<u16> {
+        if buffers.is_empty() || buffers.len() > usize::from(self.num_free) {
+            return None;
+        }
+
+        let head = self.free_head;
+        let mut id = head;
+        for (i, buffer) in buffers.iter().enumerate() {
+            let next = self.read_next(id);
+            let mut flags = if buffer.device_writable {
+                DESC_F_WRITE
+            } else {
+                0
+            };
+            if i + 1 < buffers.len() {
+                flags |= DESC_F_NEXT;
+            }
+            self.write_descriptor(id, buffer.addr.as_u64(), buffer.len, flags, next);
+            id = next;
+        }
+        self.free_head = id;
+        self.num_free -= buffers.len() as u16;
+
+        let avail = self.virt + DESCRIPTOR_SIZE * u64::from(self.size);
+        let idx_ptr = (avail + RING_IDX_OFFSET).as_mut_ptr::<u16>();
+        // UNSAFE: The available ring belongs to this queue, and the driver is its only writer.
+        unsafe {
+            let idx = ptr::read_volatile(idx_ptr);
+            let slot = avail + RING_HEADER_SIZE + AVAIL_ELEMENT_SIZE * self.slot(idx);
+            ptr::write_volatile(slot.as_mut_ptr::<u16>(), head);
+            // The device must see the ring entry before the new index
+            fence(Ordering::SeqCst);
+            ptr::write_volatile(idx_ptr, idx.wrapping_add(1));
+            // ...and the new index before the notification
+            fence(Ordering::SeqCst);
+        }
+        Some(head)
+    }
+
+    /// Take the next chain the device has finished with, returning its head descriptor ID and
+    /// the number of bytes the device wrote. Its descriptors are freed.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        let used = self.virt + self.used_offset;
+        // UNSAFE: The used ring belongs to this queue; the device only ever advances it.
+        let (id, len) = unsafe {
+            let idx = ptr::read_volatile((used + RING_IDX_OFFSET).as_ptr::<u16>());
+            if idx == self.last_used {
+                return None;
+            }
+            // Don't read the element before the index that published it
+            fence(Ordering::SeqCst);
+            let element = used + RING_HEADER_SIZE + USED_ELEMENT_SIZE * self.slot(self.last_used);
+            (
+                ptr::read_volatile(element.as_ptr::<u32>()),
+                ptr::read_volatile((element + 4u64).as_ptr::<u32>()),
+            )
+        };
+        self.last_used = self.last_used.wrapping_add(1);
+
+        let head = id as u16;
+        self.free_chain(head);
+        Some((head, len))
+    }
+
+    /// Return the chain starting at `head` to the free list.
+    fn free_chain(&mut self, head: u16) {
+        let mut id = head;
+        loop {
+            self.num_free += 1;
+            if (self.read_flags(id) & DESC_F_NEXT) == 0 {
+                break;
+            }
+            id = self.read_next(id);
+        }
+        let free_head = self.free_head;
+        self.set_next(id, free_head);
+        self.free_head = head;
+    }
+
+    fn slot(&self, idx: u16) -> u64 {
+        u64::from(idx % self.size)
+    }
+
+    fn descriptor(&self, id: u16) -> VirtAddr {
+        self.virt + DESCRIPTOR_SIZE * u64::from(id % self.size)
+    }
+
+    fn write_descriptor(&self, id: u16, addr: u64, len: u32, flags: u16, next: u16) {
+        let desc = self.descriptor(id);
+        // UNSAFE: Descriptors in the free list aren't owned by the device.
+        unsafe {
+            ptr::write_volatile(desc.as_mut_ptr::<u64>(), addr);
+            ptr::write_volatile((desc + 8u64).as_mut_ptr::<u32>(), len);
+            ptr::write_volatile((desc + 12u64).as_mut_ptr::<u16>(), flags);
+            ptr::write_volatile((desc + 14u64).as_mut_ptr::<u16>(), next);
+        }
+    }
+
+    fn read_flags(&self, id: u16) -> u16 {
+        // UNSAFE: The descriptor table lies within this queue's allocation.
+        unsafe { ptr::read_volatile((self.descriptor(id) + 12u64).as_ptr::<u16>()) }
+    }
+
+    fn read_next(&self, id: u16) -> u16 {
+        // UNSAFE: See `read_flags`.
+        unsafe { ptr::read_volatile((self.descriptor(id) + 14u64).as_ptr::<u16>()) }
+    }
+
+    fn set_next(&self, id: u16, next: u16) {
+        // UNSAFE: Only called on descriptors the device has handed back.
+        unsafe { ptr::write_volatile((self.descriptor(id) + 14u64).as_mut_ptr::<u16>(), next) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(len: u32) -> Buffer {
+        Buffer {
+            addr: PhysAddr::new(0x1000),
+            len,
+            device_writable: false,
+        }
+    }
+
+    /// Pretend to be the device and complete the chain starting at `head`.
+    fn complete(queue: &VirtQueue, head: u16) {
+        let used = queue.virt + queue.used_offset;
+        unsafe {
+            let idx_ptr = (used + RING_IDX_OFFSET).as_mut_ptr::<u16>();
+            let idx = ptr::read_volatile(idx_ptr);
+            let element = used + RING_HEADER_SIZE + USED_ELEMENT_SIZE * queue.slot(idx);
+            ptr::write_volatile(element.as_mut_ptr::<u32>(), u32::from(head));
+            ptr::write_volatile((element + 4u64).as_mut_ptr::<u32>(), 0);
+            ptr::write_volatile(idx_ptr, idx.wrapping_add(1));
+        }
+    }
+
+    #[test_case]
+    fn layout() {
+        let queue = VirtQueue::new(0, 256).unwrap();
+        assert_eq!(queue.avail_ring() - queue.descriptor_table(), 4096);
+        assert_eq!(queue.used_ring() - queue.descriptor_table(), 8192);
+        assert!(queue.used_ring().is_aligned(PAGE_SIZE));
+    }
+
+    #[test_case]
+    fn add_and_free() {
+        let mut queue = VirtQueue::new(0, 8).unwrap();
+        let first = queue.add(&[buffer(1), buffer(2), buffer(3)]).unwrap();
+        let second = queue.add(&[buffer(4), buffer(5)]).unwrap();
+        assert_eq!(queue.num_free(), 3);
+        assert_eq!(queue.read_flags(first), DESC_F_NEXT);
+        assert!(queue.add(&[buffer(1); 4]).is_none());
+
+        assert_eq!(queue.pop_used(), None);
+        complete(&queue, second);
+        complete(&queue, first);
+        assert_eq!(queue.pop_used(), Some((second, 0)));
+        assert_eq!(queue.pop_used(), Some((first, 0)));
+        assert_eq!(queue.pop_used(), None);
+        assert_eq!(queue.num_free(), 8);
+
+        // Every descriptor is usable again
+        assert!(queue.add(&[buffer(1); 8]).is_some());
+    }
+}
@@ -0,0 +1,90 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(tlenek_core::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+use tlenek_core::{
+    ata::{self, AtaDrive, Channel, Position},
+    block::{BlockDevice, SECTOR_SIZE},
+    hlt_loop, init, test_panic_handler,
+};
+
+// Must match tests/images/mkimages.sh
+const MAGIC: &[u8] = b"TLENEK ATA TEST\0";
+const MAGIC_SECTOR: u64 = 1;
+const SCRATCH_SECTOR: u64 = 3;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init(boot_info);
+    test_main();
+    hlt_loop();
+}
+
+fn test_drive() -> Arc<AtaDrive> {
+    ata::drives()
+        .into_iter()
+        .find(|d| d.channel() == Channel::Primary && d.position() == Position::Slave)
+        .expect("test disk not attached as primary slave")
+}
+
+#[test_case]
+fn drives_identified() {
+    let drive = test_drive();
+    assert_eq!(drive.block_count(), 2048);
+    assert!(drive.identity().model.contains("QEMU"));
+    // The boot disk is the primary master
+    assert!(ata::drives()
+        .iter()
+        .any(|d| d.channel() == Channel::Primary && d.position() == Position::Master));
+}
+
+#[test_case]
+fn read_known_sector() {
+    let drive = test_drive();
+    let mut buf = [0; SECTOR_SIZE];
+    drive.read_blocks(MAGIC_SECTOR, &mut buf).unwrap();
+
+    assert_eq!(&buf[..MAGIC.len()], MAGIC);
+    for (i, &byte) in buf[MAGIC.len()..].iter().enumerate() {
+        assert_eq!(byte, (i % 251) as u8);
+    }
+}
+
+#[test_case]
+fn read_multiple_sectors() {
+    let drive = test_drive();
+    let mut buf = [0xFF; SECTOR_SIZE * 2];
+    drive.read_blocks(MAGIC_SECTOR - 1, &mut buf).unwrap();
+
+    assert!(buf[..SECTOR_SIZE].iter().all(|&b| b == 0));
+    assert_eq!(&buf[SECTOR_SIZE..(SECTOR_SIZE + MAGIC.len())], MAGIC);
+}
+
+#[test_case]
+fn write_read_back() {
+    let drive = test_drive();
+    let mut data = [0; SECTOR_SIZE * 2];
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte = (i % 7) as u8;
+    }
+    drive.write_blocks(SCRATCH_SECTOR, &data).unwrap();
+    drive.flush().unwrap();
+
+    let mut buf = [0; SECTOR_SIZE * 2];
+    drive.read_blocks(SCRATCH_SECTOR, &mut buf).unwrap();
+    assert!(buf == data);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
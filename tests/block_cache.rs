@@ -0,0 +1,132 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(tlenek_core::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+use tlenek_core::{
+    block::{
+        cache::{self, CachedDevice, CACHE, DIRTY_EXPIRE_TICKS},
+        read_bytes, write_bytes, BlockDevice,
+    },
+    fs::{ext2::Ext2Fs, ramfs::RamFs},
+    hlt_loop, init, interrupts, test_panic_handler, vfs,
+    virtio::blk,
+};
+
+// Must match tests/images/mkimages.sh
+const EXT2_LABEL: &str = "EXT2TEST";
+const BIG_LEN: usize = 300000;
+// Past the end of the ext2 image's data
+const SCRATCH_OFFSET: u64 = 7 * 1024 * 1024;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init(boot_info);
+    vfs::mount("/", RamFs::new()).expect("failed to mount root");
+    vfs::mkdir("/ext2").unwrap();
+    vfs::mount("/ext2", Ext2Fs::new(device()).unwrap()).unwrap();
+    test_main();
+    hlt_loop();
+}
+
+/// The ext2 test disk, wrapped in the cache.
+fn device() -> Arc<CachedDevice> {
+    blk::devices()
+        .into_iter()
+        .map(|d| cache::cached(d))
+        .find(|d| Ext2Fs::new(Arc::clone(d) as _).is_ok_and(|fs| fs.label() == EXT2_LABEL))
+        .expect("ext2 test disk not attached")
+}
+
+#[test_case]
+fn hits_and_readahead() {
+    let before = CACHE.stats();
+    for _ in 0..2 {
+        assert_eq!(
+            vfs::read_to_string("/ext2/hello.txt").unwrap(),
+            "Hello from ext2!\n"
+        );
+    }
+
+    let fd = vfs::open("/ext2/big.bin", vfs::OpenFlags::READ).unwrap();
+    let mut buf = vec![0; BIG_LEN];
+    let mut len = 0;
+    while len < BIG_LEN {
+        len += vfs::read(fd, &mut buf[len..]).unwrap();
+    }
+    vfs::close(fd).unwrap();
+    assert!(buf.iter().enumerate().all(|(i, &b)| b == (i % 251) as u8));
+
+    let after = CACHE.stats();
+    assert!(after.hits > before.hits);
+    assert!(after.readahead > before.readahead);
+    assert!(after.cached > 0);
+}
+
+#[test_case]
+fn writes_stay_cached_until_flushed() {
+    let cached = device();
+    let raw = Arc::clone(cached.device());
+    let mut original = [0; 16];
+    read_bytes(raw.as_ref(), SCRATCH_OFFSET, &mut original).unwrap();
+
+    write_bytes(cached.as_ref(), SCRATCH_OFFSET, b"cached, not yet").unwrap();
+    let mut buf = [0; 15];
+    read_bytes(raw.as_ref(), SCRATCH_OFFSET, &mut buf).unwrap();
+    assert_ne!(&buf, b"cached, not yet");
+    assert!(CACHE.stats().dirty > 0);
+
+    cached.flush().unwrap();
+    read_bytes(raw.as_ref(), SCRATCH_OFFSET, &mut buf).unwrap();
+    assert_eq!(&buf, b"cached, not yet");
+    assert_eq!(CACHE.stats().dirty, 0);
+
+    write_bytes(cached.as_ref(), SCRATCH_OFFSET, &original).unwrap();
+    CACHE.sync().unwrap();
+}
+
+#[test_case]
+fn expired_blocks_written_back() {
+    let cached = device();
+    let raw = Arc::clone(cached.device());
+    write_bytes(cached.as_ref(), SCRATCH_OFFSET, b"expired").unwrap();
+
+    let now = interrupts::ticks();
+    CACHE.writeback_expired(now).unwrap();
+    let mut buf = [0; 7];
+    read_bytes(raw.as_ref(), SCRATCH_OFFSET, &mut buf).unwrap();
+    assert_ne!(&buf, b"expired");
+
+    CACHE.writeback_expired(now + DIRTY_EXPIRE_TICKS).unwrap();
+    read_bytes(raw.as_ref(), SCRATCH_OFFSET, &mut buf).unwrap();
+    assert_eq!(&buf, b"expired");
+}
+
+/// Filesystem changes go through the cache and reach the disk on sync.
+#[test_case]
+fn filesystem_sync() {
+    vfs::write_all("/ext2/cached.txt", b"through the cache").unwrap();
+    vfs::sync().unwrap();
+    assert_eq!(CACHE.stats().dirty, 0);
+
+    let raw = Arc::clone(device().device());
+    vfs::mkdir("/raw").unwrap();
+    vfs::mount("/raw", Ext2Fs::new(raw).unwrap()).unwrap();
+    assert_eq!(
+        vfs::read_to_string("/raw/cached.txt").unwrap(),
+        "through the cache"
+    );
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
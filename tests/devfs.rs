@@ -0,0 +1,209 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(tlenek_core::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+use tlenek_core::{
+    ata::{self, Channel, Position},
+    block::{cache::CACHE, partition::partitions, read_bytes, BlockDevice, SECTOR_SIZE},
+    fs::{
+        devfs::{self, CharDevice, DevFs, Device},
+        ramfs::RamFs,
+    },
+    hlt_loop, init, test_panic_handler,
+    vfs::{self, InodeKind, OpenFlags, SeekFrom, VfsError},
+    virtio::blk,
+};
+
+// Must match tests/images/mkimages.sh
+const ATA_MAGIC: &[u8] = b"TLENEK ATA TEST\0";
+const MBR_DISK_SECTORS: u64 = 32768;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init(boot_info);
+    vfs::mount("/", RamFs::new()).expect("failed to mount root");
+    vfs::mkdir("/dev").unwrap();
+    vfs::mount("/dev", DevFs::new()).unwrap();
+    test_main();
+    hlt_loop();
+}
+
+fn names() -> Vec<String> {
+    vfs::readdir("/dev")
+        .unwrap()
+        .into_iter()
+        .map(|e| e.name)
+        .collect()
+}
+
+/// Read `buf.len()` bytes from the start of the file at `path`.
+fn read_start(path: &str, buf: &mut [u8]) -> usize {
+    let fd = vfs::open(path, OpenFlags::READ).unwrap();
+    let n = vfs::read(fd, buf).unwrap();
+    vfs::close(fd).unwrap();
+    n
+}
+
+#[test_case]
+fn builtin_devices() {
+    let names = names();
+    for name in ["console", "ttyS0", "vga", "null", "zero", "random"] {
+        assert!(names.iter().any(|n| n == name), "/dev/{} missing", name);
+        assert_eq!(
+            vfs::stat(&format!("/dev/{}", name)).unwrap().kind,
+            InodeKind::CharDevice
+        );
+    }
+}
+
+#[test_case]
+fn null_and_zero() {
+    vfs::write_all("/dev/null", b"into the void").unwrap();
+    assert_eq!(vfs::read_to_string("/dev/null").unwrap(), "");
+
+    let mut buf = [0xAA; 100];
+    assert_eq!(read_start("/dev/zero", &mut buf), 100);
+    assert_eq!(buf, [0; 100]);
+}
+
+#[test_case]
+fn random() {
+    let mut a = [0; 32];
+    let mut b = [0; 32];
+    assert_eq!(read_start("/dev/random", &mut a), 32);
+    assert_eq!(read_start("/dev/random", &mut b), 32);
+    assert_ne!(a, b);
+}
+
+#[test_case]
+fn output_devices() {
+    for path in ["/dev/console", "/dev/ttyS0", "/dev/vga"] {
+        let fd = vfs::open(path, OpenFlags::WRITE).unwrap();
+        assert_eq!(vfs::write(fd, b"devfs ").unwrap(), 6);
+        vfs::close(fd).unwrap();
+    }
+}
+
+#[test_case]
+fn read_only_directory() {
+    assert_eq!(
+        vfs::write_all("/dev/new", b"nope"),
+        Err(VfsError::PermissionDenied)
+    );
+    assert_eq!(vfs::mkdir("/dev/dir"), Err(VfsError::PermissionDenied));
+    assert_eq!(vfs::unlink("/dev/null"), Err(VfsError::PermissionDenied));
+}
+
+#[test_case]
+fn registered_on_probe() {
+    let drive = ata::drives()
+        .into_iter()
+        .find(|d| d.channel() == Channel::Primary && d.position() == Position::Slave)
+        .expect("ATA test disk not attached");
+    let path = format!("/dev/{}", drive.name());
+    let metadata = vfs::stat(&path).unwrap();
+    assert_eq!(metadata.kind, InodeKind::BlockDevice);
+    assert_eq!(metadata.size, drive.block_count() * SECTOR_SIZE as u64);
+
+    let fd = vfs::open(&path, OpenFlags::READ).unwrap();
+    vfs::seek(fd, SeekFrom::Start(SECTOR_SIZE as u64)).unwrap();
+    let mut magic = [0; ATA_MAGIC.len()];
+    assert_eq!(vfs::read(fd, &mut magic).unwrap(), magic.len());
+    vfs::close(fd).unwrap();
+    assert_eq!(magic, ATA_MAGIC);
+
+    for disk in blk::devices() {
+        assert!(names().iter().any(|n| n == disk.name()));
+    }
+}
+
+#[test_case]
+fn partitions_registered() {
+    let disk = blk::devices()
+        .into_iter()
+        .map(|d| d as Arc<dyn BlockDevice>)
+        .find(|d| d.block_count() == MBR_DISK_SECTORS)
+        .expect("partitioned test disk not attached");
+    let partitions = partitions(&disk).unwrap();
+    assert!(!partitions.is_empty());
+    for partition in &partitions {
+        assert!(devfs::device(partition.name()).is_some());
+    }
+
+    // Writes through the device file land on the partition, and stop at its end
+    let last = partitions.last().unwrap();
+    let path = format!("/dev/{}", last.name());
+    let end = last.block_count() * SECTOR_SIZE as u64;
+    let fd = vfs::open(&path, OpenFlags::READ_WRITE).unwrap();
+    vfs::seek(fd, SeekFrom::Start(end - 4)).unwrap();
+    assert_eq!(vfs::write(fd, b"devfs!").unwrap(), 4);
+    assert_eq!(vfs::write(fd, b"!"), Err(VfsError::NoSpace));
+    vfs::close(fd).unwrap();
+    let Some(Device::Block(device)) = devfs::device(last.name()) else {
+        panic!("{} is not a block device", last.name());
+    };
+    let mut buf = [0; 4];
+    read_bytes(device.as_ref(), end - 4, &mut buf).unwrap();
+    assert_eq!(&buf, b"devf");
+}
+
+#[test_case]
+fn disks_are_cached() {
+    let drive = ata::drives()
+        .into_iter()
+        .find(|d| d.channel() == Channel::Primary && d.position() == Position::Slave)
+        .expect("ATA test disk not attached");
+    let path = format!("/dev/{}", drive.name());
+    let read_magic = || {
+        let fd = vfs::open(&path, OpenFlags::READ).unwrap();
+        vfs::seek(fd, SeekFrom::Start(SECTOR_SIZE as u64)).unwrap();
+        let mut magic = [0; ATA_MAGIC.len()];
+        assert_eq!(vfs::read(fd, &mut magic).unwrap(), magic.len());
+        vfs::close(fd).unwrap();
+        assert_eq!(magic, ATA_MAGIC);
+    };
+
+    let before = CACHE.stats();
+    read_magic();
+    read_magic();
+    let after = CACHE.stats();
+    assert!(after.hits > before.hits);
+    assert!(after.cached > 0);
+}
+
+#[test_case]
+fn register_and_unregister() {
+    struct Upper;
+    impl CharDevice for Upper {
+        fn read(&self, buf: &mut [u8]) -> vfs::Result<usize> {
+            buf.fill(b'A');
+            Ok(buf.len())
+        }
+
+        fn write(&self, buf: &[u8]) -> vfs::Result<usize> {
+            Ok(buf.len())
+        }
+    }
+
+    devfs::register("upper", Device::Char(Arc::new(Upper))).unwrap();
+    let mut buf = [0; 3];
+    assert_eq!(read_start("/dev/upper", &mut buf), 3);
+    assert_eq!(&buf, b"AAA");
+
+    devfs::unregister("upper").unwrap();
+    assert_eq!(vfs::stat("/dev/upper"), Err(VfsError::NotFound));
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
@@ -0,0 +1,103 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(tlenek_core::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{format, string::String, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+use log::Level;
+use tlenek_core::{
+    dmesg::{self, Message, DMESG_CAPACITY, MAX_MESSAGE_LEN},
+    hlt_loop, init, print, println, test_panic_handler,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init(boot_info);
+    test_main();
+    hlt_loop();
+}
+
+/// Messages recorded after the one with sequence number `seq`.
+fn messages_after(seq: Option<u64>) -> Vec<Message> {
+    dmesg::messages()
+        .into_iter()
+        .filter(|m| seq.is_none_or(|seq| m.seq > seq))
+        .collect()
+}
+
+fn last_seq() -> Option<u64> {
+    dmesg::messages().last().map(|m| m.seq)
+}
+
+#[test_case]
+fn printed_lines_recorded() {
+    let before = last_seq();
+    print!("one ");
+    print!("line");
+    assert!(messages_after(before).is_empty());
+    println!();
+    println!("two\nthree");
+
+    let messages = messages_after(before);
+    let texts: Vec<&str> = messages.iter().map(|m| m.text.as_str()).collect();
+    assert_eq!(texts, ["one line", "two", "three"]);
+    assert!(messages.iter().all(|m| m.level.is_none()));
+    assert!(format!("{}", messages[1]).ends_with("] two"));
+}
+
+#[test_case]
+fn log_records_recorded() {
+    let before = last_seq();
+    log::warn!(target: "dmesg test", "value {}", 3);
+
+    let messages = messages_after(before);
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].level, Some(Level::Warn));
+    assert_eq!(messages[0].text, "dmesg test: value 3");
+    assert!(format!("{}", messages[0]).ends_with("] WARN  dmesg test: value 3"));
+}
+
+#[test_case]
+fn long_lines_cut_short() {
+    let before = last_seq();
+    let long = "y".repeat(MAX_MESSAGE_LEN + 20);
+    println!("{}", long);
+
+    let messages = messages_after(before);
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].text, long[..MAX_MESSAGE_LEN]);
+}
+
+#[test_case]
+fn ring_is_bounded() {
+    for i in 0..(DMESG_CAPACITY + 10) {
+        println!("line {}", i);
+    }
+
+    let messages = dmesg::messages();
+    assert_eq!(messages.len(), DMESG_CAPACITY);
+    assert!(messages.windows(2).all(|w| w[1].seq == w[0].seq + 1));
+    assert!(messages
+        .windows(2)
+        .all(|w| w[0].timestamp <= w[1].timestamp));
+    assert_eq!(
+        messages.last().unwrap().text,
+        format!("line {}", DMESG_CAPACITY + 9)
+    );
+    assert_eq!(
+        messages.first().map(|m| m.text.clone()),
+        Some(String::from("line 10"))
+    );
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
@@ -0,0 +1,324 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(tlenek_core::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+use tlenek_core::{
+    block::BlockDevice,
+    fs::{ext2::Ext2Fs, ramfs::RamFs},
+    hlt_loop, init, test_panic_handler,
+    vfs::{self, FileSystem, InodeKind, OpenFlags, Permissions, SeekFrom, VfsError},
+    virtio::blk,
+};
+
+// Must match tests/images/mkimages.sh
+const LABEL: &str = "EXT2TEST";
+const ROOT: &str = "/ext2";
+const HELLO: &str = "Hello from ext2!\n";
+const BIG_LEN: usize = 300000;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init(boot_info);
+    vfs::mount("/", RamFs::new()).expect("failed to mount root");
+    vfs::mkdir(ROOT).unwrap();
+    vfs::mount(ROOT, open()).unwrap();
+    test_main();
+    hlt_loop();
+}
+
+/// A new [Ext2Fs] for the test image.
+fn open() -> Arc<Ext2Fs> {
+    blk::devices()
+        .into_iter()
+        .map(|d| d as Arc<dyn BlockDevice>)
+        .filter_map(|d| Ext2Fs::new(d).ok())
+        .find(|fs| fs.label() == LABEL)
+        .expect("ext2 test disk not attached")
+}
+
+fn path(name: &str) -> String {
+    format!("{ROOT}/{name}")
+}
+
+fn names(path: &str) -> Vec<String> {
+    let mut names: Vec<String> = vfs::readdir(path)
+        .unwrap()
+        .into_iter()
+        .map(|e| e.name)
+        .collect();
+    names.sort();
+    names
+}
+
+/// Read `len` bytes at `offset` in the file at `path`.
+fn read_range(path: &str, offset: u64, len: usize) -> Vec<u8> {
+    let fd = vfs::open(path, OpenFlags::READ).unwrap();
+    vfs::seek(fd, SeekFrom::Start(offset)).unwrap();
+    let mut buf = vec![0; len];
+    let mut done = 0;
+    while done < len {
+        let n = vfs::read(fd, &mut buf[done..]).unwrap();
+        if n == 0 {
+            break;
+        }
+        done += n;
+    }
+    vfs::close(fd).unwrap();
+    buf.truncate(done);
+    buf
+}
+
+fn big_contents() -> Vec<u8> {
+    (0..BIG_LEN).map(|i| (i % 251) as u8).collect()
+}
+
+#[test_case]
+fn volume_found() {
+    let fs = open();
+    assert_eq!(fs.name(), "ext2");
+    assert!(!fs.is_read_only());
+}
+
+#[test_case]
+fn read_files() {
+    assert_eq!(vfs::read_to_string(&path("hello.txt")).unwrap(), HELLO);
+    assert_eq!(
+        vfs::read_to_string(&path("dir/nested/deep.txt")).unwrap(),
+        "Deep inside\n"
+    );
+    assert_eq!(
+        names(ROOT),
+        [
+            "big.bin",
+            "dir",
+            "hello.txt",
+            "link",
+            "long-link",
+            "lost+found",
+            "secret.txt"
+        ]
+    );
+
+    let metadata = vfs::stat(&path("secret.txt")).unwrap();
+    assert_eq!(metadata.kind, InodeKind::File);
+    assert_eq!(metadata.permissions, Permissions(0o600));
+    assert_eq!(metadata.nlink, 1);
+    assert_eq!(
+        vfs::stat(&path("hello.txt")).unwrap().permissions,
+        Permissions(0o644)
+    );
+    // `.`, the entry in the root and `nested`'s `..`
+    assert_eq!(vfs::stat(&path("dir")).unwrap().nlink, 3);
+}
+
+/// Reads and writes past the direct blocks.
+#[test_case]
+fn indirect_blocks() {
+    let big = path("big.bin");
+    assert_eq!(vfs::stat(&big).unwrap().size, BIG_LEN as u64);
+    assert!(read_range(&big, 0, BIG_LEN + 1) == big_contents());
+
+    // Doubly indirect with 1 KiB blocks
+    let fd = vfs::open(&big, OpenFlags::WRITE).unwrap();
+    vfs::seek(fd, SeekFrom::Start(290000)).unwrap();
+    assert_eq!(vfs::write(fd, b"patched").unwrap(), 7);
+    vfs::close(fd).unwrap();
+    assert_eq!(read_range(&big, 289999, 9), b"\x5epatched\x66");
+
+    // Writing past the end leaves a hole that reads as zeroes
+    let sparse = path("sparse.bin");
+    let fd = vfs::open(&sparse, OpenFlags::WRITE | OpenFlags::CREATE).unwrap();
+    vfs::seek(fd, SeekFrom::Start(100000)).unwrap();
+    vfs::write(fd, b"end").unwrap();
+    vfs::close(fd).unwrap();
+    assert_eq!(vfs::stat(&sparse).unwrap().size, 100003);
+    assert!(read_range(&sparse, 0, 100000).iter().all(|&b| b == 0));
+    assert_eq!(read_range(&sparse, 100000, 10), b"end");
+    vfs::unlink(&sparse).unwrap();
+}
+
+#[test_case]
+fn write_and_create() {
+    let file = path("new.bin");
+    let data: Vec<u8> = (0..50000).map(|i| (i % 7) as u8).collect();
+    vfs::write_all(&file, &data).unwrap();
+
+    let metadata = vfs::stat(&file).unwrap();
+    assert_eq!(metadata.kind, InodeKind::File);
+    assert_eq!(metadata.size, data.len() as u64);
+    assert_eq!(metadata.permissions, Permissions::DEFAULT_FILE);
+    assert!(read_range(&file, 0, data.len()) == data);
+
+    // Overwriting truncates and frees the extra blocks
+    vfs::write_all(&file, b"short").unwrap();
+    assert_eq!(vfs::read_to_string(&file).unwrap(), "short");
+    assert_eq!(
+        vfs::write_all(&path("dir"), b"x").unwrap_err(),
+        VfsError::IsADirectory
+    );
+}
+
+#[test_case]
+fn symlinks() {
+    assert_eq!(vfs::read_link(&path("link")).unwrap(), "hello.txt");
+    assert_eq!(vfs::read_to_string(&path("link")).unwrap(), HELLO);
+    assert_eq!(vfs::lstat(&path("link")).unwrap().kind, InodeKind::Symlink);
+    // Stored in a data block rather than the inode
+    assert_eq!(
+        vfs::read_to_string(&path("long-link")).unwrap(),
+        "Deep inside\n"
+    );
+
+    let short = path("dir/short-link");
+    vfs::symlink("nested/deep.txt", &short).unwrap();
+    assert_eq!(vfs::read_link(&short).unwrap(), "nested/deep.txt");
+    assert_eq!(vfs::read_to_string(&short).unwrap(), "Deep inside\n");
+
+    let target = "../dir/./nested/../nested/../nested/../nested/../nested/deep.txt";
+    let long = path("dir/long-link");
+    vfs::symlink(target, &long).unwrap();
+    assert_eq!(vfs::read_link(&long).unwrap(), target);
+    assert_eq!(vfs::read_to_string(&long).unwrap(), "Deep inside\n");
+
+    vfs::unlink(&short).unwrap();
+    vfs::unlink(&long).unwrap();
+}
+
+#[test_case]
+fn directories() {
+    let dir = path("new dir");
+    vfs::mkdir(&dir).unwrap();
+    assert_eq!(vfs::mkdir(&dir).unwrap_err(), VfsError::AlreadyExists);
+    assert!(names(&dir).is_empty());
+    let metadata = vfs::stat(&dir).unwrap();
+    assert_eq!(metadata.permissions, Permissions::DEFAULT_DIR);
+    assert_eq!(metadata.nlink, 2);
+
+    // Enough entries to grow the directory past one block
+    for i in 0..100 {
+        vfs::write_all(&format!("{dir}/file number {i}"), b"x").unwrap();
+    }
+    vfs::mkdir(&format!("{dir}/sub")).unwrap();
+    assert_eq!(names(&dir).len(), 101);
+    assert!(vfs::stat(&dir).unwrap().size > 1024);
+    assert_eq!(vfs::stat(&dir).unwrap().nlink, 3);
+
+    assert_eq!(vfs::unlink(&dir).unwrap_err(), VfsError::DirectoryNotEmpty);
+    for i in 0..100 {
+        vfs::unlink(&format!("{dir}/file number {i}")).unwrap();
+    }
+    vfs::unlink(&format!("{dir}/sub")).unwrap();
+    assert_eq!(vfs::stat(&dir).unwrap().nlink, 2);
+    vfs::unlink(&dir).unwrap();
+    assert_eq!(vfs::stat(&dir).unwrap_err(), VfsError::NotFound);
+}
+
+#[test_case]
+fn hard_links() {
+    let hello = path("hello.txt");
+    let other = path("dir/hello again");
+    vfs::link(&hello, &other).unwrap();
+    assert_eq!(vfs::stat(&hello).unwrap().nlink, 2);
+    assert_eq!(vfs::stat(&hello).unwrap(), vfs::stat(&other).unwrap());
+
+    vfs::write_all(&path("link target"), b"linked").unwrap();
+    vfs::link(&path("link target"), &path("dir/linked")).unwrap();
+    vfs::unlink(&path("link target")).unwrap();
+    assert_eq!(vfs::read_to_string(&path("dir/linked")).unwrap(), "linked");
+    assert_eq!(vfs::stat(&path("dir/linked")).unwrap().nlink, 1);
+
+    assert_eq!(
+        vfs::link(&path("dir"), &path("dir link")).unwrap_err(),
+        VfsError::PermissionDenied
+    );
+    assert_eq!(
+        vfs::link(&hello, &path("secret.txt")).unwrap_err(),
+        VfsError::AlreadyExists
+    );
+}
+
+#[test_case]
+fn rename() {
+    vfs::mkdir(&path("moved")).unwrap();
+    vfs::write_all(&path("moved/inner.txt"), b"inner").unwrap();
+    let root_links = vfs::stat(ROOT).unwrap().nlink;
+    let dir_links = vfs::stat(&path("dir")).unwrap().nlink;
+    vfs::rename(&path("moved"), &path("dir/moved")).unwrap();
+    assert_eq!(
+        vfs::read_to_string(&path("dir/moved/inner.txt")).unwrap(),
+        "inner"
+    );
+    // The moved directory's `..` changed parent
+    assert_eq!(vfs::stat(ROOT).unwrap().nlink, root_links - 1);
+    assert_eq!(vfs::stat(&path("dir")).unwrap().nlink, dir_links + 1);
+
+    // Names are case-sensitive
+    vfs::rename(&path("dir/moved"), &path("dir/MOVED")).unwrap();
+    assert!(names(&path("dir")).contains(&"MOVED".into()));
+    assert!(!names(&path("dir")).contains(&"moved".into()));
+
+    // Replacing a file
+    vfs::write_all(&path("target.txt"), b"old").unwrap();
+    vfs::rename(&path("dir/MOVED/inner.txt"), &path("target.txt")).unwrap();
+    assert_eq!(vfs::read_to_string(&path("target.txt")).unwrap(), "inner");
+    assert_eq!(
+        vfs::rename(&path("target.txt"), &path("dir")).unwrap_err(),
+        VfsError::IsADirectory
+    );
+}
+
+/// Changes are on disk, not just in memory.
+#[test_case]
+fn remount() {
+    vfs::sync().unwrap();
+    let root = "/remount-ext2";
+    vfs::mkdir(root).unwrap();
+    vfs::mount(root, open()).unwrap();
+
+    assert_eq!(
+        names(root),
+        [
+            "big.bin",
+            "dir",
+            "hello.txt",
+            "link",
+            "long-link",
+            "lost+found",
+            "new.bin",
+            "secret.txt",
+            "target.txt"
+        ]
+    );
+    assert_eq!(
+        read_range(&format!("{root}/big.bin"), 290000, 7),
+        b"patched"
+    );
+    assert_eq!(
+        vfs::read_to_string(&format!("{root}/new.bin")).unwrap(),
+        "short"
+    );
+    assert_eq!(
+        vfs::read_to_string(&format!("{root}/target.txt")).unwrap(),
+        "inner"
+    );
+    assert_eq!(
+        names(&format!("{root}/dir")),
+        ["MOVED", "hello again", "linked", "nested"]
+    );
+    assert!(names(&format!("{root}/dir/MOVED")).is_empty());
+    assert_eq!(vfs::stat(&format!("{root}/hello.txt")).unwrap().nlink, 2);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
@@ -0,0 +1,282 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(tlenek_core::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+use tlenek_core::{
+    block::{read_bytes, BlockDevice},
+    fs::{
+        fat::{FatFs, FatType},
+        ramfs::RamFs,
+    },
+    hlt_loop, init, test_panic_handler,
+    vfs::{self, FileSystem, InodeKind, VfsError},
+    virtio::blk,
+};
+
+// Must match tests/images/mkimages.sh
+const VOLUMES: [(FatType, &str, &str); 3] = [
+    (FatType::Fat12, "FAT12TEST", "/fat12"),
+    (FatType::Fat16, "FAT16TEST", "/fat16"),
+    (FatType::Fat32, "FAT32TEST", "/fat32"),
+];
+const HELLO: &str = "Hello from FAT!\n";
+const LONG_NAME: &str = "Long File Name.txt";
+const LONG_LEN: usize = 20000;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init(boot_info);
+    vfs::mount("/", RamFs::new()).expect("failed to mount root");
+    for (fat_type, _, path) in VOLUMES {
+        vfs::mkdir(path).unwrap();
+        vfs::mount(path, open(fat_type)).unwrap();
+    }
+    test_main();
+    hlt_loop();
+}
+
+/// The virtio disk holding the `fat_type` image.
+fn device(fat_type: FatType) -> Arc<dyn BlockDevice> {
+    blk::devices()
+        .into_iter()
+        .map(|d| d as Arc<dyn BlockDevice>)
+        .find(|d| FatFs::new(Arc::clone(d)).is_ok_and(|fs| fs.fat_type() == fat_type))
+        .expect("FAT test disk not attached")
+}
+
+/// A new [FatFs] for the `fat_type` image.
+fn open(fat_type: FatType) -> Arc<FatFs> {
+    FatFs::new(device(fat_type)).unwrap()
+}
+
+fn names(path: &str) -> Vec<String> {
+    let mut names: Vec<String> = vfs::readdir(path)
+        .unwrap()
+        .into_iter()
+        .map(|e| e.name)
+        .collect();
+    names.sort();
+    names
+}
+
+fn long_contents() -> Vec<u8> {
+    (0..LONG_LEN).map(|i| (i % 253) as u8).collect()
+}
+
+#[test_case]
+fn volumes_found() {
+    for (fat_type, label, _) in VOLUMES {
+        let fs = open(fat_type);
+        assert_eq!(fs.label(), label);
+        assert_eq!(fs.name(), "fat");
+    }
+}
+
+#[test_case]
+fn read_files() {
+    for (_, _, root) in VOLUMES {
+        let hello = format!("{root}/HELLO.TXT");
+        assert_eq!(vfs::read_to_string(&hello).unwrap(), HELLO);
+        // Names are case-insensitive
+        let lower = format!("{root}/hello.txt");
+        assert_eq!(vfs::stat(&lower).unwrap(), vfs::stat(&hello).unwrap());
+
+        let deep = format!("{root}/dir/nested/deep.txt");
+        assert_eq!(vfs::read_to_string(&deep).unwrap(), "Deep inside\n");
+    }
+}
+
+#[test_case]
+fn long_file_names() {
+    for (_, _, root) in VOLUMES {
+        assert_eq!(names(root), ["HELLO.TXT", LONG_NAME, "dir"]);
+
+        // Spans several clusters on every volume
+        let path = format!("{root}/{LONG_NAME}");
+        let fd = vfs::open(&path, vfs::OpenFlags::READ).unwrap();
+        let mut buf = vec![0; LONG_LEN + 1];
+        let mut len = 0;
+        loop {
+            let n = vfs::read(fd, &mut buf[len..]).unwrap();
+            if n == 0 {
+                break;
+            }
+            len += n;
+        }
+        vfs::close(fd).unwrap();
+        assert_eq!(len, LONG_LEN);
+        assert!(buf[..len] == long_contents());
+        assert_eq!(vfs::stat(&path).unwrap().size, LONG_LEN as u64);
+    }
+}
+
+#[test_case]
+fn write_and_create() {
+    for (_, _, root) in VOLUMES {
+        let path = format!("{root}/A much longer name than 8.3 allows.bin");
+        let data: Vec<u8> = (0..10000).map(|i| (i % 7) as u8).collect();
+        vfs::write_all(&path, &data).unwrap();
+
+        let metadata = vfs::stat(&path).unwrap();
+        assert_eq!(metadata.kind, InodeKind::File);
+        assert_eq!(metadata.size, data.len() as u64);
+        assert_eq!(vfs::read_to_string(&path).unwrap().as_bytes(), &data[..]);
+
+        // Short names that would clash get numeric tails
+        vfs::write_all(&format!("{root}/A much longer name 2.bin"), b"2").unwrap();
+        assert_eq!(
+            vfs::read_to_string(&format!("{root}/A much longer name 2.bin")).unwrap(),
+            "2"
+        );
+        vfs::unlink(&format!("{root}/A much longer name 2.bin")).unwrap();
+
+        // Overwriting truncates and frees the extra clusters
+        vfs::write_all(&path, b"short").unwrap();
+        assert_eq!(vfs::read_to_string(&path).unwrap(), "short");
+    }
+}
+
+#[test_case]
+fn directories() {
+    for (_, _, root) in VOLUMES {
+        let dir = format!("{root}/new dir");
+        vfs::mkdir(&dir).unwrap();
+        assert_eq!(vfs::mkdir(&dir).unwrap_err(), VfsError::AlreadyExists);
+        assert!(names(&dir).is_empty());
+
+        // Enough entries to grow the directory past one cluster on every volume
+        for i in 0..40 {
+            vfs::write_all(&format!("{dir}/file number {i}"), b"x").unwrap();
+        }
+        assert_eq!(names(&dir).len(), 40);
+        assert_eq!(vfs::unlink(&dir).unwrap_err(), VfsError::DirectoryNotEmpty);
+        for i in 0..40 {
+            vfs::unlink(&format!("{dir}/file number {i}")).unwrap();
+        }
+        vfs::unlink(&dir).unwrap();
+        assert_eq!(vfs::stat(&dir).unwrap_err(), VfsError::NotFound);
+    }
+}
+
+#[test_case]
+fn rename() {
+    for (_, _, root) in VOLUMES {
+        vfs::mkdir(&format!("{root}/moved")).unwrap();
+        vfs::write_all(&format!("{root}/moved/inner.txt"), b"inner").unwrap();
+        vfs::rename(&format!("{root}/moved"), &format!("{root}/dir/moved")).unwrap();
+        assert_eq!(
+            vfs::read_to_string(&format!("{root}/dir/moved/inner.txt")).unwrap(),
+            "inner"
+        );
+
+        // Changing only the case
+        vfs::rename(&format!("{root}/dir/moved"), &format!("{root}/dir/MOVED")).unwrap();
+        assert!(names(&format!("{root}/dir")).contains(&"MOVED".into()));
+
+        // Replacing a file
+        vfs::write_all(&format!("{root}/target.txt"), b"old").unwrap();
+        vfs::rename(
+            &format!("{root}/dir/MOVED/inner.txt"),
+            &format!("{root}/target.txt"),
+        )
+        .unwrap();
+        assert_eq!(
+            vfs::read_to_string(&format!("{root}/target.txt")).unwrap(),
+            "inner"
+        );
+        assert_eq!(
+            vfs::rename(&format!("{root}/target.txt"), &format!("{root}/dir")).unwrap_err(),
+            VfsError::IsADirectory
+        );
+    }
+}
+
+#[test_case]
+fn unsupported() {
+    for (_, _, root) in VOLUMES {
+        assert_eq!(
+            vfs::symlink("HELLO.TXT", &format!("{root}/link")).unwrap_err(),
+            VfsError::Unsupported
+        );
+        assert_eq!(
+            vfs::link(&format!("{root}/HELLO.TXT"), &format!("{root}/link")).unwrap_err(),
+            VfsError::Unsupported
+        );
+    }
+}
+
+/// Every FAT copy is updated.
+#[test_case]
+fn fats_match() {
+    vfs::sync().unwrap();
+    for (fat_type, _, _) in VOLUMES {
+        let device = device(fat_type);
+        let mut boot = [0; 512];
+        read_bytes(device.as_ref(), 0, &mut boot).unwrap();
+        let sector_size = u64::from(u16::from_le_bytes([boot[11], boot[12]]));
+        let reserved = u64::from(u16::from_le_bytes([boot[14], boot[15]]));
+        let fat_sectors = match u16::from_le_bytes([boot[22], boot[23]]) {
+            0 => u64::from(u32::from_le_bytes([boot[36], boot[37], boot[38], boot[39]])),
+            size => u64::from(size),
+        };
+        assert_eq!(boot[16], 2);
+
+        let fat_size = (fat_sectors * sector_size) as usize;
+        let mut first = vec![0; fat_size];
+        let mut second = vec![0; fat_size];
+        read_bytes(device.as_ref(), reserved * sector_size, &mut first).unwrap();
+        read_bytes(
+            device.as_ref(),
+            (reserved + fat_sectors) * sector_size,
+            &mut second,
+        )
+        .unwrap();
+        assert!(first == second);
+    }
+}
+
+/// Changes are on disk, not just in memory.
+#[test_case]
+fn remount() {
+    vfs::sync().unwrap();
+    for (fat_type, _, root) in VOLUMES {
+        let path = format!("/remount-{}", &root[1..]);
+        vfs::mkdir(&path).unwrap();
+        vfs::mount(&path, open(fat_type)).unwrap();
+
+        assert_eq!(
+            names(&path),
+            [
+                "A much longer name than 8.3 allows.bin",
+                "HELLO.TXT",
+                LONG_NAME,
+                "dir",
+                "target.txt"
+            ]
+        );
+        assert_eq!(
+            vfs::read_to_string(&format!("{path}/A much longer name than 8.3 allows.bin")).unwrap(),
+            "short"
+        );
+        assert_eq!(
+            vfs::read_to_string(&format!("{path}/target.txt")).unwrap(),
+            "inner"
+        );
+        assert_eq!(names(&format!("{path}/dir")), ["MOVED", "nested"]);
+        assert!(names(&format!("{path}/dir/MOVED")).is_empty());
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
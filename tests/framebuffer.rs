@@ -0,0 +1,84 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(tlenek_core::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+use tlenek_core::{
+    framebuffer::{
+        self,
+        console::{self, CELL_HEIGHT, CELL_WIDTH},
+        Colour, Rect,
+    },
+    hlt_loop, init, print, test_panic_handler,
+    vga_text::{self, screen},
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init(boot_info);
+    test_main();
+    hlt_loop();
+}
+
+const RED: Colour = Colour::rgb(0xFF, 0, 0);
+
+#[test_case]
+fn drawing() {
+    let info = framebuffer::set_mode(640, 480).unwrap();
+    assert_eq!((info.width, info.height), (640, 480));
+    assert_eq!(framebuffer::info(), Some(info));
+
+    framebuffer::with_framebuffer(|fb| {
+        assert_eq!(fb.pixel(639, 479), Some(Colour::BLACK));
+        fb.fill_rect(Rect::new(10, 10, 20, 20), RED);
+        fb.draw_line((0, 479), (639, 479), Colour::WHITE);
+
+        assert_eq!(fb.pixel(10, 10), Some(RED));
+        assert_eq!(fb.pixel(29, 29), Some(RED));
+        assert_eq!(fb.pixel(30, 30), Some(Colour::BLACK));
+        assert_eq!(fb.pixel(320, 479), Some(Colour::WHITE));
+        assert_eq!(fb.pixel(640, 0), None);
+    })
+    .unwrap();
+}
+
+#[test_case]
+fn console_draws_the_screen() {
+    console::enable().unwrap();
+    assert!(console::is_enabled());
+    let (rows, cols) = screen::size();
+    let info = framebuffer::info().unwrap();
+    assert_eq!(
+        (info.width, info.height),
+        (cols * CELL_WIDTH, rows * CELL_HEIGHT)
+    );
+
+    vga_text::clear_screen();
+    let (row, col) = vga_text::output_position();
+    print!("#");
+    // Whether any pixel in scan lines `lines` of the cell is lit
+    let lit = |col: usize, lines: core::ops::Range<usize>| {
+        framebuffer::with_framebuffer(|fb| {
+            lines.clone().any(|line| {
+                (col * CELL_WIDTH..(col + 1) * CELL_WIDTH)
+                    .any(|x| fb.pixel(x, row * CELL_HEIGHT + line) != Some(Colour::BLACK))
+            })
+        })
+        .unwrap()
+    };
+    assert!(lit(col, 0..CELL_HEIGHT));
+    // The cursor is an underline, at the bottom of the next cell
+    assert!(!lit(col + 1, 0..CELL_HEIGHT - 2));
+    assert!(lit(col + 1, CELL_HEIGHT - 2..CELL_HEIGHT));
+    assert!(!lit(col + 2, 0..CELL_HEIGHT));
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
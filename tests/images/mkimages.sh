@@ -0,0 +1,143 @@
+#!/bin/sh
+# Build the disk images attached to QEMU by `cargo test` (see `package.metadata.bootimage`).
+set -eu
+
+OUT_DIR="$(dirname "$0")/../../target/test-images"
+mkdir -p "$OUT_DIR"
+
+# pattern_image FILE MAGIC: 1 MiB raw disk. Sector 1 starts with MAGIC (NUL-terminated) followed
+# by the byte pattern `i % 251`; every other sector is zero.
+pattern_image() {
+    dd if=/dev/zero of="$1" bs=512 count=2048 status=none
+    python3 - "$1" "$2" <<'PY'
+import sys
+
+magic = sys.argv[2].encode() + b"\0"
+sector = bytearray(magic + bytes(i % 251 for i in range(512 - len(magic))))
+with open(sys.argv[1], "r+b") as f:
+    f.seek(512)
+    f.write(sector)
+PY
+}
+
+pattern_image "$OUT_DIR/ata.img" "TLENEK ATA TEST"
+pattern_image "$OUT_DIR/virtio-legacy.img" "TLENEK VIRTIO TEST"
+pattern_image "$OUT_DIR/virtio-modern.img" "TLENEK VIRTIO TEST"
+
+# fat_image FILE KIB FAT_BITS LABEL [MKFS_ARGS...]: FAT volume holding the files tests/fat.rs
+# expects.
+fat_image() {
+    file="$1" kib="$2" bits="$3" label="$4"
+    shift 4
+    rm -f "$file"
+    mkfs.fat -C -F "$bits" -n "$label" "$@" "$file" "$kib" >/dev/null
+
+    tmp="$(mktemp -d)"
+    printf 'Hello from FAT!\n' >"$tmp/hello.txt"
+    python3 -c 'import sys; sys.stdout.buffer.write(bytes(i % 253 for i in range(20000)))' \
+        >"$tmp/long.bin"
+    printf 'Deep inside\n' >"$tmp/deep.txt"
+
+    export MTOOLS_SKIP_CHECK=1
+    mcopy -i "$file" "$tmp/hello.txt" ::HELLO.TXT
+    mcopy -i "$file" "$tmp/long.bin" "::Long File Name.txt"
+    mmd -i "$file" ::dir ::dir/nested
+    mcopy -i "$file" "$tmp/deep.txt" ::dir/nested/deep.txt
+    rm -r "$tmp"
+}
+
+fat_image "$OUT_DIR/fat12.img" 1536 12 FAT12TEST
+fat_image "$OUT_DIR/fat16.img" 16384 16 FAT16TEST
+fat_image "$OUT_DIR/fat32.img" 40960 32 FAT32TEST -s 1
+
+# ext2_image FILE: 8 MiB ext2 volume with 1 KiB blocks holding the files tests/ext2.rs expects.
+ext2_image() {
+    tmp="$(mktemp -d)"
+    printf 'Hello from ext2!\n' >"$tmp/hello.txt"
+    # Big enough to need doubly indirect blocks
+    python3 -c 'import sys; sys.stdout.buffer.write(bytes(i % 251 for i in range(300000)))' \
+        >"$tmp/big.bin"
+    mkdir -p "$tmp/dir/nested"
+    printf 'Deep inside\n' >"$tmp/dir/nested/deep.txt"
+    printf 'Top secret\n' >"$tmp/secret.txt"
+    chmod 600 "$tmp/secret.txt"
+    ln -s hello.txt "$tmp/link"
+    # Too long to fit in the inode
+    ln -s "dir/nested/../nested/../nested/../nested/../nested/../nested/deep.txt" \
+        "$tmp/long-link"
+
+    rm -f "$1"
+    mke2fs -q -F -t ext2 -b 1024 -L EXT2TEST -E root_owner=0:0 -d "$tmp" "$1" 8M
+    rm -r "$tmp"
+}
+
+ext2_image "$OUT_DIR/ext2.img"
+
+# partition_table FILE mbr|gpt: 16 MiB disk with the partition table tests/partitions.rs expects.
+# MBR: primary 1 and logical 5 hold ext2, logical 6 is raw. GPT: "first" holds ext2, "second" is
+# raw. Run ext2_partition afterwards to fill in the filesystems.
+partition_table() {
+    python3 - "$1" "$2" <<'PY'
+import struct, sys, uuid, zlib
+
+path, kind = sys.argv[1], sys.argv[2]
+SECTORS = 32768
+disk = bytearray(SECTORS * 512)
+
+def mbr_entry(sector, index, kind, start, count):
+    struct.pack_into("<B3xB3xII", disk, sector * 512 + 446 + index * 16, 0, kind, start, count)
+    disk[sector * 512 + 510:sector * 512 + 512] = b"\x55\xaa"
+
+if kind == "mbr":
+    mbr_entry(0, 0, 0x83, 2048, 8192)
+    mbr_entry(0, 1, 0x05, 12288, 20480)
+    # Extended boot records: the logical partition relative to the record, then the next
+    # record relative to the extended partition
+    mbr_entry(12288, 0, 0x83, 2048, 8192)
+    mbr_entry(12288, 1, 0x05, 10240, 4096)
+    mbr_entry(22528, 0, 0x83, 2048, 2048)
+else:
+    mbr_entry(0, 0, 0xEE, 1, SECTORS - 1)
+    entries = bytearray(128 * 128)
+    parts = [
+        ("0FC63DAF-8483-4772-8E79-3D69D8477DE4", 2048, 14335, "first"),
+        ("EBD0A0A2-B9E5-4433-87C0-68B6B72699C7", 16384, 24575, "second"),
+    ]
+    for i, (type_guid, first, last, name) in enumerate(parts):
+        struct.pack_into("<16s16sQQQ72s", entries, i * 128, uuid.UUID(type_guid).bytes_le,
+                         uuid.uuid4().bytes_le, first, last, 0, name.encode("utf-16-le"))
+    disk_guid = uuid.uuid4().bytes_le
+
+    def header(my_lba, alternate_lba, entries_lba):
+        fields = [b"EFI PART", 0x10000, 92, 0, 0, my_lba, alternate_lba, 34, SECTORS - 34,
+                  disk_guid, entries_lba, 128, 128, zlib.crc32(entries)]
+        raw = struct.pack("<8sIIIIQQQQ16sQIII", *fields)
+        fields[3] = zlib.crc32(raw)
+        return struct.pack("<8sIIIIQQQQ16sQIII", *fields)
+
+    disk[512:1024] = header(1, SECTORS - 1, 2).ljust(512, b"\0")
+    disk[1024:1024 + len(entries)] = entries
+    backup_entries = (SECTORS - 33) * 512
+    disk[backup_entries:backup_entries + len(entries)] = entries
+    disk[(SECTORS - 1) * 512:] = header(SECTORS - 1, 1, SECTORS - 33).ljust(512, b"\0")
+
+with open(path, "wb") as f:
+    f.write(disk)
+PY
+}
+
+# ext2_partition FILE START_SECTOR SECTORS LABEL: ext2 filesystem inside a partition, holding a
+# file named after the label.
+ext2_partition() {
+    tmp="$(mktemp -d)"
+    printf 'Hello from %s!\n' "$4" >"$tmp/hello.txt"
+    mke2fs -q -F -t ext2 -b 1024 -L "$4" -E "offset=$(($2 * 512)),nodiscard" -d "$tmp" \
+        "$1" "$(($3 / 2))"
+    rm -r "$tmp"
+}
+
+partition_table "$OUT_DIR/mbr.img" mbr
+ext2_partition "$OUT_DIR/mbr.img" 2048 8192 MBRFIRST
+ext2_partition "$OUT_DIR/mbr.img" 14336 8192 MBRFIFTH
+partition_table "$OUT_DIR/gpt.img" gpt
+ext2_partition "$OUT_DIR/gpt.img" 2048 12288 GPTFIRST
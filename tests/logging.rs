@@ -0,0 +1,105 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(tlenek_core::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{format, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+use log::{Level, LevelFilter};
+use tlenek_core::{
+    dmesg::{self, Message},
+    hlt_loop, init,
+    logger::{self, Sink, DEFAULT_LEVELS},
+    test_panic_handler,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init(boot_info);
+    test_main();
+    hlt_loop();
+}
+
+/// Log records in the dmesg ring recorded after the message with sequence number `seq`.
+fn records_after(seq: Option<u64>) -> Vec<Message> {
+    dmesg::messages()
+        .into_iter()
+        .filter(|m| m.level.is_some() && seq.is_none_or(|seq| m.seq > seq))
+        .collect()
+}
+
+fn last_seq() -> Option<u64> {
+    dmesg::messages().last().map(|m| m.seq)
+}
+
+/// Whether `message` was logged with `target`.
+fn from_target(message: &Message, target: &str) -> bool {
+    message
+        .text
+        .strip_prefix(target)
+        .is_some_and(|rest| rest.starts_with(": "))
+}
+
+/// Run `f` with only the ring sink enabled, at `filter`.
+fn ring_only(filter: LevelFilter, f: impl FnOnce()) {
+    logger::set_level(Sink::Vga, LevelFilter::Off);
+    logger::set_level(Sink::Serial, LevelFilter::Off);
+    logger::set_level(Sink::Ring, filter);
+    f();
+    for (sink, level) in Sink::ALL.into_iter().zip(DEFAULT_LEVELS) {
+        logger::set_level(sink, level);
+    }
+}
+
+#[test_case]
+fn drivers_log_at_boot() {
+    let records = records_after(None);
+    for target in ["serial", "pci", "ata", "virtio::blk"] {
+        assert!(
+            records.iter().any(|m| from_target(m, target)),
+            "nothing logged by {}",
+            target
+        );
+    }
+    assert!(records.iter().all(|m| m.level <= Some(Level::Debug)));
+}
+
+#[test_case]
+fn levels_filter_records() {
+    let before = last_seq();
+    ring_only(LevelFilter::Warn, || {
+        assert_eq!(log::max_level(), LevelFilter::Warn);
+        log::info!("not kept");
+        log::warn!("kept {}", 1);
+        log::error!(target: "custom", "kept {}", 2);
+    });
+
+    let records = records_after(before);
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].level, Some(Level::Warn));
+    assert_eq!(records[0].text, "logging: kept 1");
+    assert_eq!(records[1].level, Some(Level::Error));
+    assert!(from_target(&records[1], "custom"));
+    assert_eq!(records[1].seq, records[0].seq + 1);
+    assert!(format!("{}", records[1]).ends_with("] ERROR custom: kept 2"));
+}
+
+#[test_case]
+fn ring_sink_off() {
+    let before = last_seq();
+    ring_only(LevelFilter::Off, || {
+        log::error!("not kept");
+    });
+    assert!(records_after(before).is_empty());
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
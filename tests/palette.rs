@@ -0,0 +1,71 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(tlenek_core::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+use tlenek_core::{
+    framebuffer::{self, console, Colour},
+    hlt_loop, init, test_panic_handler,
+    vga_text::{
+        self,
+        palette::{self, DEFAULT_PALETTE},
+        VgaBgColour, VgaFgColour,
+    },
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init(boot_info);
+    test_main();
+    hlt_loop();
+}
+
+const TEAL: Colour = Colour::rgb(0x00, 0x80, 0x80);
+
+// The colour of the screen's top left pixel, in a blank cell
+fn background() -> Colour {
+    framebuffer::with_framebuffer(|fb| fb.pixel(0, 0))
+        .flatten()
+        .unwrap()
+}
+
+#[test_case]
+fn console_follows_the_palette() {
+    console::enable().unwrap();
+    vga_text::set_vga_attr(VgaBgColour::Blue, VgaFgColour::White, false);
+    vga_text::clear_screen();
+    assert_eq!(background(), DEFAULT_PALETTE[1]);
+
+    palette::set_colour(VgaFgColour::Blue, TEAL);
+    assert_eq!(background(), TEAL);
+    palette::reset_palette();
+    assert_eq!(background(), DEFAULT_PALETTE[1]);
+}
+
+#[test_case]
+fn console_shows_bright_backgrounds() {
+    // Set as blue while bit 7 blinks
+    vga_text::set_vga_attr(VgaBgColour::LightBlue, VgaFgColour::White, false);
+    vga_text::clear_screen();
+    assert_eq!(background(), DEFAULT_PALETTE[1]);
+
+    palette::set_bright_backgrounds(true);
+    assert_eq!(background(), DEFAULT_PALETTE[1]);
+    vga_text::set_vga_attr(VgaBgColour::LightBlue, VgaFgColour::White, false);
+    vga_text::clear_screen();
+    assert_eq!(background(), DEFAULT_PALETTE[9]);
+
+    // The same bit blinks again, which the console doesn't show
+    palette::set_bright_backgrounds(false);
+    assert_eq!(background(), DEFAULT_PALETTE[1]);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
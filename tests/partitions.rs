@@ -0,0 +1,165 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(tlenek_core::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+use tlenek_core::{
+    block::{
+        partition::{partitions, Guid, Partition, PartitionError, PartitionType},
+        read_bytes, write_bytes, BlockDevice, BlockError, SECTOR_SIZE,
+    },
+    fs::{ext2::Ext2Fs, ramfs::RamFs},
+    hlt_loop, init, test_panic_handler, vfs,
+    virtio::blk,
+};
+
+// Must match tests/images/mkimages.sh
+const DISK_SECTORS: u64 = 32768;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init(boot_info);
+    vfs::mount("/", RamFs::new()).expect("failed to mount root");
+    test_main();
+    hlt_loop();
+}
+
+/// The partitioned test disks, MBR first.
+fn disks() -> (Arc<dyn BlockDevice>, Arc<dyn BlockDevice>) {
+    let disks: Vec<Arc<dyn BlockDevice>> = blk::devices()
+        .into_iter()
+        .map(|d| d as Arc<dyn BlockDevice>)
+        .filter(|d| d.block_count() == DISK_SECTORS)
+        .collect();
+    let is_gpt = |disk: &Arc<dyn BlockDevice>| {
+        partitions(disk).is_ok_and(|p| matches!(p[0].kind(), PartitionType::Gpt(_)))
+    };
+    let mbr = disks
+        .iter()
+        .find(|d| !is_gpt(d))
+        .expect("MBR test disk not attached");
+    let gpt = disks
+        .iter()
+        .find(|d| is_gpt(d))
+        .expect("GPT test disk not attached");
+    (Arc::clone(mbr), Arc::clone(gpt))
+}
+
+fn summary(partitions: &[Arc<Partition>]) -> Vec<(u32, u64, u64)> {
+    partitions
+        .iter()
+        .map(|p| (p.number(), p.start(), p.block_count()))
+        .collect()
+}
+
+/// Mount the ext2 filesystem on `partition` and read the file the image put there.
+fn hello(partition: &Arc<Partition>) -> String {
+    let fs = Ext2Fs::new(Arc::clone(partition) as _).unwrap();
+    let path = format!("/{}", partition.name());
+    vfs::mkdir(&path).unwrap();
+    let label = fs.label();
+    vfs::mount(&path, fs).unwrap();
+    let contents = vfs::read_to_string(&format!("{path}/hello.txt")).unwrap();
+    assert_eq!(contents, format!("Hello from {label}!\n"));
+    vfs::unmount(&path).unwrap();
+    vfs::unlink(&path).unwrap();
+    label
+}
+
+#[test_case]
+fn mbr() {
+    let (disk, _) = disks();
+    let partitions = partitions(&disk).unwrap();
+    assert_eq!(
+        summary(&partitions),
+        [(1, 2048, 8192), (5, 14336, 8192), (6, 24576, 2048)]
+    );
+    for partition in &partitions {
+        assert_eq!(partition.kind(), PartitionType::Mbr(0x83));
+        assert!(partition.label().is_empty());
+        assert_eq!(
+            partition.name(),
+            format!("{}{}", disk.name(), partition.number())
+        );
+    }
+    assert_eq!(hello(&partitions[0]), "MBRFIRST");
+    assert_eq!(hello(&partitions[1]), "MBRFIFTH");
+}
+
+#[test_case]
+fn gpt() {
+    let (_, disk) = disks();
+    let partitions = partitions(&disk).unwrap();
+    assert_eq!(summary(&partitions), [(1, 2048, 12288), (2, 16384, 8192)]);
+    assert_eq!(
+        partitions[0].kind(),
+        PartitionType::Gpt(Guid::LINUX_FILESYSTEM)
+    );
+    assert_eq!(partitions[0].label(), "first");
+    assert_eq!(partitions[1].kind(), PartitionType::Gpt(Guid::BASIC_DATA));
+    assert_eq!(partitions[1].label(), "second");
+    assert_eq!(hello(&partitions[0]), "GPTFIRST");
+}
+
+/// Partitions can't reach outside themselves.
+#[test_case]
+fn bounds() {
+    let (disk, _) = disks();
+    let raw = &partitions(&disk).unwrap()[2];
+    let last = raw.block_count() - 1;
+    let data = [0x5A; SECTOR_SIZE];
+    raw.write_blocks(last, &data).unwrap();
+    assert_eq!(
+        raw.write_blocks(last + 1, &data),
+        Err(BlockError::OutOfRange)
+    );
+    assert_eq!(
+        raw.read_blocks(last, &mut [0; SECTOR_SIZE * 2]),
+        Err(BlockError::OutOfRange)
+    );
+
+    let mut buf = [0; SECTOR_SIZE];
+    disk.read_blocks(raw.start() + last, &mut buf).unwrap();
+    assert_eq!(buf, data);
+}
+
+/// A damaged primary GPT is replaced by the backup, but not if that's damaged too.
+#[test_case]
+fn gpt_backup() {
+    let (_, disk) = disks();
+    let expected = summary(&partitions(&disk).unwrap());
+
+    // A flipped bit in the primary header
+    let mut byte = [0];
+    read_bytes(disk.as_ref(), SECTOR_SIZE as u64 + 40, &mut byte).unwrap();
+    write_bytes(disk.as_ref(), SECTOR_SIZE as u64 + 40, &[byte[0] ^ 1]).unwrap();
+    assert_eq!(summary(&partitions(&disk).unwrap()), expected);
+
+    // And in the backup partition array
+    let backup_entries = (DISK_SECTORS - 33) * SECTOR_SIZE as u64;
+    read_bytes(disk.as_ref(), backup_entries, &mut byte).unwrap();
+    write_bytes(disk.as_ref(), backup_entries, &[byte[0] ^ 1]).unwrap();
+    assert_eq!(partitions(&disk).err(), Some(PartitionError::BadChecksum));
+}
+
+#[test_case]
+fn unpartitioned() {
+    for disk in blk::devices() {
+        if disk.block_count() != DISK_SECTORS {
+            assert!(partitions(&(disk as _)).unwrap().is_empty());
+        }
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
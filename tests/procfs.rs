@@ -0,0 +1,153 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(tlenek_core::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{format, string::String, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+use tlenek_core::{
+    allocator::HEAP_SIZE,
+    fs::{procfs::ProcFs, ramfs::RamFs},
+    hlt_loop, init,
+    interrupts::{self, InterruptIndex},
+    pci, test_panic_handler,
+    vfs::{self, OpenFlags, VfsError},
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init(boot_info);
+    vfs::mount("/", RamFs::new()).expect("failed to mount root");
+    vfs::mkdir("/proc").unwrap();
+    vfs::mount("/proc", ProcFs::new()).unwrap();
+    test_main();
+    hlt_loop();
+}
+
+fn read(name: &str) -> String {
+    vfs::read_to_string(&format!("/proc/{}", name)).unwrap()
+}
+
+/// The value on the `meminfo` line starting with `name`.
+fn meminfo_value(meminfo: &str, name: &str) -> u64 {
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+        .and_then(|rest| rest.split_whitespace().next()?.parse().ok())
+        .unwrap_or_else(|| panic!("{} missing from meminfo", name))
+}
+
+#[test_case]
+fn listing() {
+    let names: Vec<String> = vfs::readdir("/proc")
+        .unwrap()
+        .into_iter()
+        .map(|e| e.name)
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "cpuinfo",
+            "interrupts",
+            "meminfo",
+            "mounts",
+            "pci",
+            "tasks",
+            "uptime"
+        ]
+    );
+}
+
+/// Uptime in hundredths of a second.
+fn uptime_hundredths() -> u64 {
+    let uptime = read("uptime");
+    let (seconds, hundredths) = uptime.trim().split_once('.').unwrap();
+    assert_eq!(hundredths.len(), 2);
+    seconds.parse::<u64>().unwrap() * 100 + hundredths.parse::<u64>().unwrap()
+}
+
+#[test_case]
+fn uptime() {
+    let before = uptime_hundredths();
+    let ticks = interrupts::ticks();
+    while interrupts::ticks() < ticks + interrupts::TIMER_HZ / 2 {
+        x86_64::instructions::hlt();
+    }
+    assert!(uptime_hundredths() > before);
+}
+
+#[test_case]
+fn meminfo() {
+    let meminfo = read("meminfo");
+    assert_eq!(
+        meminfo_value(&meminfo, "HeapTotal"),
+        HEAP_SIZE as u64 / 1024
+    );
+    assert!(meminfo_value(&meminfo, "HeapUsed") > 0);
+    assert!(meminfo_value(&meminfo, "PhysTotal") > 0);
+    meminfo_value(&meminfo, "CacheBlocks");
+}
+
+#[test_case]
+fn interrupt_counts() {
+    let timer = format!("{}", u8::from(InterruptIndex::Timer));
+    let interrupts = read("interrupts");
+    let line = interrupts
+        .lines()
+        .skip(1)
+        .find(|line| line.split_whitespace().next() == Some(timer.as_str()))
+        .expect("no timer interrupts counted");
+    assert!(line.ends_with("timer"));
+    let count: u64 = line.split_whitespace().nth(1).unwrap().parse().unwrap();
+    assert!(count > 0);
+}
+
+#[test_case]
+fn mounts() {
+    let mounts = read("mounts");
+    assert!(mounts.lines().any(|l| l == "/ ramfs"));
+    assert!(mounts.lines().any(|l| l == "/proc procfs"));
+}
+
+#[test_case]
+fn pci_devices() {
+    let pci = read("pci");
+    assert_eq!(pci.lines().count(), pci::devices().len());
+    assert!(pci.lines().any(|l| l.ends_with("virtio-blk")));
+}
+
+#[test_case]
+fn tasks_and_cpuinfo() {
+    assert!(read("tasks").contains("kernel"));
+    let cpuinfo = read("cpuinfo");
+    for key in ["vendor_id", "model name", "cpu family", "flags"] {
+        assert!(
+            cpuinfo.lines().any(|l| l.starts_with(key)),
+            "{} missing",
+            key
+        );
+    }
+    assert!(cpuinfo.contains(" fpu"));
+}
+
+#[test_case]
+fn read_only() {
+    let fd = vfs::open("/proc/uptime", OpenFlags::WRITE).unwrap();
+    assert_eq!(vfs::write(fd, b"0"), Err(VfsError::PermissionDenied));
+    vfs::close(fd).unwrap();
+    assert_eq!(
+        vfs::write_all("/proc/new", b""),
+        Err(VfsError::PermissionDenied)
+    );
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
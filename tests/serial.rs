@@ -0,0 +1,166 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(tlenek_core::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+use tlenek_core::{
+    fs::{devfs::DevFs, ramfs::RamFs},
+    hlt_loop, init,
+    interrupts::{self, InterruptIndex},
+    serial::{self, Com, Config, DataBits, FifoTrigger, Parity, Role, SerialError, StopBits},
+    test_panic_handler,
+    vfs::{self, OpenFlags},
+};
+
+/// Timer ticks to wait for looped-back bytes to arrive.
+const TIMEOUT_TICKS: u64 = 2 * interrupts::TIMER_HZ;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init(boot_info);
+    vfs::mount("/", RamFs::new()).expect("failed to mount root");
+    vfs::mkdir("/dev").unwrap();
+    vfs::mount("/dev", DevFs::new()).unwrap();
+    test_main();
+    hlt_loop();
+}
+
+/// Send `bytes` to ourselves through `com`'s loopback mode, and wait until the receive interrupt
+/// has buffered all of them.
+fn loop_back(com: Com, bytes: &[u8]) {
+    while com.read_byte().is_some() {}
+
+    com.set_loopback(true);
+    com.write_bytes(bytes);
+    let start = interrupts::ticks();
+    while com.bytes_waiting() < bytes.len() && interrupts::ticks() - start < TIMEOUT_TICKS {
+        x86_64::instructions::hlt();
+    }
+    com.set_loopback(false);
+
+    assert_eq!(com.bytes_waiting(), bytes.len());
+}
+
+fn interrupt_count(interrupt_index: InterruptIndex) -> u64 {
+    let vector = u8::from(interrupt_index);
+    interrupts::interrupt_counts()
+        .into_iter()
+        .find(|&(v, _)| v == vector)
+        .map_or(0, |(_, count)| count)
+}
+
+#[test_case]
+fn receive_on_interrupt() {
+    let before = interrupt_count(InterruptIndex::Serial1);
+    loop_back(Com::Com1, b"hello");
+    assert!(interrupt_count(InterruptIndex::Serial1) > before);
+
+    let received: Vec<u8> = core::iter::from_fn(serial::read_byte).collect();
+    assert_eq!(received, b"hello");
+    assert_eq!(serial::read_byte(), None);
+}
+
+#[test_case]
+fn read_lines() {
+    loop_back(Com::Com1, b"ls -l\r\nnex\x7ft\nbad\x1bs\r");
+    assert_eq!(serial::read_line(), "ls -l");
+    assert_eq!(serial::read_line(), "next");
+    assert_eq!(serial::read_line(), "bads");
+    assert_eq!(serial::bytes_waiting(), 0);
+}
+
+#[test_case]
+fn read_tty_device() {
+    loop_back(Com::Com1, b"abc");
+    let fd = vfs::open("/dev/ttyS0", OpenFlags::READ).unwrap();
+    let mut buf = [0; 8];
+    assert_eq!(vfs::read(fd, &mut buf).unwrap(), 3);
+    assert_eq!(&buf[..3], b"abc");
+    assert_eq!(vfs::read(fd, &mut buf).unwrap(), 0);
+    vfs::close(fd).unwrap();
+}
+
+// QEMU is started with COM1 on stdio and COM2 discarding its output, see Cargo.toml
+#[test_case]
+fn ports_found() {
+    assert_eq!(serial::ports(), [Com::Com1, Com::Com2]);
+    assert_eq!(Com::Com1.base(), 0x3F8);
+    assert_eq!(Com::Com2.base(), 0x2F8);
+    assert_eq!(Com::Com1.config(), Some(Config::default()));
+    assert_eq!(Com::Com3.config(), None);
+
+    let names: Vec<String> = vfs::readdir("/dev")
+        .unwrap()
+        .into_iter()
+        .map(|e| e.name)
+        .filter(|name| name.starts_with("ttyS"))
+        .collect();
+    assert_eq!(names, ["ttyS0", "ttyS1"]);
+}
+
+#[test_case]
+fn configure_ports() {
+    let config = Config {
+        baud_rate: 9600,
+        data_bits: DataBits::Seven,
+        parity: Parity::Even,
+        stop_bits: StopBits::Two,
+        fifo_trigger: FifoTrigger::Bytes4,
+    };
+    Com::Com2.configure(config).unwrap();
+    assert_eq!(Com::Com2.config(), Some(config));
+
+    let bad = Config {
+        baud_rate: 12345,
+        ..config
+    };
+    assert_eq!(
+        Com::Com2.configure(bad),
+        Err(SerialError::BadBaudRate(12345))
+    );
+    assert_eq!(Com::Com2.config(), Some(config));
+    assert_eq!(
+        Com::Com4.configure(config),
+        Err(SerialError::NotPresent(Com::Com4))
+    );
+
+    // Still works with the new settings
+    let before = interrupt_count(InterruptIndex::Serial2);
+    loop_back(Com::Com2, b"second port");
+    assert!(interrupt_count(InterruptIndex::Serial2) > before);
+    let received: Vec<u8> = core::iter::from_fn(|| Com::Com2.read_byte()).collect();
+    assert_eq!(received, b"second port");
+    assert_eq!(Com::Com1.bytes_waiting(), 0);
+
+    Com::Com2.configure(Config::default()).unwrap();
+}
+
+#[test_case]
+fn roles() {
+    assert_eq!(serial::role(Role::Console), Some(Com::Com1));
+    assert_eq!(serial::role(Role::Log), Some(Com::Com1));
+    assert_eq!(serial::role(Role::Debug), None);
+
+    serial::set_role(Role::Debug, Some(Com::Com2)).unwrap();
+    assert_eq!(serial::role(Role::Debug), Some(Com::Com2));
+    assert_eq!(
+        serial::set_role(Role::Log, Some(Com::Com3)),
+        Err(SerialError::NotPresent(Com::Com3))
+    );
+    assert_eq!(serial::role(Role::Log), Some(Com::Com1));
+    serial::set_role(Role::Debug, None).unwrap();
+    assert_eq!(serial::role(Role::Debug), None);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
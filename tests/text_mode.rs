@@ -0,0 +1,81 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(tlenek_core::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{format, string::String, vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+use tlenek_core::{
+    hlt_loop, init, print, test_panic_handler,
+    vga_text::{
+        self,
+        mode::{self, Font, TextMode, TextModeError},
+        screen,
+    },
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init(boot_info);
+    test_main();
+    hlt_loop();
+}
+
+#[test_case]
+fn denser_modes() {
+    assert_eq!(mode::mode(), TextMode::Text80x25);
+    let mut last_line = String::from("before");
+    print!("\n{}", last_line);
+
+    for text_mode in [TextMode::Text90x60, TextMode::Text80x50] {
+        mode::set_mode(text_mode).unwrap();
+        assert_eq!(mode::mode(), text_mode);
+        assert_eq!(screen::size(), (text_mode.rows(), text_mode.cols()));
+
+        // The last line is still at the bottom, with output carrying on after it
+        let (row, col) = vga_text::output_position();
+        assert_eq!((row, col), (text_mode.rows() - 1, last_line.len()));
+        let shown: Vec<u8> = (0..col)
+            .map(|col| screen::cell_at(row, col).unwrap().text_byte)
+            .collect();
+        assert_eq!(shown, last_line.as_bytes());
+
+        let last_col = text_mode.cols() - 1;
+        screen::write_at(row, last_col, "#", Default::default());
+        assert_eq!(screen::cell_at(row, last_col).unwrap().text_byte, b'#');
+        assert_eq!(screen::cell_at(row, last_col + 1), None);
+
+        last_line = format!("{}", text_mode);
+        print!("\n{}", last_line);
+    }
+
+    mode::set_mode(TextMode::Text80x25).unwrap();
+    assert_eq!(screen::size(), (25, 80));
+}
+
+#[test_case]
+fn custom_fonts() {
+    let tall = vec![0x18; Font::GLYPHS * 16];
+    let short = vec![0x18; Font::GLYPHS * 8];
+
+    mode::load_font(&Font::new(16, &tall).unwrap()).unwrap();
+    assert_eq!(
+        mode::load_font(&Font::new(8, &short).unwrap()),
+        Err(TextModeError::FontHeight(8))
+    );
+
+    mode::set_mode(TextMode::Text80x50).unwrap();
+    mode::load_font(&Font::new(8, &short).unwrap()).unwrap();
+    mode::set_mode(TextMode::Text80x25).unwrap();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
@@ -0,0 +1,203 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(tlenek_core::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+use tlenek_core::{
+    fs::ramfs::RamFs,
+    hlt_loop, init, test_panic_handler,
+    vfs::{self, InodeKind, OpenFlags, SeekFrom, VfsError},
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init(boot_info);
+    vfs::mount("/", RamFs::new()).expect("failed to mount root");
+    test_main();
+    hlt_loop();
+}
+
+fn names(path: &str) -> Vec<String> {
+    let mut names: Vec<String> = vfs::readdir(path)
+        .unwrap()
+        .into_iter()
+        .map(|e| e.name)
+        .collect();
+    names.sort();
+    names
+}
+
+#[test_case]
+fn open_read_write_seek() {
+    vfs::write_all("/greeting", b"hello").unwrap();
+
+    let fd = vfs::open("/greeting", OpenFlags::READ_WRITE).unwrap();
+    assert_eq!(vfs::seek(fd, SeekFrom::End(0)).unwrap(), 5);
+    assert_eq!(vfs::write(fd, b", world").unwrap(), 7);
+    assert_eq!(vfs::seek(fd, SeekFrom::Start(7)).unwrap(), 7);
+
+    let mut buf = [0; 16];
+    let n = vfs::read(fd, &mut buf).unwrap();
+    assert_eq!(&buf[..n], b"world");
+    assert_eq!(vfs::read(fd, &mut buf).unwrap(), 0);
+    assert_eq!(
+        vfs::seek(fd, SeekFrom::Current(-100)).unwrap_err(),
+        VfsError::InvalidArgument
+    );
+    vfs::close(fd).unwrap();
+
+    assert_eq!(
+        vfs::read(fd, &mut buf).unwrap_err(),
+        VfsError::BadDescriptor
+    );
+    assert_eq!(vfs::close(fd).unwrap_err(), VfsError::BadDescriptor);
+    assert_eq!(vfs::read_to_string("/greeting").unwrap(), "hello, world");
+}
+
+#[test_case]
+fn open_flags() {
+    assert_eq!(
+        vfs::open("/missing", OpenFlags::READ).unwrap_err(),
+        VfsError::NotFound
+    );
+
+    let fd = vfs::open("/created", OpenFlags::WRITE | OpenFlags::CREATE).unwrap();
+    assert_eq!(
+        vfs::read(fd, &mut [0]).unwrap_err(),
+        VfsError::BadDescriptor
+    );
+    vfs::write(fd, b"abc").unwrap();
+    vfs::close(fd).unwrap();
+
+    assert_eq!(
+        vfs::open(
+            "/created",
+            OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::EXCLUSIVE
+        )
+        .unwrap_err(),
+        VfsError::AlreadyExists
+    );
+
+    let fd = vfs::open("/created", OpenFlags::WRITE | OpenFlags::APPEND).unwrap();
+    vfs::write(fd, b"def").unwrap();
+    vfs::close(fd).unwrap();
+    assert_eq!(vfs::read_to_string("/created").unwrap(), "abcdef");
+
+    let fd = vfs::open("/created", OpenFlags::WRITE | OpenFlags::TRUNCATE).unwrap();
+    assert_eq!(vfs::fstat(fd).unwrap().size, 0);
+    vfs::close(fd).unwrap();
+
+    vfs::mkdir("/a_dir").unwrap();
+    assert_eq!(
+        vfs::open("/a_dir", OpenFlags::WRITE).unwrap_err(),
+        VfsError::IsADirectory
+    );
+}
+
+#[test_case]
+fn relative_paths_and_dots() {
+    vfs::mkdir("/rel").unwrap();
+    vfs::mkdir("/rel/sub").unwrap();
+    vfs::write_all("/rel/sub/file", b"x").unwrap();
+
+    vfs::chdir("/rel").unwrap();
+    assert_eq!(vfs::cwd(), "/rel");
+    assert_eq!(vfs::stat("sub/file").unwrap().size, 1);
+    assert_eq!(vfs::stat("./sub/../sub/./file").unwrap().size, 1);
+    assert_eq!(vfs::canonicalize("sub/..").unwrap(), "/rel");
+    assert_eq!(vfs::canonicalize("../../..").unwrap(), "/");
+    assert_eq!(
+        vfs::stat("sub/file/oops").unwrap_err(),
+        VfsError::NotADirectory
+    );
+    vfs::chdir("/").unwrap();
+}
+
+#[test_case]
+fn symlinks() {
+    vfs::mkdir("/real").unwrap();
+    vfs::write_all("/real/data", b"through the link").unwrap();
+    vfs::symlink("/real", "/abs_link").unwrap();
+    vfs::symlink("real/data", "/rel_link").unwrap();
+    vfs::symlink("loop_b", "/loop_a").unwrap();
+    vfs::symlink("loop_a", "/loop_b").unwrap();
+
+    assert_eq!(
+        vfs::read_to_string("/abs_link/data").unwrap(),
+        "through the link"
+    );
+    assert_eq!(
+        vfs::read_to_string("/rel_link").unwrap(),
+        "through the link"
+    );
+    assert_eq!(vfs::canonicalize("/abs_link/data").unwrap(), "/real/data");
+    assert_eq!(vfs::lstat("/rel_link").unwrap().kind, InodeKind::Symlink);
+    assert_eq!(vfs::stat("/rel_link").unwrap().kind, InodeKind::File);
+    assert_eq!(vfs::read_link("/rel_link").unwrap(), "real/data");
+    assert_eq!(vfs::stat("/loop_a").unwrap_err(), VfsError::TooManySymlinks);
+}
+
+#[test_case]
+fn mount_points() {
+    vfs::mkdir("/mnt").unwrap();
+    vfs::write_all("/mnt/hidden", b"").unwrap();
+    vfs::mount("/mnt", RamFs::new()).unwrap();
+
+    assert!(names("/mnt").is_empty());
+    vfs::write_all("/mnt/on_mount", b"mounted").unwrap();
+    assert_eq!(
+        vfs::read_to_string("/mnt/../mnt/on_mount").unwrap(),
+        "mounted"
+    );
+    assert_eq!(vfs::canonicalize("/mnt/..").unwrap(), "/");
+    assert_eq!(
+        vfs::link("/mnt/on_mount", "/cross").unwrap_err(),
+        VfsError::CrossDevice
+    );
+    assert_eq!(
+        vfs::rename("/mnt/on_mount", "/cross").unwrap_err(),
+        VfsError::CrossDevice
+    );
+    assert_eq!(vfs::unlink("/mnt").unwrap_err(), VfsError::Busy);
+    assert_eq!(
+        vfs::mount("/mnt", RamFs::new()).unwrap_err(),
+        VfsError::Busy
+    );
+    assert!(vfs::mounts().iter().any(|m| m.path == "/mnt"));
+
+    vfs::unmount("/mnt").unwrap();
+    assert_eq!(names("/mnt"), ["hidden"]);
+}
+
+#[test_case]
+fn rename_and_link() {
+    vfs::mkdir("/mv").unwrap();
+    vfs::mkdir("/mv/dir").unwrap();
+    vfs::write_all("/mv/file", b"moving").unwrap();
+
+    vfs::rename("/mv/file", "/mv/dir/file").unwrap();
+    assert_eq!(names("/mv"), ["dir"]);
+    assert_eq!(
+        vfs::rename("/mv/dir", "/mv/dir/inside").unwrap_err(),
+        VfsError::InvalidArgument
+    );
+
+    vfs::link("/mv/dir/file", "/mv/hard").unwrap();
+    assert_eq!(vfs::stat("/mv/hard").unwrap().nlink, 2);
+    vfs::unlink("/mv/dir/file").unwrap();
+    assert_eq!(vfs::read_to_string("/mv/hard").unwrap(), "moving");
+    assert_eq!(vfs::stat("/mv/hard").unwrap().nlink, 1);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
@@ -0,0 +1,123 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(tlenek_core::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+use tlenek_core::{
+    block::{BlockDevice, BlockError, SECTOR_SIZE},
+    hlt_loop, init, test_panic_handler,
+    virtio::blk::{self, VirtioBlk, MAX_REQUEST_SIZE},
+};
+
+// Must match tests/images/mkimages.sh
+const MAGIC: &[u8] = b"TLENEK VIRTIO TEST\0";
+const MAGIC_SECTOR: u64 = 1;
+const SCRATCH_SECTOR: u64 = 16;
+const DISK_SECTORS: u64 = 2048;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init(boot_info);
+    test_main();
+    hlt_loop();
+}
+
+/// The pattern disks, as opposed to the filesystem images other tests use.
+fn pattern_disks() -> Vec<Arc<VirtioBlk>> {
+    blk::devices()
+        .into_iter()
+        .filter(|d| d.block_count() == DISK_SECTORS)
+        .collect()
+}
+
+fn disk(modern: bool) -> Arc<VirtioBlk> {
+    pattern_disks()
+        .into_iter()
+        .find(|d| d.is_modern() == modern)
+        .expect("virtio test disk not attached")
+}
+
+fn check_known_sector(disk: &VirtioBlk) {
+    let mut buf = [0; SECTOR_SIZE];
+    disk.read_blocks(MAGIC_SECTOR, &mut buf).unwrap();
+
+    assert_eq!(&buf[..MAGIC.len()], MAGIC);
+    for (i, &byte) in buf[MAGIC.len()..].iter().enumerate() {
+        assert_eq!(byte, (i % 251) as u8);
+    }
+}
+
+/// Write a buffer big enough to need several requests, then read it back.
+fn check_large_write(disk: &VirtioBlk) {
+    let len = MAX_REQUEST_SIZE * 3 + SECTOR_SIZE;
+    let data: Vec<u8> = (0..len).map(|i| (i % 13) as u8).collect();
+    disk.write_blocks(SCRATCH_SECTOR, &data).unwrap();
+    disk.flush().unwrap();
+
+    let mut buf = vec![0; len];
+    disk.read_blocks(SCRATCH_SECTOR, &mut buf).unwrap();
+    assert!(buf == data);
+}
+
+#[test_case]
+fn both_transports_found() {
+    assert_eq!(pattern_disks().len(), 2);
+    for modern in [false, true] {
+        let disk = disk(modern);
+        assert!(!disk.is_read_only());
+        assert!(disk.name().starts_with("vd"));
+    }
+}
+
+#[test_case]
+fn legacy_read() {
+    check_known_sector(&disk(false));
+}
+
+#[test_case]
+fn modern_read() {
+    check_known_sector(&disk(true));
+}
+
+#[test_case]
+fn legacy_large_write() {
+    check_large_write(&disk(false));
+}
+
+#[test_case]
+fn modern_large_write() {
+    check_large_write(&disk(true));
+}
+
+#[test_case]
+fn read_into_stack_buffer() {
+    // Stack memory is translated the same way as heap memory
+    let mut buf = [0xFF; SECTOR_SIZE * 2];
+    let disk = disk(true);
+    disk.read_blocks(MAGIC_SECTOR - 1, &mut buf).unwrap();
+    assert!(buf[..SECTOR_SIZE].iter().all(|&b| b == 0));
+    assert_eq!(&buf[SECTOR_SIZE..(SECTOR_SIZE + MAGIC.len())], MAGIC);
+}
+
+#[test_case]
+fn out_of_range() {
+    let disk = disk(false);
+    let mut buf = [0; SECTOR_SIZE];
+    assert_eq!(
+        disk.read_blocks(DISK_SECTORS, &mut buf),
+        Err(BlockError::OutOfRange)
+    );
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
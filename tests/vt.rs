@@ -0,0 +1,82 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(tlenek_core::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+use log::LevelFilter;
+use tlenek_core::{
+    hlt_loop, init,
+    logger::{self, Sink, DEFAULT_LEVELS},
+    print, test_panic_handler,
+    vga_text::{
+        screen,
+        vt::{self, Role, Vt},
+    },
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    init(boot_info);
+    test_main();
+    hlt_loop();
+}
+
+/// The text of every row of `vt`, read through the screen API by making it the console for a
+/// moment.
+fn rows_of(vt: Vt) -> Vec<String> {
+    let console = vt::role(Role::Console);
+    vt::set_role(Role::Console, vt);
+    let (height, width) = screen::size();
+    let rows = (0..height)
+        .map(|row| {
+            (0..width)
+                .map(|col| char::from(screen::cell_at(row, col).unwrap().text_byte))
+                .collect()
+        })
+        .collect();
+    vt::set_role(Role::Console, console);
+    rows
+}
+
+#[test_case]
+fn switching_keeps_the_screen() {
+    print!("\nbefore switching");
+    let saved = screen::save();
+
+    vt::switch_to(Vt::Tty3);
+    assert_eq!(vt::active(), Vt::Tty3);
+    Vt::Tty3.write_bytes(b"\non tty3");
+    vt::switch_to(Vt::Tty1);
+
+    assert_eq!(vt::active(), Vt::Tty1);
+    assert_eq!(screen::save(), saved);
+    assert!(rows_of(Vt::Tty3)
+        .iter()
+        .any(|row| row.starts_with("on tty3")));
+}
+
+#[test_case]
+fn logs_go_to_the_log_terminal() {
+    vt::set_role(Role::Log, Vt::Tty2);
+    logger::set_level(Sink::Vga, LevelFilter::Info);
+    log::info!(target: "vt test", "on the log terminal");
+    vt::set_role(Role::Log, Vt::Tty1);
+    logger::set_level(Sink::Vga, DEFAULT_LEVELS[0]);
+
+    let logged = |row: &String| row.contains("vt test: on the log terminal");
+    assert!(rows_of(Vt::Tty2).iter().any(logged));
+    assert!(!rows_of(Vt::Tty1).iter().any(logged));
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}